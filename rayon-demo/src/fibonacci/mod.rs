@@ -77,6 +77,48 @@ fn fibonacci_join_2_1(b: &mut test::Bencher) {
 }
 
 
+#[bench]
+/// Compute the Fibonacci number recursively, using rayon::join within
+/// a single-threaded pool. Compare against `fibonacci_recursive` to
+/// see the overhead of `join` when no stealing is possible.
+fn fibonacci_join_single_thread(b: &mut test::Bencher) {
+    let pool = rayon::ThreadPool::new(Configuration::new().set_num_threads(1))
+                                 .expect(INIT_FAILED);
+
+    fn fib(n: u32) -> u32 {
+        if n < 2 { return n; }
+
+        let (a, b) = rayon::join(
+            || fib(n - 1),
+            || fib(n - 2));
+        a + b
+    }
+
+    b.iter(|| assert_eq!(pool.install(|| fib(test::black_box(N))), FN));
+}
+
+
+#[bench]
+/// Compute the Fibonacci number recursively, using rayon::join within
+/// a pool that uses heartbeat scheduling, so that most joins do not
+/// touch the deque. Compare against `fibonacci_join_1_2`.
+fn fibonacci_join_heartbeat(b: &mut test::Bencher) {
+    let pool = rayon::ThreadPool::new(Configuration::new().set_heartbeat_interval(128))
+                                 .expect(INIT_FAILED);
+
+    fn fib(n: u32) -> u32 {
+        if n < 2 { return n; }
+
+        let (a, b) = rayon::join(
+            || fib(n - 1),
+            || fib(n - 2));
+        a + b
+    }
+
+    b.iter(|| assert_eq!(pool.install(|| fib(test::black_box(N))), FN));
+}
+
+
 #[bench]
 /// Compute the Fibonacci number iteratively, just to show how silly the others
 /// are.  Parallelism can't make up for a bad choice of algorithm.
@@ -0,0 +1,252 @@
+//! Some microbenchmarks that stress test a pure `join` path.
+
+use rayon;
+use rayon::prelude::*;
+use test::{self, Bencher};
+
+/// A complete binary tree of `join` calls, `depth` levels deep, with a
+/// trivial amount of work at the leaves.
+fn join_tree(depth: usize) -> usize {
+    if depth == 0 {
+        1
+    } else {
+        let (a, b) = rayon::join(|| join_tree(depth - 1), || join_tree(depth - 1));
+        a + b
+    }
+}
+
+#[bench]
+fn join_tree_wide(b: &mut Bencher) {
+    b.iter(|| assert_eq!(join_tree(16), 1 << 16));
+}
+
+/// The standard yardstick from `rayon::bench`; see `join-overhead`.
+#[bench]
+fn recursive_sum(b: &mut Bencher) {
+    let v: Vec<u64> = (0..1 << 16).collect();
+    b.iter(|| assert_eq!(rayon::bench::sum(&v), (1 << 15) * ((1 << 16) - 1)));
+}
+
+/// A `join` whose closures return large arrays, which the job of the
+/// second closure has to hold on to if it is stolen.
+#[bench]
+fn join_large_result(b: &mut Bencher) {
+    fn make(x: u8) -> [u8; 4096] {
+        let mut a = [x; 4096];
+        a[0] = x.wrapping_add(1);
+        test::black_box(a)
+    }
+
+    b.iter(|| {
+        let (a, b) = rayon::join(|| make(1), || make(2));
+        assert_eq!((a[0], b[4095]), (2, 2));
+    });
+}
+
+/// An iterator that splits down to single items, far finer than the
+/// work calls for; most of its joins happen on a deque that is already
+/// crowded, so they need not push anything.
+#[bench]
+fn over_split_sum(b: &mut Bencher) {
+    let v: Vec<u64> = (0..1 << 16).collect();
+    b.iter(|| {
+        let sum: u64 = v.par_iter().weight_max().map(|&x| x * 2).sum();
+        assert_eq!(sum, (1 << 16) * ((1 << 16) - 1));
+    });
+}
+
+/// A wide fan-out: one job spawns many small jobs at once, giving
+/// thieves a long deque to steal from.
+#[bench]
+fn scope_fan_out_wide(b: &mut Bencher) {
+    b.iter(|| {
+        rayon::scope(|s| {
+            for _ in 0..10000 {
+                s.spawn(|_| {
+                    rayon::join(|| (), || ());
+                });
+            }
+        })
+    });
+}
+
+/// A wide, shallow tree: a scope that spawns `width` tasks, each of
+/// which spawns another `width` tasks.
+fn scope_two_levels(width: usize) {
+    rayon::scope(|s| {
+        for _ in 0..width {
+            s.spawn(move |s| {
+                for _ in 0..width {
+                    s.spawn(|_| {
+                        rayon::join(|| (), || ());
+                    });
+                }
+            });
+        }
+    })
+}
+
+fn scope_two_levels_in_pool(b: &mut Bencher, breadth_first: bool) {
+    let config = rayon::Configuration::new().set_breadth_first(breadth_first);
+    let pool = rayon::ThreadPool::new(config).unwrap();
+    b.iter(|| pool.install(|| scope_two_levels(100)));
+}
+
+#[bench]
+fn scope_two_levels_depth_first(b: &mut Bencher) {
+    scope_two_levels_in_pool(b, false);
+}
+
+#[bench]
+fn scope_two_levels_breadth_first(b: &mut Bencher) {
+    scope_two_levels_in_pool(b, true);
+}
+
+/// A chain of `join` calls, in which only a single job is available to
+/// steal at any time. So most workers sit idle and compete for the
+/// work of one busy worker, which stresses victim selection.
+///
+/// Each level pushes its continuation and, usually, pops it right back.
+/// That push/pop pair is the whole cost of exposing the continuation
+/// to thieves while the leaf runs: `join` can't tell that its second
+/// closure is going to call `join` again, and the new continuation is
+/// a different closure in a different frame, so there is no slot that
+/// one level could hand on to the next.
+fn join_chain(length: usize) -> usize {
+    if length == 0 {
+        0
+    } else {
+        let (a, b) = rayon::join(|| 1, || join_chain(length - 1));
+        a + b
+    }
+}
+
+#[bench]
+fn join_chain_idle_thieves(b: &mut Bencher) {
+    b.iter(|| assert_eq!(join_chain(1000), 1000));
+}
+
+/// Many small `join` trees at once: every worker pushes small jobs
+/// while the others steal them, often several at a time, so a `join`
+/// regularly finds its second closure gone to a thief that has yet to
+/// start it, and takes it back off the thief's deque.
+#[bench]
+fn join_small_contended(b: &mut Bencher) {
+    let pool = rayon::ThreadPool::new(rayon::Configuration::new().set_num_threads(4)).unwrap();
+    b.iter(|| {
+        pool.install(|| {
+            rayon::scope(|s| for _ in 0..64 {
+                s.spawn(|_| assert_eq!(join_tree(6), 1 << 6));
+            })
+        })
+    });
+}
+
+/// Some busy work that the optimizer can't see through.
+fn spin(iterations: u64) -> u64 {
+    (0..iterations).fold(0, |acc, i| acc ^ test::black_box(i))
+}
+
+/// A chain of lopsided `join` calls: at each level, one half is a bit
+/// of leaf work and the other is the whole rest of the chain. With
+/// `weighted`, `join_weighted` is told which half is heavier.
+fn lopsided_chain(length: usize, weighted: bool) -> u64 {
+    if length == 0 {
+        return 0;
+    }
+    let leaf = || spin(1000);
+    let rest = || lopsided_chain(length - 1, weighted);
+    let (a, b) = if weighted {
+        rayon::join_weighted(1.0, length as f64, leaf, rest)
+    } else {
+        rayon::join(leaf, rest)
+    };
+    a ^ b
+}
+
+#[bench]
+fn join_lopsided_unweighted(b: &mut Bencher) {
+    b.iter(|| lopsided_chain(100, false));
+}
+
+#[bench]
+fn join_lopsided_weighted(b: &mut Bencher) {
+    b.iter(|| lopsided_chain(100, true));
+}
+
+/// A memory-bound kernel: summing a vector that is much larger than
+/// the caches, split with `join` or, with `local`, with `join_local`.
+/// On a machine with several NUMA nodes, the difference between the
+/// two shows the cost of reading memory from a remote node.
+fn sum_split(v: &[u64], local: bool) -> u64 {
+    if v.len() <= 1 << 14 {
+        return v.iter().fold(0, |a, &b| a.wrapping_add(b));
+    }
+    let (left, right) = v.split_at(v.len() / 2);
+    let (a, b) = if local {
+        rayon::join_local(|| sum_split(left, local), || sum_split(right, local))
+    } else {
+        rayon::join(|| sum_split(left, local), || sum_split(right, local))
+    };
+    a.wrapping_add(b)
+}
+
+fn sum_split_bench(b: &mut Bencher, local: bool) {
+    let v: Vec<u64> = (0..1 << 24).collect();
+    b.iter(|| assert_eq!(sum_split(&v, local), (1 << 23) * ((1 << 24) - 1)));
+}
+
+#[bench]
+fn join_memory_bound(b: &mut Bencher) {
+    sum_split_bench(b, false);
+}
+
+#[bench]
+fn join_local_memory_bound(b: &mut Bencher) {
+    sum_split_bench(b, true);
+}
+
+/// Many `join` calls whose second closure is usually stolen: both
+/// halves take about as long, so by the time the caller is done with
+/// its half, a thief has long taken the other one, and the caller
+/// probes the latch of the stolen job while the thief works right next
+/// to it (see `SpinLatch`).
+fn stolen_halves(depth: usize) -> u64 {
+    if depth == 0 {
+        spin(500)
+    } else {
+        let (a, b) = rayon::join(|| stolen_halves(depth - 1), || stolen_halves(depth - 1));
+        a ^ b
+    }
+}
+
+#[bench]
+fn join_contended_steals(b: &mut Bencher) {
+    let pool = rayon::ThreadPool::new(rayon::Configuration::new().set_num_threads(2)).unwrap();
+    b.iter(|| pool.install(|| stolen_halves(10)));
+}
+
+/// Hands a pool 100k trivial tasks from outside, one `submit` (and one
+/// lock acquisition on the pool's job queue) at a time, or all at once
+/// with `submit_all`, and waits for all of them.
+const INJECTED_TASKS: usize = 100_000;
+
+#[bench]
+fn inject_per_item(b: &mut Bencher) {
+    let pool = rayon::ThreadPool::new(rayon::Configuration::new().set_num_threads(4)).unwrap();
+    b.iter(|| {
+        let pending: Vec<_> = (0..INJECTED_TASKS)
+            .map(|i| pool.submit(move || i).unwrap())
+            .collect();
+        pending.into_iter().fold(0, |sum, p| sum ^ p.get())
+    });
+}
+
+#[bench]
+fn inject_batch(b: &mut Bencher) {
+    let pool = rayon::ThreadPool::new(rayon::Configuration::new().set_num_threads(4)).unwrap();
+    b.iter(|| {
+        let pending = pool.submit_all((0..INJECTED_TASKS).map(|i| move || i)).unwrap();
+        pending.into_iter().fold(0, |sum, p| sum ^ p.get())
+    });
+}
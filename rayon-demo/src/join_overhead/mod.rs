@@ -0,0 +1,51 @@
+const USAGE: &'static str = "
+Usage: join-overhead bench [--size N] [--iters N]
+       join-overhead --help
+
+Measures the overhead of `join` by summing a slice of integers, split
+down to single elements, in parallel and sequentially (see the
+`rayon::bench` module).
+
+Commands:
+    bench              Run the benchmark and print the time per join.
+
+Options:
+    --size N           Number of integers to sum [default: 1048576]
+    --iters N          Number of times to sum them [default: 20]
+    -h, --help         Show this message.
+";
+
+#[derive(RustcDecodable)]
+pub struct Args {
+    cmd_bench: bool,
+    flag_size: usize,
+    flag_iters: usize,
+}
+
+use docopt::Docopt;
+use rayon::bench;
+use std::time::Duration;
+
+fn nanos(dur: Duration) -> u64 {
+    dur.subsec_nanos() as u64 + dur.as_secs() * 1_000_000_000u64
+}
+
+pub fn main(args: &[String]) {
+    let args: Args =
+        Docopt::new(USAGE)
+            .and_then(|d| d.argv(args).decode())
+            .unwrap_or_else(|e| e.exit());
+
+    if args.cmd_bench {
+        let v: Vec<u64> = (0..args.flag_size as u64).collect();
+        let expected = bench::sum_sequential(&v);
+        assert_eq!(bench::sum(&v), expected);
+
+        let joins = bench::num_joins(v.len()) as f64;
+        let seq = nanos(bench::measure(args.flag_iters, || bench::sum_sequential(&v)));
+        let par = nanos(bench::measure(args.flag_iters, || bench::sum(&v)));
+        println!("seq: {:.2} ns/split", seq as f64 / joins);
+        println!("par: {:.2} ns/join", par as f64 / joins);
+        println!("overhead: {:.2} ns/join", (par as f64 - seq as f64) / joins);
+    }
+}
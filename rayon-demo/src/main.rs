@@ -6,6 +6,7 @@ use std::io;
 use std::io::prelude::*;
 use std::process::exit;
 
+mod join_overhead;
 mod matmul;
 mod mergesort;
 mod nbody;
@@ -21,6 +22,7 @@ mod tsp;
 #[cfg(test)] mod pythagoras;
 #[cfg(test)] mod fibonacci;
 #[cfg(test)] mod find;
+#[cfg(test)] mod join_microbench;
 
 extern crate rayon; // all
 extern crate docopt; // all
@@ -55,6 +57,8 @@ benchmark has its own options and modes, so try `rayon-demo foo
 
 Benchmarks:
 
+  - join-overhead: The time taken by a single `join`, compared to a plain
+                   function call.
   - nbody: A physics simulation of multiple bodies attracting and repelling
            one another.
   - sieve: Finding primes using a Sieve of Eratosthenes.
@@ -78,6 +82,7 @@ fn main() {
 
     let bench_name = &args[1];
     match &bench_name[..] {
+        "join-overhead" => join_overhead::main(&args[1..]),
         "matmul" => matmul::main(&args[1..]),
         "mergesort" => mergesort::main(&args[1..]),
         "nbody" => nbody::main(&args[1..]),
@@ -1,23 +1,49 @@
-use latch::{LockLatch, SpinLatch};
+use context;
+use latch::{self, Latch, LockLatch, SpinLatch};
 #[allow(unused_imports)]
 use log::Event::*;
-use job::StackJob;
-use std::sync::Arc;
+use job::{JobRef, StackJob};
+use std::any::Any;
+use std::sync::{Arc, Once, ONCE_INIT};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use std::error::Error;
 use std::fmt;
-use thread_pool::{self, Registry, WorkerThread};
+use std::io::{self, Write};
+use thread_pool::{self, DonatedWorker, Registry, WorkerThread};
 use std::mem;
-use unwind;
+use std::thread;
+use std::time::Duration;
+use unwind::{self, NoteOriginIfPanic, PanicOrigin};
+use util::leak;
+use spawn::{self, PendingResult, ScopeHandle};
+use stack;
+#[cfg(feature = "log")]
+use schedule::{StealMode, StealSchedule};
+#[cfg(feature = "unstable")]
+use future::{self, RayonFuture};
+#[cfg(feature = "unstable")]
+use futures::Future;
 
 /// Custom error type for the rayon thread pool configuration.
-#[derive(Debug,PartialEq)]
+#[derive(Clone,Debug,PartialEq)]
 pub enum InitError {
     /// Error if number of threads is set to zero.
     NumberOfThreadsZero,
 
     /// Error if the gloal thread pool is initialized multiple times
-    /// and the configuration is not equal for all configurations.
+    /// and the configuration is not equal for all configurations, or
+    /// if it was already started with the default configuration
+    /// before `initialize()` was called.
     GlobalPoolAlreadyInitialized,
+
+    /// Error if one of the worker threads could not be spawned, e.g.
+    /// because the process ran into a limit on the number of threads.
+    ThreadSpawnFailed,
+
+    /// Error if `initialize` is asked to run the global thread pool on
+    /// donated threads (see `Configuration::set_use_current_threads`):
+    /// nobody could hand a thread over before `initialize` returns.
+    GlobalPoolOnCurrentThreads,
 }
 
 impl fmt::Display for InitError {
@@ -32,6 +58,13 @@ impl fmt::Display for InitError {
                        "The gobal thread pool has already been initialized with a different \
                         configuration. Only one valid configuration is allowed.")
             }
+            InitError::ThreadSpawnFailed => {
+                write!(f, "A worker thread of the thread pool could not be spawned.")
+            }
+            InitError::GlobalPoolOnCurrentThreads => {
+                write!(f,
+                       "The global thread pool cannot run on threads donated by the caller.")
+            }
         }
     }
 }
@@ -43,21 +76,229 @@ impl Error for InitError {
             InitError::GlobalPoolAlreadyInitialized => {
                 "global thread pool has already been initialized"
             }
+            InitError::ThreadSpawnFailed => "failed to spawn a worker thread",
+            InitError::GlobalPoolOnCurrentThreads => {
+                "global thread pool cannot use the current threads"
+            }
+        }
+    }
+}
+
+/// Error returned by `try_join` and `ThreadPool::try_install` if
+/// there is no thread pool to run the closures in, and by
+/// `ThreadPool::submit` if the pool does not take on more work.
+#[derive(Debug,PartialEq)]
+pub enum PoolError {
+    /// The global thread pool could not be started (see
+    /// `InitError::ThreadSpawnFailed`).
+    PoolUnavailable,
+
+    /// The thread pool has been terminated, so its workers no longer
+    /// take on new work.
+    PoolShutdown,
+
+    /// The injector queue of the thread pool is full (see
+    /// `Configuration::set_max_queued`).
+    QueueFull,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PoolError::PoolUnavailable => write!(f, "The global thread pool is unavailable."),
+            PoolError::PoolShutdown => write!(f, "The thread pool has been shut down."),
+            PoolError::QueueFull => write!(f, "The job queue of the thread pool is full."),
+        }
+    }
+}
+
+impl Error for PoolError {
+    fn description(&self) -> &str {
+        match *self {
+            PoolError::PoolUnavailable => "global thread pool unavailable",
+            PoolError::PoolShutdown => "thread pool shut down",
+            PoolError::QueueFull => "thread pool job queue full",
         }
     }
 }
 
+/// The type for a panic handling closure. Note that this same closure
+/// may be invoked multiple times in parallel.
+pub type PanicHandler = Fn(Box<Any + Send>) + Send + Sync;
+
+/// The type for a closure that gets invoked when a worker thread
+/// starts, with the index of the thread. Note that this same closure
+/// may be invoked multiple times in parallel.
+pub type StartHandler = Fn(usize) + Send + Sync;
+
+/// The type for a closure that gets invoked before a worker thread
+/// exits, with the index of the thread. Note that this same closure
+/// may be invoked multiple times in parallel.
+pub type ExitHandler = Fn(usize) + Send + Sync;
+
+/// The type for a closure that gets invoked when the worker threads
+/// of a thread pool are deadlocked; see
+/// `Configuration::set_deadlock_handler`.
+pub type DeadlockHandler = Fn() + Send + Sync;
+
+/// The type for a closure that gets invoked when a worker thread starts
+/// to execute a job, with the index of the thread; see
+/// `Configuration::set_job_start_handler`.
+pub type JobStartHandler = Fn(usize) + Send + Sync;
+
+/// The type for a closure that gets invoked when a worker thread is
+/// done executing a job, with the index of the thread and the time the
+/// job took; see `Configuration::set_job_end_handler`.
+pub type JobEndHandler = Fn(usize, Duration) + Send + Sync;
+
+/// What `ThreadPool::submit` does when the injector queue of the pool
+/// is full; see `Configuration::set_max_queued`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Wait until the workers have taken enough jobs off the queue.
+    Block,
+
+    /// Fail with `PoolError::QueueFull`.
+    Reject,
+}
+
+/// A processor core, by the number that the operating system gives it
+/// (on Linux, as in `sched_setaffinity` or `/proc/cpuinfo`); see
+/// `Configuration::set_affinity`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CoreId(pub usize);
+
+/// The default for `Configuration::set_stack_guard`.
+const DEFAULT_STACK_GUARD: usize = 64 * 1024;
+
 /// Contains the rayon thread pool configuration.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Configuration {
     /// The number of threads in the rayon thread pool. Must not be zero.
     num_threads: Option<usize>,
+
+    /// Closure invoked on panics in jobs that have no caller to
+    /// propagate the panic to. If this is `None`, such panics abort.
+    panic_handler: Option<Arc<PanicHandler>>,
+
+    /// If true, `join` runs its closures sequentially.
+    deterministic: bool,
+
+    /// How deeply `join` calls may nest on one thread before they
+    /// stop splitting. If this is `None`, there is no limit.
+    join_depth_limit: Option<usize>,
+
+    /// If set, `join` only makes its second closure available for
+    /// stealing on every so many calls.
+    heartbeat_interval: Option<usize>,
+
+    /// If set, parallel iterators do not split pieces of this many
+    /// items or fewer, unless told otherwise with `split_when`.
+    default_split_threshold: Option<usize>,
+
+    /// If true, workers execute their own jobs in FIFO order.
+    breadth_first: bool,
+
+    /// If true, new work wakes the worker that has slept the longest.
+    fair_wake: bool,
+
+    /// Closure to compute the name of a worker thread from its index.
+    /// If this is `None`, the threads are unnamed.
+    thread_name: Option<Arc<Fn(usize) -> String + Send + Sync>>,
+
+    /// Closure to compute the NUMA node of a worker thread from its
+    /// index. If this is `None`, each worker asks the operating system.
+    numa_node: Option<Arc<Fn(usize) -> usize + Send + Sync>>,
+
+    /// Closure to compute the core that a worker thread is pinned to
+    /// from its index. If this is `None`, no worker is pinned.
+    affinity: Option<Arc<Fn(usize) -> Option<CoreId> + Send + Sync>>,
+
+    /// The stack size for the worker threads, in bytes. If this is
+    /// `None`, the default of the standard library is used.
+    stack_size: Option<usize>,
+
+    /// How much of its stack a worker keeps in reserve, in bytes: once
+    /// less than this is left, `join` stops splitting.
+    stack_guard: usize,
+
+    /// The size of the stack segments that a worker runs on once its
+    /// stack runs low. If this is `None`, it runs sequentially instead.
+    growable_stacks: Option<usize>,
+
+    /// How many times an idle or waiting worker polls for work before
+    /// it goes to sleep. If this is `None`, the default back-off is used.
+    spin_iterations: Option<usize>,
+
+    /// How many jobs `submit` may queue up in the injector queue, and
+    /// what it does when the queue is full. If this is `None`, there is
+    /// no limit.
+    max_queued: Option<usize>,
+    queue_policy: QueuePolicy,
+
+    /// If true, the pool spawns no threads of its own, and runs its
+    /// workers on threads that the caller donates.
+    use_current_threads: bool,
+
+    /// How many spare workers may stand in for workers that are
+    /// blocked in `mark_blocking`.
+    blocking_replacements: usize,
+
+    /// If true, the pool does not keep handles for its threads, and
+    /// never waits for them to exit.
+    detach_threads: bool,
+
+    /// Closure invoked on each worker thread when it starts.
+    start_handler: Option<Arc<StartHandler>>,
+
+    /// Closure invoked on each worker thread before it exits.
+    exit_handler: Option<Arc<ExitHandler>>,
+
+    /// Closure invoked when all worker threads are deadlocked. If
+    /// this is `None`, deadlocks are not detected.
+    deadlock_handler: Option<Arc<DeadlockHandler>>,
+
+    /// Closures invoked around each job that a worker executes.
+    job_start_handler: Option<Arc<JobStartHandler>>,
+    job_end_handler: Option<Arc<JobEndHandler>>,
+
+    /// Whether to record or replay the steal schedule.
+    #[cfg(feature = "log")]
+    steal_mode: Option<StealMode>,
 }
 
 impl Configuration {
     /// Creates and return a valid rayon thread pool configuration, but does not initialize it.
     pub fn new() -> Configuration {
-        Configuration { num_threads: None }
+        Configuration {
+            num_threads: None,
+            panic_handler: None,
+            deterministic: false,
+            join_depth_limit: None,
+            heartbeat_interval: None,
+            default_split_threshold: None,
+            breadth_first: false,
+            fair_wake: false,
+            thread_name: None,
+            numa_node: None,
+            affinity: None,
+            stack_size: None,
+            stack_guard: DEFAULT_STACK_GUARD,
+            growable_stacks: None,
+            spin_iterations: None,
+            max_queued: None,
+            queue_policy: QueuePolicy::Block,
+            use_current_threads: false,
+            blocking_replacements: 0,
+            detach_threads: false,
+            start_handler: None,
+            exit_handler: None,
+            deadlock_handler: None,
+            job_start_handler: None,
+            job_end_handler: None,
+            #[cfg(feature = "log")]
+            steal_mode: None,
+        }
     }
 
     /// Get the number of threads that will be used for the thread
@@ -75,6 +316,652 @@ impl Configuration {
         self
     }
 
+    /// Returns the panic handler, if one was set. See
+    /// `set_panic_handler` for more information.
+    pub fn panic_handler(&self) -> Option<Arc<PanicHandler>> {
+        self.panic_handler.clone()
+    }
+
+    /// Normally, whenever Rayon catches a panic, it tries to
+    /// propagate it to someplace sensible, to try and reflect the
+    /// semantics of sequential execution. But in some cases,
+    /// particularly with the `spawn()` APIs, there is no obvious
+    /// place where we should propagate the panic to. In that case,
+    /// this panic handler is invoked with the panic payload.
+    ///
+    /// If no panic handler is set, the default is to abort the
+    /// process, under the principle that panics should not go
+    /// unobserved.
+    ///
+    /// If the panic handler itself panics, this will abort the
+    /// process too.
+    pub fn set_panic_handler<H>(mut self, panic_handler: H) -> Configuration
+        where H: Fn(Box<Any + Send>) + Send + Sync + 'static
+    {
+        self.panic_handler = Some(Arc::new(panic_handler));
+        self
+    }
+
+    /// Returns whether the thread pool runs in deterministic mode. See
+    /// `set_deterministic` for more information.
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Enables or disables deterministic mode, which is meant for
+    /// testing and debugging. In deterministic mode, `join` (and hence
+    /// everything built on it, such as parallel iterators) always runs
+    /// `oper_a` and then `oper_b` on the current thread, without ever
+    /// making `oper_b` available for stealing. So a tree of `join`
+    /// calls executes in the same order as its sequential counterpart
+    /// every time, which makes ordering bugs reproducible. This is
+    /// stronger than setting the number of threads to one, which
+    /// makes no promises about the order in which jobs run.
+    ///
+    /// Only `join` is affected: tasks submitted via `spawn()` or a
+    /// `scope()` still run whenever a worker gets to them.
+    pub fn set_deterministic(mut self, deterministic: bool) -> Configuration {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Returns the join depth limit, if one was set. See
+    /// `set_join_depth_limit` for more information.
+    pub fn join_depth_limit(&self) -> Option<usize> {
+        self.join_depth_limit
+    }
+
+    /// Limits how deeply `join` calls may nest on a single thread.
+    /// Every `join` that makes its second closure available for
+    /// stealing keeps that closure (and bookkeeping for it) alive on
+    /// its stack frame until the closure completes, so a very deep
+    /// tree of `join` calls can overflow the stack. Once `limit` such
+    /// calls are active on a thread, any further `join` on that thread
+    /// just runs its two closures one after the other. Shallow levels
+    /// of the tree, which are where the parallelism matters, are not
+    /// affected.
+    ///
+    /// By default, there is no limit.
+    pub fn set_join_depth_limit(mut self, limit: usize) -> Configuration {
+        self.join_depth_limit = Some(limit);
+        self
+    }
+
+    /// Returns the heartbeat interval, if one was set. See
+    /// `set_heartbeat_interval` for more information.
+    pub fn heartbeat_interval(&self) -> Option<usize> {
+        self.heartbeat_interval
+    }
+
+    /// Enables heartbeat scheduling, which cuts down the overhead of
+    /// very fine-grained `join` calls. Normally, every `join` pushes
+    /// its second closure onto the deque of the worker, so that other
+    /// workers can steal it. With heartbeat scheduling, `join` merely
+    /// makes a note of the second closure, which is much cheaper, and
+    /// runs it inline once the first closure is done. Every `interval`
+    /// calls to `join` on a worker (the "heartbeat"), the *oldest* of
+    /// the closures noted on that worker is promoted and pushed onto
+    /// the deque after all. Since the oldest closure generally stands
+    /// for the most work, this still gives idle workers plenty to
+    /// steal, while most `join` calls never touch the deque.
+    ///
+    /// The larger the interval, the lower the overhead, but the longer
+    /// it takes for work to become available to other workers. An
+    /// interval of 1 promotes a closure on every call, much like the
+    /// default behavior. By default, heartbeat scheduling is off.
+    pub fn set_heartbeat_interval(mut self, interval: usize) -> Configuration {
+        self.heartbeat_interval = Some(interval);
+        self
+    }
+
+    /// Returns the default split threshold, if one was set. See
+    /// `set_default_split_threshold` for more information.
+    pub fn default_split_threshold(&self) -> Option<usize> {
+        self.default_split_threshold
+    }
+
+    /// Sets the sequential cutoff for all parallel iterators that run
+    /// in the pool: a piece of `threshold` items or fewer is processed
+    /// sequentially, rather than split in two, whatever the splitting
+    /// policy (or `weight`) would otherwise do. This saves tuning every
+    /// call for work whose items are all cheap. An iterator that gets a
+    /// cost model of its own, with `split_when`, ignores the threshold,
+    /// which also makes a finer split possible where one is needed.
+    ///
+    /// The threshold only applies where the length of a piece is known,
+    /// so it has no effect on iterators that split without knowing it,
+    /// such as those over the characters of a string or over a range of
+    /// 64-bit integers. By default, and with a threshold of 0 or 1, any
+    /// piece of more than one item may be split.
+    pub fn set_default_split_threshold(mut self, threshold: usize) -> Configuration {
+        self.default_split_threshold = Some(threshold);
+        self
+    }
+
+    /// Returns whether workers execute their own jobs breadth-first.
+    /// See `set_breadth_first` for more information.
+    pub fn breadth_first(&self) -> bool {
+        self.breadth_first
+    }
+
+    /// Normally, a worker executes the jobs on its own deque in LIFO
+    /// order, i.e., depth-first, which is good for cache locality,
+    /// while thieves take the oldest jobs from the other end. If
+    /// `breadth_first` is true, workers take their own jobs from the
+    /// same end as thieves, executing them in FIFO order instead. For
+    /// wide, shallow task trees (e.g., a `scope` that spawns many
+    /// tasks), this can expose more work to thieves sooner.
+    ///
+    /// This affects the tasks spawned in a `scope`, and which job a
+    /// worker picks when it looks for work. Once `join`
+    /// has run its first closure, it still takes back its own second
+    /// closure (if that was not stolen) from the top of the deque;
+    /// going through all the older jobs first would nest them on the
+    /// stack of this `join`, without bound.
+    pub fn set_breadth_first(mut self, breadth_first: bool) -> Configuration {
+        self.breadth_first = breadth_first;
+        self
+    }
+
+    /// Returns whether new work wakes the sleeping workers in FIFO
+    /// order. See `set_fair_wake` for more information.
+    pub fn fair_wake(&self) -> bool {
+        self.fair_wake
+    }
+
+    /// Normally, when a job is pushed while workers are asleep, which
+    /// of them wakes up is up to the operating system, and may well be
+    /// the same worker every time. If `fair_wake` is true, sleeping
+    /// workers instead queue up in the order in which they fell
+    /// asleep, and each new job (or batch of injected jobs) wakes the
+    /// ones at the front of the queue, i.e., those that have been idle
+    /// the longest. This spreads light loads evenly over the workers,
+    /// at the cost of a little bookkeeping whenever a worker goes to
+    /// sleep. It makes no difference while the workers are busy, since
+    /// they only sleep after failing to find work for a while.
+    pub fn set_fair_wake(mut self, fair_wake: bool) -> Configuration {
+        self.fair_wake = fair_wake;
+        self
+    }
+
+    /// Returns the name that the worker thread with the given index
+    /// should get, if any. See `set_thread_name` for more information.
+    pub fn thread_name(&self, index: usize) -> Option<String> {
+        self.thread_name.as_ref().map(|thread_name| thread_name(index))
+    }
+
+    /// Sets a closure which computes the name of each worker thread
+    /// from its index (see `current_thread_index()`), e.g. so that the
+    /// workers are easy to tell apart in a debugger. By default, the
+    /// worker threads are unnamed.
+    pub fn set_thread_name<F>(mut self, thread_name: F) -> Configuration
+        where F: Fn(usize) -> String + Send + Sync + 'static
+    {
+        self.thread_name = Some(Arc::new(thread_name));
+        self
+    }
+
+    /// Returns the NUMA node of the worker thread with the given index,
+    /// if it was set. See `set_numa_node` for more information.
+    pub fn numa_node(&self, index: usize) -> Option<usize> {
+        self.numa_node.as_ref().map(|numa_node| numa_node(index))
+    }
+
+    /// Sets a closure which computes the NUMA node of each worker thread
+    /// from its index. This only matters for `join_local`, which keeps
+    /// its second closure on the node of the caller. By default, each
+    /// worker asks the operating system which node it is running on
+    /// when it starts (on Linux; elsewhere, all workers are taken to be
+    /// on node 0). A worker that is not pinned to a node may be moved
+    /// to another one later, so if locality matters, pin the workers
+    /// (e.g., from the start handler) and set their nodes here.
+    pub fn set_numa_node<F>(mut self, numa_node: F) -> Configuration
+        where F: Fn(usize) -> usize + Send + Sync + 'static
+    {
+        self.numa_node = Some(Arc::new(numa_node));
+        self
+    }
+
+    /// Returns the core that the worker thread with the given index is
+    /// to be pinned to, if any. See `set_affinity` for more information.
+    pub fn affinity(&self, index: usize) -> Option<CoreId> {
+        self.affinity.as_ref().and_then(|affinity| affinity(index))
+    }
+
+    /// Sets a closure which computes, from the index of each worker
+    /// thread, the core that the worker is pinned to, or `None` to
+    /// leave that worker free to move. Each worker pins itself when it
+    /// starts, before the start handler runs (which may change the
+    /// affinity yet again); spare workers (see
+    /// `set_blocking_replacements`) and donated threads (see
+    /// `set_use_current_threads`) are pinned, too, and donated threads
+    /// stay pinned after they return. Several workers may share a core,
+    /// e.g. with `|index| Some(CoreId(index % cores))` for more workers
+    /// than cores.
+    ///
+    /// Pinning is best effort: it is only implemented on Linux, and
+    /// elsewhere this setting is ignored. If the operating system
+    /// refuses to pin a worker (e.g. because the core does not exist,
+    /// or is not in the affinity mask of the process), that worker runs
+    /// unpinned, as it would without this setting, rather than making
+    /// `ThreadPool::new` fail; so use it for performance, never for
+    /// correctness.
+    pub fn set_affinity<F>(mut self, affinity: F) -> Configuration
+        where F: Fn(usize) -> Option<CoreId> + Send + Sync + 'static
+    {
+        self.affinity = Some(Arc::new(affinity));
+        self
+    }
+
+    /// Returns the stack size of the worker threads, if one was set.
+    /// See `set_stack_size` for more information.
+    pub fn stack_size(&self) -> Option<usize> {
+        self.stack_size
+    }
+
+    /// Sets the stack size of the worker threads, in bytes. A larger
+    /// stack allows for deeper recursion (e.g., of `join` calls) on
+    /// the workers. By default, the stack size is whatever
+    /// `std::thread` picks.
+    pub fn set_stack_size(mut self, stack_size: usize) -> Configuration {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Returns how much stack the workers keep in reserve, in bytes.
+    /// See `set_stack_guard` for more information.
+    pub fn stack_guard(&self) -> usize {
+        self.stack_guard
+    }
+
+    /// Sets how much of its stack a worker keeps in reserve, in bytes.
+    /// Once less than `stack_guard` bytes of a worker's stack are left,
+    /// `join` on that worker runs its two closures one after the other
+    /// rather than making the second one available for stealing, which
+    /// takes much less stack per call (see `set_join_depth_limit`), and
+    /// also keeps the worker from running stolen jobs on top of it. So
+    /// a deep recursion gets to go on for longer before it overflows the
+    /// stack, which would crash the whole process. The guard only helps
+    /// if the rest of the recursion fits into the reserve, though; it
+    /// cannot make a stack grow.
+    ///
+    /// On Linux, the worker asks the operating system where its stack
+    /// ends. Elsewhere, it estimates that from where it starts out and
+    /// from the stack size (see `set_stack_size`; without one, the
+    /// standard library's default of 2 MiB is assumed), so the guard is
+    /// a heuristic there. The default is 64 KiB; zero turns the guard
+    /// off.
+    pub fn set_stack_guard(mut self, stack_guard: usize) -> Configuration {
+        self.stack_guard = stack_guard;
+        self
+    }
+
+    /// Returns the size of the stack segments that the workers grow
+    /// their stacks by, if set. See `set_growable_stacks` for more
+    /// information.
+    pub fn growable_stacks(&self) -> Option<usize> {
+        self.growable_stacks
+    }
+
+    /// Lets the workers grow their stacks by segments of `segment_size`
+    /// bytes (rounded up to whole pages, and to at least 64 KiB). Once
+    /// a worker's stack is down to the stack guard (see
+    /// `set_stack_guard`), a `join` on that worker no longer runs its
+    /// closures in turn, but switches to a freshly allocated segment,
+    /// runs there as usual (pushing its second closure for thieves,
+    /// and so on), and switches back once both closures are done. A
+    /// recursion that runs low on a segment, too, moves on to yet
+    /// another one, so it is only limited by memory. The last spare
+    /// segments of each worker are kept for reuse.
+    ///
+    /// Support by platform:
+    ///
+    /// - Linux with glibc, on x86_64 and aarch64: supported.
+    /// - Everywhere else: the setting is ignored, and `join` runs its
+    ///   closures in turn past the stack guard, as it does without it.
+    ///
+    /// Only `join` (and what is built on it, such as the parallel
+    /// iterators) switches stacks; a deep recursion that does not go
+    /// through `join` still overflows. A stack guard of zero turns the
+    /// guard off, and hence this setting, too. By default, stacks do not
+    /// grow.
+    pub fn set_growable_stacks(mut self, segment_size: usize) -> Configuration {
+        self.growable_stacks = Some(segment_size);
+        self
+    }
+
+    /// Returns how many times a worker polls for work before it goes
+    /// to sleep, if configured. See `set_spin_iterations` for more
+    /// information.
+    pub fn spin_iterations(&self) -> Option<usize> {
+        self.spin_iterations
+    }
+
+    /// Sets how many times a worker that has nothing to do polls for
+    /// work before it goes to sleep. This applies both to idle workers
+    /// and to workers that wait for a stolen job to complete (in `join`,
+    /// `scope` or `wait_until()`). Polling finds new work the soonest,
+    /// but keeps a core busy; on an oversubscribed machine, or for batch
+    /// jobs that do not care much about latency, the cycles are better
+    /// spent elsewhere.
+    ///
+    /// With zero, workers go to sleep right away. An idle worker sleeps
+    /// until new work is pushed, while a waiting worker sleeps for short
+    /// intervals (of at most a millisecond) between polls, since nobody
+    /// wakes it up when its job completes; either way, no wakeup can be
+    /// lost. By default, workers poll about a thousand times (spinning
+    /// at first, then yielding their timeslice between polls).
+    pub fn set_spin_iterations(mut self, spin_iterations: usize) -> Configuration {
+        self.spin_iterations = Some(spin_iterations);
+        self
+    }
+
+    /// Returns the limit on the number of jobs that `submit` may queue
+    /// up, if any. See `set_max_queued` for more information.
+    pub fn max_queued(&self) -> Option<usize> {
+        self.max_queued
+    }
+
+    /// Returns what `submit` does when the queue is full. See
+    /// `set_max_queued` for more information.
+    pub fn queue_policy(&self) -> QueuePolicy {
+        self.queue_policy
+    }
+
+    /// Limits how many jobs `ThreadPool::submit` and `submit_all` may
+    /// queue up in the pool's injector queue (where jobs from outside
+    /// the pool wait for a worker), so that a thread that submits work
+    /// faster than the workers get through it can not make the queue
+    /// grow without bound. When the queue is full, `policy` says what
+    /// happens: with `QueuePolicy::Block`, `submit` waits until the
+    /// workers have taken enough jobs off the queue, and with
+    /// `QueuePolicy::Reject`, it fails with `PoolError::QueueFull`.
+    ///
+    /// A batch from `submit_all` goes in whole, once all of it fits, or
+    /// into an empty queue if it is larger than the limit. Called from
+    /// a worker thread (of this pool or any other), `submit` never
+    /// waits, since the jobs that would make room may be waiting for
+    /// that very worker; it fails with `PoolError::QueueFull` instead,
+    /// whatever the policy. Only `submit` and `submit_all` are limited:
+    /// the jobs of `spawn`, `install` and the like do count towards the
+    /// length of the queue, but always go in, and so do the jobs that
+    /// workers push onto their own deques, such as the second closures
+    /// of `join`. By default, the queue is not limited.
+    pub fn set_max_queued(mut self, max_queued: usize, policy: QueuePolicy) -> Configuration {
+        self.max_queued = Some(max_queued);
+        self.queue_policy = policy;
+        self
+    }
+
+    /// Keeps the global thread pool from being started implicitly: from
+    /// now on, `join` and `scope` called from outside of any thread
+    /// pool run their closures sequentially on the calling thread, as
+    /// do parallel iterators (since they are built on `join`), and
+    /// `current_num_threads()` returns 1 there. This is for programs
+    /// like short-lived command-line tools, which would rather not pay
+    /// for starting a pool of threads that they hardly use.
+    ///
+    /// This affects the whole process, not a particular pool, and
+    /// cannot be undone. Pools created with `ThreadPool::new` work as
+    /// usual, and so does the global pool if it was (or is later)
+    /// started with `initialize`. Operations that cannot do without a
+    /// pool, such as `spawn` and `ThreadPool::global`, still start it.
+    pub fn disable_implicit_pool() {
+        thread_pool::disable_implicit_registry();
+    }
+
+    /// Returns whether the pool runs on threads that the caller
+    /// donates. See `set_use_current_threads` for more information.
+    pub fn use_current_threads(&self) -> bool {
+        self.use_current_threads
+    }
+
+    /// If `use_current_threads` is true, the pool does not spawn any
+    /// threads of its own. Instead, the caller creates the threads
+    /// (e.g., to control their affinity or priority, or because it has
+    /// a pool of its own), takes each worker out of the pool with
+    /// `ThreadPool::take_worker`, and runs it on one of them with
+    /// `DonatedWorker::run`, which schedules jobs like any worker does
+    /// until the pool is dropped. `set_thread_name` and
+    /// `set_stack_size` have no effect in this case.
+    ///
+    /// Jobs wait in the pool until somebody runs a worker, and every
+    /// worker should be run: e.g., `broadcast` only returns once each
+    /// of them has run its operation. This is only for pools created
+    /// with `ThreadPool::new`; `initialize` rejects it for the global
+    /// pool with `InitError::GlobalPoolOnCurrentThreads`.
+    pub fn set_use_current_threads(mut self, use_current_threads: bool) -> Configuration {
+        self.use_current_threads = use_current_threads;
+        self
+    }
+
+    /// Returns how many spare workers may stand in for blocked ones.
+    /// See `set_blocking_replacements` for more information.
+    pub fn blocking_replacements(&self) -> usize {
+        self.blocking_replacements
+    }
+
+    /// Sets how many spare workers the pool may run, at most, while
+    /// some of its workers are blocked in `mark_blocking`: each worker
+    /// that blocks lets one more spare run, until the replacements run
+    /// out, so the number of workers that can actually run stays at
+    /// the size of the pool even though some of them are stuck on I/O.
+    /// The spare threads are spawned by the pool the first time they
+    /// are needed (even if it otherwise runs on donated threads), and
+    /// stay around, idle, once the workers they stood in for return;
+    /// they have the indices from `num_threads` up. The default is
+    /// zero, in which case `mark_blocking` just runs its closure.
+    pub fn set_blocking_replacements(mut self, replacements: usize) -> Configuration {
+        self.blocking_replacements = replacements;
+        self
+    }
+
+    /// Returns whether the pool detaches its worker threads. See
+    /// `set_detach_threads` for more information.
+    pub fn detach_threads(&self) -> bool {
+        self.detach_threads
+    }
+
+    /// If `detach_threads` is true, the pool does not hold on to the
+    /// threads it spawns, so dropping it only tells the workers to
+    /// terminate, and returns without waiting for them to exit. This
+    /// suits a program that runs one burst of parallel work and then
+    /// exits: its workers need not be joined, and just die with the
+    /// process. Each worker keeps the state of the pool alive for as
+    /// long as it runs, so a detached worker never touches freed memory,
+    /// even if it is still busy when the `ThreadPool` is dropped.
+    ///
+    /// Without joining, nothing guarantees that a worker's exit handler
+    /// (see `set_exit_handler`) has run, or will run before the process
+    /// exits. The default is false, in which case dropping the pool
+    /// waits for all its threads.
+    pub fn set_detach_threads(mut self, detach_threads: bool) -> Configuration {
+        self.detach_threads = detach_threads;
+        self
+    }
+
+    /// Returns the start handler, if one was set. See
+    /// `set_start_handler` for more information.
+    pub fn start_handler(&self) -> Option<Arc<StartHandler>> {
+        self.start_handler.clone()
+    }
+
+    /// Sets a closure which is invoked on each worker thread when it
+    /// starts, with the index of the thread, before the thread runs
+    /// any jobs. This can be used to set up thread-local resources.
+    /// `initialize()` waits for the start handlers to return.
+    ///
+    /// If the start handler panics, the thread exits and the pool is
+    /// poisoned, i.e., it refuses to accept any work from then on.
+    pub fn set_start_handler<H>(mut self, start_handler: H) -> Configuration
+        where H: Fn(usize) + Send + Sync + 'static
+    {
+        self.start_handler = Some(Arc::new(start_handler));
+        self
+    }
+
+    /// Returns the exit handler, if one was set. See
+    /// `set_exit_handler` for more information.
+    pub fn exit_handler(&self) -> Option<Arc<ExitHandler>> {
+        self.exit_handler.clone()
+    }
+
+    /// Sets a closure which is invoked on each worker thread before it
+    /// exits once the pool terminates, with the index of the thread.
+    /// This can be used to tear down what the start handler set up.
+    /// Note that the global thread pool never terminates.
+    ///
+    /// If the exit handler panics, the pool is poisoned.
+    pub fn set_exit_handler<H>(mut self, exit_handler: H) -> Configuration
+        where H: Fn(usize) + Send + Sync + 'static
+    {
+        self.exit_handler = Some(Arc::new(exit_handler));
+        self
+    }
+
+    /// Returns the deadlock handler, if one was set. See
+    /// `set_deadlock_handler` for more information.
+    pub fn deadlock_handler(&self) -> Option<Arc<DeadlockHandler>> {
+        self.deadlock_handler.clone()
+    }
+
+    /// Sets a closure which is invoked if the pool deadlocks, i.e., if
+    /// some worker threads are waiting for jobs to complete (in `join`,
+    /// `scope`, or `ScopeHandle::join`), but none of the workers can
+    /// find any work to do, so that none of the jobs waited for can
+    /// make progress. This typically means that the jobs depend on each
+    /// other in a cycle. By default, deadlocks are not detected, and
+    /// the pool just hangs.
+    ///
+    /// A worker that finds nothing to do counts as blocked, whether it
+    /// is waiting or just idle. Once all of them are blocked, and stay
+    /// so for a grace period (currently 100ms) during which none of
+    /// them finds any work, the handler is called on the worker that
+    /// was blocked last. It is called once per deadlock; if it resolves
+    /// the deadlock somehow, detection starts over. Workers that are
+    /// blocked on something other than a job of this pool (e.g., on a
+    /// lock, or in `install` on another pool) do not count as blocked,
+    /// and a job that is waited for by a thread outside of the pool is
+    /// not assumed to be stuck, so this does not catch every deadlock.
+    ///
+    /// The handler can't unwind into the waiting worker, which would
+    /// leave the jobs it waits for referring to its stack; so if the
+    /// handler panics (e.g., with a "rayon deadlock detected"
+    /// message), the process aborts after the panic is reported.
+    pub fn set_deadlock_handler<H>(mut self, deadlock_handler: H) -> Configuration
+        where H: Fn() + Send + Sync + 'static
+    {
+        self.deadlock_handler = Some(Arc::new(deadlock_handler));
+        self
+    }
+
+    /// Returns the job start handler, if one was set. See
+    /// `set_job_start_handler` for more information.
+    pub fn job_start_handler(&self) -> Option<Arc<JobStartHandler>> {
+        self.job_start_handler.clone()
+    }
+
+    /// Sets a closure which is invoked on a worker thread whenever it
+    /// starts to execute a job, with the index of the thread. Together
+    /// with the job end handler (see `set_job_end_handler`), this lets
+    /// a profiler draw a timeline of which worker ran what, and when.
+    ///
+    /// A job is anything that goes through a worker's deque, its
+    /// mailbox or the injector queue: the second closure of a `join`
+    /// (whether it was stolen or taken back), a spawned task, a task of
+    /// a `scope`, or the closure of an `install` from outside the pool.
+    /// The first closure of a `join` is not a job, and neither is
+    /// anything that runs sequentially (e.g., both closures of a `join`
+    /// in a single-threaded pool, or past the join depth limit). A job
+    /// that steals other jobs while it waits runs their handlers in
+    /// between its own, so the calls on each worker nest like frames
+    /// on a stack.
+    ///
+    /// Without either handler, executing a job only costs one extra
+    /// check. If the handler panics, the panic propagates into the
+    /// pool machinery, as for a panicking job.
+    pub fn set_job_start_handler<H>(mut self, job_start_handler: H) -> Configuration
+        where H: Fn(usize) + Send + Sync + 'static
+    {
+        self.job_start_handler = Some(Arc::new(job_start_handler));
+        self
+    }
+
+    /// Returns the job end handler, if one was set. See
+    /// `set_job_end_handler` for more information.
+    pub fn job_end_handler(&self) -> Option<Arc<JobEndHandler>> {
+        self.job_end_handler.clone()
+    }
+
+    /// Sets a closure which is invoked on a worker thread whenever it
+    /// is done executing a job, with the index of the thread and the
+    /// wall-clock time the job took, including the time spent on any
+    /// jobs it stole in the meantime. It is called even if the job
+    /// panicked. See `set_job_start_handler` for what counts as a job.
+    pub fn set_job_end_handler<H>(mut self, job_end_handler: H) -> Configuration
+        where H: Fn(usize, Duration) + Send + Sync + 'static
+    {
+        self.job_end_handler = Some(Arc::new(job_end_handler));
+        self
+    }
+
+    /// Returns whether the pool records or replays its steal schedule,
+    /// if at all. See `set_steal_mode` for more information.
+    #[cfg(feature = "log")]
+    pub fn steal_mode(&self) -> Option<StealMode> {
+        self.steal_mode.clone()
+    }
+
+    /// Makes the pool record which worker takes which job from another
+    /// worker (or from outside the pool), or replay such a recording,
+    /// in order to reproduce a bug that depends on the order in which
+    /// jobs run. Only available with the `log` feature.
+    ///
+    /// With `StealMode::Record`, the recording is available from
+    /// `ThreadPool::steal_schedule`. With `StealMode::Replay`, instead
+    /// of stealing, each worker takes exactly the jobs it took in the
+    /// recorded run, in the same order. This only works for the same
+    /// program, with the same input and number of threads; if the run
+    /// diverges from the recording, it may deadlock. Both modes are
+    /// slow, and neither takes jobs in bulk when stealing.
+    #[cfg(feature = "log")]
+    pub fn set_steal_mode(mut self, steal_mode: StealMode) -> Configuration {
+        self.steal_mode = Some(steal_mode);
+        self
+    }
+
+    #[cfg(feature = "log")]
+    fn fmt_steal_mode(&self, debug: &mut fmt::DebugStruct) {
+        debug.field("steal_mode", &self.steal_mode);
+    }
+
+    #[cfg(not(feature = "log"))]
+    fn fmt_steal_mode(&self, _debug: &mut fmt::DebugStruct) {}
+
+    /// True if nothing was configured, i.e., if this is equivalent to
+    /// `Configuration::new()`. (Must cover every field set there.)
+    fn is_default(&self) -> bool {
+        #[cfg(feature = "log")]
+        let steal_mode_is_default = self.steal_mode.is_none();
+        #[cfg(not(feature = "log"))]
+        let steal_mode_is_default = true;
+
+        self.num_threads.is_none() && self.panic_handler.is_none() && !self.deterministic &&
+        self.join_depth_limit.is_none() && self.heartbeat_interval.is_none() &&
+        self.default_split_threshold.is_none() &&
+        !self.breadth_first && !self.fair_wake && self.thread_name.is_none() &&
+        self.numa_node.is_none() && self.affinity.is_none() && self.stack_size.is_none() &&
+        self.stack_guard == DEFAULT_STACK_GUARD && self.growable_stacks.is_none() &&
+        self.spin_iterations.is_none() && self.max_queued.is_none() &&
+        self.queue_policy == QueuePolicy::Block &&
+        !self.use_current_threads &&
+        self.blocking_replacements == 0 && !self.detach_threads &&
+        self.start_handler.is_none() &&
+        self.exit_handler.is_none() && self.deadlock_handler.is_none() &&
+        self.job_start_handler.is_none() && self.job_end_handler.is_none() &&
+        steal_mode_is_default
+    }
+
     /// Checks whether the configuration is valid.
     fn validate(&self) -> Result<(), InitError> {
         if let Some(value) = self.num_threads {
@@ -87,6 +974,84 @@ impl Configuration {
     }
 }
 
+impl fmt::Debug for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let panic_handler = if self.panic_handler.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let thread_name = if self.thread_name.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let numa_node = if self.numa_node.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let affinity = if self.affinity.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let start_handler = if self.start_handler.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let exit_handler = if self.exit_handler.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let deadlock_handler = if self.deadlock_handler.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let job_start_handler = if self.job_start_handler.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let job_end_handler = if self.job_end_handler.is_some() {
+            "Some(..)"
+        } else {
+            "None"
+        };
+        let mut debug = f.debug_struct("Configuration");
+        debug.field("num_threads", &self.num_threads)
+            .field("panic_handler", &panic_handler)
+            .field("deterministic", &self.deterministic)
+            .field("join_depth_limit", &self.join_depth_limit)
+            .field("heartbeat_interval", &self.heartbeat_interval)
+            .field("default_split_threshold", &self.default_split_threshold)
+            .field("breadth_first", &self.breadth_first)
+            .field("fair_wake", &self.fair_wake)
+            .field("thread_name", &thread_name)
+            .field("numa_node", &numa_node)
+            .field("affinity", &affinity)
+            .field("stack_size", &self.stack_size)
+            .field("stack_guard", &self.stack_guard)
+            .field("growable_stacks", &self.growable_stacks)
+            .field("spin_iterations", &self.spin_iterations)
+            .field("max_queued", &self.max_queued)
+            .field("queue_policy", &self.queue_policy)
+            .field("use_current_threads", &self.use_current_threads)
+            .field("blocking_replacements", &self.blocking_replacements)
+            .field("detach_threads", &self.detach_threads)
+            .field("start_handler", &start_handler)
+            .field("exit_handler", &exit_handler)
+            .field("deadlock_handler", &deadlock_handler)
+            .field("job_start_handler", &job_start_handler)
+            .field("job_end_handler", &job_end_handler);
+        self.fmt_steal_mode(&mut debug);
+        debug.finish()
+    }
+}
+
 /// Initializes the global thread pool. This initialization is
 /// **optional**.  If you do not call this function, the thread pool
 /// will be automatically initialized with the default
@@ -105,14 +1070,34 @@ impl Configuration {
 /// will simply check that the global thread pool already has the
 /// configuration you requested, rather than making changes.
 ///
+/// The global thread pool is also started, with the default
+/// configuration, the first time that anything uses it (e.g., a `join`
+/// from outside of any pool). If that happened before `initialize` is
+/// called, the configuration passed to `initialize` is ignored, so it
+/// fails with `GlobalPoolAlreadyInitialized` (unless the configuration
+/// it was given is the default one, too).
+///
 /// An `Ok` result indicates that the thread pool is running with the
 /// given configuration. Otherwise, a suitable error is returned.
 pub fn initialize(config: Configuration) -> Result<(), InitError> {
     try!(config.validate());
 
+    // the workers would wait for threads to be donated, but `initialize`
+    // waits for the workers, and nobody gets to `take_worker` before it
+    // returns
+    if config.use_current_threads() {
+        return Err(InitError::GlobalPoolOnCurrentThreads);
+    }
+
     let num_threads = config.num_threads;
+    let is_default = config.is_default();
 
-    let registry = thread_pool::get_registry_with_config(config);
+    let registry = try!(thread_pool::get_registry_with_config(config));
+
+    // a registry started lazily ignored our configuration
+    if thread_pool::registry_is_default() && !is_default {
+        return Err(InitError::GlobalPoolAlreadyInitialized);
+    }
 
     if let Some(value) = num_threads {
         if value != registry.num_threads() {
@@ -125,88 +1110,1162 @@ pub fn initialize(config: Configuration) -> Result<(), InitError> {
     Ok(())
 }
 
-/// This is a debugging API not really intended for end users. It will
-/// dump some performance statistics out using `println`.
-pub fn dump_stats() {
-    dump_stats!();
-}
-
-pub fn join<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
-    where A: FnOnce() -> RA + Send,
-          B: FnOnce() -> RB + Send,
-          RA: Send,
-          RB: Send
-{
+/// Returns the number of threads in the current thread pool. When
+/// called from a worker thread, this is the size of the pool that the
+/// worker belongs to (which may be a `ThreadPool` created by the
+/// user); otherwise, it is the size of the global thread pool, which
+/// is initialized if that has not happened yet (or 1, if it was not
+/// and `Configuration::disable_implicit_pool` was called).
+///
+/// This can be useful when deciding on a sequential cutoff for a
+/// recursive, `join`-based algorithm, but be aware that it is only a
+/// hint: the number of threads which are actually idle and available
+/// to steal work at any given moment may be smaller.
+pub fn current_num_threads() -> usize {
     unsafe {
         let worker_thread = WorkerThread::current();
-
-        // slow path: not yet in the thread pool
         if worker_thread.is_null() {
-            return join_inject(oper_a, oper_b);
+            // without the global pool, `join` runs sequentially
+            thread_pool::get_implicit_registry().map_or(1, |registry| registry.num_threads())
+        } else {
+            (*worker_thread).registry().num_threads()
         }
+    }
+}
 
-        log!(Join { worker: (*worker_thread).index() });
+/// The default split threshold of the current thread pool, as for
+/// `current_num_threads`; 1 if none was set, since a piece of one item
+/// can not be split anyway. See `Configuration::set_default_split_threshold`.
+pub fn current_split_threshold() -> usize {
+    let threshold = unsafe {
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() {
+            thread_pool::get_implicit_registry()
+                .and_then(|registry| registry.default_split_threshold())
+        } else {
+            (*worker_thread).registry().default_split_threshold()
+        }
+    };
+    threshold.unwrap_or(1)
+}
 
-        // create virtual wrapper for task b; this all has to be
-        // done here so that the stack frame can keep it all live
-        // long enough
-        let job_b = StackJob::new(oper_b, SpinLatch::new());
-        (*worker_thread).push(job_b.as_job_ref());
-
-        // record how many async spawns have occurred on this thread
-        // before task A is executed
-        let spawn_count = (*worker_thread).current_spawn_count();
-
-        // execute task a; hopefully b gets stolen
-        let result_a;
-        {
-            let guard = unwind::finally(&job_b.latch, |job_b_latch| {
-                // If another thread stole our job when we panic, we must halt unwinding
-                // until that thread is finished using it.
-                if (*WorkerThread::current()).pop().is_none() {
-                    job_b_latch.spin();
-                }
-            });
-            result_a = oper_a();
-            mem::forget(guard);
+/// If called from a worker thread, returns the index of that thread
+/// within its pool; otherwise, returns `None`.
+///
+/// The index is in the range `0..current_num_threads()` (except on the
+/// spare workers of `Configuration::set_blocking_replacements`, which
+/// come after that, and on a thread that helps with
+/// `ThreadPool::try_help`, which comes after the spares) and remains
+/// the same for the lifetime of the pool.
+/// Distinct workers of the same pool always have distinct indices, so
+/// this can be used to look up per-thread state, such as scratch
+/// buffers, in a `Vec` with one entry per worker.
+///
+/// Note that workers of *different* pools may share an index, so
+/// per-thread state should be kept per pool.
+pub fn current_thread_index() -> Option<usize> {
+    unsafe {
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() {
+            None
+        } else {
+            Some((*worker_thread).index())
         }
+    }
+}
 
-        // before we can try to pop b, we have to first pop off any async spawns
-        // that have occurred on this thread
-        (*worker_thread).pop_spawned_jobs(spawn_count);
+/// Returns true if called from a worker thread, of any pool: i.e.,
+/// wherever `current_thread_index()` returns `Some`, but without
+/// looking up the index. This is just one thread-local read, so it is
+/// cheap enough to guard a fast path, e.g. to run sequentially rather
+/// than have `join` start the global pool, or hand its closures to a
+/// pool from outside. (A thread that runs a donated worker, or helps
+/// with `ThreadPool::try_help`, is a worker thread while it does so.)
+#[inline]
+pub fn is_worker_thread() -> bool {
+    unsafe { !WorkerThread::current().is_null() }
+}
 
-        // if b was not stolen, do it ourselves, else wait for the thief to finish
-        let result_b;
-        if (*worker_thread).pop().is_some() {
-            log!(PoppedJob { worker: (*worker_thread).index() });
-            result_b = job_b.run_inline(); // not stolen, let's do it!
+/// Gives the scheduler a chance to run while a long job is executing:
+/// if called from a worker thread, runs one job that another worker
+/// (or a thread outside the pool) has queued up, and returns true; if
+/// there is no such job, or if called from outside the pool, returns
+/// false right away. A long loop that never calls `join` can call this
+/// every so often, so that its worker still helps out with the rest of
+/// the work.
+///
+/// Note that this never takes jobs from the worker's own deque. All of
+/// those were pushed by `join` (or `scope`) calls that the current job
+/// is nested inside of, and each of them expects to take back its own
+/// job once the nested work is done; running them ahead of time would
+/// make those calls go through all the older jobs, too. The only jobs
+/// above the current frame that this runs are those spawned by the job
+/// it stole.
+pub fn yield_now() -> bool {
+    unsafe {
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() {
+            false
         } else {
-            log!(LostJob { worker: (*worker_thread).index() });
-            (*worker_thread).steal_until(&job_b.latch); // stolen, wait for them to finish
-            result_b = job_b.into_result();
+            (*worker_thread).yield_now()
         }
-
-        // now result_b should be initialized
-        (result_a, result_b)
     }
 }
 
-#[cold] // cold path
-unsafe fn join_inject<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
-    where A: FnOnce() -> RA + Send,
+/// Blocks until `latch` is set. If called from a worker thread, the
+/// worker keeps running other jobs in the meantime, as `join` does
+/// while it waits for a stolen closure, so this may be used to wait for
+/// jobs that need this very worker to get done; from outside the pool,
+/// it spins, backing off to sleeping if the wait goes on.
+///
+/// # Example
+///
+/// ```rust
+/// use rayon::{CountLatch, Latch};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// let latch = CountLatch::new(4);
+/// let sum = AtomicUsize::new(0);
+/// rayon::scope(|s| {
+///     for i in 0..4 {
+///         let (latch, sum) = (&latch, &sum);
+///         s.spawn(move |_| {
+///             sum.fetch_add(i, Ordering::SeqCst);
+///             latch.set();
+///         });
+///     }
+///     rayon::wait_until(&latch);
+///     assert_eq!(sum.load(Ordering::SeqCst), 6);
+/// });
+/// ```
+pub fn wait_until<L: Latch + ?Sized>(latch: &L) {
+    unsafe {
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() {
+            latch::spin_until(latch);
+        } else {
+            (*worker_thread).steal_until(latch);
+        }
+    }
+}
+
+/// Runs `op`, which is expected to block (e.g., on I/O), telling the
+/// pool of the current worker thread that this worker will be stuck
+/// until `op` returns. If the pool was configured to have spare
+/// workers (see `Configuration::set_blocking_replacements`), one of
+/// them takes over in the meantime, stealing the work that is queued
+/// up, so that the blocked worker does not cost the pool a thread's
+/// worth of parallelism; once `op` returns (or panics), the spare
+/// goes back to idling as soon as it is done with its current job.
+///
+/// Only the outermost of nested `mark_blocking` calls on a thread
+/// counts, since the worker can only be blocked once. Called from
+/// outside the pool, this just runs `op`.
+pub fn mark_blocking<OP, R>(op: OP) -> R
+    where OP: FnOnce() -> R
+{
+    unsafe {
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() || !(*worker_thread).start_blocking() {
+            return op();
+        }
+        let _guard = unwind::finally(worker_thread, |worker_thread| {
+            (**worker_thread).stop_blocking();
+        });
+        op()
+    }
+}
+
+/// This is a debugging API not really intended for end users. It will
+/// dump some performance statistics out using `println`, along with
+/// the scheduling events recorded if the `log` feature is enabled.
+pub fn dump_stats() {
+    dump_stats!();
+    ::log::dump();
+}
+
+/// Takes two closures and *potentially* runs them in parallel. It
+/// returns a pair of the results from those closures.
+///
+/// When called from a worker thread, `join` executes `oper_a` on the
+/// calling thread, while `oper_b` is made available for other threads
+/// to steal. If no other thread steals it, `oper_b` is executed by the
+/// caller once `oper_a` completes. That `oper_a` never leaves the
+/// calling worker is a guarantee that callers may rely on, e.g., for
+/// thread-local state. (When called from outside of the thread pool,
+/// on the other hand, both closures are sent off to run in the pool,
+/// while the caller blocks; see `join_context`.)
+///
+/// If `oper_a` panics, `oper_b` still runs to completion before the
+/// panic is propagated, since it may borrow from the caller's stack: we
+/// run it ourselves if nobody stole it, or else wait for the thief (a
+/// panic in `oper_b` is then dropped, in favor of that of `oper_a`).
+/// The exception is a `join` that runs its closures one after the
+/// other, e.g. in a pool with a single thread, where `oper_b` never
+/// runs if `oper_a` panics. Otherwise, a panic in `oper_b` is
+/// propagated once `oper_a` completes. (If the program is
+/// built with `-C panic=abort`, a panic in either closure aborts the
+/// process, wherever it is running.) The payload is propagated as is,
+/// so it still downcasts to whatever the closure panicked with; to
+/// find out which of the closures it came from, pass it to
+/// `join_panic_origin()`.
+///
+/// Each closure is called exactly once, hence the `FnOnce` bounds.
+/// Note that any `FnMut` closure is also `FnOnce`, so the closures may
+/// freely mutate the state they capture (as long as the two of them
+/// capture disjoint state, which the borrow checker enforces), and you
+/// may also pass `&mut f` for some `f: FnMut() -> R + Send`, in order
+/// to keep using `f` after the `join`.
+///
+/// Neither the closures nor their results need to be `'static`: both
+/// closures are done by the time `join` returns, so they may return
+/// borrows of anything that outlives the call, such as `&data[..]` or
+/// the halves of a `split_at_mut`. They only need to be `Send`, since
+/// the result of `oper_b` may be computed on another thread.
+///
+/// If called from outside a thread pool and the global pool cannot be
+/// started, both closures simply run one after the other on the
+/// calling thread (so if `oper_a` panics, `oper_b` never runs). Use
+/// `try_join` to find out whether that happened. The same goes if
+/// `join` is called while the thread is unwinding from a panic (e.g.,
+/// from a destructor); note that if one of the closures panics in that
+/// case, the process aborts, as with any panic during unwinding.
+///
+/// `join` only returns once `oper_b` has run, so if the thread running
+/// it never finishes (e.g., because it jumped out of the closure with
+/// `longjmp`), the `join` waits forever. Most such cases look just like
+/// a long-running `oper_b` from the outside. In a debug build, `join`
+/// does report the one it can tell apart: `oper_b` taken off the deque
+/// without being stolen (as only `CurrentWorker::pop` can) and not run
+/// ever since. The report goes to stderr, and to the debug log as an
+/// `AbandonedJob` event.
+pub fn join<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    join_context(|_| oper_a(), |_| oper_b())
+}
+
+/// Like `join`, but if called from outside a thread pool and the
+/// global pool cannot be started, returns `PoolError::PoolUnavailable`
+/// without running either closure, so that the caller can decide how
+/// to degrade. Otherwise, this is just `join`.
+pub fn try_join<A, B, RA, RB>(oper_a: A, oper_b: B) -> Result<(RA, RB), PoolError>
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    unsafe {
+        if WorkerThread::current().is_null() &&
+           thread_pool::try_get_implicit_registry().map_or(false, |registry| registry.is_err()) {
+            return Err(PoolError::PoolUnavailable);
+        }
+    }
+    Ok(join(oper_a, oper_b))
+}
+
+/// Like `join`, but never makes `oper_b` available for stealing: runs
+/// `oper_a` and then `oper_b` on the calling thread, and returns their
+/// results, exactly as `join` does when it runs sequentially (e.g., in
+/// a pool with a single thread). This is for the leaves of a recursion
+/// that already decided, with a cutoff of its own, not to go parallel
+/// any further; unlike a plain sequential call, the code can keep the
+/// shape of `join`, and switch between the two freely. Nothing is
+/// pushed onto the deque, so there is no job to set up, take back or
+/// wait for, and nothing for the job handlers to report.
+///
+/// Panics are handled as in a sequential `join`: if `oper_a` panics,
+/// `oper_b` never runs; either way, the panic propagates, and
+/// `join_panic_origin` tells which closure it came from.
+pub fn join_no_steal<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    join_sequential(|_| oper_a(), |_| oper_b())
+}
+
+/// Like `join`, but labels the two closures with `name` for profiling.
+/// If the `profiling` feature is enabled, entering and leaving each
+/// closure is recorded as an event in the debug log (see
+/// `dump_log_to()`), along with `name`, which of the two closures it
+/// was, and the index of the worker thread that executed it (which,
+/// for `oper_b`, may be a thief). Otherwise, this is just `join`.
+pub fn join_named<A, B, RA, RB>(name: &'static str, oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    join(move || in_span(name, "a", oper_a),
+         move || in_span(name, "b", oper_b))
+}
+
+#[cfg(feature = "profiling")]
+fn in_span<OP, R>(_name: &'static str, _oper: &'static str, op: OP) -> R
+    where OP: FnOnce() -> R
+{
+    let _worker = current_thread_index().unwrap_or(::std::usize::MAX);
+    log!(SpanEnter {
+        worker: _worker,
+        name: _name,
+        oper: _oper,
+    });
+    let _guard = unwind::finally((), |_| {
+        log!(SpanExit {
+            worker: _worker,
+            name: _name,
+            oper: _oper,
+        })
+    });
+    op()
+}
+
+#[cfg(not(feature = "profiling"))]
+#[inline]
+fn in_span<OP, R>(_name: &'static str, _oper: &'static str, op: OP) -> R
+    where OP: FnOnce() -> R
+{
+    op()
+}
+
+/// Provides the calling context to a closure called by `join_context`.
+#[derive(Debug)]
+pub struct FnContext {
+    migrated: bool,
+}
+
+impl FnContext {
+    #[inline]
+    fn new(migrated: bool) -> Self {
+        FnContext { migrated: migrated }
+    }
+
+    /// Returns `true` if the closure was called from a different thread
+    /// than it was provided. This is typically because it was stolen
+    /// by another worker thread, in which case further subdividing the
+    /// work is likely to be profitable.
+    #[inline]
+    pub fn migrated(&self) -> bool {
+        self.migrated
+    }
+}
+
+/// Identical to `join`, except that the closures have a parameter
+/// that provides context for the way the closure has been called,
+/// especially indicating whether they're executing on a different
+/// thread than where `join_context` was called. This will occur if
+/// the second job is stolen by a different thread, or if
+/// `join_context` was called from outside the thread pool to begin
+/// with.
+pub fn join_context<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce(FnContext) -> RA + Send,
+          B: FnOnce(FnContext) -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    join_in(false, oper_a, oper_b)
+}
+
+/// Like `join`, but `oper_b` may only be stolen by workers on the same
+/// NUMA node as the caller (see `Configuration::set_numa_node`). On a
+/// machine with several memory nodes, this keeps both closures close
+/// to data that was allocated on the caller's node, at the price of
+/// fewer workers to share the load; nested calls to `join` in either
+/// closure split as usual. Called from outside of a thread pool, or if
+/// the pool records or replays its steal schedule, this is just `join`.
+pub fn join_local<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce() -> RA + Send,
           B: FnOnce() -> RB + Send,
           RA: Send,
           RB: Send
 {
-    let job_a = StackJob::new(oper_a, LockLatch::new());
-    let job_b = StackJob::new(oper_b, LockLatch::new());
+    join_in(true, |_| oper_a(), |_| oper_b())
+}
+
+/// The body of `join_context` and `join_local`; `local` says whether
+/// `oper_b` must stay on the NUMA node of the caller.
+fn join_in<A, B, RA, RB>(local: bool, oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce(FnContext) -> RA + Send,
+          B: FnOnce(FnContext) -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    // if we are called from a destructor while unwinding, don't go
+    // anywhere near the deque or the injector; just run the closures
+    if unwind::is_unwinding() {
+        return join_sequential(oper_a, oper_b);
+    }
+
+    unsafe {
+        let worker_thread = WorkerThread::current();
+
+        // slow path: not yet in the thread pool
+        if worker_thread.is_null() {
+            return match thread_pool::try_get_implicit_registry() {
+                Some(Ok(registry)) if registry.deterministic() => {
+                    registry.in_worker(|| join_sequential(oper_a, oper_b))
+                }
+                Some(Ok(registry)) => join_inject(registry, oper_a, oper_b),
+                Some(Err(_)) | None => join_sequential(oper_a, oper_b),
+            };
+        }
+
+        (*worker_thread).registry().check_poisoned();
+
+        log!(Join { worker: (*worker_thread).index() });
+
+        // rather than running low on stack, move on to a new segment
+        // if we may (see `Configuration::set_growable_stacks`)
+        if (*worker_thread).stack_is_low() {
+            if let Some(segment_size) = (*worker_thread).registry().growable_stacks() {
+                return join_on_new_segment(worker_thread, segment_size, local, oper_a, oper_b);
+            }
+        }
+
+        // fast path: with a single worker (and no spare that could
+        // stand in for it), nobody could ever steal task b, so skip the
+        // deque and just run both tasks in turn (if task a panics, task
+        // b never runs); deterministic mode asks for exactly this
+        // behavior regardless of the pool size, and so does exceeding
+        // the join depth limit or running low on stack
+        if runs_sequentially(worker_thread) {
+            return join_sequential(oper_a, oper_b);
+        }
+
+        // thieves have plenty to take already, and task b is the least
+        // of it, so pushing it would only add deque traffic; the jobs
+        // that stay up for stealing are the older, bigger ones (task b
+        // still runs if task a panics, as if we had pushed it)
+        if !local && (*worker_thread).deque_is_crowded() {
+            return join_sequential_catch(oper_a, oper_b);
+        }
+
+        // (A steal log only knows about the deques; see
+        // `Configuration::set_steal_mode`.)
+        let local = local && !(*worker_thread).registry().has_steal_log();
+        let depth = (*worker_thread).join_depth();
+        join_push(worker_thread, depth, local, oper_a, oper_b)
+    }
+}
+
+/// Runs `join_in` on a new stack segment, if one can be had.
+/// This is kept out of line, like `join_push`, so that it takes no
+/// room in the frames of all the other joins.
+#[inline(never)]
+unsafe fn join_on_new_segment<A, B, RA, RB>(worker_thread: *mut WorkerThread,
+                                            segment_size: usize,
+                                            local: bool,
+                                            oper_a: A,
+                                            oper_b: B)
+                                            -> (RA, RB)
+    where A: FnOnce(FnContext) -> RA + Send,
+          B: FnOnce(FnContext) -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    // on the segment, the stack is not low, so `join_in` goes on as
+    // usual; without one, it is, so `join_in` would run the closures
+    // in turn
+    stack::on_new_segment(segment_size, |low_end| match low_end {
+        Some(low_end) => {
+            (*worker_thread).with_stack_low_end(low_end, || join_in(local, oper_a, oper_b))
+        }
+        None => join_sequential(oper_a, oper_b),
+    })
+}
+
+/// True if a `join` on `worker_thread` should run its closures in
+/// turn, without pushing any of them; see `join_in`.
+unsafe fn runs_sequentially(worker_thread: *mut WorkerThread) -> bool {
+    let registry = (*worker_thread).registry();
+    let depth = (*worker_thread).join_depth();
+    (registry.num_threads() == 1 && !registry.has_spares()) || registry.deterministic() ||
+    registry.join_depth_limit().map_or(false, |limit| depth >= limit) ||
+    (*worker_thread).stack_is_low()
+}
+
+/// The parallel half of `join_context`: pushes `oper_b` onto the
+/// deque (or, in heartbeat mode, notes it as pending; or, if `local`
+/// is true, makes it available to workers on our NUMA node only),
+/// runs `oper_a`, and then gets `oper_b` back or waits for the
+/// thief. This is kept out of line, so that the frame of a `join`
+/// that runs sequentially (e.g., because it is past the join depth
+/// limit) does not have to make room for the `StackJob`.
+#[inline(never)]
+unsafe fn join_push<A, B, RA, RB>(worker_thread: *mut WorkerThread,
+                                  depth: usize,
+                                  local: bool,
+                                  oper_a: A,
+                                  oper_b: B)
+                                  -> (RA, RB)
+    where A: FnOnce(FnContext) -> RA + Send,
+          B: FnOnce(FnContext) -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    (*worker_thread).set_join_depth(depth + 1);
+    let _depth_guard = unwind::finally(worker_thread, |worker_thread| {
+        (**worker_thread).set_join_depth(depth);
+    });
+
+    // create virtual wrapper for task b; this all has to be
+    // done here so that the stack frame can keep it all live
+    // long enough; if b gets stolen, the thief takes our context
+    // along
+    let context = context::capture();
+    let job_b = StackJob::new(move |migrated| if migrated {
+                                  context.install(|| oper_b(FnContext::new(true)))
+                              } else {
+                                  oper_b(FnContext::new(false))
+                              },
+                              SpinLatch::new());
+    let job_b_ref = job_b.as_job_ref();
+
+    // in heartbeat mode, b only goes onto the deque if a heartbeat
+    // promotes it (see `WorkerThread::push_pending`)
+    let pending = !local && (*worker_thread).registry().heartbeat_interval().is_some();
+    if local {
+        (*worker_thread).push_node_local(job_b_ref);
+    } else if pending {
+        (*worker_thread).push_pending(job_b_ref);
+    } else {
+        (*worker_thread).push(job_b_ref);
+    }
+    let watch = AbandonWatch::new(worker_thread, pending || local);
+
+    // record how many async spawns have occurred on this thread
+    // before task A is executed
+    let spawn_count = (*worker_thread).current_spawn_count();
+
+    // execute task a; hopefully b gets stolen
+    let index = (*worker_thread).index();
+    let result_a = match unwind::halt_unwinding(|| oper_a(FnContext::new(false))) {
+        Ok(result_a) => result_a,
+        Err(err) => join_recover_from_panic(worker_thread, &job_b, pending, local, err),
+    };
+    debug_assert!(WorkerThread::current() == worker_thread && (*worker_thread).index() == index,
+                  "oper_a must run on the worker that called join");
+
+    // before we can try to pop b, we have to first pop off any async spawns
+    // that have occurred on this thread
+    (*worker_thread).pop_spawned_jobs(spawn_count);
+
+    // if b was not stolen, do it ourselves, else wait for the thief to finish
+    let note = NoteOriginIfPanic(PanicOrigin::OperB);
+    let result_b;
+    if pending && !(*worker_thread).pop_pending(&job_b_ref) {
+        // never promoted, so nobody else saw it
+        result_b = (*worker_thread).run_job(|| job_b.run_inline(false));
+    } else if take_back(worker_thread, &job_b_ref, local) {
+        (*worker_thread).record_pop(true);
+        result_b = (*worker_thread).run_job(|| job_b.run_inline(false)); // not stolen, let's do it!
+    } else if !local && (*worker_thread).reclaim(&job_b_ref, &job_b.latch) {
+        // stolen, but we got it back before the thief got to it
+        (*worker_thread).record_pop(false);
+        result_b = (*worker_thread).run_job(|| job_b.run_inline(false));
+    } else {
+        (*worker_thread).record_pop(false);
+        watch.check(worker_thread, &job_b.latch);
+        (*worker_thread).steal_until(&job_b.latch); // stolen, wait for them to finish
+        result_b = job_b.take_result();
+    }
+    mem::forget(note);
+
+    // now result_b should be initialized
+    (result_a, result_b)
+}
+
+/// A debug check for the second closure of a `join` going missing. A
+/// `join` that can not take `oper_b` back off its deque assumes that it
+/// was stolen, and waits for the thief to finish it. But the closures
+/// themselves can get at the deque, too, through `with_worker()` and
+/// `CurrentWorker::pop`; if one of them pops `oper_b` and neither runs
+/// it nor hands it on, the `join` would wait forever. In debug builds,
+/// the watch notes how many jobs had been stolen from the deque when
+/// `oper_b` was pushed; if none were stolen since, and `oper_b` has not
+/// run, somebody else removed it, and the `join` reports that (as an
+/// `AbandonedJob` event in the debug log, and on stderr) before it goes
+/// on to wait. Thefts of other jobs can hide a missing `oper_b`, so the
+/// check may miss some cases, but it does not cry wolf.
+struct AbandonWatch {
+    stolen: Option<usize>,
+}
+
+impl AbandonWatch {
+    /// Starts watching the `oper_b` just pushed onto the deque of
+    /// `worker_thread`, unless it went elsewhere (see `join_push`).
+    #[inline]
+    unsafe fn new(worker_thread: *mut WorkerThread, elsewhere: bool) -> AbandonWatch {
+        let watched = cfg!(debug_assertions) && !elsewhere &&
+                      !(*worker_thread).registry().has_steal_log();
+        AbandonWatch {
+            stolen: if watched { Some((*worker_thread).stolen_count()) } else { None },
+        }
+    }
+
+    /// Called when `oper_b` could not be taken back, before waiting for
+    /// `latch`, which is set once it has run.
+    #[inline]
+    unsafe fn check(&self, worker_thread: *mut WorkerThread, latch: &SpinLatch) {
+        if let Some(stolen) = self.stolen {
+            if !latch.probe() && (*worker_thread).stolen_count() == stolen {
+                AbandonWatch::report(worker_thread, latch, stolen);
+            }
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    unsafe fn report(worker_thread: *mut WorkerThread, latch: &SpinLatch, stolen: usize) {
+        // A thief counts its steal just after taking the job, so give
+        // one that got descheduled in between a chance to catch up.
+        thread::sleep(Duration::from_millis(ABANDON_GRACE_MILLIS));
+        if latch.probe() || (*worker_thread).stolen_count() != stolen {
+            return;
+        }
+        let worker = (*worker_thread).index();
+        log!(AbandonedJob { worker: worker });
+        ABANDONED_JOBS.fetch_add(1, Ordering::SeqCst);
+        let _ = writeln!(&mut io::stderr(),
+                         "Rayon: oper_b of a join on worker {} was taken off the deque, but \
+                          not stolen, and has not run; unless whoever took it runs it, the \
+                          join waits forever",
+                         worker);
+    }
+}
+
+/// How long `AbandonWatch` waits for a thief to count its steal.
+const ABANDON_GRACE_MILLIS: u64 = 10;
+
+/// How many missing `oper_b` closures `AbandonWatch` reported.
+static ABANDONED_JOBS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The number of times that a `join` found its second closure missing,
+/// in a debug build; see `AbandonWatch`.
+#[cfg(all(test, feature = "unstable"))]
+pub fn abandoned_jobs() -> usize {
+    ABANDONED_JOBS.load(Ordering::SeqCst)
+}
+
+/// Called when `oper_a` of `join_push` panicked. Since `oper_b` may
+/// borrow from the stack frame that the panic is about to unwind, we
+/// must make sure that it is done first: if nobody stole it, it runs
+/// here (any panic of its own is dropped, since the one of `oper_a`
+/// comes first); otherwise, we wait for the thief to finish it. Then
+/// the panic of `oper_a` goes on.
+#[cold]
+unsafe fn join_recover_from_panic<F, R>(worker_thread: *mut WorkerThread,
+                                        job_b: &StackJob<SpinLatch, F, R>,
+                                        pending: bool,
+                                        local: bool,
+                                        err: Box<Any + Send>)
+                                        -> !
+    where F: FnOnce(bool) -> R + Send
+{
+    // (A job that is still pending was never pushed, so nobody else
+    // can have it.)
+    let job_b_ref = job_b.as_job_ref();
+    let pushed = !pending || (*worker_thread).pop_pending(&job_b_ref);
+    if !pushed || take_back(worker_thread, &job_b_ref, local) {
+        // (`job_b` stays where it is, and keeps its result, or panic,
+        // until the frame is gone; `oper_b` is told that it migrated.)
+        (*worker_thread).execute(job_b_ref);
+    } else {
+        (*worker_thread).steal_until(&job_b.latch);
+    }
+    unwind::note_unwinding_origin(PanicOrigin::OperA);
+    unwind::resume_unwinding(err)
+}
 
-    thread_pool::get_registry().inject(&[job_a.as_job_ref(), job_b.as_job_ref()]);
+/// Takes `job` back from where `join_push` put it, unless it was
+/// stolen.
+unsafe fn take_back(worker_thread: *mut WorkerThread, job: &JobRef, local: bool) -> bool {
+    if local {
+        (*worker_thread).pop_node_local(job)
+    } else {
+        (*worker_thread).pop_job(job)
+    }
+}
+
+/// Runs `oper_a` and then `oper_b` on the calling thread. If `oper_a`
+/// panics, `oper_b` never runs.
+fn join_sequential<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce(FnContext) -> RA,
+          B: FnOnce(FnContext) -> RB
+{
+    let mut note = NoteOriginIfPanic(PanicOrigin::OperA);
+    let result_a = oper_a(FnContext::new(false));
+    note.0 = PanicOrigin::OperB;
+    let result_b = oper_b(FnContext::new(false));
+    mem::forget(note);
+    (result_a, result_b)
+}
+
+/// Like `join_sequential`, but with the panic semantics of a `join`
+/// that pushed `oper_b`: if `oper_a` panics, `oper_b` still runs (any
+/// panic of its own is dropped) before the panic of `oper_a` goes on.
+fn join_sequential_catch<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce(FnContext) -> RA,
+          B: FnOnce(FnContext) -> RB
+{
+    let result_a = match unwind::halt_unwinding(|| oper_a(FnContext::new(false))) {
+        Ok(result_a) => result_a,
+        Err(err) => {
+            let _ = unwind::halt_unwinding(|| oper_b(FnContext::new(false)));
+            unwind::note_unwinding_origin(PanicOrigin::OperA);
+            unwind::resume_unwinding(err)
+        }
+    };
+    let note = NoteOriginIfPanic(PanicOrigin::OperB);
+    let result_b = oper_b(FnContext::new(false));
+    mem::forget(note);
+    (result_a, result_b)
+}
+
+/// Given the payload of a panic that was propagated by `join` (e.g.,
+/// as caught by `std::panic::catch_unwind`), tells which of the two
+/// closures it came from, even if that closure ran on another thread
+/// (or the `join` ran in a pool that `install` was called on). If the
+/// panic passed through several nested `join` calls, this refers to
+/// the outermost one, which is the one the catching code called
+/// (directly or through parallel iterators and the like).
+///
+/// Only `join`, and the functions built on it (e.g. `join_context`),
+/// note where a panic came from. `join_n`, `join3` and `join4` do not,
+/// since there are only two origins to choose from: a panic that one of
+/// them propagates has no origin of its own, only whichever one a
+/// `join` inside the closure that panicked may have noted for it.
+///
+/// Returns `None` if the panic did not pass through a `join`. Nothing
+/// is caught to find out: `join` notes the origin in a thread-local as
+/// the panic unwinds, and the first code to catch it there (Rayon, or
+/// the first call to this function) ties the note to the payload. So
+/// call this on the thread that caught the panic, before it catches
+/// another one with `catch_unwind`; a note that is never asked for may
+/// be taken for the origin of such a later panic.
+///
+/// This is meant for diagnostics: the panic's message and location are
+/// printed by the panic hook where the panic occurred, and Rayon leaves
+/// the payload alone, so that it still downcasts to `&str` or `String`.
+pub fn join_panic_origin(payload: &Box<Any + Send>) -> Option<PanicOrigin> {
+    unwind::panic_origin(payload)
+}
+
+#[cold] // cold path
+unsafe fn join_inject<A, B, RA, RB>(registry: &Registry, oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce(FnContext) -> RA + Send,
+          B: FnOnce(FnContext) -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    let (context_a, context_b) = (context::capture(), context::capture());
+    let job_a = StackJob::new(|migrated| context_a.install(|| oper_a(FnContext::new(migrated))),
+                              LockLatch::new());
+    let job_b = StackJob::new(|migrated| context_b.install(|| oper_b(FnContext::new(migrated))),
+                              LockLatch::new());
+
+    registry.inject(&[job_a.as_job_ref(), job_b.as_job_ref()]);
 
     job_a.latch.wait();
     job_b.latch.wait();
 
-    (job_a.into_result(), job_b.into_result())
+    let mut note = NoteOriginIfPanic(PanicOrigin::OperA);
+    let result_a = job_a.take_result();
+    note.0 = PanicOrigin::OperB;
+    let result_b = job_b.take_result();
+    mem::forget(note);
+    (result_a, result_b)
+}
+
+/// Like `join`, but rather than propagating a panic from either
+/// closure, returns the outcome of each as a `thread::Result`. This is
+/// useful when both closures may panic: `join` propagates the panic
+/// from `oper_a` (if any) and silently drops whatever payload `oper_b`
+/// may have produced, whereas `join_catch` hands both payloads back to
+/// the caller.
+///
+/// As with `std::panic::catch_unwind`, you should be careful when
+/// observing data that was being modified by a closure that panicked.
+///
+/// If the program is built with `-C panic=abort`, a panic aborts the
+/// process before it could be caught, so both results are always `Ok`.
+pub fn join_catch<A, B, RA, RB>(oper_a: A,
+                                oper_b: B)
+                                -> (thread::Result<RA>, thread::Result<RB>)
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    join(|| unwind::halt_unwinding(oper_a),
+         || unwind::halt_unwinding(oper_b))
+}
+
+/// Like `join`, but for closures that may fail: if both closures
+/// return `Ok`, returns both values; otherwise, returns the error of
+/// `oper_a` if it failed, or else the error of `oper_b`. Both closures
+/// always run to completion, even if one of them fails early.
+///
+/// (Not to be confused with `try_join`, which reports whether the
+/// thread pool was available, rather than the outcome of the
+/// closures.)
+pub fn join_try<A, B, T, U, E>(oper_a: A, oper_b: B) -> Result<(T, U), E>
+    where A: FnOnce() -> Result<T, E> + Send,
+          B: FnOnce() -> Result<U, E> + Send,
+          T: Send,
+          U: Send,
+          E: Send
+{
+    match join(oper_a, oper_b) {
+        (Ok(a), Ok(b)) => Ok((a, b)),
+        (Err(err), _) => Err(err),
+        (Ok(_), Err(err)) => Err(err),
+    }
+}
+
+/// Like `join`, but both closures get a shared `CancelToken`, through
+/// which `oper_a` can tell `oper_b` that its work is no longer needed,
+/// e.g. because `oper_a` already found what both were looking for.
+/// Cancellation is cooperative and best effort: `oper_b` always runs,
+/// and may well have started (or finished) on another thread by the
+/// time `oper_a` cancels, so it must check `is_cancelled()` itself,
+/// and still return a result. `oper_b` may cancel, too, for `oper_a`
+/// to see.
+///
+/// # Example
+///
+/// ```rust
+/// use rayon::CancelToken;
+///
+/// let v: Vec<u32> = (0..1000).collect();
+/// let (left, right) = v.split_at(500);
+/// let search_left = |token: &CancelToken| {
+///     let found = left.contains(&42);
+///     if found {
+///         token.cancel();
+///     }
+///     found
+/// };
+/// let search_right = |token: &CancelToken| !token.is_cancelled() && right.contains(&42);
+/// let (a, b) = rayon::join_cancellable(search_left, search_right);
+/// assert!(a && !b);
+/// ```
+pub fn join_cancellable<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce(&CancelToken) -> RA + Send,
+          B: FnOnce(&CancelToken) -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    let token = CancelToken { cancelled: AtomicBool::new(false) };
+    join(|| oper_a(&token), || oper_b(&token))
+}
+
+/// The token that the two closures of `join_cancellable` share.
+pub struct CancelToken {
+    cancelled: AtomicBool,
+}
+
+impl CancelToken {
+    /// Asks the other closure to skip whatever work it has left.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// True if either closure has called `cancel()`.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// Like `join`, but with a hint of how expensive each closure is
+/// relative to the other. `join` runs `oper_a` on the calling thread
+/// and offers `oper_b` to thieves; if `oper_a` is the cheap one, the
+/// caller soon runs out of work and just waits for (or takes back) the
+/// expensive one. So `join_weighted` offers the *lighter* closure to
+/// thieves and runs the heavier one itself: if `weight_a < weight_b`,
+/// the roles of the two closures are swapped, and otherwise this is
+/// just `join`. The results are returned in argument order either way.
+///
+/// Panics are propagated as from `join`, whichever of the closures ran
+/// on the calling thread: once both are done, if `oper_a` panicked,
+/// its panic is propagated, else that of `oper_b` (if any). When the
+/// roles are swapped, both closures always run to completion, even
+/// where `join` would run them one after the other.
+pub fn join_weighted<A, B, RA, RB>(weight_a: f64, weight_b: f64, oper_a: A, oper_b: B) -> (RA, RB)
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    if !(weight_a < weight_b) {
+        return join(oper_a, oper_b);
+    }
+
+    // (`join_catch` has the closures the other way around, so note the
+    // origins ourselves, in terms of the arguments)
+    match join_catch(oper_b, oper_a) {
+        (Ok(result_b), Ok(result_a)) => (result_a, result_b),
+        (_, Err(err)) => {
+            unwind::note_unwinding_origin(PanicOrigin::OperA);
+            unwind::resume_unwinding(err)
+        }
+        (Err(err), Ok(_)) => {
+            unwind::note_unwinding_origin(PanicOrigin::OperB);
+            unwind::resume_unwinding(err)
+        }
+    }
+}
+
+/// Executes all the closures in `opers`, potentially in parallel, and
+/// returns their results in the same order. This is a generalization
+/// of `join` for a small, fixed fan-out: rather than nesting calls to
+/// `join` (which yields an unbalanced tree), all but the first
+/// closure are pushed onto the local deque at once, where they may be
+/// stolen by other threads, and the first closure is executed
+/// directly.
+///
+/// If one of the closures panics, all the others still run to
+/// completion before the panic is propagated, as with `join` (except in
+/// a call that runs them one after the other, e.g. in a pool with a
+/// single thread, where the closures after the one that panicked never
+/// run). The panic from the earliest closure (in input order) is
+/// propagated.
+pub fn join_n<F, R>(opers: &mut [F]) -> Vec<R>
+    where F: FnMut() -> R + Send,
+          R: Send
+{
+    if opers.is_empty() {
+        return vec![];
+    }
+
+    unsafe {
+        if unwind::is_unwinding() {
+            return join_n_sequential(opers);
+        }
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() {
+            return match thread_pool::try_get_implicit_registry() {
+                Some(Ok(registry)) => registry.in_worker(move || join_n(opers)),
+                Some(Err(_)) | None => join_n_sequential(opers),
+            };
+        }
+        (*worker_thread).registry().check_poisoned();
+        if runs_sequentially(worker_thread) {
+            return join_n_sequential(opers);
+        }
+
+        let (oper_head, opers_tail) = opers.split_first_mut().unwrap();
+        let jobs: Vec<_> = opers_tail.iter_mut()
+            .map(|oper| {
+                let context = context::capture();
+                StackJob::new(move |migrated| run_tail(migrated, context, oper),
+                              SpinLatch::new())
+            })
+            .collect();
+        let tail: Vec<_> = jobs.iter().map(|job| (job.as_job_ref(), &job.latch)).collect();
+        let result_head = join_tail(worker_thread, oper_head, &tail);
+
+        let mut results = Vec::with_capacity(jobs.len() + 1);
+        results.push(result_head);
+        results.extend(jobs.iter().map(|job| job.take_result()));
+        results
+    }
+}
+
+/// Runs all of `opers` in turn, on the calling thread. If one of them
+/// panics, the ones after it never run.
+fn join_n_sequential<F, R>(opers: &mut [F]) -> Vec<R>
+    where F: FnMut() -> R
+{
+    opers.iter_mut().map(|oper| oper()).collect()
+}
+
+/// Like `join`, but for three closures, which may each return a
+/// different type. As with `join_n`, `oper_a` runs on the calling
+/// thread while the other two are pushed onto the local deque at
+/// once, where other threads may steal them.
+///
+/// Panics are propagated only once no other thread is running any of
+/// the closures anymore. As with `join`, all of the closures run to
+/// completion even if `oper_a` panics (except in a call that runs them
+/// one after the other, e.g. in a pool with a single thread), and the
+/// panic of the earliest closure (in argument order) is propagated.
+///
+/// # Example
+///
+/// ```
+/// let (a, b, c) = rayon::join3(|| 1 + 1, || "two".to_string(), || vec![2u8]);
+/// assert_eq!((a, &b[..], &c[..]), (2, "two", &[2u8][..]));
+/// ```
+pub fn join3<A, B, C, RA, RB, RC>(oper_a: A, oper_b: B, oper_c: C) -> (RA, RB, RC)
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          C: FnOnce() -> RC + Send,
+          RA: Send,
+          RB: Send,
+          RC: Send
+{
+    unsafe {
+        if unwind::is_unwinding() {
+            return (oper_a(), oper_b(), oper_c());
+        }
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() {
+            return match thread_pool::try_get_implicit_registry() {
+                Some(Ok(registry)) => registry.in_worker(|| join3(oper_a, oper_b, oper_c)),
+                Some(Err(_)) | None => (oper_a(), oper_b(), oper_c()),
+            };
+        }
+        (*worker_thread).registry().check_poisoned();
+        if runs_sequentially(worker_thread) {
+            return (oper_a(), oper_b(), oper_c());
+        }
+
+        let context_b = context::capture();
+        let job_b = StackJob::new(move |migrated| run_tail(migrated, context_b, oper_b),
+                                  SpinLatch::new());
+        let context_c = context::capture();
+        let job_c = StackJob::new(move |migrated| run_tail(migrated, context_c, oper_c),
+                                  SpinLatch::new());
+        let result_a = join_tail(worker_thread,
+                                 oper_a,
+                                 &[(job_b.as_job_ref(), &job_b.latch),
+                                   (job_c.as_job_ref(), &job_c.latch)]);
+        (result_a, job_b.take_result(), job_c.take_result())
+    }
+}
+
+/// Like `join3`, but for four closures.
+pub fn join4<A, B, C, D, RA, RB, RC, RD>(oper_a: A,
+                                         oper_b: B,
+                                         oper_c: C,
+                                         oper_d: D)
+                                         -> (RA, RB, RC, RD)
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          C: FnOnce() -> RC + Send,
+          D: FnOnce() -> RD + Send,
+          RA: Send,
+          RB: Send,
+          RC: Send,
+          RD: Send
+{
+    unsafe {
+        if unwind::is_unwinding() {
+            return (oper_a(), oper_b(), oper_c(), oper_d());
+        }
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() {
+            return match thread_pool::try_get_implicit_registry() {
+                Some(Ok(registry)) => registry.in_worker(|| join4(oper_a, oper_b, oper_c, oper_d)),
+                Some(Err(_)) | None => (oper_a(), oper_b(), oper_c(), oper_d()),
+            };
+        }
+        (*worker_thread).registry().check_poisoned();
+        if runs_sequentially(worker_thread) {
+            return (oper_a(), oper_b(), oper_c(), oper_d());
+        }
+
+        let context_b = context::capture();
+        let job_b = StackJob::new(move |migrated| run_tail(migrated, context_b, oper_b),
+                                  SpinLatch::new());
+        let context_c = context::capture();
+        let job_c = StackJob::new(move |migrated| run_tail(migrated, context_c, oper_c),
+                                  SpinLatch::new());
+        let context_d = context::capture();
+        let job_d = StackJob::new(move |migrated| run_tail(migrated, context_d, oper_d),
+                                  SpinLatch::new());
+        let result_a = join_tail(worker_thread,
+                                 oper_a,
+                                 &[(job_b.as_job_ref(), &job_b.latch),
+                                   (job_c.as_job_ref(), &job_c.latch),
+                                   (job_d.as_job_ref(), &job_d.latch)]);
+        (result_a, job_b.take_result(), job_c.take_result(), job_d.take_result())
+    }
+}
+
+/// Runs `oper`, one of the closures that `join_n`, `join3` or `join4`
+/// push, in the `context` of the caller if it was stolen.
+fn run_tail<OP, R>(migrated: bool, context: context::Captured, oper: OP) -> R
+    where OP: FnOnce() -> R
+{
+    if migrated {
+        context.install(oper)
+    } else {
+        oper()
+    }
+}
+
+/// The parallel half of `join_n`, `join3` and `join4`: pushes the
+/// `tail` jobs onto the deque, runs `oper_head`, and then executes each
+/// of the tail jobs that was not stolen, or waits for the thief. Once
+/// this returns, every tail job has completed, so its result may be
+/// taken.
+unsafe fn join_tail<OP, R>(worker_thread: *mut WorkerThread,
+                           oper_head: OP,
+                           tail: &[(JobRef, &SpinLatch)])
+                           -> R
+    where OP: FnOnce() -> R
+{
+    log!(Join { worker: (*worker_thread).index() });
+
+    let depth = (*worker_thread).join_depth();
+    (*worker_thread).set_join_depth(depth + 1);
+    let _depth_guard = unwind::finally(worker_thread, |worker_thread| {
+        (**worker_thread).set_join_depth(depth);
+    });
+
+    // we push the jobs in reverse order, so that the second closure
+    // ends up at the top of our deque and the last one is the first
+    // to be stolen
+    for &(job_ref, _) in tail.iter().rev() {
+        (*worker_thread).push(job_ref);
+    }
+
+    let spawn_count = (*worker_thread).current_spawn_count();
+
+    let result_head = match unwind::halt_unwinding(oper_head) {
+        Ok(result_head) => result_head,
+        Err(err) => join_tail_recover_from_panic(worker_thread, tail, err),
+    };
+
+    (*worker_thread).pop_spawned_jobs(spawn_count);
+
+    for &(ref job_ref, latch) in tail {
+        if (*worker_thread).pop_job(job_ref) {
+            (*worker_thread).record_pop(true);
+            (*worker_thread).execute(*job_ref);
+        } else {
+            (*worker_thread).record_pop(false);
+            (*worker_thread).steal_until(latch);
+        }
+    }
+    result_head
+}
+
+/// Like `join_recover_from_panic`, for the `tail` jobs of `join_tail`:
+/// if the head closure panics, runs every tail job that was not stolen
+/// (dropping any panic of its own) and waits for the thieves of the
+/// others, since they may all borrow from the caller's stack; then
+/// propagates the panic of the head.
+#[cold]
+unsafe fn join_tail_recover_from_panic(worker_thread: *mut WorkerThread,
+                                       tail: &[(JobRef, &SpinLatch)],
+                                       err: Box<Any + Send>)
+                                       -> ! {
+    for &(ref job_ref, latch) in tail {
+        if (*worker_thread).pop_job(job_ref) {
+            (*worker_thread).execute(*job_ref);
+        } else {
+            (*worker_thread).steal_until(latch);
+        }
+    }
+    unwind::resume_unwinding(err)
 }
 
 pub struct ThreadPool {
@@ -219,25 +2278,331 @@ impl ThreadPool {
     /// result.  See `InitError` for more details.
     pub fn new(configuration: Configuration) -> Result<ThreadPool, InitError> {
         try!(configuration.validate());
-        Ok(ThreadPool { registry: Registry::new(configuration.num_threads) })
+        Ok(ThreadPool { registry: try!(Registry::new(configuration)) })
+    }
+
+    /// Returns the global thread pool, i.e., the one that `join`,
+    /// `scope`, `spawn` and parallel iterators use when they are not
+    /// called from within some other pool. It is started if it has not
+    /// been yet, with the default configuration (unless `initialize`
+    /// gets there first); panics if its threads cannot be started.
+    ///
+    /// There is only ever one global pool per process, however many
+    /// crates use Rayon (as long as they link to the same version of
+    /// it), and however many threads race to use it first: starting it
+    /// is guarded by a `std::sync::Once`, so exactly one set of worker
+    /// threads is spawned, and everybody else waits for that to finish.
+    /// The global pool is never shut down.
+    pub fn global() -> &'static ThreadPool {
+        static mut THE_POOL: Option<&'static ThreadPool> = None;
+        static THE_POOL_SET: Once = ONCE_INIT;
+
+        let registry = thread_pool::get_registry();
+        THE_POOL_SET.call_once(|| unsafe {
+            THE_POOL = Some(leak(ThreadPool { registry: registry.clone() }));
+        });
+        unsafe { THE_POOL.unwrap() }
+    }
+
+    /// Takes the worker with the given index out of a pool that was
+    /// configured with `set_use_current_threads`, so that it can be run
+    /// on a thread of the caller's choosing. Returns `None` if the index
+    /// is out of range, if that worker was already taken, or if the
+    /// pool spawned threads of its own.
+    ///
+    /// ```
+    /// use rayon::{Configuration, ThreadPool};
+    /// use std::thread;
+    ///
+    /// let pool = ThreadPool::new(Configuration::new()
+    ///         .set_num_threads(2)
+    ///         .set_use_current_threads(true))
+    ///     .unwrap();
+    /// let threads: Vec<_> = (0..2)
+    ///     .map(|index| {
+    ///         let worker = pool.take_worker(index).unwrap();
+    ///         thread::spawn(move || worker.run())
+    ///     })
+    ///     .collect();
+    ///
+    /// assert_eq!(pool.install(|| rayon::join(|| 1, || 2)), (1, 2));
+    ///
+    /// // dropping the pool lets the workers return
+    /// drop(pool);
+    /// for thread in threads {
+    ///     thread.join().unwrap();
+    /// }
+    /// ```
+    pub fn take_worker(&self, index: usize) -> Option<DonatedWorker> {
+        Registry::take_worker(&self.registry, index)
+    }
+
+    /// Lets the current thread help the pool out: takes one job that is
+    /// waiting in the pool (one spawned or submitted from outside, or
+    /// one that a worker pushed onto its deque, such as the second
+    /// closure of a `join`), runs it right here, and returns true; if
+    /// there is no job to take, returns false right away. This suits a
+    /// thread with work of its own that now and then finds itself with
+    /// nothing to do, and would rather lend a hand than sit idle.
+    ///
+    /// While it runs the job, the current thread counts as an extra
+    /// worker of the pool, the "helper", so the job may `join`, spawn
+    /// into a scope and the like, as it would on any worker; it sees
+    /// `current_thread_index()` return the index after those of the
+    /// regular and spare workers, and what it pushes may be stolen by
+    /// them. If the job waits for work that was stolen from it, the
+    /// current thread runs other jobs of the pool in the meantime, so
+    /// it may take a while to return. Jobs that it leaves behind on its
+    /// own deque are run before it returns.
+    ///
+    /// There is only one helper per pool: if another thread is helping
+    /// already, this returns false, too, and so it does if called from
+    /// a worker thread (of this pool or any other), or if the pool
+    /// records or replays a steal schedule. The jobs that the helper
+    /// ran do not show up in `steal_stats()`.
+    ///
+    /// ```
+    /// use rayon::{Configuration, ThreadPool};
+    /// use std::sync::mpsc::channel;
+    ///
+    /// let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    /// let (tx, rx) = channel();
+    /// for i in 0..100 {
+    ///     let tx = tx.clone();
+    ///     pool.spawn(move || tx.send(i).unwrap());
+    /// }
+    /// drop(tx);
+    ///
+    /// // lend a hand until there is nothing left to take
+    /// while pool.try_help() {}
+    /// assert_eq!(rx.iter().take(100).sum::<i32>(), 4950);
+    /// ```
+    pub fn try_help(&self) -> bool {
+        Registry::try_help(&self.registry)
+    }
+
+    /// Returns the number of worker threads in this pool.
+    pub fn num_threads(&self) -> usize {
+        self.registry.num_threads()
+    }
+
+    /// Returns the approximate number of jobs that are waiting in this
+    /// pool for a worker to pick them up (not counting the ones that
+    /// are running): those that were submitted or spawned from outside,
+    /// and those that the workers pushed onto their deques (e.g., the
+    /// second closures of `join`). This is meant for backpressure, e.g.
+    /// so that a thread that submits tasks can hold off while the
+    /// backlog is large. It reads a few counters per worker, without
+    /// taking any locks, and is racy: jobs come and go while it counts,
+    /// so the result may be a little off.
+    pub fn queued_len(&self) -> usize {
+        self.registry.queued_len()
     }
 
     /// Executes `op` within the threadpool. Any attempts to `join`
     /// which occur there will then operate within that threadpool.
+    ///
+    /// Note that `install` may itself be called from within another
+    /// thread pool: in that case, `op` still executes in `self`, and
+    /// the calling worker blocks until it completes. Once `install`
+    /// returns, any subsequent `join` or `scope` calls once again
+    /// operate within the pool of the caller. If `install` is called
+    /// from one of the workers of `self`, on the other hand, `op` just
+    /// runs right away on that worker.
     pub fn install<OP, R>(&self, op: OP) -> R
         where OP: FnOnce() -> R + Send
     {
-        unsafe {
-            let job_a = StackJob::new(op, LockLatch::new());
-            self.registry.inject(&[job_a.as_job_ref()]);
-            job_a.latch.wait();
-            job_a.into_result()
-        }
+        self.registry.in_worker(op)
+    }
+
+    /// Like `install`, but returns `Err(PoolError::PoolShutdown)`
+    /// rather than scheduling `op` if the pool has been terminated, or
+    /// if it is terminated before any worker got to `op`; it never
+    /// blocks on work that will not run. Note that a pool is only
+    /// terminated when it is dropped (see `Drop for ThreadPool`), and
+    /// `try_install` borrows it, so as things stand this always
+    /// succeeds when called through a live `ThreadPool`.
+    pub fn try_install<OP, R>(&self, op: OP) -> Result<R, PoolError>
+        where OP: FnOnce() -> R + Send
+    {
+        self.registry.try_in_worker(op)
+    }
+
+    /// Like `install`, but rather than propagating a panic from `op`
+    /// (or from a `join` or `scope` within it), returns it to the
+    /// caller as the `Err` of a `thread::Result`, with its original
+    /// payload. This is useful at an FFI boundary, where unwinding out
+    /// of a Rust function is undefined behavior: the panic can be
+    /// turned into an error code instead.
+    ///
+    /// As with `std::panic::catch_unwind`, you should be careful when
+    /// observing data that was being modified by `op` when it panicked.
+    ///
+    /// If the program is built with `-C panic=abort`, a panic aborts the
+    /// process before it could be caught, so the result is always `Ok`.
+    pub fn install_catch<OP, R>(&self, op: OP) -> thread::Result<R>
+        where OP: FnOnce() -> R + Send
+    {
+        unwind::halt_unwinding(|| self.install(op))
     }
+
+    /// Spawns an asynchronous task in this thread pool. See `spawn()`
+    /// for more details.
+    pub fn spawn<OP>(&self, op: OP)
+        where OP: FnOnce() + Send + 'static
+    {
+        spawn::spawn_in(op, &self.registry);
+    }
+
+    /// Spawns an asynchronous task in this thread pool, returning a
+    /// handle to wait for its result. See `spawn_handle()` for more
+    /// details.
+    pub fn spawn_handle<F, T>(&self, func: F) -> ScopeHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        spawn::spawn_handle_in(func, &self.registry)
+    }
+
+    /// Submits `func` to run in this thread pool, returning a
+    /// `PendingResult` that can be polled for its result without
+    /// blocking. This is meant for threads outside the pool (such as
+    /// the main thread) that have other work to get on with, and want
+    /// to check back later; it does not need the futures crate, as
+    /// `spawn_future()` does. Otherwise, it works like `spawn_handle()`:
+    /// if the task panics, the panic is propagated from `try_get()` or
+    /// `get()`, and if the `PendingResult` is dropped, the task still
+    /// runs.
+    ///
+    /// If the pool limits its queue (see `Configuration::set_max_queued`)
+    /// and the queue is full, this waits for room, or returns
+    /// `Err(PoolError::QueueFull)`, in which case `func` is dropped
+    /// without running. Without a limit, this always succeeds.
+    pub fn submit<F, T>(&self, func: F) -> Result<PendingResult<T>, PoolError>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        spawn::submit_in(func, &self.registry)
+    }
+
+    /// Submits each of `funcs` to run in this thread pool, like
+    /// `submit()`, returning their `PendingResult`s in the same order.
+    /// The whole batch is injected into the pool at once, taking the
+    /// lock of the pool's job queue only once rather than once per
+    /// task, which makes a difference when a thread outside the pool
+    /// hands it many small tasks. The tasks may run in any order, and
+    /// in parallel, but each of them runs exactly once. If the queue
+    /// is full, either all of the tasks are rejected, or none.
+    pub fn submit_all<I, F, T>(&self, funcs: I) -> Result<Vec<PendingResult<T>>, PoolError>
+        where I: IntoIterator<Item = F>,
+              F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static
+    {
+        spawn::submit_all_in(funcs, &self.registry)
+    }
+
+    /// Runs `op(index)` exactly once on each worker thread of the
+    /// pool, where `index` is that worker's index (as returned by
+    /// `current_thread_index()`), and blocks until all of them are
+    /// done. This is meant for per-thread setup or bookkeeping, such
+    /// as warming up thread-local caches or collecting per-thread
+    /// metrics.
+    ///
+    /// Each call runs on its worker as soon as the worker looks for
+    /// new work, even if it is waiting inside a `join` or `scope` at
+    /// the time; so a worker that is blocked some other way (e.g., in
+    /// a call to `install` on another pool) delays the broadcast until
+    /// it is done. If `op` panics on any of the workers, the panic is
+    /// propagated once all of the calls have completed.
+    pub fn broadcast<OP>(&self, op: OP)
+        where OP: Fn(usize) + Sync
+    {
+        self.registry.broadcast(op)
+    }
+
+    /// Spawns a future in this thread pool. See `spawn_future()` for
+    /// more details.
+    #[cfg(feature = "unstable")]
+    pub fn spawn_future<F>(&self, future: F) -> RayonFuture<F::Item, F::Error>
+        where F: Future + Send + 'static,
+              F::Item: Send,
+              F::Error: Send
+    {
+        future::spawn_future_in(future, self.registry.clone())
+    }
+
+    /// Returns a snapshot of the steal statistics of each worker
+    /// thread in the pool, indexed by `current_thread_index()`. The
+    /// counts accumulate from the moment the pool was created. They
+    /// are read while the workers may still be updating them, so they
+    /// are only exact once the pool is quiet, e.g. after `install`
+    /// returned.
+    pub fn steal_stats(&self) -> Vec<WorkerStats> {
+        self.registry.steal_stats()
+    }
+
+    /// Returns which worker took which job so far, if the pool was
+    /// configured with `StealMode::Record`; see
+    /// `Configuration::set_steal_mode`. Only available with the `log`
+    /// feature.
+    #[cfg(feature = "log")]
+    pub fn steal_schedule(&self) -> Option<StealSchedule> {
+        self.registry.steal_schedule()
+    }
+}
+
+/// What one worker thread did with its jobs; see
+/// `ThreadPool::steal_stats`. This is meant to help diagnose load
+/// imbalance: e.g., a worker that shows many failed steals and few
+/// stolen jobs spent much of its time idle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    /// Number of jobs this worker stole from other workers.
+    pub stolen: usize,
+
+    /// Number of times this worker looked for a job to steal, but
+    /// found that none of the other workers had any.
+    pub failed_steals: usize,
+
+    /// Number of times `join` found its second closure still on this
+    /// worker's deque, and ran it locally.
+    pub popped: usize,
+
+    /// Number of times `join` found that its second closure had been
+    /// stolen by another worker.
+    pub lost: usize,
 }
 
+/// Dropping a `ThreadPool` terminates its worker threads and waits for
+/// them to exit. `install` borrows the pool, but tasks that were handed
+/// to it with `spawn`, `spawn_handle`, `submit` or `spawn_future` are
+/// `'static`, so they may still be queued up or running at this point:
+///
+/// - Jobs that a worker has already started run to completion, and so
+///   do the jobs on the deques of the workers (pushed there by jobs of
+///   the pool), since a worker only exits once it has nothing left to
+///   do.
+/// - Jobs still waiting in the pool's injector queue are aborted, and
+///   never run; so are jobs that are injected after termination (e.g.,
+///   by a running job that calls `spawn`). An aborted `spawn` task is
+///   just dropped, while the handle of an aborted `spawn_handle` or
+///   `submit` task, or an aborted future, panics instead of returning
+///   a result.
+///
+/// The one exception is if the pool is dropped from within one of
+/// its own worker threads (e.g., because a job held the last
+/// reference to it). Waiting for the workers would deadlock in that
+/// case, so we just signal them to terminate and return immediately;
+/// they exit once they are done with their current work. Workers that
+/// run on donated threads are likewise only signalled: each returns
+/// from `DonatedWorker::run` once it has noticed. So are the workers
+/// of a pool that detaches its threads (see
+/// `Configuration::set_detach_threads`).
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         self.registry.terminate();
+        if !self.registry.is_current() {
+            self.registry.join_threads();
+        }
     }
 }
@@ -0,0 +1,106 @@
+//! Helpers for measuring the overhead of `join`, without pulling in a
+//! benchmarking framework. This gives everyone the same yardstick
+//! when working on the `join` hot path: `sum()` over a slice of a
+//! given length, compared to `sum_sequential()`. See `join-overhead`
+//! in `rayon-demo` for a runnable version.
+//!
+//! Only available with the `bench` feature.
+
+use api::{join, Configuration, ThreadPool};
+use std::mem;
+use std::ptr;
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+/// How long one steal-free `join` may take in a release build, as
+/// measured by `steal_free_join_nanos()`, before the timing test
+/// (`cargo test --release --features timing`) fails. When this was
+/// set, the fast path took about 33ns on an x86-64 VM, so this leaves
+/// room for CI noise while still catching a 2x regression there. Debug
+/// builds are over ten times slower, and are not checked.
+pub const STEAL_FREE_JOIN_BUDGET_NANOS: u64 = 80;
+
+/// Runs `f` `iters` times and returns the average time per call. The
+/// results are read back, so that the optimizer cannot drop the calls
+/// altogether.
+///
+/// Panics if `iters` is zero.
+pub fn measure<F, T>(iters: usize, mut f: F) -> Duration
+    where F: FnMut() -> T
+{
+    assert!(iters > 0, "measure needs at least one iteration");
+    let start = Instant::now();
+    for _ in 0..iters {
+        let result = f();
+        unsafe {
+            mem::forget(ptr::read_volatile(&result));
+        }
+    }
+    start.elapsed() / iters as u32
+}
+
+/// Sums `slice` by recursively splitting it in half with `join`, all
+/// the way down to single elements. There is next to no work besides
+/// the splitting, so this mostly measures `join` itself: summing a
+/// slice of length `n` takes `num_joins(n)` calls.
+pub fn sum(slice: &[u64]) -> u64 {
+    if slice.len() <= 1 {
+        slice.first().cloned().unwrap_or(0)
+    } else {
+        let (left, right) = slice.split_at(slice.len() / 2);
+        let (a, b) = join(|| sum(left), || sum(right));
+        a.wrapping_add(b)
+    }
+}
+
+/// Same as `sum()`, but recursing without `join`, as a baseline.
+pub fn sum_sequential(slice: &[u64]) -> u64 {
+    if slice.len() <= 1 {
+        slice.first().cloned().unwrap_or(0)
+    } else {
+        let (left, right) = slice.split_at(slice.len() / 2);
+        sum_sequential(left).wrapping_add(sum_sequential(right))
+    }
+}
+
+/// Returns the median time per `join` call, in nanoseconds, over
+/// `rounds` rounds of `sum()` over a slice of 4096 elements, run such
+/// that no job ever gets stolen: one of the two workers of the pool is
+/// kept busy waiting, so every `join` on the other one takes the fast
+/// path of pushing its second closure, running the first one, and
+/// popping the second one back. Taking the median of many short
+/// rounds keeps the occasional descheduled round from skewing the
+/// result.
+///
+/// Panics if `rounds` is zero.
+pub fn steal_free_join_nanos(rounds: usize) -> u64 {
+    assert!(rounds > 0, "steal_free_join_nanos needs at least one round");
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let (started_tx, started_rx) = channel();
+    let (release_tx, release_rx) = channel::<()>();
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+    });
+    started_rx.recv().unwrap();
+
+    let v: Vec<u64> = (0..4096).collect();
+    let joins = num_joins(v.len()) as u64;
+    let mut times: Vec<u64> = pool.install(|| {
+        (0..rounds)
+            .map(|_| {
+                let time = measure(1, || sum(&v));
+                (time.as_secs() * 1_000_000_000 + time.subsec_nanos() as u64) / joins
+            })
+            .collect()
+    });
+    release_tx.send(()).unwrap();
+    times.sort();
+    times[times.len() / 2]
+}
+
+/// The number of `join` calls that `sum()` makes for a slice of length
+/// `len`.
+pub fn num_joins(len: usize) -> usize {
+    if len == 0 { 0 } else { len - 1 }
+}
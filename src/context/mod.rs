@@ -0,0 +1,102 @@
+//! Ambient context that follows jobs across threads.
+//!
+//! `with_context()` installs a value in a thread-local slot for the
+//! duration of a closure, and `current_context()` reads it back. Each
+//! time a job is created (by `join`, `spawn`, `spawn_handle` or
+//! `Scope::spawn`), the current context is captured along with it; if
+//! the job ends up running on some other thread, that thread installs
+//! the captured context while the job runs, and restores its own
+//! afterwards. So a request ID or similar set before a `join` is still
+//! visible in the second closure even if it was stolen.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use unwind;
+
+#[cfg(test)]
+mod test;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Box<ContextValue>>> = RefCell::new(None)
+}
+
+/// Set once `with_context()` is first called. Until then, no thread
+/// can have a context, so `capture()` need not look; this keeps the
+/// cost of contexts to programs that use them.
+static CONTEXT_USED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Runs `op` with `value` as the current context, which is visible
+/// via `current_context()` within `op`, as well as within any jobs
+/// that `op` spawns. The previous context (if any) is restored once
+/// `op` returns or panics.
+pub fn with_context<C, OP, R>(value: C, op: OP) -> R
+    where C: Clone + Send + 'static,
+          OP: FnOnce() -> R
+{
+    CONTEXT_USED.store(true, Ordering::Relaxed);
+    Captured { value: Some(Box::new(value)) }.install(op)
+}
+
+/// Returns a clone of the current context, if there is one and it has
+/// type `C`; otherwise, returns `None`.
+pub fn current_context<C>() -> Option<C>
+    where C: Clone + 'static
+{
+    CURRENT.with(|current| {
+        current.borrow()
+            .as_ref()
+            .and_then(|value| value.as_any().downcast_ref::<C>())
+            .cloned()
+    })
+}
+
+/// A context value, type-erased so that it can be cloned into jobs.
+trait ContextValue: Send {
+    fn clone_box(&self) -> Box<ContextValue>;
+    fn as_any(&self) -> &Any;
+}
+
+impl<C> ContextValue for C
+    where C: Clone + Send + 'static
+{
+    fn clone_box(&self) -> Box<ContextValue> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+/// The context of the thread that created a job, to be installed by
+/// whichever thread executes it.
+pub struct Captured {
+    value: Option<Box<ContextValue>>,
+}
+
+/// Captures the current context of this thread.
+#[inline]
+pub fn capture() -> Captured {
+    if !CONTEXT_USED.load(Ordering::Relaxed) {
+        return Captured { value: None };
+    }
+    Captured { value: CURRENT.with(|current| current.borrow().as_ref().map(|v| v.clone_box())) }
+}
+
+impl Captured {
+    /// Runs `op` with the captured context installed.
+    pub fn install<OP, R>(self, op: OP) -> R
+        where OP: FnOnce() -> R
+    {
+        if self.value.is_none() && !CONTEXT_USED.load(Ordering::Relaxed) {
+            return op();
+        }
+        let previous = CURRENT.with(|current| mem::replace(&mut *current.borrow_mut(), self.value));
+        let _guard = unwind::finally(previous, |previous| {
+            CURRENT.with(|current| *current.borrow_mut() = previous.take());
+        });
+        op()
+    }
+}
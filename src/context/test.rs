@@ -0,0 +1,62 @@
+use Configuration;
+use ThreadPool;
+use {current_context, current_thread_index, join, spawn_handle, with_context};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use unwind;
+
+#[test]
+fn context_is_scoped() {
+    assert_eq!(current_context::<u32>(), None);
+    with_context(22u32, || {
+        assert_eq!(current_context::<u32>(), Some(22));
+        assert_eq!(current_context::<String>(), None);
+        with_context(23u32, || assert_eq!(current_context::<u32>(), Some(23)));
+        assert_eq!(current_context::<u32>(), Some(22));
+    });
+    assert_eq!(current_context::<u32>(), None);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn context_restored_after_panic() {
+    with_context(22u32, || {
+        unwind::halt_unwinding(|| with_context(23u32, || panic!("boom"))).unwrap_err();
+        assert_eq!(current_context::<u32>(), Some(22));
+    });
+}
+
+#[test]
+fn context_follows_stolen_job() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let b_started = AtomicBool::new(false);
+    let ((thread_a, context_a), (thread_b, context_b)) = pool.install(|| {
+        with_context("request-1".to_string(), || {
+            join(|| {
+                     // keep this thread busy, so that `b` has to be stolen
+                     while !b_started.load(Ordering::SeqCst) {
+                         thread::yield_now();
+                     }
+                     (current_thread_index(), current_context::<String>())
+                 },
+                 || {
+                     b_started.store(true, Ordering::SeqCst);
+                     (current_thread_index(), current_context::<String>())
+                 })
+        })
+    });
+    assert!(thread_a != thread_b);
+    assert_eq!(context_a, Some("request-1".to_string()));
+    assert_eq!(context_b, Some("request-1".to_string()));
+
+    // the thief does not keep the context around
+    assert_eq!(pool.install(|| join(|| current_context::<String>(),
+                                    || current_context::<String>())),
+               (None, None));
+}
+
+#[test]
+fn context_follows_spawned_job() {
+    let handle = with_context(22u32, || spawn_handle(|| current_context::<u32>()));
+    assert_eq!(handle.join(), Some(22));
+}
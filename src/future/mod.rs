@@ -0,0 +1,285 @@
+//! Support for driving futures on the Rayon threadpool.
+//!
+//! `spawn_future()` moves a future into the pool, where it is polled
+//! by an ordinary (heap-allocated) job. When the future is not ready,
+//! the job simply finishes; once the future is notified, a fresh job
+//! is injected to poll it again. So a pending future never occupies a
+//! worker thread, and is never spun on.
+//!
+//! The state of a spawned future moves through the following states:
+//!
+//! - `UNPARKED`: a job to poll the future has been injected (or is
+//!   about to be), but has not started yet.
+//! - `EXECUTING`: the future is being polled.
+//! - `EXECUTING_UNPARKED`: the future was notified while being polled;
+//!   the executing job will poll it again before it finishes.
+//! - `PARKED`: the future returned `NotReady` and is waiting for a
+//!   notification, which moves it back to `UNPARKED`.
+//! - `COMPLETE`: the future is done, and has been dropped.
+
+use futures::{Async, Future, Poll};
+use futures::executor::{self, Notify, Spawn};
+use futures::task::{self, Task};
+use job::{HeapJob, JobMode};
+use std::any::Any;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thread_pool::Registry;
+use unwind;
+
+#[cfg(test)]
+mod test;
+
+const STATE_PARKED: usize = 0;
+const STATE_UNPARKED: usize = 1;
+const STATE_EXECUTING: usize = 2;
+const STATE_EXECUTING_UNPARKED: usize = 3;
+const STATE_COMPLETE: usize = 4;
+
+/// Spawns a future into the Rayon threadpool, returning a
+/// `RayonFuture` that can be used to get at its result. If called from
+/// within a worker thread, the future is spawned into the pool of that
+/// worker; otherwise, it is spawned into the global pool.
+///
+/// The future is polled on the worker threads. Whenever it is not
+/// ready, it is set aside until it gets notified, at which point it
+/// is queued up to be polled again. Like `spawn()`, this does not wait
+/// for the future to complete, and the future starts executing even
+/// if the returned `RayonFuture` is never polled.
+///
+/// If polling the future panics, the panic is propagated to whoever
+/// polls the `RayonFuture`. If the pool is dropped before the future
+/// completes, the future is dropped and polling the `RayonFuture`
+/// panics.
+pub fn spawn_future<F>(future: F) -> RayonFuture<F::Item, F::Error>
+    where F: Future + Send + 'static,
+          F::Item: Send,
+          F::Error: Send
+{
+    spawn_future_in(future, Registry::current())
+}
+
+/// Spawns `future` into the given registry. See `spawn_future()` for
+/// details.
+pub fn spawn_future_in<F>(future: F, registry: Arc<Registry>) -> RayonFuture<F::Item, F::Error>
+    where F: Future + Send + 'static,
+          F::Item: Send,
+          F::Error: Send
+{
+    let job = Arc::new(FutureJob {
+        state: AtomicUsize::new(STATE_UNPARKED),
+        registry: registry,
+        spawn: Mutex::new(Some(executor::spawn(future))),
+        result: Mutex::new(FutureResult {
+            outcome: None,
+            waiting_task: None,
+        }),
+    });
+    FutureJob::inject(&job);
+    RayonFuture { inner: job }
+}
+
+/// A handle to a future that was spawned with `spawn_future()`. The
+/// `RayonFuture` is itself a future, which yields the same result as
+/// the future that was spawned.
+pub struct RayonFuture<T, E> {
+    inner: Arc<FutureResultSource<T, E>>,
+}
+
+impl<T, E> Future for RayonFuture<T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<T, E> {
+        self.inner.poll_result()
+    }
+}
+
+/// Type-erased access to the outcome of a `FutureJob`, so that
+/// `RayonFuture` doesn't need to name the type of the future.
+trait FutureResultSource<T, E>: Send + Sync {
+    fn poll_result(&self) -> Poll<T, E>;
+}
+
+enum Outcome<T, E> {
+    Ok(T),
+    Err(E),
+    Panic(Box<Any + Send>),
+    Aborted,
+    Taken,
+}
+
+struct FutureResult<T, E> {
+    /// `None` until the future completes.
+    outcome: Option<Outcome<T, E>>,
+
+    /// The task, if any, that polled the `RayonFuture` and is waiting
+    /// to be notified when the outcome becomes available.
+    waiting_task: Option<Task>,
+}
+
+struct FutureJob<F: Future> {
+    state: AtomicUsize,
+    registry: Arc<Registry>,
+
+    /// The future being driven; `None` once it completed. Only the
+    /// job in the `EXECUTING` state polls it. (A re-injected job may
+    /// briefly wait for the lock until the previous job, which just
+    /// parked the future, has released it.)
+    spawn: Mutex<Option<Spawn<F>>>,
+
+    result: Mutex<FutureResult<F::Item, F::Error>>,
+}
+
+impl<F> FutureJob<F>
+    where F: Future + Send + 'static,
+          F::Item: Send,
+          F::Error: Send
+{
+    /// Queues up a job to poll the future. Must only be called by
+    /// whoever moved the state to `UNPARKED`.
+    fn inject(this: &Arc<Self>) {
+        let job = this.clone();
+        unsafe {
+            let job_ref = Box::new(HeapJob::new(move |mode| match mode {
+                    JobMode::Execute => FutureJob::execute(job),
+                    JobMode::Abort => job.abort(),
+                }))
+                .as_job_ref();
+            this.registry.inject_or_abort(job_ref);
+        }
+    }
+
+    /// Records that the future was notified. Returns true if it was
+    /// parked, in which case the caller must `inject()` it.
+    fn unpark(&self) -> bool {
+        loop {
+            match self.state.load(Ordering::SeqCst) {
+                STATE_PARKED => {
+                    if self.state.compare_and_swap(STATE_PARKED,
+                                                   STATE_UNPARKED,
+                                                   Ordering::SeqCst) == STATE_PARKED {
+                        return true;
+                    }
+                }
+                STATE_EXECUTING => {
+                    if self.state.compare_and_swap(STATE_EXECUTING,
+                                                   STATE_EXECUTING_UNPARKED,
+                                                   Ordering::SeqCst) == STATE_EXECUTING {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    fn execute(this: Arc<Self>) {
+        let old_state = this.state.swap(STATE_EXECUTING, Ordering::SeqCst);
+        debug_assert_eq!(old_state, STATE_UNPARKED);
+
+        let notify = Arc::new(FutureNotify { job: this.clone() });
+        let mut spawn = this.spawn.lock().unwrap();
+        loop {
+            let poll = {
+                let spawn = spawn.as_mut().unwrap();
+                unwind::halt_unwinding(|| spawn.poll_future_notify(&notify, 0))
+            };
+
+            let outcome = match poll {
+                Ok(Ok(Async::NotReady)) => {
+                    // Park, unless we got notified in the meantime, in
+                    // which case we go around again.
+                    match this.state.compare_and_swap(STATE_EXECUTING,
+                                                      STATE_PARKED,
+                                                      Ordering::SeqCst) {
+                        STATE_EXECUTING => return,
+                        STATE_EXECUTING_UNPARKED => {
+                            this.state.store(STATE_EXECUTING, Ordering::SeqCst);
+                            continue;
+                        }
+                        state => panic!("unexpected state {} while executing", state),
+                    }
+                }
+                Ok(Ok(Async::Ready(item))) => Outcome::Ok(item),
+                Ok(Err(err)) => Outcome::Err(err),
+                Err(panic) => Outcome::Panic(panic),
+            };
+
+            *spawn = None;
+            this.complete(outcome);
+            return;
+        }
+    }
+
+    /// Called if the pool terminated while a job to poll the future
+    /// was still queued up.
+    fn abort(&self) {
+        *self.spawn.lock().unwrap() = None;
+        self.complete(Outcome::Aborted);
+    }
+
+    fn complete(&self, outcome: Outcome<F::Item, F::Error>) {
+        self.state.store(STATE_COMPLETE, Ordering::SeqCst);
+        let waiting_task = {
+            let mut result = self.result.lock().unwrap();
+            result.outcome = Some(outcome);
+            result.waiting_task.take()
+        };
+        if let Some(task) = waiting_task {
+            task.notify();
+        }
+    }
+}
+
+impl<F> FutureResultSource<F::Item, F::Error> for FutureJob<F>
+    where F: Future + Send + 'static,
+          F::Item: Send,
+          F::Error: Send
+{
+    fn poll_result(&self) -> Poll<F::Item, F::Error> {
+        let mut result = self.result.lock().unwrap();
+        match mem::replace(&mut result.outcome, Some(Outcome::Taken)) {
+            None => {
+                result.outcome = None;
+                result.waiting_task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+            Some(Outcome::Ok(item)) => Ok(Async::Ready(item)),
+            Some(Outcome::Err(err)) => Err(err),
+            Some(Outcome::Panic(panic)) => {
+                drop(result);
+                unwind::resume_unwinding(panic)
+            }
+            Some(Outcome::Aborted) => {
+                drop(result);
+                panic!("future was dropped because its thread pool terminated")
+            }
+            Some(Outcome::Taken) => {
+                drop(result);
+                panic!("`RayonFuture` polled after it completed")
+            }
+        }
+    }
+}
+
+/// The `Notify` handle given to the future while it is polled. The
+/// future may hold on to it (via a `Task`) for as long as it likes,
+/// and it keeps the `FutureJob` alive, so that the job can always be
+/// re-injected when the notification comes in.
+struct FutureNotify<F: Future> {
+    job: Arc<FutureJob<F>>,
+}
+
+impl<F> Notify for FutureNotify<F>
+    where F: Future + Send + 'static,
+          F::Item: Send,
+          F::Error: Send
+{
+    fn notify(&self, _id: usize) {
+        if self.job.unpark() {
+            FutureJob::inject(&self.job);
+        }
+    }
+}
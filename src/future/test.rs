@@ -0,0 +1,72 @@
+use Configuration;
+use ThreadPool;
+use futures::{self, Future};
+use futures::future::lazy;
+use futures::sync::oneshot;
+use std::sync::mpsc::channel;
+use super::spawn_future;
+use unwind;
+
+#[test]
+fn future_chain() {
+    let future = spawn_future(lazy(|| Ok::<_, ()>(1))
+        .and_then(|v| Ok(v + 1))
+        .and_then(|v| Ok(v * 10))
+        .and_then(|v| futures::finished(v + 2)));
+    assert_eq!(future.wait(), Ok(22));
+}
+
+#[test]
+fn future_error() {
+    let future = spawn_future(lazy(|| Ok::<i32, _>(1)).and_then(|_| Err::<i32, _>("bad")));
+    assert_eq!(future.wait(), Err("bad"));
+}
+
+#[test]
+fn future_in_pool() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let future = pool.spawn_future(lazy(|| Ok::<_, ()>(::current_num_threads())));
+    assert_eq!(future.wait(), Ok(2));
+}
+
+#[test]
+fn future_notified_from_outside() {
+    // The receiver is not ready when first polled, so the future
+    // parks, and is re-injected once the sender fires.
+    let (tx, rx) = oneshot::channel::<i32>();
+    let (polled_tx, polled_rx) = channel();
+    let future = spawn_future(lazy(move || {
+            polled_tx.send(()).unwrap();
+            Ok(())
+        })
+        .and_then(|()| rx)
+        .and_then(|v| Ok(v * 2)));
+    polled_rx.recv().unwrap();
+    tx.send(11).unwrap();
+    assert_eq!(future.wait(), Ok(22));
+}
+
+#[test]
+fn future_many_notifications() {
+    // A chain of futures, each completed by the previous one.
+    let mut senders = vec![];
+    let mut future: Box<Future<Item = usize, Error = oneshot::Canceled> + Send> =
+        Box::new(futures::finished(0));
+    for _ in 0..100 {
+        let (tx, rx) = oneshot::channel::<usize>();
+        senders.push(tx);
+        future = Box::new(future.and_then(move |sum| rx.map(move |v| sum + v)));
+    }
+    let future = spawn_future(future);
+    for (i, tx) in senders.into_iter().enumerate() {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(future.wait(), Ok(99 * 100 / 2));
+}
+
+#[test]
+fn future_panic_propagates() {
+    let future = spawn_future(lazy(|| -> Result<(), ()> { panic!("Hello, world!") }));
+    let err = unwind::halt_unwinding(|| future.wait()).unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"Hello, world!"));
+}
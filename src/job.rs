@@ -2,12 +2,24 @@ use latch::Latch;
 use std::any::Any;
 use std::cell::UnsafeCell;
 use std::mem;
-use unwind;
+use unwind::{self, PanicOrigin};
 
 enum JobResult<T> {
     None,
     Ok(T),
-    Panic(Box<Any + Send>),
+
+    /// The payload, and the origin noted for it on the thread that
+    /// executed the job, if any.
+    Panic(Box<Any + Send>, Option<PanicOrigin>),
+}
+
+/// Propagates a panic that a job caught on another thread, along with
+/// its origin.
+fn resume_job_panic(err: Box<Any + Send>, origin: Option<PanicOrigin>) -> ! {
+    if let Some(origin) = origin {
+        unwind::note_unwinding_origin(origin);
+    }
+    unwind::resume_unwinding(err)
 }
 
 /// A `Job` is used to advertise work for other threads that they may
@@ -15,18 +27,47 @@ enum JobResult<T> {
 /// arranged in a deque, so that thieves can take from the top of the
 /// deque while the main worker manages the bottom of the deque. This
 /// deque is managed by the `thread_pool` module.
+///
+/// Rayon's own job kinds (the stack-allocated jobs behind `join`, and
+/// the heap-allocated ones behind `scope` and `spawn`) are built on
+/// this trait, and other kinds can be too: implement `Job`, make a
+/// `JobRef` to the job with `JobRef::new`, and hand it to the pool
+/// with `push_job()`. How the job is allocated, and how it reports
+/// that it is done, is up to the implementation.
 pub trait Job {
+    /// Runs the job that `this` points to, or (if `mode` is
+    /// `JobMode::Abort`) just releases whatever it holds on to.
+    ///
+    /// This is called exactly once per `JobRef`, on whichever worker
+    /// thread takes the job, so anything the job refers to must be
+    /// safe to use from that thread. `execute` must not unwind: a
+    /// panic that escapes it is treated as a bug in the thread pool,
+    /// and poisons the pool. Catch any panic of user code (e.g., with
+    /// `std::panic::catch_unwind`) and report it some other way.
     unsafe fn execute(this: *const Self, mode: JobMode);
 }
 
+/// How a job is to be executed; see `Job::execute`.
 pub enum JobMode {
+    /// Do the work.
     Execute,
+
+    /// Don't do the work, since the pool was shut down before the job
+    /// got to run, but clean up (and, e.g., tell whoever waits for the
+    /// job that it is not going to happen).
     Abort,
 }
 
 /// Effectively a Job trait object. Each JobRef **must** be executed
 /// exactly once, or else data may leak.
 ///
+/// A `JobRef` is just a pointer, with no lifetime attached, so it is up
+/// to its creator to keep the job alive (and at the same address)
+/// until it has been executed, e.g., by waiting for it on the stack
+/// frame that owns it, or by putting it on the heap and freeing it in
+/// `Job::execute`. A job that is pushed more than once, or executed by
+/// hand as well as by the pool, is executed more than once.
+///
 /// Internally, we store the job's data in a `*const ()` pointer.  The
 /// true type is something like `*const StackJob<...>`, but we hide
 /// it. We also carry the "execute fn" from the `Job` trait.
@@ -40,6 +81,9 @@ unsafe impl Send for JobRef {}
 unsafe impl Sync for JobRef {}
 
 impl JobRef {
+    /// Creates a `JobRef` to the job at `data`. Unsafe because the job
+    /// must stay valid until the `JobRef` has been executed; see the
+    /// type's docs.
     pub unsafe fn new<T>(data: *const T) -> JobRef
         where T: Job
     {
@@ -55,6 +99,21 @@ impl JobRef {
         }
     }
 
+    /// Returns true if `self` and `other` refer to the same job.
+    #[inline]
+    pub fn same_job(&self, other: &JobRef) -> bool {
+        self.pointer == other.pointer
+    }
+
+    /// The address of the job's data, which identifies it for as long
+    /// as it is live.
+    #[cfg(feature = "log")]
+    pub fn address(&self) -> usize {
+        self.pointer as usize
+    }
+
+    /// Executes the job. Unsafe because this must happen exactly once
+    /// per job; see the type's docs.
     #[inline]
     pub unsafe fn execute(&self, mode: JobMode) {
         (self.execute_fn)(self.pointer, mode)
@@ -64,6 +123,10 @@ impl JobRef {
 /// A job that will be owned by a stack slot. This means that when it
 /// executes it need not free any heap data, the cleanup occurs when
 /// the stack frame is later popped.
+///
+/// The closure receives a boolean indicating whether the job was
+/// stolen (or injected), i.e., executed via the `Job` trait, rather
+/// than run inline by the thread that created it.
 pub struct StackJob<L: Latch, F, R> {
     pub latch: L,
     func: UnsafeCell<Option<F>>,
@@ -71,7 +134,7 @@ pub struct StackJob<L: Latch, F, R> {
 }
 
 impl<L: Latch, F, R> StackJob<L, F, R>
-    where F: FnOnce() -> R + Send
+    where F: FnOnce(bool) -> R + Send
 {
     pub fn new(func: F, latch: L) -> StackJob<L, F, R> {
         StackJob {
@@ -85,21 +148,39 @@ impl<L: Latch, F, R> StackJob<L, F, R>
         JobRef::new(self)
     }
 
-    pub unsafe fn run_inline(self) -> R {
-        self.func.into_inner().unwrap()()
+    /// Runs the closure right here, as nobody stole the job. Like the
+    /// other accessors below, this takes what it needs out of the job,
+    /// rather than the job by value: moving the job would copy all of
+    /// it, including the room for its result, which matters for a
+    /// large `R`. Whatever is left (e.g. the result of a job whose
+    /// caller panicked) is dropped along with the job.
+    pub unsafe fn run_inline(&self, stolen: bool) -> R {
+        (*self.func.get()).take().unwrap()(stolen)
     }
 
-    pub unsafe fn into_result(self) -> R {
-        match self.result.into_inner() {
+    /// Takes the result of the executed job, resuming its panic if it
+    /// had one.
+    pub unsafe fn take_result(&self) -> R {
+        match mem::replace(&mut *self.result.get(), JobResult::None) {
             JobResult::None => unreachable!(),
             JobResult::Ok(x) => x,
-            JobResult::Panic(x) => unwind::resume_unwinding(x),
+            JobResult::Panic(x, origin) => resume_job_panic(x, origin),
+        }
+    }
+
+    /// Like `take_result`, but returns `None` if the job was executed
+    /// with `JobMode::Abort`.
+    pub unsafe fn try_take_result(&self) -> Option<R> {
+        match mem::replace(&mut *self.result.get(), JobResult::None) {
+            JobResult::None => None,
+            JobResult::Ok(x) => Some(x),
+            JobResult::Panic(x, origin) => resume_job_panic(x, origin),
         }
     }
 }
 
 impl<L: Latch, F, R> Job for StackJob<L, F, R>
-    where F: FnOnce() -> R
+    where F: FnOnce(bool) -> R
 {
     unsafe fn execute(this: *const Self, mode: JobMode) {
         let this = &*this;
@@ -107,9 +188,12 @@ impl<L: Latch, F, R> Job for StackJob<L, F, R>
             JobMode::Execute => {
                 let abort = unwind::AbortIfPanic;
                 let func = (*this.func.get()).take().unwrap();
-                (*this.result.get()) = match unwind::halt_unwinding(|| func()) {
+                (*this.result.get()) = match unwind::halt_unwinding(|| func(true)) {
                     Ok(x) => JobResult::Ok(x),
-                    Err(x) => JobResult::Panic(x),
+                    Err(x) => {
+                        let origin = unwind::panic_origin(&x);
+                        JobResult::Panic(x, origin)
+                    }
                 };
                 this.latch.set();
                 mem::forget(abort);
@@ -1,39 +1,76 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::cmp;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Mutex, Condvar};
 use std::thread;
+use std::time::Duration;
 
+/// A latch starts out unset and is eventually set, from which point on
+/// it stays set. Rayon signals the completion of a job by setting a
+/// latch; one can wait for that with `wait_until()`, which (on a worker
+/// thread) keeps running other jobs in the meantime.
+///
+/// Implementations must make `probe` return true once `set` was
+/// called, as often as the latch needs, and forever after. Everything
+/// the setting thread did before calling `set` must be visible to a
+/// thread that saw `probe` return true (e.g., by storing with
+/// `Release` ordering and loading with `Acquire`). Note that the latch
+/// may be freed as soon as it is set, so `set` must not touch `self`
+/// afterwards.
 pub trait Latch {
+    /// Tests if the latch is set.
+    fn probe(&self) -> bool;
+
+    /// Sets the latch, releasing all threads who are waiting on it.
     fn set(&self);
 }
 
 /// A Latch starts as false and eventually becomes true. You can block
 /// until it becomes true.
+///
+/// The flag gets a cache line to itself: a thread that is waiting for
+/// a stolen job probes its latch over and over, while the thief writes
+/// the job's result right next to it, and the owner of the stack frame
+/// may be writing its other locals. Without the padding, each of those
+/// writes would take the line away from the prober. The padding is
+/// done by hand, so as not to require `#[repr(align)]` (Rust 1.25):
+/// with `CACHE_LINE - 1` bytes on either side of the flag, whatever
+/// line the flag falls into holds nothing but the latch. This makes
+/// every `StackJob` a few cache lines big (e.g., 160 bytes instead of
+/// 40 for the second closure of a small `join`), which is still cheap
+/// next to the frame of `join` itself.
+#[repr(C)]
 pub struct SpinLatch {
+    _pad_before: [u8; CACHE_LINE - 1],
     b: AtomicBool,
+    _pad_after: [u8; CACHE_LINE - 1],
 }
 
+/// The size of a cache line, at least on the machines we care about.
+pub const CACHE_LINE: usize = 64;
+
 impl SpinLatch {
     #[inline]
     pub fn new() -> SpinLatch {
-        SpinLatch { b: AtomicBool::new(false) }
-    }
-
-    /// Test if latch is set.
-    #[inline]
-    pub fn probe(&self) -> bool {
-        self.b.load(Ordering::Acquire)
+        SpinLatch {
+            _pad_before: [0; CACHE_LINE - 1],
+            b: AtomicBool::new(false),
+            _pad_after: [0; CACHE_LINE - 1],
+        }
     }
 
     /// Block until latch is set. Use with caution.
     #[inline]
     pub fn spin(&self) {
-        while !self.probe() {
-            thread::yield_now();
-        }
+        spin_until(self)
     }
 }
 
 impl Latch for SpinLatch {
+    #[inline]
+    fn probe(&self) -> bool {
+        self.b.load(Ordering::Acquire)
+    }
+
     /// Set the latch to true, releasing all threads who are waiting.
     #[inline]
     fn set(&self) {
@@ -67,6 +104,10 @@ impl LockLatch {
 }
 
 impl Latch for LockLatch {
+    fn probe(&self) -> bool {
+        *self.m.lock().unwrap()
+    }
+
     /// Set the latch to true, releasing all threads who are waiting.
     #[inline]
     fn set(&self) {
@@ -75,3 +116,128 @@ impl Latch for LockLatch {
         self.v.notify_all();
     }
 }
+
+/// A latch that counts down: it starts out with a count, each `set`
+/// takes one off, and it is set once the count reaches zero. This is
+/// how a scope waits for all of its jobs.
+pub struct CountLatch {
+    counter: AtomicUsize,
+}
+
+impl CountLatch {
+    /// A latch that is set after `count` calls to `set`. (With a count
+    /// of zero, it starts out set.)
+    #[inline]
+    pub fn new(count: usize) -> CountLatch {
+        CountLatch { counter: AtomicUsize::new(count) }
+    }
+
+    /// Adds one to the count, i.e., requires one more call to `set`.
+    /// Panics if the latch is already set, since threads may already
+    /// have stopped waiting for it.
+    #[inline]
+    pub fn increment(&self) {
+        let old_value = self.counter.fetch_add(1, Ordering::Relaxed);
+        assert!(old_value > 0, "cannot increment a latch that is already set");
+    }
+}
+
+impl Latch for CountLatch {
+    #[inline]
+    fn probe(&self) -> bool {
+        self.counter.load(Ordering::Acquire) == 0
+    }
+
+    /// Takes one off the count. (`AcqRel`, so that whoever sees the
+    /// count reach zero also sees what all the other setters did.)
+    /// Panics if the latch is already set.
+    #[inline]
+    fn set(&self) {
+        let old_value = self.counter.fetch_sub(1, Ordering::AcqRel);
+        assert!(old_value > 0, "latch set more often than its count");
+    }
+}
+
+/// Spins (with back-off) until `latch` is set.
+pub fn spin_until<L: Latch + ?Sized>(latch: &L) {
+    let mut backoff = Backoff::new();
+    while !latch.probe() {
+        backoff.snooze();
+    }
+}
+
+/// Number of rounds for which `Backoff::snooze` returns immediately,
+/// so that the caller effectively spins.
+const SPIN_ROUNDS: u32 = 32;
+
+/// Number of rounds (including the spin rounds) after which
+/// `Backoff::snooze` stops yielding and starts to sleep.
+const YIELD_ROUNDS: u32 = SPIN_ROUNDS + 1024;
+
+/// Upper bound on how long `Backoff::snooze` sleeps for, in
+/// microseconds. This bounds the extra latency that a sleeping
+/// thread may incur once the condition it waits for becomes true.
+const MAX_SLEEP_MICROS: u64 = 1000;
+
+/// Helps a thread wait for a condition that it can only poll (such as
+/// a `SpinLatch` being set). Short waits are cheapest if we simply
+/// spin, so we do that first; then we start yielding our timeslice;
+/// and, if the wait goes on for a long time, we sleep for
+/// increasingly long (but bounded) intervals, so that we don't burn a
+/// whole core when there are more threads than cores. Since the
+/// caller polls its condition between each call to `snooze`, there is
+/// no wakeup that can be lost.
+pub struct Backoff {
+    rounds: u32,
+
+    /// Number of rounds (including the spin rounds) after which we
+    /// start to sleep; `YIELD_ROUNDS` unless configured otherwise.
+    yield_rounds: u32,
+}
+
+impl Backoff {
+    #[inline]
+    pub fn new() -> Backoff {
+        Backoff::with_spin_rounds(YIELD_ROUNDS)
+    }
+
+    /// Like `new`, but starts to sleep after `spin_rounds` rounds (of
+    /// which at most the first `SPIN_ROUNDS` spin, and the rest yield);
+    /// with zero, right away. See `Configuration::set_spin_iterations`.
+    #[inline]
+    pub fn with_spin_rounds(spin_rounds: u32) -> Backoff {
+        Backoff {
+            rounds: 0,
+            yield_rounds: spin_rounds,
+        }
+    }
+
+    /// Start from scratch, e.g. because the caller found some work to
+    /// do in between polls.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.rounds = 0;
+    }
+
+    /// True once we are done spinning and yielding, and `snooze`
+    /// would start to sleep. A caller that has some better means of
+    /// blocking can switch to that at this point.
+    #[inline]
+    pub fn is_completed(&self) -> bool {
+        self.rounds >= self.yield_rounds
+    }
+
+    /// Wait a bit before the caller polls again.
+    pub fn snooze(&mut self) {
+        if self.rounds < cmp::min(SPIN_ROUNDS, self.yield_rounds) {
+            // just spin
+        } else if self.rounds < self.yield_rounds {
+            thread::yield_now();
+        } else {
+            let shift = cmp::min(self.rounds - self.yield_rounds, 10);
+            let micros = cmp::min(1 << shift, MAX_SLEEP_MICROS);
+            thread::sleep(Duration::new(0, (micros * 1000) as u32));
+        }
+        self.rounds = self.rounds.saturating_add(1);
+    }
+}
@@ -2,6 +2,8 @@
 #![cfg_attr(test, feature(conservative_impl_trait))]
 
 extern crate deque;
+#[cfg(feature = "unstable")]
+extern crate futures;
 extern crate libc;
 extern crate num_cpus;
 extern crate rand;
@@ -10,23 +12,79 @@ extern crate rand;
 mod log;
 
 mod api;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod context;
+#[cfg(feature = "unstable")]
+mod future;
 mod latch;
 mod job;
 pub mod par_iter;
+mod par_sort;
 pub mod prelude;
 #[cfg(test)]
 mod test;
-#[cfg(feature = "unstable")]
+#[cfg(feature = "log")]
+mod schedule;
 mod scope;
+mod scratch;
+mod spawn;
+mod split;
+mod stack;
 mod thread_pool;
+mod tile;
 mod unwind;
 mod util;
+#[cfg(feature = "unstable")]
+mod worker;
 
 pub use api::Configuration;
+pub use api::CoreId;
+pub use api::PanicHandler;
+pub use api::{StartHandler, ExitHandler, DeadlockHandler};
+pub use api::{JobStartHandler, JobEndHandler};
 pub use api::InitError;
+pub use api::PoolError;
+pub use api::QueuePolicy;
+pub use api::current_num_threads;
+pub use api::current_thread_index;
+pub use api::is_worker_thread;
+pub use api::yield_now;
+pub use api::mark_blocking;
+pub use api::wait_until;
 pub use api::dump_stats;
+#[cfg(feature = "log")]
+pub use log::dump_to as dump_log_to;
+#[cfg(feature = "log")]
+pub use schedule::{JobKey, StealMode, StealSchedule};
 pub use api::initialize;
 pub use api::join;
+pub use api::{join_context, FnContext};
+pub use api::join_catch;
+pub use api::join_panic_origin;
+pub use unwind::PanicOrigin;
+pub use api::join_try;
+pub use api::{join_cancellable, CancelToken};
+pub use api::join_weighted;
+pub use api::join_local;
+pub use api::join_n;
+pub use api::{join3, join4};
+pub use api::join_named;
+pub use api::join_no_steal;
+pub use api::try_join;
 pub use api::ThreadPool;
+pub use thread_pool::DonatedWorker;
+pub use par_sort::ParallelSliceSort;
+pub use api::WorkerStats;
+pub use context::{with_context, current_context};
+#[cfg(feature = "unstable")]
+pub use future::{spawn_future, RayonFuture};
+pub use scope::{scope, scope_collect_panics, Scope};
+pub use scratch::{with_scratch, Scratch};
+pub use spawn::{spawn, spawn_handle, push_job, PendingResult, ScopeHandle};
+pub use job::{Job, JobMode, JobRef};
+pub use latch::{CountLatch, Latch, LockLatch, SpinLatch};
+pub use split::{split, Splitter};
+pub use tile::for_each_tile;
 #[cfg(feature = "unstable")]
-pub use scope::{scope, Scope};
+pub use worker::{with_worker, CurrentWorker};
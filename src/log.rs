@@ -1,26 +1,281 @@
 //! Debug Logging
+//!
+//! The `log!` macro records scheduling events. It expands to nothing
+//! (and its argument is never evaluated) unless the `log` feature is
+//! enabled, in which case each thread appends its events to a bounded
+//! ring buffer of its own. The buffers can be printed with `dump()`,
+//! which `rayon::dump_stats()` invokes, or written to a file with
+//! `dump_to()` (exported as `rayon::dump_log_to()`).
+//!
+//! Since some kinds of events are very frequent, the `RAYON_LOG`
+//! environment variable can restrict logging to a comma-separated list
+//! of kinds, named like the variants of `Event` (e.g.,
+//! `RAYON_LOG=Join,StoleWork`). It is read when the first event is
+//! logged; if it is unset or empty, everything is logged. Checking
+//! whether an event is filtered out takes one relaxed atomic load.
 
+#[cfg(feature = "log")]
+use std::collections::VecDeque;
+#[cfg(feature = "log")]
+use std::env;
 use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+#[cfg(feature = "log")]
+use std::sync::atomic::Ordering;
+#[cfg(feature = "log")]
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+#[cfg(feature = "log")]
+use std::fs::File;
+#[cfg(feature = "log")]
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "log")]
+use std::path::Path;
+#[cfg(feature = "log")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "log")]
+use util::leak;
 
 #[derive(Debug)]
+#[cfg_attr(not(feature = "log"), allow(dead_code))]
 pub enum Event {
-    StartWorking { index: usize },
     InjectJobs { count: usize },
-    WaitForWork { worker: usize, was_active: bool },
+    Sleep { worker: usize },
+    Awake { worker: usize },
     StoleWork { worker: usize },
     Join { worker: usize },
     PoppedJob { worker: usize },
     LostJob { worker: usize },
+    Deadlock { worker: usize },
+    AbandonedJob { worker: usize },
+    SpanEnter { worker: usize, name: &'static str, oper: &'static str },
+    SpanExit { worker: usize, name: &'static str, oper: &'static str },
 }
 
-pub const DUMP_LOGS: bool = false;
+/// The names of the kinds of events, for `RAYON_LOG`, indexed by
+/// `Event::kind`.
+#[cfg(feature = "log")]
+const KIND_NAMES: [&'static str; 11] = ["InjectJobs", "Sleep", "Awake", "StoleWork", "Join",
+                                         "PoppedJob", "LostJob", "Deadlock", "AbandonedJob",
+                                         "SpanEnter", "SpanExit"];
 
+#[cfg(feature = "log")]
+impl Event {
+    #[inline]
+    fn kind(&self) -> usize {
+        match *self {
+            Event::InjectJobs { .. } => 0,
+            Event::Sleep { .. } => 1,
+            Event::Awake { .. } => 2,
+            Event::StoleWork { .. } => 3,
+            Event::Join { .. } => 4,
+            Event::PoppedJob { .. } => 5,
+            Event::LostJob { .. } => 6,
+            Event::Deadlock { .. } => 7,
+            Event::AbandonedJob { .. } => 8,
+            Event::SpanEnter { .. } => 9,
+            Event::SpanExit { .. } => 10,
+        }
+    }
+}
+
+#[cfg(feature = "log")]
 macro_rules! log {
     ($event:expr) => {
-        if ::log::DUMP_LOGS { println!("{:?}", $event); }
+        {
+            let event = $event;
+            if ::log::enabled(&event) {
+                ::log::record(event);
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log {
+    ($event:expr) => {}
+}
+
+/// Number of events retained per thread. Once a thread's buffer is
+/// full, its oldest events are discarded.
+#[cfg(feature = "log")]
+pub const LOG_CAPACITY: usize = 4096;
+
+/// An event along with the time it was recorded, measured from the
+/// moment the first event was recorded by any thread.
+#[cfg(feature = "log")]
+pub struct Record {
+    pub time: Duration,
+    pub event: Event,
+}
+
+#[cfg(feature = "log")]
+type EventBuffer = Arc<Mutex<VecDeque<Record>>>;
+
+#[cfg(feature = "log")]
+struct Logs {
+    start: Instant,
+
+    /// The buffers of all threads that have ever recorded an event. A
+    /// buffer outlives its thread, so events from workers of a dropped
+    /// pool can still be dumped.
+    buffers: Mutex<Vec<EventBuffer>>,
+}
+
+#[cfg(feature = "log")]
+static mut THE_LOGS: Option<&'static Logs> = None;
+#[cfg(feature = "log")]
+static THE_LOGS_SET: Once = ONCE_INIT;
+
+#[cfg(feature = "log")]
+fn logs() -> &'static Logs {
+    THE_LOGS_SET.call_once(|| unsafe {
+        THE_LOGS = Some(leak(Logs {
+            start: Instant::now(),
+            buffers: Mutex::new(Vec::new()),
+        }))
+    });
+    unsafe { THE_LOGS.unwrap() }
+}
+
+#[cfg(feature = "log")]
+thread_local! {
+    static THREAD_BUFFER: EventBuffer = {
+        let buffer = Arc::new(Mutex::new(VecDeque::with_capacity(LOG_CAPACITY)));
+        logs().buffers.lock().unwrap().push(buffer.clone());
+        buffer
+    }
+}
+
+/// One bit per kind of event that is logged, plus `FILTER_READ`; zero
+/// until `RAYON_LOG` has been read.
+#[cfg(feature = "log")]
+static FILTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+#[cfg(feature = "log")]
+const FILTER_READ: usize = 1 << 31;
+
+/// True if events of the kind of `event` are to be logged.
+#[cfg(feature = "log")]
+#[inline]
+pub fn enabled(event: &Event) -> bool {
+    let mut filter = FILTER.load(Ordering::Relaxed);
+    if filter == 0 {
+        filter = read_filter();
     }
+    filter & (1 << event.kind()) != 0
 }
 
+#[cfg(feature = "log")]
+#[cold]
+fn read_filter() -> usize {
+    let filter = parse_filter(&env::var("RAYON_LOG").unwrap_or(String::new()));
+
+    // unless `set_filter` got there first
+    FILTER.compare_and_swap(0, filter, Ordering::Relaxed);
+    FILTER.load(Ordering::Relaxed)
+}
+
+/// Restricts logging to the kinds of events listed in `spec`, in the
+/// format of `RAYON_LOG`, overriding the variable.
+#[cfg(feature = "log")]
+pub fn set_filter(spec: &str) {
+    FILTER.store(parse_filter(spec), Ordering::Relaxed);
+}
+
+#[cfg(feature = "log")]
+fn parse_filter(spec: &str) -> usize {
+    if spec.trim().is_empty() {
+        return FILTER_READ | ((1 << KIND_NAMES.len()) - 1);
+    }
+    let mut filter = FILTER_READ;
+    for name in spec.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        match KIND_NAMES.iter().position(|&kind| kind == name) {
+            Some(kind) => filter |= 1 << kind,
+            None => {
+                let _ = writeln!(io::stderr(), "rayon: unknown kind of event in RAYON_LOG: {}", name);
+            }
+        }
+    }
+    filter
+}
+
+/// Appends `event` to the current thread's buffer.
+#[cfg(feature = "log")]
+pub fn record(event: Event) {
+    let logs = logs();
+    let time = Instant::now().duration_since(logs.start);
+    THREAD_BUFFER.with(|buffer| {
+        let mut buffer = buffer.lock().unwrap();
+        if buffer.len() == LOG_CAPACITY {
+            buffer.pop_front();
+        }
+        buffer.push_back(Record {
+            time: time,
+            event: event,
+        });
+    });
+}
+
+/// Removes the recorded events from all buffers and returns them,
+/// grouped by thread.
+#[cfg(feature = "log")]
+pub fn drain() -> Vec<Vec<Record>> {
+    logs()
+        .buffers
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|buffer| buffer.lock().unwrap().drain(..).collect())
+        .collect()
+}
+
+/// Removes the recorded events from all buffers and merges them into
+/// a single sequence, ordered by time. Each record is paired with the
+/// index of the thread that recorded it (in order of first use, so
+/// this is not necessarily the worker index).
+#[cfg(feature = "log")]
+fn drain_merged() -> Vec<(usize, Record)> {
+    let mut records: Vec<_> = drain()
+        .into_iter()
+        .enumerate()
+        .flat_map(|(thread, records)| records.into_iter().map(move |r| (thread, r)))
+        .collect();
+    records.sort_by_key(|&(_, ref record)| record.time);
+    records
+}
+
+#[cfg(feature = "log")]
+fn write_record<W: Write>(out: &mut W, thread: usize, record: &Record) -> io::Result<()> {
+    let nanos = record.time.as_secs() * 1_000_000_000 + record.time.subsec_nanos() as u64;
+    writeln!(out, "{}\t{}\t{:?}", nanos, thread, record.event)
+}
+
+/// Prints (and removes) the recorded events using `println`.
+#[cfg(feature = "log")]
+pub fn dump() {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for (thread, record) in drain_merged() {
+        let _ = write_record(&mut stdout, thread, &record);
+    }
+}
+
+/// Writes (and removes) the events recorded by all threads into the
+/// file at `path`, one per line, ordered by time. Each line consists
+/// of the time in nanoseconds since the first recorded event, the
+/// index of the recording thread, and the event itself, separated by
+/// tabs.
+#[cfg(feature = "log")]
+pub fn dump_to(path: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(try!(File::create(path)));
+    for (thread, record) in drain_merged() {
+        try!(write_record(&mut file, thread, &record));
+    }
+    file.flush()
+}
+
+#[cfg(not(feature = "log"))]
+pub fn dump() {}
+
 pub static STOLEN_JOB: AtomicUsize = ATOMIC_USIZE_INIT;
 
 macro_rules! stat_stolen {
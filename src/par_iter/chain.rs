@@ -172,6 +172,10 @@ impl<A, B> Producer for ChainProducer<A, B>
         self.a.cost(a_len) + self.b.cost(b_len)
     }
 
+    fn should_split(&self, len: usize) -> Option<bool> {
+        self.a.should_split(len).or_else(|| self.b.should_split(len))
+    }
+
     fn split_at(self, index: usize) -> (Self, Self) {
         if index <= self.a_len {
             let a_rem = self.a_len - index;
@@ -1,4 +1,5 @@
 use super::{ParallelIterator, ExactParallelIterator, IntoParallelIterator};
+use super::extend::ParallelExtend;
 use super::from_par_iter::FromParallelIterator;
 use std::collections::LinkedList;
 use std::slice;
@@ -12,12 +13,13 @@ pub fn collect_into<PAR_ITER, T>(mut pi: PAR_ITER, v: &mut Vec<T>)
     where PAR_ITER: ExactParallelIterator<Item = T>,
           T: Send
 {
+    v.truncate(0); // clear any old data
     let mut collect = Collect::new(v, pi.len());
     pi.drive(collect.as_consumer());
     collect.complete();
 }
 
-/// Collects the results of the iterator into the specified vector.
+/// Appends the results of the iterator to the specified vector.
 ///
 /// Technically, this only works for `ExactParallelIterator`, but we're faking a
 /// bit of specialization here until Rust can do that natively.  Callers are
@@ -28,7 +30,7 @@ pub fn collect_into<PAR_ITER, T>(mut pi: PAR_ITER, v: &mut Vec<T>)
 /// *any* `ParallelIterator` here, and `CollectConsumer` has to also implement
 /// `UnindexedConsumer`.  That implementation panics `unreachable!` in case
 /// there's a bug where we actually do try to use this unindexed.
-fn special_collect_into<PAR_ITER, T>(pi: PAR_ITER, len: usize, v: &mut Vec<T>)
+fn special_extend<PAR_ITER, T>(pi: PAR_ITER, len: usize, v: &mut Vec<T>)
     where PAR_ITER: ParallelIterator<Item = T>,
           T: Send
 {
@@ -38,7 +40,8 @@ fn special_collect_into<PAR_ITER, T>(pi: PAR_ITER, len: usize, v: &mut Vec<T>)
 }
 
 
-/// Manage the collection vector.
+/// Manage the collection vector. The new items are written after the
+/// ones already in it.
 struct Collect<'c, T: Send + 'c> {
     writes: AtomicUsize,
     vec: &'c mut Vec<T>,
@@ -47,7 +50,6 @@ struct Collect<'c, T: Send + 'c> {
 
 impl<'c, T: Send + 'c> Collect<'c, T> {
     fn new(vec: &'c mut Vec<T>, len: usize) -> Self {
-        vec.truncate(0); // clear any old data
         vec.reserve(len); // reserve enough space
 
         Collect {
@@ -57,10 +59,13 @@ impl<'c, T: Send + 'c> Collect<'c, T> {
         }
     }
 
-    /// Create a consumer on a slice of our memory.
+    /// Create a consumer on the spare capacity of our vector.
     fn as_consumer(&mut self) -> CollectConsumer<T> {
-        // Get a correct borrow, then extend it to the original length.
-        let mut slice = self.vec.as_mut_slice();
+        // Get a correct borrow, then move it past the existing items and
+        // extend it to the number of new ones. `reserve` made sure that
+        // there is room for them.
+        let start = self.vec.len();
+        let mut slice = &mut self.vec[start..];
         slice = unsafe { slice::from_raw_parts_mut(slice.as_mut_ptr(), self.len) };
         CollectConsumer::new(&self.writes, slice)
     }
@@ -68,19 +73,20 @@ impl<'c, T: Send + 'c> Collect<'c, T> {
     /// Update the final vector length.
     fn complete(mut self) {
         unsafe {
-            // Here, we assert that `v` is fully initialized. This is
-            // checked by the following assert, which counts how many
-            // total writes occurred. Since we know that the consumer
-            // cannot have escaped from `drive` (by parametricity,
-            // essentially), we know that any stores that will happen,
-            // have happened. Unless some code is buggy, that means we
-            // should have seen `len` total writes.
+            // Here, we assert that the new part of `v` is fully
+            // initialized. This is checked by the following assert,
+            // which counts how many total writes occurred. Since we
+            // know that the consumer cannot have escaped from `drive`
+            // (by parametricity, essentially), we know that any stores
+            // that will happen, have happened. Unless some code is
+            // buggy, that means we should have seen `len` total writes.
             let actual_writes = self.writes.load(Ordering::Relaxed);
             assert!(actual_writes == self.len,
                     "expected {} total writes, but got {}",
                     self.len,
                     actual_writes);
-            self.vec.set_len(self.len);
+            let new_len = self.vec.len() + self.len;
+            self.vec.set_len(new_len);
         }
     }
 }
@@ -92,17 +98,29 @@ impl<T> FromParallelIterator<T> for Vec<T>
 {
     fn from_par_iter<PAR_ITER>(par_iter: PAR_ITER) -> Self
         where PAR_ITER: IntoParallelIterator<Item = T>
+    {
+        let mut vec = Vec::new();
+        vec.par_extend(par_iter);
+        vec
+    }
+}
+
+/// Extend a vector with items from a parallel iterator.
+impl<T> ParallelExtend<T> for Vec<T>
+    where T: Send
+{
+    fn par_extend<PAR_ITER>(&mut self, par_iter: PAR_ITER)
+        where PAR_ITER: IntoParallelIterator<Item = T>
     {
         // See the vec_collect benchmarks in rayon-demo for different strategies.
         let mut par_iter = par_iter.into_par_iter();
         match par_iter.opt_len() {
             Some(len) => {
-                // When Rust gets specialization, call `par_iter.collect_into()`
-                // for exact iterators.  Until then, `special_collect_into()` fakes
-                // the same thing on the promise that `opt_len()` is accurate.
-                let mut vec = vec![];
-                super::collect::special_collect_into(par_iter, len, &mut vec);
-                vec
+                // When Rust gets specialization, write the items of exact
+                // iterators in place with `drive()`.  Until then,
+                // `special_extend()` fakes the same thing on the promise
+                // that `opt_len()` is accurate.
+                special_extend(par_iter, len, self);
             }
             None => {
                 // This works like `combine`, but `Vec::append` is more efficient than `extend`.
@@ -112,13 +130,10 @@ impl<T> FromParallelIterator<T> for Vec<T>
                     })
                     .collect();
 
-                let len = list.iter().map(Vec::len).sum();
-                let start = Vec::with_capacity(len);
-                list.into_iter()
-                    .fold(start, |mut vec, mut sub| {
-                        vec.append(&mut sub);
-                        vec
-                    })
+                self.reserve(list.iter().map(Vec::len).sum());
+                for mut vec in list {
+                    self.append(&mut vec);
+                }
             }
         }
     }
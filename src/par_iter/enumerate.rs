@@ -97,6 +97,10 @@ impl<P> Producer for EnumerateProducer<P>
         self.base.cost(items) // enumerating is basically free
     }
 
+    fn should_split(&self, len: usize) -> Option<bool> {
+        self.base.should_split(len)
+    }
+
     fn split_at(self, index: usize) -> (Self, Self) {
         let (left, right) = self.base.split_at(index);
         (EnumerateProducer {
@@ -0,0 +1,12 @@
+use super::IntoParallelIterator;
+
+/// `ParallelExtend` extends an existing collection with items from a
+/// parallel iterator, the same way `Extend` does with a sequential one.
+pub trait ParallelExtend<ITEM>
+    where ITEM: Send
+{
+    fn par_extend<PAR_ITER>(&mut self, par_iter: PAR_ITER)
+        where PAR_ITER: IntoParallelIterator<Item = ITEM>;
+}
+
+// See the `collect` module for the `Vec<T>` implementation.
@@ -0,0 +1,165 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::*;
+use super::len::*;
+use super::internal::*;
+
+/// `find_first` needs to know where each piece of the iterator is with
+/// respect to the others, but an unindexed consumer does not learn the
+/// indices of its items. So instead, each consumer owns a range of
+/// abstract positions, starting with all of `0..usize::MAX`: splitting
+/// a consumer gives the left half of its range to the left piece and
+/// the right half to the right one (and `split_off` carves the new,
+/// left piece off the front of our range). So the ranges of two pieces
+/// are ordered just like the items they see.
+///
+/// A folder that finds a match records the start of its range in
+/// `best_found` (if that is lower than what is there) and stops, since
+/// anything after its first match is further to the right. Any piece
+/// whose range starts after `best_found` can stop as well, but a piece
+/// to the left of it must keep looking, since it may still find an
+/// earlier match. Each piece returns the match it found, if any, and
+/// the reduction prefers the left one, so we end up with the leftmost.
+///
+/// After about 64 nested splits, ranges shrink to nothing and pieces
+/// start to share positions. That only weakens the cancellation: a
+/// piece is only ever cut short by a match at a position strictly
+/// lower than its own, which must be to its left.
+pub fn find_first<PAR_ITER, FIND_OP>(pi: PAR_ITER, find_op: FIND_OP) -> Option<PAR_ITER::Item>
+    where PAR_ITER: ParallelIterator,
+          FIND_OP: Fn(&PAR_ITER::Item) -> bool + Sync
+{
+    let best_found = AtomicUsize::new(usize::MAX);
+    let consumer = FindFirstConsumer::new(&find_op, 0, usize::MAX, &best_found);
+    pi.drive_unindexed(consumer)
+}
+
+struct FindFirstConsumer<'f, FIND_OP: 'f> {
+    find_op: &'f FIND_OP,
+
+    /// Our range of positions; `split_off` moves the lower bound up.
+    lower_bound: Cell<usize>,
+    upper_bound: usize,
+
+    /// The lowest position at which a match was found so far.
+    best_found: &'f AtomicUsize,
+}
+
+impl<'f, FIND_OP> FindFirstConsumer<'f, FIND_OP> {
+    fn new(find_op: &'f FIND_OP,
+           lower_bound: usize,
+           upper_bound: usize,
+           best_found: &'f AtomicUsize)
+           -> Self {
+        FindFirstConsumer {
+            find_op: find_op,
+            lower_bound: Cell::new(lower_bound),
+            upper_bound: upper_bound,
+            best_found: best_found,
+        }
+    }
+
+    /// The middle of our range, where it splits.
+    fn median(&self) -> usize {
+        let lower_bound = self.lower_bound.get();
+        lower_bound + (self.upper_bound - lower_bound) / 2
+    }
+}
+
+impl<'f, ITEM, FIND_OP: 'f> Consumer<ITEM> for FindFirstConsumer<'f, FIND_OP>
+    where ITEM: Send,
+          FIND_OP: Fn(&ITEM) -> bool + Sync
+{
+    type Folder = FindFirstFolder<'f, ITEM, FIND_OP>;
+    type Reducer = FindFirstReducer;
+    type Result = Option<ITEM>;
+
+    fn cost(&mut self, cost: f64) -> f64 {
+        // As with `find_any`, this underestimates the reductions.
+        cost * FUNC_ADJUSTMENT
+    }
+
+    fn split_at(self, _index: usize) -> (Self, Self, Self::Reducer) {
+        let median = self.median();
+        (FindFirstConsumer::new(self.find_op, self.lower_bound.get(), median, self.best_found),
+         FindFirstConsumer::new(self.find_op, median, self.upper_bound, self.best_found),
+         FindFirstReducer)
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        FindFirstFolder {
+            find_op: self.find_op,
+            boundary: self.lower_bound.get(),
+            best_found: self.best_found,
+            item: None,
+        }
+    }
+
+    fn full(&self) -> bool {
+        // only a match strictly to our left makes us redundant
+        self.best_found.load(Ordering::Relaxed) < self.lower_bound.get()
+    }
+}
+
+impl<'f, ITEM, FIND_OP: 'f> UnindexedConsumer<ITEM> for FindFirstConsumer<'f, FIND_OP>
+    where ITEM: Send,
+          FIND_OP: Fn(&ITEM) -> bool + Sync
+{
+    fn split_off(&self) -> Self {
+        let (lower_bound, median) = (self.lower_bound.get(), self.median());
+        self.lower_bound.set(median);
+        FindFirstConsumer::new(self.find_op, lower_bound, median, self.best_found)
+    }
+
+    fn to_reducer(&self) -> Self::Reducer {
+        FindFirstReducer
+    }
+}
+
+struct FindFirstFolder<'f, ITEM, FIND_OP: 'f> {
+    find_op: &'f FIND_OP,
+
+    /// The start of the range of the consumer we came from.
+    boundary: usize,
+    best_found: &'f AtomicUsize,
+    item: Option<ITEM>,
+}
+
+impl<'f, ITEM, FIND_OP> Folder<ITEM> for FindFirstFolder<'f, ITEM, FIND_OP>
+    where FIND_OP: Fn(&ITEM) -> bool + 'f
+{
+    type Result = Option<ITEM>;
+
+    fn consume(mut self, item: ITEM) -> Self {
+        if (self.find_op)(&item) {
+            // lower `best_found` to our boundary, unless it is lower
+            let mut best = self.best_found.load(Ordering::Relaxed);
+            while self.boundary < best {
+                let previous = self.best_found.compare_and_swap(best, self.boundary,
+                                                                Ordering::Relaxed);
+                if previous == best {
+                    break;
+                }
+                best = previous;
+            }
+            self.item = Some(item);
+        }
+        self
+    }
+
+    fn complete(self) -> Self::Result {
+        self.item
+    }
+
+    fn full(&self) -> bool {
+        self.item.is_some() || self.best_found.load(Ordering::Relaxed) < self.boundary
+    }
+}
+
+struct FindFirstReducer;
+
+impl<ITEM> Reducer<Option<ITEM>> for FindFirstReducer {
+    fn reduce(self, left: Option<ITEM>, right: Option<ITEM>) -> Option<ITEM> {
+        left.or(right)
+    }
+}
@@ -2,6 +2,8 @@ use super::ParallelIterator;
 use super::len::*;
 use super::internal::*;
 use super::noop::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 pub fn for_each<PAR_ITER, OP, T>(pi: PAR_ITER, op: &OP)
     where PAR_ITER: ParallelIterator<Item = T>,
@@ -61,3 +63,108 @@ impl<'f, OP, ITEM> UnindexedConsumer<ITEM> for ForEachConsumer<'f, OP>
         NoopReducer
     }
 }
+
+pub fn for_each_until<PAR_ITER, OP, T>(pi: PAR_ITER, deadline: Instant, op: &OP) -> usize
+    where PAR_ITER: ParallelIterator<Item = T>,
+          OP: Fn(T) + Sync,
+          T: Send
+{
+    let expired = AtomicBool::new(false);
+    let consumer = ForEachUntilConsumer {
+        op: op,
+        deadline: deadline,
+        expired: &expired,
+    };
+    pi.drive_unindexed(consumer)
+}
+
+struct ForEachUntilConsumer<'f, OP: 'f> {
+    op: &'f OP,
+    deadline: Instant,
+
+    /// Set by the first chunk to see that the deadline has passed, so
+    /// that the others don't need to look at the clock.
+    expired: &'f AtomicBool,
+}
+
+impl<'f, OP, ITEM> Consumer<ITEM> for ForEachUntilConsumer<'f, OP>
+    where OP: Fn(ITEM) + Sync
+{
+    type Folder = ForEachUntilFolder<'f, OP>;
+    type Reducer = CountReducer;
+    type Result = usize;
+
+    fn cost(&mut self, cost: f64) -> f64 {
+        cost * FUNC_ADJUSTMENT
+    }
+
+    fn split_at(self, _index: usize) -> (Self, Self, CountReducer) {
+        (self.split_off(), self, CountReducer)
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        ForEachUntilFolder {
+            op: self.op,
+            count: 0,
+        }
+    }
+
+    /// Checked before each chunk is split or started, but never while
+    /// a chunk is being folded, so this is the only place that reads
+    /// the clock.
+    fn full(&self) -> bool {
+        if self.expired.load(Ordering::Relaxed) {
+            true
+        } else if Instant::now() >= self.deadline {
+            self.expired.store(true, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<'f, OP, ITEM> UnindexedConsumer<ITEM> for ForEachUntilConsumer<'f, OP>
+    where OP: Fn(ITEM) + Sync
+{
+    fn split_off(&self) -> Self {
+        ForEachUntilConsumer {
+            op: self.op,
+            deadline: self.deadline,
+            expired: self.expired,
+        }
+    }
+
+    fn to_reducer(&self) -> CountReducer {
+        CountReducer
+    }
+}
+
+struct ForEachUntilFolder<'f, OP: 'f> {
+    op: &'f OP,
+    count: usize,
+}
+
+impl<'f, OP, ITEM> Folder<ITEM> for ForEachUntilFolder<'f, OP>
+    where OP: Fn(ITEM) + Sync
+{
+    type Result = usize;
+
+    fn consume(mut self, item: ITEM) -> Self {
+        (self.op)(item);
+        self.count += 1;
+        self
+    }
+
+    fn complete(self) -> usize {
+        self.count
+    }
+}
+
+struct CountReducer;
+
+impl Reducer<usize> for CountReducer {
+    fn reduce(self, left: usize, right: usize) -> usize {
+        left + right
+    }
+}
@@ -6,7 +6,8 @@
 use join;
 use super::IndexedParallelIterator;
 use super::len::*;
-use thread_pool::get_registry;
+use current_num_threads;
+use api::current_split_threshold;
 
 pub trait ProducerCallback<ITEM> {
     type Output;
@@ -25,6 +26,13 @@ pub trait Producer: IntoIterator + Send + Sized {
     /// Cost to produce `len` items, where `len` must be `N`.
     fn cost(&mut self, len: usize) -> f64;
 
+    /// Whether to split a producer of `len` items, where `len` must be
+    /// `N`, if it has a cost model of its own (see `split_when`); `None`
+    /// leaves the decision to the usual splitting policy.
+    fn should_split(&self, _len: usize) -> Option<bool> {
+        None
+    }
+
     /// Split into two producers; one produces items `0..index`, the
     /// other `index..N`. Index must be less than `N`.
     fn split_at(self, index: usize) -> (Self, Self);
@@ -120,7 +128,7 @@ impl Splitter {
 
     #[inline]
     fn new_thief() -> Splitter {
-        Splitter::Thief(Splitter::thief_id(), get_registry().num_threads())
+        Splitter::Thief(Splitter::thief_id(), current_num_threads())
     }
 
     #[inline]
@@ -139,7 +147,7 @@ impl Splitter {
                 let id = Splitter::thief_id();
                 if *origin != id {
                     *origin = id;
-                    *splits = get_registry().num_threads();
+                    *splits = current_num_threads();
                     true
                 } else if *splits > 0 {
                     *splits /= 2;
@@ -190,21 +198,30 @@ pub fn bridge_producer_consumer<P, C>(len: usize, mut producer: P, mut consumer:
     } else {
         Splitter::new_thief()
     };
-    return helper(len, splitter, producer, consumer);
+    // the pool's sequential cutoff, unless the producer decides itself
+    let threshold = current_split_threshold();
+    return helper(len, splitter, threshold, producer, consumer);
 
-    fn helper<P, C>(len: usize, mut splitter: Splitter, producer: P, consumer: C) -> C::Result
+    fn helper<P, C>(len: usize,
+                    mut splitter: Splitter,
+                    threshold: usize,
+                    producer: P,
+                    consumer: C)
+                    -> C::Result
         where P: Producer,
               C: Consumer<P::Item>
     {
         if consumer.full() {
             consumer.into_folder().complete()
-        } else if len > 1 && splitter.try() {
+        } else if len > 1 &&
+                  producer.should_split(len)
+                      .unwrap_or_else(|| len > threshold && splitter.try()) {
             let mid = len / 2;
             let (left_producer, right_producer) = producer.split_at(mid);
             let (left_consumer, right_consumer, reducer) = consumer.split_at(mid);
             let (left_result, right_result) =
-                join(|| helper(mid, splitter, left_producer, left_consumer),
-                     || helper(len - mid, splitter, right_producer, right_consumer));
+                join(|| helper(mid, splitter, threshold, left_producer, left_consumer),
+                     || helper(len - mid, splitter, threshold, right_producer, right_consumer));
             reducer.reduce(left_result, right_result)
         } else {
             let mut folder = consumer.into_folder();
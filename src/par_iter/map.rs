@@ -157,6 +157,10 @@ impl<'m, P, MAP_OP> Producer for MapProducer<'m, P, MAP_OP>
         self.base.cost(len) * FUNC_ADJUSTMENT
     }
 
+    fn should_split(&self, len: usize) -> Option<bool> {
+        self.base.should_split(len)
+    }
+
     fn split_at(self, index: usize) -> (Self, Self) {
         let (left, right) = self.base.split_at(index);
         (MapProducer {
@@ -0,0 +1,301 @@
+use super::*;
+use super::len::*;
+use super::internal::*;
+
+/// `MapWith` is an iterator that transforms the elements of an
+/// underlying iterator, like `Map`, but lends `map_op` mutable access
+/// to a seed value. Each piece that the iterator is split into gets a
+/// clone of the seed of its own, so the seed need not be `Sync`.
+///
+/// This struct is created by the `map_with()` method on
+/// `ParallelIterator`.
+pub struct MapWith<M, S, MAP_OP> {
+    base: M,
+    seed: S,
+    map_op: MAP_OP,
+}
+
+impl<M, S, MAP_OP> MapWith<M, S, MAP_OP> {
+    pub fn new(base: M, seed: S, map_op: MAP_OP) -> MapWith<M, S, MAP_OP> {
+        MapWith {
+            base: base,
+            seed: seed,
+            map_op: map_op,
+        }
+    }
+}
+
+impl<M, S, MAP_OP, R> ParallelIterator for MapWith<M, S, MAP_OP>
+    where M: ParallelIterator,
+          S: Send + Clone,
+          MAP_OP: Fn(&mut S, M::Item) -> R + Sync,
+          R: Send
+{
+    type Item = R;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+        where C: UnindexedConsumer<Self::Item>
+    {
+        let consumer1 = MapWithConsumer::new(consumer, self.seed, &self.map_op);
+        self.base.drive_unindexed(consumer1)
+    }
+
+    fn opt_len(&mut self) -> Option<usize> {
+        self.base.opt_len()
+    }
+}
+
+impl<M, S, MAP_OP, R> BoundedParallelIterator for MapWith<M, S, MAP_OP>
+    where M: BoundedParallelIterator,
+          S: Send + Clone,
+          MAP_OP: Fn(&mut S, M::Item) -> R + Sync,
+          R: Send
+{
+    fn upper_bound(&mut self) -> usize {
+        self.base.upper_bound()
+    }
+
+    fn drive<C>(self, consumer: C) -> C::Result
+        where C: Consumer<Self::Item>
+    {
+        let consumer1 = MapWithConsumer::new(consumer, self.seed, &self.map_op);
+        self.base.drive(consumer1)
+    }
+}
+
+impl<M, S, MAP_OP, R> ExactParallelIterator for MapWith<M, S, MAP_OP>
+    where M: ExactParallelIterator,
+          S: Send + Clone,
+          MAP_OP: Fn(&mut S, M::Item) -> R + Sync,
+          R: Send
+{
+    fn len(&mut self) -> usize {
+        self.base.len()
+    }
+}
+
+impl<M, S, MAP_OP, R> IndexedParallelIterator for MapWith<M, S, MAP_OP>
+    where M: IndexedParallelIterator,
+          S: Send + Clone,
+          MAP_OP: Fn(&mut S, M::Item) -> R + Sync,
+          R: Send
+{
+    fn with_producer<CB>(self, callback: CB) -> CB::Output
+        where CB: ProducerCallback<Self::Item>
+    {
+        return self.base.with_producer(Callback {
+            callback: callback,
+            seed: self.seed,
+            map_op: self.map_op,
+        });
+
+        struct Callback<CB, S, MAP_OP> {
+            callback: CB,
+            seed: S,
+            map_op: MAP_OP,
+        }
+
+        impl<ITEM, S, MAP_OP, R, CB> ProducerCallback<ITEM> for Callback<CB, S, MAP_OP>
+            where S: Send + Clone,
+                  MAP_OP: Fn(&mut S, ITEM) -> R + Sync,
+                  R: Send,
+                  CB: ProducerCallback<R>
+        {
+            type Output = CB::Output;
+
+            fn callback<P>(self, base: P) -> CB::Output
+                where P: Producer<Item = ITEM>
+            {
+                let producer = MapWithProducer {
+                    base: base,
+                    seed: self.seed,
+                    map_op: &self.map_op,
+                };
+                self.callback.callback(producer)
+            }
+        }
+    }
+}
+
+/// ////////////////////////////////////////////////////////////////////////
+
+struct MapWithProducer<'m, P, S, MAP_OP: 'm> {
+    base: P,
+    seed: S,
+    map_op: &'m MAP_OP,
+}
+
+impl<'m, P, S, MAP_OP, R> Producer for MapWithProducer<'m, P, S, MAP_OP>
+    where P: Producer,
+          S: Send + Clone,
+          MAP_OP: Fn(&mut S, P::Item) -> R + Sync,
+          R: Send
+{
+    fn weighted(&self) -> bool {
+        self.base.weighted()
+    }
+
+    fn cost(&mut self, len: usize) -> f64 {
+        self.base.cost(len) * FUNC_ADJUSTMENT
+    }
+
+    fn should_split(&self, len: usize) -> Option<bool> {
+        self.base.should_split(len)
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let (left, right) = self.base.split_at(index);
+        (MapWithProducer {
+             base: left,
+             seed: self.seed.clone(),
+             map_op: self.map_op,
+         },
+         MapWithProducer {
+             base: right,
+             seed: self.seed,
+             map_op: self.map_op,
+         })
+    }
+}
+
+impl<'m, P, S, MAP_OP, R> IntoIterator for MapWithProducer<'m, P, S, MAP_OP>
+    where P: Producer,
+          S: Send + Clone,
+          MAP_OP: Fn(&mut S, P::Item) -> R + Sync,
+          R: Send
+{
+    type Item = R;
+    type IntoIter = MapWithIter<'m, P::IntoIter, S, MAP_OP>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        MapWithIter {
+            base: self.base.into_iter(),
+            seed: self.seed,
+            map_op: self.map_op,
+        }
+    }
+}
+
+struct MapWithIter<'m, I, S, MAP_OP: 'm> {
+    base: I,
+    seed: S,
+    map_op: &'m MAP_OP,
+}
+
+impl<'m, I, S, MAP_OP, R> Iterator for MapWithIter<'m, I, S, MAP_OP>
+    where I: Iterator,
+          MAP_OP: Fn(&mut S, I::Item) -> R + Sync
+{
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        match self.base.next() {
+            Some(value) => Some((self.map_op)(&mut self.seed, value)),
+            None => None,
+        }
+    }
+}
+
+/// ////////////////////////////////////////////////////////////////////////
+/// Consumer implementation
+
+struct MapWithConsumer<'m, C, S, MAP_OP: 'm> {
+    base: C,
+    seed: S,
+    map_op: &'m MAP_OP,
+}
+
+impl<'m, C, S, MAP_OP> MapWithConsumer<'m, C, S, MAP_OP> {
+    fn new(base: C, seed: S, map_op: &'m MAP_OP) -> Self {
+        MapWithConsumer {
+            base: base,
+            seed: seed,
+            map_op: map_op,
+        }
+    }
+}
+
+impl<'m, ITEM, C, S, MAP_OP, R> Consumer<ITEM> for MapWithConsumer<'m, C, S, MAP_OP>
+    where C: Consumer<R>,
+          S: Send + Clone,
+          MAP_OP: Fn(&mut S, ITEM) -> R + Sync,
+          R: Send
+{
+    type Folder = MapWithFolder<'m, C::Folder, S, MAP_OP>;
+    type Reducer = C::Reducer;
+    type Result = C::Result;
+
+    fn weighted(&self) -> bool {
+        self.base.weighted()
+    }
+
+    fn cost(&mut self, cost: f64) -> f64 {
+        self.base.cost(cost) * FUNC_ADJUSTMENT
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self, Self::Reducer) {
+        let (left, right, reducer) = self.base.split_at(index);
+        (MapWithConsumer::new(left, self.seed.clone(), self.map_op),
+         MapWithConsumer::new(right, self.seed, self.map_op),
+         reducer)
+    }
+
+    fn into_folder(self) -> Self::Folder {
+        MapWithFolder {
+            base: self.base.into_folder(),
+            seed: self.seed,
+            map_op: self.map_op,
+        }
+    }
+
+    fn full(&self) -> bool {
+        self.base.full()
+    }
+}
+
+impl<'m, ITEM, C, S, MAP_OP, R> UnindexedConsumer<ITEM> for MapWithConsumer<'m, C, S, MAP_OP>
+    where C: UnindexedConsumer<R>,
+          S: Send + Clone,
+          MAP_OP: Fn(&mut S, ITEM) -> R + Sync,
+          R: Send
+{
+    fn split_off(&self) -> Self {
+        MapWithConsumer::new(self.base.split_off(), self.seed.clone(), self.map_op)
+    }
+
+    fn to_reducer(&self) -> Self::Reducer {
+        self.base.to_reducer()
+    }
+}
+
+struct MapWithFolder<'m, C, S, MAP_OP: 'm> {
+    base: C,
+    seed: S,
+    map_op: &'m MAP_OP,
+}
+
+impl<'m, ITEM, C, S, MAP_OP, R> Folder<ITEM> for MapWithFolder<'m, C, S, MAP_OP>
+    where C: Folder<R>,
+          MAP_OP: Fn(&mut S, ITEM) -> R + Sync
+{
+    type Result = C::Result;
+
+    fn consume(mut self, item: ITEM) -> Self {
+        let mapped_item = (self.map_op)(&mut self.seed, item);
+        let base = self.base.consume(mapped_item);
+        MapWithFolder {
+            base: base,
+            seed: self.seed,
+            map_op: self.map_op,
+        }
+    }
+
+    /// The seed is dropped here, as soon as this piece is done.
+    fn complete(self) -> C::Result {
+        self.base.complete()
+    }
+
+    fn full(&self) -> bool {
+        self.base.full()
+    }
+}
@@ -14,6 +14,7 @@
 use std::f64;
 use std::cmp::{self, Ordering};
 use std::ops::Fn;
+use std::time::Instant;
 use self::chain::ChainIter;
 use self::collect::collect_into;
 use self::enumerate::Enumerate;
@@ -22,17 +23,21 @@ use self::filter_map::FilterMap;
 use self::flat_map::FlatMap;
 use self::from_par_iter::FromParallelIterator;
 use self::map::{Map, MapFn, MapCloned, MapInspect};
+use self::map_with::MapWith;
 use self::reduce::{reduce, ReduceOp, SumOp, ProductOp, ReduceWithIdentityOp, SUM, PRODUCT};
 use self::skip::Skip;
+use self::split_when::SplitWhen;
 use self::take::Take;
 use self::internal::*;
 use self::weight::Weight;
 use self::zip::ZipIter;
 
 pub mod find;
+pub mod find_first;
 pub mod chain;
 pub mod collect;
 pub mod enumerate;
+pub mod extend;
 pub mod filter;
 pub mod filter_map;
 pub mod flat_map;
@@ -43,11 +48,13 @@ pub mod for_each;
 pub mod fold;
 pub mod reduce;
 pub mod skip;
+pub mod split_when;
 pub mod take;
 pub mod slice;
 pub mod slice_mut;
 pub mod string;
 pub mod map;
+pub mod map_with;
 pub mod weight;
 pub mod zip;
 pub mod range;
@@ -113,7 +120,11 @@ pub trait ToParallelChunks<'data> {
     /// dictated here (e.g. a B-tree-like collection may by necessity
     /// produce many chunks with fewer than `size` elements), but an
     /// implementation should strive to maximize chunk size when
-    /// possible.
+    /// possible. For slices, every chunk has exactly `size` elements,
+    /// except for the last one, which may be shorter, no matter how the
+    /// iterator is split up.
+    ///
+    /// Panics if `size` is zero.
     fn par_chunks(&'data self, size: usize) -> Self::Iter;
 }
 
@@ -128,7 +139,11 @@ pub trait ToParallelChunksMut<'data> {
     /// dictated here (e.g. a B-tree-like collection may by necessity
     /// produce many chunks with fewer than `size` elements), but an
     /// implementation should strive to maximize chunk size when
-    /// possible.
+    /// possible. For slices, every chunk has exactly `size` elements,
+    /// except for the last one, which may be shorter, no matter how the
+    /// iterator is split up.
+    ///
+    /// Panics if `size` is zero.
     fn par_chunks_mut(&'data mut self, size: usize) -> Self::Iter;
 }
 
@@ -173,6 +188,36 @@ pub trait ParallelIterator: Sized {
         for_each::for_each(self, &op)
     }
 
+    /// Executes `OP` on each item produced by the iterator, in
+    /// parallel, until `deadline` passes, and returns the number of
+    /// items that `OP` was executed on.
+    ///
+    /// This is cooperative: the deadline is checked whenever the
+    /// iterator is about to be split, or a piece of it about to be
+    /// processed sequentially, but a piece that has been started is
+    /// always finished, so some items may still be processed after the
+    /// deadline. How big those pieces are depends on the splitting
+    /// policy; use `weight` (or `weight_max`) to make them smaller.
+    /// Items are not processed in order, so the ones that were
+    /// processed need not be a prefix of the iterator.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let deadline = Instant::now() + Duration::from_millis(16);
+    /// let done = (0..1000).into_par_iter()
+    ///                     .for_each_until(deadline, |i| { let _ = i * i; });
+    /// assert!(done <= 1000);
+    /// ```
+    fn for_each_until<OP>(self, deadline: Instant, op: OP) -> usize
+        where OP: Fn(Self::Item) + Sync
+    {
+        for_each::for_each_until(self, deadline, &op)
+    }
+
     /// Counts the number of items in this parallel iterator.
     fn count(self) -> usize {
         self.map(|_| 1).sum()
@@ -186,6 +231,36 @@ pub trait ParallelIterator: Sized {
         Map::new(self, MapFn(map_op))
     }
 
+    /// Applies `map_op` to the given `seed` value and each item of this
+    /// iterator, producing a new iterator with the results.
+    ///
+    /// The `seed` is cloned whenever the iterator is split into pieces
+    /// to be processed in parallel, so each piece gets a mutable copy of
+    /// its own, which is dropped as soon as that piece is done. This
+    /// means that the seed only needs to be `Send`, not `Sync`, which
+    /// makes it a good place for an RNG or a scratch buffer.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    /// let squares: Vec<_> = (0..10).into_par_iter()
+    ///                              .map_with(Vec::new(), |buf, i| {
+    ///                                  buf.clear();
+    ///                                  buf.push(i * i);
+    ///                                  buf[0]
+    ///                              })
+    ///                              .collect();
+    /// assert_eq!(squares, [0, 1, 4, 9, 16, 25, 36, 49, 64, 81]);
+    /// ```
+    fn map_with<MAP_OP, S, R>(self, seed: S, map_op: MAP_OP) -> MapWith<Self, S, MAP_OP>
+        where MAP_OP: Fn(&mut S, Self::Item) -> R + Sync,
+              S: Send + Clone,
+              R: Send
+    {
+        MapWith::new(self, seed, map_op)
+    }
+
     /// Creates an iterator which clones all of its elements.  This may be
     /// useful when you have an iterator over `&T`, but you need `T`.
     fn cloned<'a, T>(self) -> Map<Self, MapCloned>
@@ -258,6 +333,11 @@ pub trait ParallelIterator: Sized {
     /// will be non-deterministic. And of course `identity()` should
     /// produce a true identity.
     ///
+    /// `op` need not be commutative, however: its left argument
+    /// always comes from items that precede those of its right
+    /// argument, no matter how the work was split up or stolen. So,
+    /// for example, concatenating strings yields them in order.
+    ///
     /// [associative]: https://en.wikipedia.org/wiki/Associative_property
     fn reduce<OP, IDENTITY>(self, identity: IDENTITY, op: OP) -> Self::Item
         where OP: Fn(Self::Item, Self::Item) -> Self::Item + Sync,
@@ -278,6 +358,7 @@ pub trait ParallelIterator: Sized {
     /// which `op` will be applied to reduce the result is not fully
     /// specified. So `op` should be [associative] or else the results
     /// will be non-deterministic.
+    /// As with `reduce()`, `op` need not be commutative.
     ///
     /// [associative]: https://en.wikipedia.org/wiki/Associative_property
     fn reduce_with<OP>(self, op: OP) -> Option<Self::Item>
@@ -427,6 +508,14 @@ pub trait ParallelIterator: Sized {
     ///                .sum();
     /// assert_eq!(sum, (0..22).sum()); // compare to sequential
     /// ```
+    ///
+    /// The accumulator need not have the type of the items, and the
+    /// operation that combines accumulators need not be the one that
+    /// folds items into them, as the string example above shows. Each
+    /// group folds a contiguous run of items, in order, and `reduce`
+    /// only ever combines a group with the one right after it, so a
+    /// `combine` that is not commutative still sees the groups in
+    /// sequential order.
     fn fold<IDENTITY_ITEM, IDENTITY, FOLD_OP>(self,
                                               identity: IDENTITY,
                                               fold_op: FOLD_OP)
@@ -566,7 +655,10 @@ pub trait ParallelIterator: Sized {
     ///
     /// Once a match is found, we will attempt to stop processing
     /// the rest of the items in the iterator as soon as possible
-    /// (just as `find` stops iterating once a match is found).
+    /// (just as `find` stops iterating once a match is found): every
+    /// piece of the iterator checks a shared flag before it splits
+    /// further, and after each item it tests. The match itself is
+    /// handed back through the reduction, whichever thread found it.
     ///
     /// [find]: https://doc.rust-lang.org/std/iter/trait.Iterator.html#method.find
     fn find_any<FIND_OP>(self, predicate: FIND_OP) -> Option<Self::Item>
@@ -575,6 +667,32 @@ pub trait ParallelIterator: Sized {
         find::find(self, predicate)
     }
 
+    /// Searches for the **first** item in the parallel iterator that
+    /// matches the given predicate and returns it, just like `find` on
+    /// sequential iterators, even though the pieces of the iterator are
+    /// searched in parallel and finish in no particular order.
+    ///
+    /// Once a match is found, the search stops in every piece to the
+    /// right of it, but pieces to its left keep going, since they may
+    /// still find an earlier match. So this tends to examine more items
+    /// than `find_any`, and to take longer if the first match is near
+    /// the end.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rayon::prelude::*;
+    ///
+    /// let a = [1, 2, 3, 3];
+    /// assert_eq!(a.par_iter().find_first(|&&x| x == 3), Some(&a[2]));
+    /// assert_eq!(a.par_iter().find_first(|&&x| x > 3), None);
+    /// ```
+    fn find_first<FIND_OP>(self, predicate: FIND_OP) -> Option<Self::Item>
+        where FIND_OP: Fn(&Self::Item) -> bool + Sync
+    {
+        find_first::find_first(self, predicate)
+    }
+
     #[doc(hidden)]
     #[deprecated(note = "parallel `find` does not search in order -- use `find_any`")]
     fn find<FIND_OP>(self, predicate: FIND_OP) -> Option<Self::Item>
@@ -692,6 +810,18 @@ pub trait IndexedParallelIterator: ExactParallelIterator {
     /// Like the `zip` method on ordinary iterators, if the two
     /// iterators are of unequal length, you only get the items they
     /// have in common.
+    ///
+    /// Both iterators are split at the same points, so each piece of
+    /// work gets the items of the two that correspond to each other:
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    ///
+    /// let a = vec![1, 2, 3, 4];
+    /// let b = vec![10, 20, 30];
+    /// let dot: i32 = a.par_iter().zip(&b).map(|(x, y)| x * y).sum();
+    /// assert_eq!(dot, 1 * 10 + 2 * 20 + 3 * 30);
+    /// ```
     fn zip<ZIP_OP>(self, zip_op: ZIP_OP) -> ZipIter<Self, ZIP_OP::Iter>
         where ZIP_OP: IntoParallelIterator,
               ZIP_OP::Iter: IndexedParallelIterator
@@ -810,6 +940,35 @@ pub trait IndexedParallelIterator: ExactParallelIterator {
         Take::new(self, n)
     }
 
+    /// Hands the decision of how finely to divide this iterator to a
+    /// cost model of your own. Whenever a piece of `remaining_work`
+    /// items could be split in two, `should_split(remaining_work)` is
+    /// called to decide, at every level of the recursion; once it says
+    /// no, that piece is processed sequentially. This replaces the
+    /// adaptive splitting policy, any `weight`, and the default split
+    /// threshold of the pool (see `Configuration`). Pieces of a single
+    /// item are never split, and `should_split` may be called from many
+    /// threads at once, so it must be `Sync`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rayon::prelude::*;
+    ///
+    /// // split into pieces of at most 100 items, and no smaller
+    /// let v: Vec<u32> = (0..10_000).collect();
+    /// let sum = v.par_iter()
+    ///            .split_when(|remaining_work| remaining_work > 100)
+    ///            .map(|&x| x as u64)
+    ///            .sum();
+    /// assert_eq!(sum, 49_995_000);
+    /// ```
+    fn split_when<SPLIT_OP>(self, should_split: SPLIT_OP) -> SplitWhen<Self, SPLIT_OP>
+        where SPLIT_OP: Fn(usize) -> bool + Sync
+    {
+        SplitWhen::new(self, should_split)
+    }
+
     /// Searches for **some** item in the parallel iterator that
     /// matches the given predicate, and returns its index.  Like
     /// `ParallelIterator::find_any`, the parallel search will not
@@ -28,6 +28,7 @@ impl<'data, T: Sync + 'data> ToParallelChunks<'data> for [T] {
     type Iter = ChunksIter<'data, T>;
 
     fn par_chunks(&'data self, chunk_size: usize) -> Self::Iter {
+        assert!(chunk_size != 0, "chunk_size must not be zero");
         ChunksIter {
             chunk_size: chunk_size,
             slice: self,
@@ -159,6 +160,8 @@ impl<'data, T: 'data + Sync> Producer for SliceChunksProducer<'data, T> {
         len as f64
     }
 
+    /// `index` counts chunks, so the split always falls on a chunk
+    /// boundary, and only the last chunk of the slice can be short.
     fn split_at(self, index: usize) -> (Self, Self) {
         let elem_index = index * self.chunk_size;
         let (left, right) = self.slice.split_at(elem_index);
@@ -28,6 +28,7 @@ impl<'data, T: Send + 'data> ToParallelChunksMut<'data> for [T] {
     type Iter = ChunksMutIter<'data, T>;
 
     fn par_chunks_mut(&'data mut self, chunk_size: usize) -> Self::Iter {
+        assert!(chunk_size != 0, "chunk_size must not be zero");
         ChunksMutIter {
             chunk_size: chunk_size,
             slice: self,
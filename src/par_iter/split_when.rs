@@ -0,0 +1,146 @@
+use super::*;
+use super::internal::*;
+
+/// `SplitWhen` is an iterator that leaves the decision of whether to
+/// split any piece of the underlying iterator to a user-supplied cost
+/// model. `should_split` is called with the number of items of each
+/// piece that might be split, at every level of the recursion, and
+/// its answer replaces the adaptive splitting policy entirely.
+///
+/// This struct is created by the `split_when()` method on
+/// `IndexedParallelIterator`.
+pub struct SplitWhen<M, SPLIT_OP> {
+    base: M,
+    should_split: SPLIT_OP,
+}
+
+impl<M, SPLIT_OP> SplitWhen<M, SPLIT_OP> {
+    pub fn new(base: M, should_split: SPLIT_OP) -> SplitWhen<M, SPLIT_OP> {
+        SplitWhen {
+            base: base,
+            should_split: should_split,
+        }
+    }
+}
+
+impl<M, SPLIT_OP> ParallelIterator for SplitWhen<M, SPLIT_OP>
+    where M: IndexedParallelIterator,
+          SPLIT_OP: Fn(usize) -> bool + Sync
+{
+    type Item = M::Item;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+        where C: UnindexedConsumer<Self::Item>
+    {
+        bridge(self, consumer)
+    }
+
+    fn opt_len(&mut self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<M, SPLIT_OP> BoundedParallelIterator for SplitWhen<M, SPLIT_OP>
+    where M: IndexedParallelIterator,
+          SPLIT_OP: Fn(usize) -> bool + Sync
+{
+    fn upper_bound(&mut self) -> usize {
+        self.len()
+    }
+
+    fn drive<C: Consumer<Self::Item>>(self, consumer: C) -> C::Result {
+        bridge(self, consumer)
+    }
+}
+
+impl<M, SPLIT_OP> ExactParallelIterator for SplitWhen<M, SPLIT_OP>
+    where M: IndexedParallelIterator,
+          SPLIT_OP: Fn(usize) -> bool + Sync
+{
+    fn len(&mut self) -> usize {
+        self.base.len()
+    }
+}
+
+impl<M, SPLIT_OP> IndexedParallelIterator for SplitWhen<M, SPLIT_OP>
+    where M: IndexedParallelIterator,
+          SPLIT_OP: Fn(usize) -> bool + Sync
+{
+    fn with_producer<CB>(self, callback: CB) -> CB::Output
+        where CB: ProducerCallback<Self::Item>
+    {
+        return self.base.with_producer(Callback {
+            callback: callback,
+            should_split: &self.should_split,
+        });
+
+        struct Callback<'s, CB, SPLIT_OP: 's> {
+            callback: CB,
+            should_split: &'s SPLIT_OP,
+        }
+
+        impl<'s, ITEM, CB, SPLIT_OP> ProducerCallback<ITEM> for Callback<'s, CB, SPLIT_OP>
+            where CB: ProducerCallback<ITEM>,
+                  SPLIT_OP: Fn(usize) -> bool + Sync
+        {
+            type Output = CB::Output;
+
+            fn callback<P>(self, base: P) -> CB::Output
+                where P: Producer<Item = ITEM>
+            {
+                self.callback.callback(SplitWhenProducer {
+                    base: base,
+                    should_split: self.should_split,
+                })
+            }
+        }
+    }
+}
+
+/// ////////////////////////////////////////////////////////////////////////
+
+struct SplitWhenProducer<'s, P, SPLIT_OP: 's> {
+    base: P,
+    should_split: &'s SPLIT_OP,
+}
+
+impl<'s, P, SPLIT_OP> Producer for SplitWhenProducer<'s, P, SPLIT_OP>
+    where P: Producer,
+          SPLIT_OP: Fn(usize) -> bool + Sync
+{
+    fn weighted(&self) -> bool {
+        self.base.weighted()
+    }
+
+    fn cost(&mut self, len: usize) -> f64 {
+        self.base.cost(len)
+    }
+
+    fn should_split(&self, len: usize) -> Option<bool> {
+        Some((self.should_split)(len))
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let (left, right) = self.base.split_at(index);
+        (SplitWhenProducer {
+             base: left,
+             should_split: self.should_split,
+         },
+         SplitWhenProducer {
+             base: right,
+             should_split: self.should_split,
+         })
+    }
+}
+
+impl<'s, P, SPLIT_OP> IntoIterator for SplitWhenProducer<'s, P, SPLIT_OP>
+    where P: Producer,
+          SPLIT_OP: Fn(usize) -> bool + Sync
+{
+    type Item = P::Item;
+    type IntoIter = P::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.base.into_iter()
+    }
+}
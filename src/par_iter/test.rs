@@ -1,6 +1,7 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use super::*;
+use super::extend::ParallelExtend;
 use super::internal::*;
 
 use rand::{Rng, SeedableRng, XorShiftRng};
@@ -8,6 +9,7 @@ use std::collections::LinkedList;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::collections::{BinaryHeap, VecDeque};
 use std::f64;
+use std::time::Instant;
 
 fn is_bounded<T: ExactParallelIterator>(_: T) {}
 fn is_exact<T: ExactParallelIterator>(_: T) {}
@@ -47,6 +49,29 @@ pub fn execute_range() {
     assert_eq!(b, c);
 }
 
+#[test]
+pub fn par_extend_vec() {
+    let mut v: Vec<i32> = Vec::with_capacity(1 + 100_000);
+    v.push(7);
+    let ptr = v.as_ptr();
+    v.par_extend((0..100_000).into_par_iter().map(|i| i * 2));
+
+    // the items were written in place, without reallocating
+    assert_eq!(v.as_ptr(), ptr);
+    assert_eq!(v.len(), 1 + 100_000);
+    assert_eq!(v[0], 7);
+    assert!(v[1..].iter().enumerate().all(|(i, &x)| x == i as i32 * 2));
+}
+
+#[test]
+pub fn par_extend_vec_unindexed() {
+    let mut v = vec![-1];
+    v.par_extend((0..1024).into_par_iter().filter(|i| i % 3 == 0));
+    let mut w = vec![-1];
+    w.extend((0..1024).filter(|i| i % 3 == 0));
+    assert_eq!(v, w);
+}
+
 #[test]
 pub fn execute_unindexed_range() {
     let a = 0i64..1024;
@@ -147,6 +172,88 @@ pub fn fold_map_reduce() {
                          vec![29], vec![30], vec![31]]));
 }
 
+#[test]
+pub fn fold_then_reduce_keeps_order() {
+    use {Configuration, ThreadPool};
+
+    // Leaves fold their items into a `Vec`, and the tree combines the
+    // vectors by concatenation, which is not commutative; the result
+    // must still be in sequential order, however the pool split the
+    // work. Splitting down to 100 items gives the pool plenty of
+    // leaves, each with more than one item.
+    let mut rng = XorShiftRng::from_seed([13, 17, 19, 23]);
+    let a: Vec<u32> = rng.gen_iter().take(10_000).collect();
+    let leaves = AtomicUsize::new(0);
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let v = pool.install(|| {
+        a.par_iter()
+            .split_when(|len| len > 100)
+            .fold(|| {
+                      leaves.fetch_add(1, Ordering::SeqCst);
+                      Vec::new()
+                  },
+                  |mut v, &x| {
+                      v.push(x);
+                      v
+                  })
+            .reduce(Vec::new, |mut left, right| {
+                left.extend(right);
+                left
+            })
+    });
+    assert_eq!(v, a);
+    assert!(leaves.load(Ordering::SeqCst) >= 64);
+}
+
+/// The number of pieces that summing 10,000 items with
+/// `weight_max()`, which splits as finely as it may, comes down to in
+/// `pool`, and in how many of those `split_when(len > 1000)` does.
+fn leaves_with_and_without_split_when(pool: &::ThreadPool) -> (usize, usize) {
+    let count_leaves = |split_when: bool| {
+        let leaves = AtomicUsize::new(0);
+        let v: Vec<u32> = (0..10_000).collect();
+        let fold = |sum: u64, &x: &u32| sum + x as u64;
+        let identity = || {
+            leaves.fetch_add(1, Ordering::SeqCst);
+            0
+        };
+        let sum: u64 = pool.install(|| if split_when {
+            v.par_iter().split_when(|len| len > 1000).fold(identity, fold).sum()
+        } else {
+            v.par_iter().weight_max().fold(identity, fold).sum()
+        });
+        assert_eq!(sum, 49_995_000);
+        leaves.load(Ordering::SeqCst)
+    };
+    (count_leaves(false), count_leaves(true))
+}
+
+#[test]
+pub fn default_split_threshold_limits_splits() {
+    use {Configuration, ThreadPool};
+
+    // halving 10,000 items down to at most 100 takes 7 levels
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    assert_eq!(leaves_with_and_without_split_when(&pool), (10_000, 16));
+    let pool = ThreadPool::new(Configuration::new()
+            .set_num_threads(2)
+            .set_default_split_threshold(100))
+        .unwrap();
+    assert_eq!(leaves_with_and_without_split_when(&pool), (128, 16));
+}
+
+#[test]
+pub fn split_when_ignores_default_split_threshold() {
+    use {Configuration, ThreadPool};
+
+    // `split_when` splits finer than the threshold would allow
+    let pool = ThreadPool::new(Configuration::new()
+            .set_num_threads(2)
+            .set_default_split_threshold(5000))
+        .unwrap();
+    assert_eq!(leaves_with_and_without_split_when(&pool), (2, 16));
+}
+
 #[test]
 pub fn check_weight_exact_and_bounded() {
     let a = [1, 2, 3];
@@ -155,6 +262,54 @@ pub fn check_weight_exact_and_bounded() {
     is_indexed(a.par_iter().weight(2.0));
 }
 
+#[test]
+pub fn check_split_when_exact_and_bounded() {
+    let a = [1, 2, 3];
+    is_bounded(a.par_iter().split_when(|_| true));
+    is_exact(a.par_iter().split_when(|_| true));
+    is_indexed(a.par_iter().split_when(|_| true));
+}
+
+#[test]
+pub fn check_split_when_tree_shape() {
+    // Split every piece down to 128 items, and no further: the cost
+    // model should be asked once about the whole, then about both of
+    // its halves, and so on, and never about a piece of 64.
+    let calls: Vec<AtomicUsize> = (0..12).map(|_| AtomicUsize::new(0)).collect();
+    let splits = AtomicUsize::new(0);
+    let a: Vec<usize> = (0..1024).collect();
+    let pieces = a.par_iter()
+        .split_when(|remaining_work| {
+            let level = remaining_work.trailing_zeros() as usize;
+            calls[level].fetch_add(1, Ordering::SeqCst);
+            if remaining_work > 128 {
+                splits.fetch_add(1, Ordering::SeqCst);
+                true
+            } else {
+                false
+            }
+        })
+        .fold(|| vec![], |mut v, &e| {
+            v.push(e);
+            v
+        })
+        .map(|v| vec![v])
+        .reduce_with(|mut v_a, v_b| {
+            v_a.extend(v_b);
+            v_a
+        })
+        .unwrap();
+
+    let calls: Vec<usize> = calls.iter().map(|c| c.load(Ordering::SeqCst)).collect();
+    assert_eq!(calls, [0, 0, 0, 0, 0, 0, 0, 8, 4, 2, 1, 0]);
+    assert_eq!(splits.load(Ordering::SeqCst), 7);
+    assert_eq!(pieces.len(), 8);
+    for (i, piece) in pieces.iter().enumerate() {
+        let expected: Vec<usize> = (i * 128..(i + 1) * 128).collect();
+        assert_eq!(*piece, expected);
+    }
+}
+
 #[test]
 pub fn check_enumerate() {
     let a: Vec<usize> = (0..1024).rev().collect();
@@ -191,6 +346,34 @@ pub fn check_indices_after_enumerate_split() {
     }
 }
 
+#[test]
+pub fn check_enumerate_absolute_indices_in_for_each() {
+    use std::sync::Mutex;
+    use {Configuration, ThreadPool};
+
+    // Split down to pieces of at most three items, which the leaves run
+    // through in a loop, on four workers that steal from each other; the
+    // values have nothing to do with their indices.
+    let mut rng = XorShiftRng::from_seed([4, 3, 2, 1]);
+    let a: Vec<u32> = (0..10_000).map(|_| rng.next_u32()).collect();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    for &skip in &[0, 7] {
+        let pairs = Mutex::new(Vec::new());
+        pool.install(|| {
+            a.par_iter()
+                .skip(skip)
+                .enumerate()
+                .split_when(|len| len > 3)
+                .for_each(|(i, &x)| pairs.lock().unwrap().push((i, x)))
+        });
+        let mut pairs = pairs.into_inner().unwrap();
+        pairs.sort();
+        let expected: Vec<(usize, u32)> =
+            a.iter().skip(skip).enumerate().map(|(i, &x)| (i, x)).collect();
+        assert_eq!(pairs, expected);
+    }
+}
+
 #[test]
 pub fn check_increment() {
     let mut a: Vec<usize> = (0..1024).rev().collect();
@@ -676,6 +859,23 @@ pub fn check_zip_range() {
     assert!(a.iter().all(|&x| x == a.len() - 1));
 }
 
+#[test]
+pub fn check_zip_unequal_sum_of_products() {
+    let a: Vec<i64> = (0..1000).collect();
+    let b: Vec<i64> = (0..1500).map(|i| 3 * i + 1).collect();
+    let expected: i64 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+
+    // split all the way down to single items, with either side longer
+    let sum: i64 = a.par_iter().zip(&b).weight_max().map(|(x, y)| x * y).sum();
+    assert_eq!(sum, expected);
+    let sum: i64 = b.par_iter().zip(&a).weight_max().map(|(x, y)| x * y).sum();
+    assert_eq!(sum, expected);
+
+    let pairs: Vec<_> = b.par_iter().zip(&a).weight_max().collect();
+    assert_eq!(pairs.len(), a.len());
+    assert!(pairs.iter().all(|&(&y, &x)| y == 3 * x + 1));
+}
+
 #[test]
 pub fn check_range_split_at_overflow() {
     // Note, this split index overflows i8!
@@ -798,6 +998,42 @@ pub fn check_chunks_mut() {
     assert_eq!(a, b);
 }
 
+#[test]
+pub fn check_chunks_aligned() {
+    // `weight_max` splits down to single chunks, at whatever midpoints
+    // the splitter picks; every chunk must still start at a multiple of
+    // the chunk size, and only the last may be short.
+    let a: Vec<usize> = (0..1003).collect();
+    let mut chunks: Vec<(usize, usize)> = a.par_chunks(10)
+        .weight_max()
+        .map(|c| (c[0], c.len()))
+        .collect();
+    assert_eq!(chunks.len(), 101);
+    assert_eq!(chunks.pop(), Some((1000, 3)));
+    for (i, &(start, len)) in chunks.iter().enumerate() {
+        assert_eq!((start, len), (i * 10, 10));
+    }
+
+    let mut b = vec![0; 1003];
+    b.par_chunks_mut(10)
+        .weight_max()
+        .for_each(|c| {
+            let len = c.len();
+            for x in c {
+                *x += len;
+            }
+        });
+    assert!(b[..1000].iter().all(|&x| x == 10));
+    assert_eq!(&b[1000..], &[3, 3, 3]);
+}
+
+#[test]
+#[should_panic(expected = "chunk_size must not be zero")]
+pub fn check_chunks_zero_size() {
+    let a = [1, 2, 3];
+    a.par_chunks(0);
+}
+
 #[test]
 pub fn check_options() {
     let mut a = vec![None, Some(1), None, None, Some(2), Some(4)];
@@ -1024,6 +1260,58 @@ pub fn check_find_is_present() {
     assert!(counter.load(Ordering::SeqCst) < 2048); // should not have visited every single one
 }
 
+#[test]
+pub fn check_find_any_single_match() {
+    // one match planted in the middle of a large slice: whoever finds
+    // it (most likely a thief) stops the others
+    let mut a = vec![0_u32; 1 << 20];
+    a[654_321] = 1;
+    let counter = AtomicUsize::new(0);
+    let value = a.par_iter()
+        .enumerate()
+        .find_any(|&(_, &x)| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            x == 1
+        });
+    assert_eq!(value, Some((654_321, &1)));
+    assert!(counter.load(Ordering::SeqCst) < a.len());
+}
+
+#[test]
+pub fn check_find_first_two_matches() {
+    use {Configuration, ThreadPool};
+
+    // Two matches, the second one right after a split point, so that
+    // both are usually found, by different workers, and in either
+    // order. The first one must win every time.
+    let mut a = vec![0_u32; 1 << 16];
+    a[20_000] = 1;
+    a[1 << 15] = 1;
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    for _ in 0..100 {
+        pool.install(|| {
+            let first = a.par_iter().enumerate().find_first(|&(_, &x)| x == 1);
+            assert_eq!(first, Some((20_000, &1)));
+
+            // unindexed, through `filter` and `chain`
+            let first = (0..a.len())
+                .into_par_iter()
+                .filter(|&i| i % 2 == 0)
+                .chain(0..a.len())
+                .find_first(|&i| a[i] == 1);
+            assert_eq!(first, Some(20_000));
+
+            let first = (0..256_usize)
+                .into_par_iter()
+                .flat_map(|i| (0..256_usize).into_par_iter().map(move |j| (i, j)))
+                .find_first(|&(i, j)| a[i * 256 + j] == 1);
+            assert_eq!(first, Some((20_000 / 256, 20_000 % 256)));
+        });
+    }
+
+    assert_eq!(a.par_iter().find_first(|&&x| x > 1), None);
+}
+
 #[test]
 pub fn par_iter_collect() {
     let a: Vec<i32> = (0..1024).collect();
@@ -1127,3 +1415,169 @@ fn min_max_by() {
                    slice.iter().max_by_key(|x| x.0));
     }
 }
+
+#[test]
+pub fn slice_for_each_odd_lengths() {
+    for len in (0..70).chain(vec![1000, 1023, 1025]) {
+        let counts: Vec<AtomicUsize> = (0..len).map(|_| AtomicUsize::new(0)).collect();
+        let indices: Vec<usize> = (0..len).collect();
+        indices.par_iter().for_each(|&i| {
+            counts[i].fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(counts.iter().all(|c| c.load(Ordering::SeqCst) == 1));
+    }
+}
+
+#[test]
+pub fn slice_map_collect_odd_lengths() {
+    for len in (0..70).chain(vec![1000, 1023, 1025]) {
+        let a: Vec<usize> = (0..len).collect();
+        let b: Vec<usize> = a.par_iter().map(|&i| i * 2).collect();
+        let c: Vec<usize> = a.iter().map(|&i| i * 2).collect();
+        assert_eq!(b, c);
+    }
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+pub fn slice_for_each_propagates_panic() {
+    use unwind;
+
+    let a: Vec<i32> = (0..1024).collect();
+    let result = unwind::halt_unwinding(|| {
+        a.par_iter().for_each(|&i| {
+            if i == 777 {
+                panic!("element 777");
+            }
+        });
+    });
+    let err = result.unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"element 777"));
+}
+
+#[test]
+pub fn reduce_preserves_order() {
+    let a: Vec<String> = (0..1000).map(|i| format!("{},", i)).collect();
+    let b = a.par_iter()
+        .cloned()
+        .reduce(String::new, |mut left, right| {
+            left.push_str(&right);
+            left
+        });
+    let c: String = a.iter().map(|s| &s[..]).collect();
+    assert_eq!(b, c);
+}
+
+#[test]
+pub fn reduce_with_preserves_order() {
+    let a: Vec<String> = (0..1000).map(|i| format!("{},", i)).collect();
+    let b = a.par_iter()
+        .cloned()
+        .reduce_with(|mut left, right| {
+            left.push_str(&right);
+            left
+        });
+    let c: String = a.iter().map(|s| &s[..]).collect();
+    assert_eq!(b, Some(c));
+}
+
+#[test]
+pub fn for_each_until_deadline_passed() {
+    let deadline = Instant::now();
+    assert_eq!((0..1024).into_par_iter().for_each_until(deadline, |_| panic!("too late")),
+               0);
+}
+
+#[test]
+pub fn for_each_until_deadline_never_reached() {
+    use std::time::Duration;
+
+    let deadline = Instant::now() + Duration::from_secs(3600);
+    let sum = AtomicUsize::new(0);
+    let done = (0..1024usize).into_par_iter().for_each_until(deadline, |i| {
+        sum.fetch_add(i, Ordering::Relaxed);
+    });
+    assert_eq!(done, 1024);
+    assert_eq!(sum.load(Ordering::Relaxed), 1023 * 1024 / 2);
+}
+
+#[test]
+pub fn for_each_until_counts_processed_items() {
+    use std::thread;
+    use std::time::Duration;
+
+    // Every item takes a millisecond, and `weight_max` makes each one
+    // a chunk of its own, so the loop is stopped well short of the end.
+    let deadline = Instant::now() + Duration::from_millis(20);
+    let calls = AtomicUsize::new(0);
+    let done = (0..10_000).into_par_iter().weight_max().for_each_until(deadline, |_| {
+        calls.fetch_add(1, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(1));
+    });
+    assert_eq!(done, calls.load(Ordering::Relaxed));
+    assert!(done > 0);
+    assert!(done < 10_000);
+}
+
+#[test]
+pub fn map_with_seed_per_piece() {
+    use std::cell::Cell;
+    use std::sync::mpsc::{channel, Sender};
+
+    // Sums up the items it sees; reports the sum when dropped. Each
+    // clone starts from zero, so the reported sums partition the total.
+    // `Cell` makes it `!Sync`.
+    struct Seed {
+        sum: Cell<usize>,
+        sender: Sender<usize>,
+    }
+
+    impl Clone for Seed {
+        fn clone(&self) -> Seed {
+            Seed {
+                sum: Cell::new(0),
+                sender: self.sender.clone(),
+            }
+        }
+    }
+
+    impl Drop for Seed {
+        fn drop(&mut self) {
+            self.sender.send(self.sum.get()).unwrap();
+        }
+    }
+
+    let (tx, rx) = channel();
+    let seed = Seed {
+        sum: Cell::new(0),
+        sender: tx,
+    };
+    let v: Vec<usize> = (0..1024usize)
+        .into_par_iter()
+        .map_with(seed, |seed, i| {
+            seed.sum.set(seed.sum.get() + i);
+            i * 2
+        })
+        .collect();
+    assert_eq!(v, (0..1024).map(|i| i * 2).collect::<Vec<_>>());
+
+    // All the seeds were dropped by the time `collect` returned, and
+    // the sender went with the last of them.
+    let sums: Vec<usize> = rx.iter().collect();
+    assert!(sums.len() <= 1024);
+    assert_eq!(sums.iter().sum::<usize>(), 1023 * 1024 / 2);
+}
+
+#[test]
+pub fn map_with_unindexed() {
+    let v: Vec<String> = (0..1024i32)
+        .into_par_iter()
+        .filter(|i| i % 3 == 0)
+        .map_with(String::new(), |buf, i| {
+            buf.clear();
+            buf.push_str(&i.to_string());
+            buf.clone()
+        })
+        .collect();
+    assert_eq!(v, (0..1024).filter(|i| i % 3 == 0).map(|i| i.to_string()).collect::<Vec<_>>());
+}
@@ -98,6 +98,10 @@ impl<P: Producer> Producer for WeightProducer<P> {
         self.base.cost(len) * self.weight
     }
 
+    fn should_split(&self, len: usize) -> Option<bool> {
+        self.base.should_split(len)
+    }
+
     fn split_at(self, index: usize) -> (Self, Self) {
         let (left, right) = self.base.split_at(index);
         (WeightProducer {
@@ -129,6 +129,15 @@ impl<A: Producer, B: Producer> Producer for ZipProducer<A, B> {
         self.a.cost(len) + self.b.cost(len)
     }
 
+    fn should_split(&self, len: usize) -> Option<bool> {
+        self.a.should_split(len).or_else(|| self.b.should_split(len))
+    }
+
+    /// Both sides are split at the same index, so every piece pairs up
+    /// corresponding items, however far down the splitting goes. If
+    /// the two are of unequal length, `index` is still within the
+    /// shorter one (the zip has its length), and only the last piece of
+    /// the longer side has items left over, which `into_iter` drops.
     fn split_at(self, index: usize) -> (Self, Self) {
         let (a_left, a_right) = self.a.split_at(index);
         let (b_left, b_right) = self.b.split_at(index);
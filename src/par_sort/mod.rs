@@ -0,0 +1,262 @@
+//! Parallel sorting of slices, built on `join`.
+//!
+//! `par_sort` is a merge sort: the slice is split in half, the halves
+//! are sorted in parallel, and then merged into a scratch buffer of
+//! the same length. So that the final merge is not a sequential
+//! bottleneck, large merges are split in turn: we take the middle
+//! element of the longer run, binary-search for where it belongs in
+//! the other run, and merge the two pairs of pieces in parallel.
+//!
+//! Merging only ever reads from the slice and writes to the buffer;
+//! the result is copied back once the whole merge is done. So if the
+//! comparison panics, the slice still holds each element exactly once
+//! (though in unspecified order), and nothing is dropped twice.
+//!
+//! `par_sort_unstable` is a quicksort, which sorts in place and needs
+//! no buffer, but does not keep equal elements in order.
+//!
+//! Below `SORT_CUTOFF` elements, both fall back to the standard
+//! library sort.
+
+use api::join;
+use std::cmp::Ordering;
+use std::ptr;
+
+#[cfg(test)]
+mod test;
+
+/// Slices shorter than this are sorted sequentially.
+const SORT_CUTOFF: usize = 1 << 12;
+
+/// Merges producing fewer elements than this are done sequentially.
+const MERGE_CUTOFF: usize = 1 << 12;
+
+/// Parallel extensions for sorting slices.
+pub trait ParallelSliceSort<T: Send> {
+    /// Sorts the slice in parallel. The sort is stable, i.e., equal
+    /// elements keep their relative order. It allocates a scratch
+    /// buffer of the same length as the slice.
+    fn par_sort(&mut self) where T: Ord;
+
+    /// Like `par_sort`, but sorts with the comparison function
+    /// `compare`.
+    fn par_sort_by<F>(&mut self, compare: F) where F: Fn(&T, &T) -> Ordering + Sync;
+
+    /// Sorts the slice in parallel, in place. The sort is not stable,
+    /// i.e., equal elements may end up in any order.
+    fn par_sort_unstable(&mut self) where T: Ord;
+
+    /// Like `par_sort_unstable`, but sorts with the comparison function
+    /// `compare`.
+    fn par_sort_unstable_by<F>(&mut self, compare: F) where F: Fn(&T, &T) -> Ordering + Sync;
+}
+
+impl<T: Send> ParallelSliceSort<T> for [T] {
+    fn par_sort(&mut self)
+        where T: Ord
+    {
+        self.par_sort_by(T::cmp);
+    }
+
+    fn par_sort_by<F>(&mut self, compare: F)
+        where F: Fn(&T, &T) -> Ordering + Sync
+    {
+        if self.len() <= SORT_CUTOFF {
+            return self.sort_by(compare);
+        }
+        let mut buf: Vec<T> = Vec::with_capacity(self.len());
+        unsafe {
+            // `buf` never owns any elements: its length stays zero, so
+            // dropping it only frees the memory.
+            merge_sort(self, Buffer(buf.as_mut_ptr()), &compare);
+        }
+    }
+
+    fn par_sort_unstable(&mut self)
+        where T: Ord
+    {
+        self.par_sort_unstable_by(T::cmp);
+    }
+
+    fn par_sort_unstable_by<F>(&mut self, compare: F)
+        where F: Fn(&T, &T) -> Ordering + Sync
+    {
+        quick_sort(self, &compare);
+    }
+}
+
+/// Uninitialized scratch space for `merge_sort`, at least as long as
+/// the slice being sorted. Different threads write to disjoint parts
+/// of it, hence the `Send` impl.
+struct Buffer<T>(*mut T);
+
+unsafe impl<T: Send> Send for Buffer<T> {}
+
+// (Not derived, since that would require `T: Copy`.)
+impl<T> Clone for Buffer<T> {
+    fn clone(&self) -> Buffer<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Buffer<T> {}
+
+impl<T> Buffer<T> {
+    unsafe fn offset(self, count: usize) -> Buffer<T> {
+        Buffer(self.0.offset(count as isize))
+    }
+}
+
+/// Sorts `v`, with `buf` as scratch space for `v.len()` elements.
+unsafe fn merge_sort<T, F>(v: &mut [T], buf: Buffer<T>, compare: &F)
+    where T: Send,
+          F: Fn(&T, &T) -> Ordering + Sync
+{
+    let len = v.len();
+    if len <= SORT_CUTOFF {
+        return v.sort_by(compare);
+    }
+
+    let mid = len / 2;
+    {
+        let (left, right) = v.split_at_mut(mid);
+        let buf_right = buf.offset(mid);
+        join(move || merge_sort(left, buf, compare),
+             move || merge_sort(right, buf_right, compare));
+    }
+
+    {
+        let (left, right) = v.split_at_mut(mid);
+        merge(left, right, buf, compare);
+    }
+
+    // All elements are in `buf` now (and still in `v`); move them back.
+    ptr::copy_nonoverlapping(buf.0, v.as_mut_ptr(), len);
+}
+
+/// Merges the sorted runs `left` and `right` into `dest`, which has
+/// room for both. Equal elements from `left` come first. The elements
+/// are copied bitwise, so afterwards the caller must forget one of the
+/// two copies.
+///
+/// The runs are only read, but taking them as `&mut` lets us hand the
+/// pieces to other threads without requiring `T: Sync`.
+unsafe fn merge<T, F>(left: &mut [T], right: &mut [T], dest: Buffer<T>, compare: &F)
+    where T: Send,
+          F: Fn(&T, &T) -> Ordering + Sync
+{
+    if left.len() + right.len() <= MERGE_CUTOFF {
+        return merge_sequential(left, right, dest, compare);
+    }
+
+    // Split the longer run in the middle, and the other one so that
+    // everything before the split in both runs goes before everything
+    // after it. For stability, elements of `right` that are equal to
+    // the pivot go after a pivot from `left`, and elements of `left`
+    // that are equal to the pivot go before a pivot from `right`.
+    let (left_mid, right_mid) = if left.len() >= right.len() {
+        let left_mid = left.len() / 2;
+        let pivot = &left[left_mid];
+        let right_mid = partition_point(right, |x| compare(x, pivot) == Ordering::Less);
+        (left_mid, right_mid)
+    } else {
+        let right_mid = right.len() / 2;
+        let pivot = &right[right_mid];
+        let left_mid = partition_point(left, |x| compare(x, pivot) != Ordering::Greater);
+        (left_mid, right_mid)
+    };
+
+    let (left_lo, left_hi) = left.split_at_mut(left_mid);
+    let (right_lo, right_hi) = right.split_at_mut(right_mid);
+    let dest_hi = dest.offset(left_mid + right_mid);
+    join(move || merge(left_lo, right_lo, dest, compare),
+         move || merge(left_hi, right_hi, dest_hi, compare));
+}
+
+unsafe fn merge_sequential<T, F>(left: &[T], right: &[T], dest: Buffer<T>, compare: &F)
+    where F: Fn(&T, &T) -> Ordering
+{
+    let (mut l, mut r, mut d) = (0, 0, dest.0);
+    while l < left.len() && r < right.len() {
+        let src = if compare(&right[r], &left[l]) == Ordering::Less {
+            r += 1;
+            &right[r - 1]
+        } else {
+            l += 1;
+            &left[l - 1]
+        };
+        ptr::copy_nonoverlapping(src, d, 1);
+        d = d.offset(1);
+    }
+    ptr::copy_nonoverlapping(left[l..].as_ptr(), d, left.len() - l);
+    d = d.offset((left.len() - l) as isize);
+    ptr::copy_nonoverlapping(right[r..].as_ptr(), d, right.len() - r);
+}
+
+/// Returns the number of elements at the start of `v` for which `pred`
+/// holds, assuming that it holds for a prefix of `v` and not after.
+fn partition_point<T, P>(v: &[T], pred: P) -> usize
+    where P: Fn(&T) -> bool
+{
+    let (mut lo, mut hi) = (0, v.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(&v[mid]) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+fn quick_sort<T, F>(v: &mut [T], compare: &F)
+    where T: Send,
+          F: Fn(&T, &T) -> Ordering + Sync
+{
+    let len = v.len();
+    if len <= SORT_CUTOFF {
+        return v.sort_by(compare);
+    }
+
+    let mid = partition(v, compare);
+
+    // A bad pivot (e.g., because most elements are equal) would make
+    // the recursion as deep as the slice is long; in that case, give
+    // up on parallelism for this part rather than risk the stack.
+    if mid < len / 8 || len - mid < len / 8 {
+        return v.sort_by(compare);
+    }
+
+    let (lo, hi) = v.split_at_mut(mid);
+    join(|| quick_sort(lo, compare), || quick_sort(&mut hi[1..], compare));
+}
+
+/// Partitions `v` around the median of its first, middle and last
+/// elements, and returns the index the pivot ends up at: everything
+/// before it is less than the pivot, nothing after it is.
+fn partition<T, F>(v: &mut [T], compare: &F) -> usize
+    where F: Fn(&T, &T) -> Ordering
+{
+    let last = v.len() - 1;
+    let median = {
+        let is_less = |a: usize, b: usize| compare(&v[a], &v[b]) == Ordering::Less;
+        let (a, b, c) = (0, last / 2, last);
+        if is_less(a, b) {
+            if is_less(b, c) { b } else if is_less(a, c) { c } else { a }
+        } else {
+            if is_less(a, c) { a } else if is_less(b, c) { c } else { b }
+        }
+    };
+    v.swap(median, last);
+
+    let mut i = 0;
+    for j in 0..last {
+        if compare(&v[j], &v[last]) == Ordering::Less {
+            v.swap(i, j);
+            i += 1;
+        }
+    }
+    v.swap(i, last);
+    i
+}
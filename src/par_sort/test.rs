@@ -0,0 +1,122 @@
+use Configuration;
+use ThreadPool;
+use prelude::*;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use unwind;
+
+fn random_vec(len: usize, max: u32) -> Vec<u32> {
+    let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+    (0..len).map(|_| rng.gen_range(0, max)).collect()
+}
+
+/// Lengths around the cutoffs, and big enough to split several times.
+const LENS: &'static [usize] = &[0, 1, 2, 3, 100, 4095, 4096, 4097, 10000, 100000];
+
+#[test]
+fn par_sort_matches_sort() {
+    for &len in LENS {
+        let mut v = random_vec(len, ::std::u32::MAX);
+        let mut expected = v.clone();
+        expected.sort();
+        v.par_sort();
+        assert_eq!(v, expected);
+    }
+}
+
+#[test]
+fn par_sort_unstable_matches_sort() {
+    for &len in LENS {
+        let mut v = random_vec(len, ::std::u32::MAX);
+        let mut expected = v.clone();
+        expected.sort();
+        v.par_sort_unstable();
+        assert_eq!(v, expected);
+    }
+}
+
+#[test]
+fn par_sort_by_reverse() {
+    let mut v = random_vec(50000, 1000);
+    let mut expected = v.clone();
+    expected.sort_by(|a, b| b.cmp(a));
+    v.par_sort_by(|a, b| b.cmp(a));
+    assert_eq!(v, expected);
+
+    let mut v = random_vec(50000, 1000);
+    v.par_sort_unstable_by(|a, b| b.cmp(a));
+    assert_eq!(v, expected);
+}
+
+#[test]
+fn par_sort_is_stable() {
+    // Few distinct keys, and the original position as a tag; sorting
+    // by key alone must keep the tags of equal keys in order.
+    let mut v: Vec<(u32, usize)> = random_vec(100000, 10).into_iter().enumerate()
+        .map(|(i, key)| (key, i))
+        .collect();
+    let mut expected = v.clone();
+    expected.sort();
+    v.par_sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(v, expected);
+}
+
+#[test]
+fn par_sort_unstable_all_equal() {
+    let mut v = vec![7; 100000];
+    v.par_sort_unstable();
+    assert!(v.iter().all(|&x| x == 7));
+}
+
+#[test]
+fn par_sort_sorted_and_reversed() {
+    let mut v: Vec<u32> = (0..100000).collect();
+    let expected = v.clone();
+    v.par_sort();
+    assert_eq!(v, expected);
+    v.reverse();
+    v.par_sort_unstable();
+    assert_eq!(v, expected);
+}
+
+#[test]
+fn par_sort_in_pool() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let mut v = random_vec(100000, ::std::u32::MAX);
+    let mut expected = v.clone();
+    expected.sort();
+    pool.install(|| v.par_sort());
+    assert_eq!(v, expected);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn par_sort_panic_keeps_elements() {
+    // Strings own heap memory, so losing or duplicating one on panic
+    // would show up as a wrong multiset, or a double free.
+    let mut v: Vec<String> = random_vec(20000, 1000).into_iter().map(|x| x.to_string()).collect();
+    let mut expected = v.clone();
+    expected.sort();
+
+    let calls = AtomicUsize::new(0);
+    let result = unwind::halt_unwinding(|| {
+        v.par_sort_by(|a, b| {
+            if calls.fetch_add(1, AtomicOrdering::SeqCst) == 100000 {
+                panic!("compare");
+            }
+            a.cmp(b)
+        })
+    });
+    assert!(result.is_err());
+    v.sort();
+    assert_eq!(v, expected);
+}
+
+#[test]
+fn par_sort_zero_sized() {
+    let mut v = vec![(); 10000];
+    v.par_sort();
+    v.par_sort_unstable_by(|_, _| Ordering::Equal);
+    assert_eq!(v.len(), 10000);
+}
@@ -0,0 +1,201 @@
+//! Recording and replaying which worker takes which job, for
+//! reproducing ordering bugs (only with the `log` feature).
+//!
+//! Every job that is pushed onto a deque, or injected, gets a
+//! `JobKey`: the index of the worker that pushed it (or `None` if it
+//! came from outside the pool), together with the number of jobs that
+//! thread had pushed before. In a recording pool, whenever a worker
+//! steals a job or takes an injected one, the key is appended to that
+//! worker's list in the `StealSchedule`.
+//!
+//! In a replaying pool, workers never steal. Instead, each job that
+//! the schedule says was taken by some other worker is handed to that
+//! worker directly when it is pushed or injected (see `route`), and
+//! each worker takes the jobs handed to it in the recorded order (see
+//! `take`). Every other job stays on the deque of the worker that
+//! pushed it, and is popped by that worker as usual. So each worker
+//! runs exactly the jobs it ran in the recorded run, and since the
+//! keys are computed the same way, this carries on through the whole
+//! job tree.
+//!
+//! Replaying only makes sense for the same program, with the same
+//! input and number of threads, and with jobs injected from at most
+//! one thread outside the pool at a time; if the run diverges from
+//! the recording, the replay may deadlock. Once a worker has taken all
+//! the jobs recorded for it, it only takes injected jobs that were not
+//! recorded.
+
+use job::JobRef;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Identifies a job across runs; see the module docs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct JobKey {
+    /// The worker that pushed the job, or `None` for jobs injected
+    /// from outside the pool.
+    pub owner: Option<usize>,
+
+    /// The number of jobs pushed or injected by `owner` before.
+    pub seq: usize,
+}
+
+/// The jobs that each worker took from other workers (or from the
+/// injector queue) during a recorded run; see
+/// `Configuration::set_record_steals`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StealSchedule {
+    /// For each worker, the keys of the jobs it took, in order.
+    pub steals: Vec<Vec<JobKey>>,
+}
+
+/// Whether a pool records its steal schedule, or replays one; see
+/// `Configuration::set_steal_mode`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StealMode {
+    /// Record which worker takes which job; the result is available
+    /// from `ThreadPool::steal_schedule`.
+    Record,
+
+    /// Have each worker take exactly the jobs it took in the recorded
+    /// run.
+    Replay(StealSchedule),
+}
+
+/// The state of a replaying pool.
+struct Replay {
+    schedule: StealSchedule,
+
+    /// Which worker takes each of the jobs in `schedule`.
+    takers: HashMap<JobKey, usize>,
+
+    /// For each worker, the jobs handed off to it but not yet taken.
+    mailboxes: Vec<Mutex<Vec<(JobKey, JobRef)>>>,
+
+    /// For each worker, how many jobs it has taken so far.
+    taken: Vec<AtomicUsize>,
+}
+
+pub struct StealLog {
+    /// `Some` in a recording pool.
+    recorded: Option<Mutex<StealSchedule>>,
+
+    /// `Some` in a replaying pool.
+    replay: Option<Replay>,
+
+    /// The number of jobs each worker has pushed, with an extra entry
+    /// for outside threads at the end.
+    pushed: Vec<AtomicUsize>,
+
+    /// The keys of the jobs that are (or were) on a deque or in the
+    /// injector, by address. Entries are never removed; the address of
+    /// a finished job may get reused, but only by a new job, which
+    /// overwrites the entry when it is pushed.
+    keys: Mutex<HashMap<usize, JobKey>>,
+}
+
+impl StealLog {
+    pub fn new(num_threads: usize, mode: StealMode) -> StealLog {
+        match mode {
+            StealMode::Record => StealLog::record(num_threads),
+            StealMode::Replay(schedule) => StealLog::replay(num_threads, schedule),
+        }
+    }
+
+    fn record(num_threads: usize) -> StealLog {
+        let schedule = StealSchedule { steals: vec![Vec::new(); num_threads] };
+        StealLog::with_state(num_threads, Some(Mutex::new(schedule)), None)
+    }
+
+    fn replay(num_threads: usize, schedule: StealSchedule) -> StealLog {
+        let mut takers = HashMap::new();
+        for (thief, keys) in schedule.steals.iter().enumerate() {
+            for &key in keys {
+                takers.insert(key, thief);
+            }
+        }
+        let replay = Replay {
+            schedule: schedule,
+            takers: takers,
+            mailboxes: (0..num_threads).map(|_| Mutex::new(Vec::new())).collect(),
+            taken: (0..num_threads).map(|_| AtomicUsize::new(0)).collect(),
+        };
+        StealLog::with_state(num_threads, None, Some(replay))
+    }
+
+    fn with_state(num_threads: usize,
+                  recorded: Option<Mutex<StealSchedule>>,
+                  replay: Option<Replay>)
+                  -> StealLog {
+        StealLog {
+            recorded: recorded,
+            replay: replay,
+            pushed: (0..num_threads + 1).map(|_| AtomicUsize::new(0)).collect(),
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Called whenever `owner` pushes or injects `job`. Returns true if
+    /// the job was handed off to the worker that is going to take it,
+    /// in which case the caller must not push it anywhere.
+    pub fn route(&self, owner: Option<usize>, job: JobRef) -> bool {
+        let counter = &self.pushed[owner.unwrap_or(self.pushed.len() - 1)];
+        let key = JobKey {
+            owner: owner,
+            seq: counter.fetch_add(1, Ordering::Relaxed),
+        };
+        self.keys.lock().unwrap().insert(job.address(), key);
+
+        if let Some(ref replay) = self.replay {
+            if let Some(&taker) = replay.takers.get(&key) {
+                replay.mailboxes[taker].lock().unwrap().push((key, job));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// In a recording pool, notes that `thief` took `job`.
+    pub fn record_steal(&self, thief: usize, job: &JobRef) {
+        if let Some(ref recorded) = self.recorded {
+            let key = self.keys.lock().unwrap()[&job.address()];
+            recorded.lock().unwrap().steals[thief].push(key);
+        }
+    }
+
+    /// True if this is a replaying pool, in which workers must not
+    /// steal, but `take` the jobs handed to them instead.
+    pub fn is_replay(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// In a replaying pool, returns the next job that `thief` is to
+    /// take, if it has been handed off yet. Returns `Err(())` once
+    /// `thief` has taken all the jobs recorded for it.
+    pub fn take(&self, thief: usize) -> Result<Option<JobRef>, ()> {
+        let replay = match self.replay {
+            Some(ref replay) => replay,
+            None => return Err(()),
+        };
+        let count = replay.taken[thief].load(Ordering::Relaxed);
+        let key = match replay.schedule.steals[thief].get(count) {
+            Some(&key) => key,
+            None => return Err(()),
+        };
+        let mut mailbox = replay.mailboxes[thief].lock().unwrap();
+        match mailbox.iter().position(|&(k, _)| k == key) {
+            Some(index) => {
+                replay.taken[thief].store(count + 1, Ordering::Relaxed);
+                Ok(Some(mailbox.swap_remove(index).1))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns what has been recorded so far, if this is a recording
+    /// pool.
+    pub fn schedule(&self) -> Option<StealSchedule> {
+        self.recorded.as_ref().map(|recorded| recorded.lock().unwrap().clone())
+    }
+}
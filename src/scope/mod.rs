@@ -1,10 +1,12 @@
-use latch::{Latch, SpinLatch, LockLatch};
-use job::{JobMode, HeapJob, StackJob};
+use context;
+use latch::{CountLatch, Latch};
+use job::{JobMode, HeapJob};
 use std::any::Any;
 use std::marker::PhantomData;
 use std::mem;
 use std::ptr;
-use std::sync::atomic::{AtomicUsize, AtomicPtr, Ordering};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
 use thread_pool::{self, WorkerThread};
 use unwind;
 
@@ -14,24 +16,38 @@ mod test;
 pub struct Scope<'scope> {
     /// thread where `scope()` was executed (note that individual jobs
     /// may be executing on different worker threads, though they
-    /// should always be within the same pool of threads)
+    /// should always be within the same pool of threads); null if the
+    /// scope runs sequentially, outside of any pool (see
+    /// `Configuration::disable_implicit_pool`)
     owner_thread: *mut WorkerThread,
 
-    /// number of jobs created that have not yet completed or errored
-    counter: AtomicUsize,
+    /// set once the jobs created (counting the body of the scope
+    /// itself) have all completed or errored, and hence this scope is
+    /// complete
+    job_completed_latch: CountLatch,
 
     /// if some job panicked, the error is stored here; it will be
-    /// propagated to the one who created the scope
+    /// propagated to the one who created the scope (the errors of any
+    /// later panics are dropped)
     panic: AtomicPtr<Box<Any + Send + 'static>>,
 
-    /// latch to set when the counter drops to zero (and hence this scope is complete)
-    job_completed_latch: SpinLatch,
+    /// set as soon as some job panics, so that long-running jobs can
+    /// notice (via `is_cancelled()`) and stop early
+    cancelled: AtomicBool,
+
+    /// `Some` for `scope_collect_panics()`: then the errors of all
+    /// panics are kept here (up to `MAX_COLLECTED_PANICS`), rather than
+    /// just the first one in `panic`
+    collected_panics: Option<Mutex<Vec<Box<Any + Send + 'static>>>>,
 
     /// you can think of a scope as containing a list of closures to
     /// execute, all of which outlive `'scope`
     marker: PhantomData<Box<FnOnce(&Scope<'scope>) + 'scope>>,
 }
 
+/// The most panics that `scope_collect_panics()` keeps; see there.
+const MAX_COLLECTED_PANICS: usize = 1024;
+
 /// Create a "fork-join" scope `s` and invokes the closure with a
 /// reference to `s`. This closure can then spawn asynchronous tasks
 /// into `s`. Those tasks may run asynchronously with respect to the
@@ -224,43 +240,126 @@ pub struct Scope<'scope> {
 ///     s.spawn(|_| println!("ok: {:?}", ok)); // we too can borrow `ok`
 /// });
 /// ```
+///
+/// ### Panics
+///
+/// If a task panics, the panic is propagated once all other tasks in
+/// the scope have completed; if several tasks panic, only the first
+/// panic is propagated. Since the remaining tasks still run to
+/// completion, tasks that may take a long time can poll
+/// `Scope::is_cancelled()` to find out whether some other task has
+/// already panicked, and bail out early:
+///
+/// ```rust
+/// # use std::panic;
+/// let result = panic::catch_unwind(|| {
+///     rayon::scope(|s| {
+///         s.spawn(|s| {
+///             while !s.is_cancelled() {
+///                 // ... do a bit of work ...
+///             }
+///         });
+///         s.spawn(|_| panic!("oh no"));
+///     });
+/// });
+/// assert!(result.is_err());
+/// ```
 pub fn scope<'scope, OP>(op: OP)
     where OP: for<'s> FnOnce(&'s Scope<'scope>) + 'scope + Send
+{
+    run_scope(op, false);
+}
+
+/// Like `scope()`, but rather than propagating the first panic of the
+/// tasks (or of `op` itself), catches every one of them and returns
+/// their payloads, in the order in which the panics occurred, once
+/// all the tasks have completed. This is useful for independent
+/// tasks, where every failure is of interest. A panic does not cancel
+/// the scope (see `Scope::is_cancelled()`), since the other tasks
+/// are meant to run regardless.
+///
+/// To bound the memory held on to when a great many tasks panic,
+/// only the first 1024 payloads are kept; any further ones are
+/// dropped right away. The payloads may own resources, which are
+/// released as soon as the returned `Vec` is dropped, so a caller
+/// who is not interested in them does not need to do anything.
+///
+/// ```rust
+/// let panics = rayon::scope_collect_panics(|s| {
+///     for i in 0..4 {
+///         s.spawn(move |_| if i % 2 == 1 {
+///             panic!("task {} failed", i);
+///         });
+///     }
+/// });
+/// assert_eq!(panics.len(), 2);
+/// ```
+pub fn scope_collect_panics<'scope, OP>(op: OP) -> Vec<Box<Any + Send + 'static>>
+    where OP: for<'s> FnOnce(&'s Scope<'scope>) + 'scope + Send
+{
+    run_scope(op, true)
+}
+
+/// The body of `scope()` and `scope_collect_panics()`. Returns the
+/// collected panics if `collect_panics` is true, or else propagates
+/// the first panic (and returns an empty `Vec`).
+fn run_scope<'scope, OP>(op: OP, collect_panics: bool) -> Vec<Box<Any + Send + 'static>>
+    where OP: for<'s> FnOnce(&'s Scope<'scope>) + 'scope + Send
 {
     unsafe {
         let owner_thread = WorkerThread::current();
         if !owner_thread.is_null() {
-            let scope: Scope<'scope> = Scope {
-                owner_thread: owner_thread,
-                counter: AtomicUsize::new(1),
-                panic: AtomicPtr::new(ptr::null_mut()),
-                job_completed_latch: SpinLatch::new(),
-                marker: PhantomData,
-            };
-            let spawn_count = (*owner_thread).current_spawn_count();
-            scope.execute_job_closure(op);
-            (*owner_thread).pop_spawned_jobs(spawn_count);
-            scope.steal_till_jobs_complete();
+            run_scope_on(owner_thread, op, collect_panics)
         } else {
-            scope_not_in_worker(op)
+            scope_not_in_worker(op, collect_panics)
         }
     }
 }
 
+/// Runs the scope with `owner_thread` as the owner, which is either the
+/// current worker thread, or null to run it sequentially.
+unsafe fn run_scope_on<'scope, OP>(owner_thread: *mut WorkerThread,
+                                   op: OP,
+                                   collect_panics: bool)
+                                   -> Vec<Box<Any + Send + 'static>>
+    where OP: for<'s> FnOnce(&'s Scope<'scope>) + 'scope + Send
+{
+    let scope: Scope<'scope> = Scope {
+        owner_thread: owner_thread,
+        job_completed_latch: CountLatch::new(1),
+        panic: AtomicPtr::new(ptr::null_mut()),
+        cancelled: AtomicBool::new(false),
+        collected_panics: if collect_panics {
+            Some(Mutex::new(Vec::new()))
+        } else {
+            None
+        },
+        marker: PhantomData,
+    };
+    if owner_thread.is_null() {
+        scope.execute_job_closure(op);
+    } else {
+        let spawn_count = (*owner_thread).current_spawn_count();
+        scope.execute_job_closure(op);
+        (*owner_thread).pop_spawned_jobs(spawn_count);
+    }
+    scope.steal_till_jobs_complete()
+}
+
 #[cold]
-unsafe fn scope_not_in_worker<'scope, OP>(op: OP)
+unsafe fn scope_not_in_worker<'scope, OP>(op: OP,
+                                           collect_panics: bool)
+                                           -> Vec<Box<Any + Send + 'static>>
     where OP: for<'s> FnOnce(&'s Scope<'scope>) + 'scope + Send
 {
-    // never run from a worker thread; just shifts over into worker threads
+    // never run from a worker thread; just shifts over into worker
+    // threads, or runs sequentially if there are none to shift to
     debug_assert!(WorkerThread::current().is_null());
 
-    let mut result = None;
-    {
-        let job = StackJob::new(|| result = Some(scope(op)), LockLatch::new());
-        thread_pool::get_registry().inject(&[job.as_job_ref()]);
-        job.latch.wait();
+    match thread_pool::get_implicit_registry() {
+        Some(registry) => registry.in_worker(|| run_scope(op, collect_panics)),
+        None => run_scope_on(ptr::null_mut(), op, collect_panics),
     }
-    result.unwrap()
 }
 
 
@@ -270,18 +369,31 @@ impl<'scope> Scope<'scope> {
     /// job is specified as a closure, and this closure receives its
     /// own reference to `self` as argument. This can be used to
     /// inject new jobs into `self`.
+    ///
+    /// Since the job may be stolen and executed by any thread in the
+    /// pool, `body` must be `Send`.
     pub fn spawn<BODY>(&self, body: BODY)
-        where BODY: FnOnce(&Scope<'scope>) + 'scope
+        where BODY: FnOnce(&Scope<'scope>) + Send + 'scope
     {
         unsafe {
-            let old_value = self.counter.fetch_add(1, Ordering::SeqCst);
-            assert!(old_value > 0); // scope can't have completed yet
-            let job_ref = Box::new(HeapJob::new(move |mode| self.execute_job(body, mode)))
+            // the scope can't have completed yet, or this panics
+            self.job_completed_latch.increment();
+
+            // without a pool, just run the job right away
+            if self.owner_thread.is_null() {
+                return self.execute_job_closure(body);
+            }
+
+            let context = context::capture();
+            let job_ref = Box::new(HeapJob::new(move |mode| {
+                    context.install(|| self.execute_job(body, mode))
+                }))
                 .as_job_ref();
             let worker_thread = WorkerThread::current();
 
-            // the `Scope` is not send or sync, and we only give out
-            // pointers to it from within a worker thread
+            // the `Scope` is not send or sync, and (unless it runs
+            // sequentially) we only give out pointers to it from within
+            // a worker thread
             debug_assert!(!WorkerThread::current().is_null());
 
             let worker_thread = &*worker_thread;
@@ -290,6 +402,17 @@ impl<'scope> Scope<'scope> {
         }
     }
 
+    /// Returns true if the scope has been cancelled, which happens as
+    /// soon as any of its jobs (or the scope body itself) panics, unless
+    /// it was created with `scope_collect_panics()`. Since
+    /// the panic is only propagated once all jobs spawned into the
+    /// scope have finished, long-running jobs may want to check this
+    /// periodically and return early, as their results are going to
+    /// be discarded anyway.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
     /// Executes `func` as a job, either aborting or executing as
     /// appropriate.
     ///
@@ -318,48 +441,56 @@ impl<'scope> Scope<'scope> {
     }
 
     unsafe fn job_panicked(&self, err: Box<Any + Send + 'static>) {
-        // capture the first error we see, free the rest
+        if let Some(ref collected_panics) = self.collected_panics {
+            let mut collected_panics = collected_panics.lock().unwrap();
+            if collected_panics.len() < MAX_COLLECTED_PANICS {
+                collected_panics.push(err);
+            } else {
+                // drop it, outside of the lock
+                drop(collected_panics);
+                drop(err);
+            }
+            return self.job_completed_ok();
+        }
+
+        // capture the first error we see, free the rest; this works
+        // the same no matter which worker the job ran on
         let nil = ptr::null_mut();
         let mut err = Box::new(err); // box up the fat ptr
         if self.panic.compare_and_swap(nil, &mut *err, Ordering::SeqCst).is_null() {
             mem::forget(err); // ownership now transferred into self.panic
         }
 
+        // only cancel once the error is in place, so that anyone who
+        // sees `is_cancelled()` can be sure which panic will propagate
+        self.cancelled.store(true, Ordering::SeqCst);
+
         self.job_completed_ok()
     }
 
     unsafe fn job_completed_ok(&self) {
-        let old_value = self.counter.fetch_sub(1, Ordering::Release);
-        if old_value == 1 {
-            // Important: grab the lock here to avoid a data race with
-            // the `block_till_jobs_complete` code. Consider what could
-            // otherwise happen:
-            //
-            // ```
-            //    Us          Them
-            //              Acquire lock
-            //              Read counter: 1
-            // Dec counter
-            // Notify all
-            //              Wait on job_completed_cvar
-            // ```
-            //
-            // By holding the lock, we ensure that the "read counter"
-            // and "wait on job_completed_cvar" occur atomically with respect to the
-            // notify.
-            self.job_completed_latch.set();
-        }
+        self.job_completed_latch.set();
     }
 
-    unsafe fn steal_till_jobs_complete(&self) {
+    unsafe fn steal_till_jobs_complete(&self) -> Vec<Box<Any + Send + 'static>> {
         // at this point, we have popped all tasks spawned since the scope
         // began. So either we've executed everything on this thread, or one of
         // those was stolen. If one of them was stolen, then everything below us on
         // the deque must have been stolen too, so we should just go ahead and steal.
-        debug_assert!(self.job_completed_latch.probe() || (*self.owner_thread).pop().is_none());
+        // (There may be unrelated jobs below us if this thread stole several jobs
+        // at once; `steal_until` will get to those first.)
 
-        // wait for job counter to reach 0:
-        (*self.owner_thread).steal_until(&self.job_completed_latch);
+        // wait for the job count to reach 0 (if we ran sequentially, it
+        // already has):
+        if self.owner_thread.is_null() {
+            debug_assert!(self.job_completed_latch.probe());
+        } else {
+            (*self.owner_thread).steal_until(&self.job_completed_latch);
+        }
+
+        if let Some(ref collected_panics) = self.collected_panics {
+            return mem::replace(&mut *collected_panics.lock().unwrap(), Vec::new());
+        }
 
         // propagate panic, if any occurred; at this point, all
         // outstanding jobs have completed, so we can use a relaxed
@@ -369,5 +500,6 @@ impl<'scope> Scope<'scope> {
             let value: Box<Box<Any + Send + 'static>> = mem::transmute(panic);
             unwind::resume_unwinding(*value);
         }
+        Vec::new()
     }
 }
@@ -1,7 +1,7 @@
 extern crate rand;
 
 use Configuration;
-use {scope, Scope};
+use {scope, scope_collect_panics, Scope};
 use ThreadPool;
 use prelude::*;
 use rand::{Rng, SeedableRng, XorShiftRng};
@@ -107,7 +107,8 @@ impl<T> Tree<T> {
     }
 
     fn update_in_scope<'scope, OP>(&'scope mut self, op: &'scope OP, scope: &Scope<'scope>)
-        where OP: Fn(&mut T) + Sync
+        where OP: Fn(&mut T) + Sync,
+              T: Send
     {
         let Tree { ref mut value, ref mut children } = *self;
         scope.spawn(move |scope| {
@@ -193,3 +194,156 @@ fn the_final_countdown<'scope>(s: &Scope<'scope>,
         s.spawn(move |s| the_final_countdown(s, bottom_of_stack, max, n - 1));
     }
 }
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn panic_propagate_after_all_spawns_complete() {
+    use std::panic;
+
+    let counter = AtomicUsize::new(0);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        scope(|s| {
+            for i in 0..10 {
+                let counter = &counter;
+                s.spawn(move |_| {
+                    if i == 5 {
+                        panic!("scope panic");
+                    }
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        });
+    }));
+    assert!(result.is_err());
+    assert_eq!(counter.load(Ordering::SeqCst), 9);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn panic_in_stolen_task_propagates_to_scope() {
+    use std::panic;
+    use std::thread;
+    use ::current_thread_index;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let (owner, thief) = (Mutex::new(None), Mutex::new(None));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        pool.install(|| {
+            scope(|s| {
+                *owner.lock().unwrap() = current_thread_index();
+                s.spawn(|_| {
+                    *thief.lock().unwrap() = current_thread_index();
+                    panic!("stolen");
+                });
+
+                // The owner does not get to run the task before the
+                // scope body returns, so it must have been stolen.
+                while !s.is_cancelled() {
+                    thread::yield_now();
+                }
+
+                // Only the first panic propagates; this one is dropped.
+                s.spawn(|_| panic!("second"));
+            })
+        })
+    }));
+    let err = result.unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"stolen"));
+    let (owner, thief) = (owner.into_inner().unwrap(), thief.into_inner().unwrap());
+    assert!(owner.is_some() && thief.is_some());
+    assert!(owner != thief);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn panic_cancels_scope() {
+    use std::panic;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Each of the long-running jobs spins until it sees the scope get
+    // cancelled; the timeout merely keeps a broken implementation from
+    // hanging the test.
+    let observed = AtomicUsize::new(0);
+    let start = Instant::now();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        pool.install(|| {
+            scope(|s| {
+                assert!(!s.is_cancelled());
+                for _ in 0..3 {
+                    s.spawn(|s| {
+                        while !s.is_cancelled() {
+                            if start.elapsed() > Duration::from_secs(10) {
+                                return;
+                            }
+                            thread::yield_now();
+                        }
+                        observed.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+                s.spawn(|_| panic!("cancel"));
+            })
+        })
+    }));
+    let err = result.unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"cancel"));
+    assert_eq!(observed.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn collect_panics_returns_all_payloads() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let counter = AtomicUsize::new(0);
+    let panics = pool.install(|| {
+        scope_collect_panics(|s| {
+            for i in 0..20 {
+                let counter = &counter;
+                s.spawn(move |s| {
+                    assert!(!s.is_cancelled());
+                    if i % 4 == 0 {
+                        ::std::panic::resume_unwind(Box::new(i));
+                    }
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        })
+    });
+    assert_eq!(counter.load(Ordering::SeqCst), 15);
+    let mut values: Vec<i32> = panics.into_iter()
+        .map(|err| *err.downcast::<i32>().unwrap())
+        .collect();
+    values.sort();
+    assert_eq!(values, vec![0, 4, 8, 12, 16]);
+}
+
+#[test]
+fn collect_panics_without_panics() {
+    let panics = scope_collect_panics(|s| {
+        s.spawn(|_| ());
+    });
+    assert!(panics.is_empty());
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn collect_panics_is_bounded_and_drops_the_rest() {
+    use std::sync::Arc;
+
+    // Each payload holds a reference to `tracker`, so the count shows
+    // how many of them are still alive.
+    let tracker = Arc::new(());
+    let panics = scope_collect_panics(|s| {
+        for _ in 0..super::MAX_COLLECTED_PANICS + 100 {
+            let tracker = tracker.clone();
+            s.spawn(move |_| {
+                ::std::panic::resume_unwind(Box::new(tracker));
+            });
+        }
+    });
+    assert_eq!(panics.len(), super::MAX_COLLECTED_PANICS);
+    assert_eq!(Arc::strong_count(&tracker), 1 + super::MAX_COLLECTED_PANICS);
+    drop(panics);
+    assert_eq!(Arc::strong_count(&tracker), 1);
+}
@@ -0,0 +1,161 @@
+//! Per-worker scratch memory for job-local temporaries.
+//!
+//! Each worker thread owns an `Arena`, a list of chunks of memory that
+//! it hands out by bumping an offset. `with_scratch()` notes the
+//! current offset, lends the arena to its closure as a `Scratch`, and
+//! moves the offset back once the closure returns; the chunks are kept
+//! for the next call. So in steady state, temporaries allocated from a
+//! `Scratch` never touch the global allocator.
+//!
+//! Calls to `with_scratch()` on one thread nest like stack frames: a
+//! job that this worker steals while it waits inside a `join` runs on
+//! top of the current frame, and is done (and has released its scratch
+//! memory) before the frame below resumes. A stolen job always uses the
+//! arena of the thief, since that is the thread it runs on. Only the
+//! innermost `Scratch` on a thread may allocate, so that releasing it
+//! cannot pull memory out from under an outer one.
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::mem;
+use std::slice;
+use thread_pool::WorkerThread;
+use unwind;
+
+#[cfg(test)]
+mod test;
+
+/// The size of the first chunk of an arena, in bytes; each further
+/// chunk is twice as large as the one before (or as large as the
+/// allocation that needed it).
+const FIRST_CHUNK_SIZE: usize = 4096;
+
+/// Runs `op` with access to the scratch memory of the current worker
+/// thread, through which it can allocate temporaries much more cheaply
+/// than from the heap. The memory is released once `op` returns, so
+/// nothing allocated from the `Scratch` can outlive the call.
+///
+/// Called from outside of a thread pool, `op` gets a fresh arena of its
+/// own, which is freed afterwards; this works, but saves nothing.
+pub fn with_scratch<OP, R>(op: OP) -> R
+    where OP: FnOnce(&Scratch) -> R
+{
+    unsafe {
+        let worker_thread = WorkerThread::current();
+        if worker_thread.is_null() {
+            Arena::new().enter(op)
+        } else {
+            (*worker_thread).scratch().enter(op)
+        }
+    }
+}
+
+/// Scratch memory lent out by `with_scratch()`.
+pub struct Scratch<'a> {
+    arena: &'a Arena,
+
+    /// The nesting depth of the `with_scratch()` call that created us.
+    depth: usize,
+}
+
+impl<'a> Scratch<'a> {
+    /// Allocates a slice of `len` copies of `value`. Only types that
+    /// are `Copy` are supported, since nothing in the slice is ever
+    /// dropped.
+    ///
+    /// Panics if a nested `with_scratch()` call is using the same
+    /// arena, i.e., if this is not the innermost `Scratch` of this
+    /// worker thread. (Memory allocated from an outer `Scratch` while
+    /// an inner one is active would be released along with the inner
+    /// one.)
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice<T>(&'a self, len: usize, value: T) -> &'a mut [T]
+        where T: Copy
+    {
+        assert!(self.depth == self.arena.depth.get(),
+                "can only allocate from the innermost scratch arena");
+        let size = len.checked_mul(mem::size_of::<T>()).expect("capacity overflow");
+        // SAFETY: handing out `&mut` from `&self` is sound because every
+        // call gets memory of its own: `Arena::alloc` bumps the offset
+        // past what it returns, and the offset only moves back once the
+        // `with_scratch()` call that lent out `self` returns, which no
+        // borrow of `self` outlives (and an outer `Scratch` can not
+        // allocate while an inner one is active, as asserted above). So
+        // no two live slices handed out here ever overlap.
+        unsafe {
+            let ptr = self.arena.alloc(size, mem::align_of::<T>()) as *mut T;
+            for i in 0..len {
+                *ptr.offset(i as isize) = value;
+            }
+            slice::from_raw_parts_mut(ptr, len)
+        }
+    }
+}
+
+pub struct Arena {
+    /// Chunks are never freed or resized while the arena lives, so
+    /// memory handed out from them stays put.
+    chunks: RefCell<Vec<Vec<u8>>>,
+
+    /// The chunk we are allocating from, and the offset of the free
+    /// space within it.
+    chunk: Cell<usize>,
+    offset: Cell<usize>,
+
+    /// The number of `with_scratch()` calls using this arena that are
+    /// currently active.
+    depth: Cell<usize>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena {
+            chunks: RefCell::new(Vec::new()),
+            chunk: Cell::new(0),
+            offset: Cell::new(0),
+            depth: Cell::new(0),
+        }
+    }
+
+    fn enter<OP, R>(&self, op: OP) -> R
+        where OP: FnOnce(&Scratch) -> R
+    {
+        let (chunk, offset, depth) = (self.chunk.get(), self.offset.get(), self.depth.get());
+        self.depth.set(depth + 1);
+        let _guard = unwind::finally(self, |arena| {
+            arena.chunk.set(chunk);
+            arena.offset.set(offset);
+            arena.depth.set(depth);
+        });
+        op(&Scratch {
+            arena: self,
+            depth: depth + 1,
+        })
+    }
+
+    /// Returns `size` bytes of memory aligned to `align`, moving on to
+    /// the next chunk (or adding one) if the current one is too full.
+    unsafe fn alloc(&self, size: usize, align: usize) -> *mut u8 {
+        if size == 0 {
+            return align as *mut u8;
+        }
+        let mut chunks = self.chunks.borrow_mut();
+        loop {
+            let index = self.chunk.get();
+            if index == chunks.len() {
+                let previous = chunks.last().map_or(FIRST_CHUNK_SIZE / 2, |chunk| chunk.len());
+                let chunk_size = cmp::max(previous * 2, size + align);
+                chunks.push(vec![0; chunk_size]);
+            }
+            let chunk = &mut chunks[index];
+            let base = chunk.as_mut_ptr() as usize;
+            let start = (base + self.offset.get() + align - 1) / align * align - base;
+            if start + size <= chunk.len() {
+                self.offset.set(start + size);
+                return (base + start) as *mut u8;
+            }
+            self.chunk.set(index + 1);
+            self.offset.set(0);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use Configuration;
+use ThreadPool;
+use {join, with_scratch};
+use std::mem;
+use std::sync::Barrier;
+
+#[test]
+fn scratch_memory_is_reused() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let first = pool.install(|| {
+        with_scratch(|scratch| {
+            let v = scratch.alloc_slice(100, 7u32);
+            assert!(v.iter().all(|&x| x == 7));
+            v.as_ptr() as usize
+        })
+    });
+    let second = pool.install(|| {
+        with_scratch(|scratch| {
+            let v = scratch.alloc_slice(100, 8u32);
+            assert!(v.iter().all(|&x| x == 8));
+            v.as_ptr() as usize
+        })
+    });
+    assert_eq!(first, second);
+}
+
+#[test]
+fn scratch_aligns_and_grows() {
+    with_scratch(|scratch| {
+        let bytes = scratch.alloc_slice(3, 1u8);
+        let words = scratch.alloc_slice(5, 2u64);
+        assert_eq!(words.as_ptr() as usize % mem::align_of::<u64>(), 0);
+
+        // bigger than any chunk so far
+        let big = scratch.alloc_slice(1 << 16, 3u32);
+        assert_eq!(bytes, &[1, 1, 1]);
+        assert_eq!(words, &[2; 5]);
+        assert!(big.iter().all(|&x| x == 3));
+
+        let empty = scratch.alloc_slice(0, ());
+        assert!(empty.is_empty());
+    });
+}
+
+#[test]
+fn scratch_nested_calls_stack() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    pool.install(|| {
+        with_scratch(|outer| {
+            let a = outer.alloc_slice(10, 1u32);
+            let inner_ptr = with_scratch(|inner| {
+                let b = inner.alloc_slice(10, 2u32);
+                b.as_ptr() as usize
+            });
+            let c = outer.alloc_slice(10, 3u32);
+            assert_eq!(a, &[1; 10]);
+
+            // the inner memory was released, so it is handed out again
+            assert_eq!(c.as_ptr() as usize, inner_ptr);
+        })
+    });
+}
+
+#[test]
+#[should_panic(expected = "innermost")]
+fn scratch_outer_alloc_in_inner_panics() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    pool.install(|| with_scratch(|outer| with_scratch(|_| outer.alloc_slice(1, 0u8).len())));
+}
+
+#[test]
+fn scratch_stolen_job_uses_thief_arena() {
+    // Both closures of the `join` hold on to their scratch memory
+    // until the other one has allocated and filled in its own, so if
+    // they shared an arena, one would clobber the other.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let barrier = Barrier::new(2);
+    let fill = |value: u32| {
+        with_scratch(|scratch| {
+            let v = scratch.alloc_slice(1000, value);
+            barrier.wait();
+            barrier.wait();
+            v.iter().all(|&x| x == value)
+        })
+    };
+    assert_eq!(pool.install(|| join(|| fill(1), || fill(2))), (true, true));
+}
@@ -0,0 +1,300 @@
+use PoolError;
+use context;
+use job::{HeapJob, JobMode, JobRef};
+use latch::{Latch, LockLatch, SpinLatch};
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use thread_pool::{Registry, WorkerThread};
+use unwind;
+
+#[cfg(test)]
+mod test;
+
+/// Fires off a task into the Rayon threadpool that will run
+/// asynchronously. If called from within a worker thread, the task
+/// is spawned into the pool of that worker; otherwise, it is spawned
+/// into the global pool.
+///
+/// Unlike `scope()`, `spawn()` does not wait for the task to
+/// complete, and hence the task may only reference data that is
+/// `'static`. If you need to get a result out of the task, you can
+/// send it back over a channel or store it in some shared
+/// (synchronized) location.
+///
+/// If the task panics, there is no caller for the panic to be
+/// propagated to. Instead, the payload is passed to the panic handler
+/// of the pool (see `Configuration::set_panic_handler()`); if no
+/// panic handler was configured, the process aborts.
+///
+/// Note that if the pool is dropped before the task gets a chance to
+/// start, it never executes.
+pub fn spawn<OP>(op: OP)
+    where OP: FnOnce() + Send + 'static
+{
+    spawn_in(op, &Registry::current());
+}
+
+/// Spawns `op` into the given registry. See `spawn()` for details.
+pub fn spawn_in<OP>(op: OP, registry: &Arc<Registry>)
+    where OP: FnOnce() + Send + 'static
+{
+    unsafe {
+        // The job owns a reference to the registry, so that it can
+        // hand any panic over to the panic handler.
+        let job_registry = registry.clone();
+        let context = context::capture();
+        let job_ref = Box::new(HeapJob::new(move |mode| match mode {
+                JobMode::Execute => {
+                    if let Err(err) = unwind::halt_unwinding(|| context.install(op)) {
+                        job_registry.handle_panic(err);
+                    }
+                }
+                JobMode::Abort => {}
+            }))
+            .as_job_ref();
+        // A job of the pool may spawn while the pool is being dropped;
+        // the task is then dropped, as if it had been queued.
+        registry.check_poisoned();
+        registry.inject_or_abort(job_ref);
+    }
+}
+
+/// Pushes a job of your own (see `Job`) into the Rayon threadpool. If
+/// called from within a worker thread, the job goes onto the deque of
+/// that worker, where the worker will get to it once it is done with
+/// newer jobs, unless a thief takes it first; otherwise, it is injected
+/// into the global pool. Nobody waits for the job: it must signal its
+/// own completion, if anybody needs to know.
+///
+/// Unsafe because the job must stay valid until it has been executed,
+/// and must not be executed by anyone else; see `JobRef`. If the pool
+/// is dropped while the job is still waiting to be executed, it is
+/// executed with `JobMode::Abort` if it was injected, and not at all
+/// if it sits on the deque of a worker.
+pub unsafe fn push_job(job_ref: JobRef) {
+    let worker_thread = WorkerThread::current();
+    if worker_thread.is_null() {
+        let registry = Registry::current();
+        registry.check_poisoned();
+        registry.inject_or_abort(job_ref);
+    } else {
+        (*worker_thread).push(job_ref);
+    }
+}
+
+/// Like `spawn()`, but returns a handle that can be used to wait for
+/// the task and get at its result. If called from within a worker
+/// thread, the task is spawned into the pool of that worker;
+/// otherwise, it is spawned into the global pool.
+///
+/// If the task panics, the panic is propagated when `join()` is called
+/// on the handle, just as `join` would propagate it; the panic handler
+/// of the pool is not involved. If the handle is dropped without being
+/// joined, the task still runs, and its result (or panic) is simply
+/// discarded.
+pub fn spawn_handle<F, T>(func: F) -> ScopeHandle<T>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    spawn_handle_in(func, &Registry::current())
+}
+
+/// Spawns `func` into the given registry. See `spawn_handle()` for
+/// details.
+pub fn spawn_handle_in<F, T>(func: F, registry: &Arc<Registry>) -> ScopeHandle<T>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    ScopeHandle { inner: inject_handle(func, registry) }
+}
+
+/// Submits `func` to the given registry. See `ThreadPool::submit()`
+/// for details.
+pub fn submit_in<F, T>(func: F, registry: &Arc<Registry>) -> Result<PendingResult<T>, PoolError>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let (inner, job_ref) = handle_job(func);
+    try!(inject_bounded(&[job_ref], registry));
+    Ok(PendingResult { inner: inner })
+}
+
+/// Submits each of `funcs` to the given registry, injecting them all
+/// at once. See `ThreadPool::submit_all()` for details.
+pub fn submit_all_in<I, F, T>(funcs: I,
+                              registry: &Arc<Registry>)
+                              -> Result<Vec<PendingResult<T>>, PoolError>
+    where I: IntoIterator<Item = F>,
+          F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let (pending, job_refs): (Vec<_>, Vec<_>) = funcs.into_iter()
+        .map(|func| {
+            let (inner, job_ref) = handle_job(func);
+            (PendingResult { inner: inner }, job_ref)
+        })
+        .unzip();
+    if !job_refs.is_empty() {
+        try!(inject_bounded(&job_refs, registry));
+    }
+    Ok(pending)
+}
+
+/// Injects `job_refs` into `registry`, subject to its queue limit. If
+/// they are rejected, they are aborted, which drops their closures.
+fn inject_bounded(job_refs: &[JobRef], registry: &Arc<Registry>) -> Result<(), PoolError> {
+    unsafe {
+        registry.inject_bounded(job_refs).map_err(|err| {
+            for job_ref in job_refs {
+                job_ref.execute(JobMode::Abort);
+            }
+            err
+        })
+    }
+}
+
+/// Injects a heap job running `func` into `registry`, returning the
+/// state it shares with the handle.
+fn inject_handle<F, T>(func: F, registry: &Arc<Registry>) -> Arc<HandleInner<T>>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let (inner, job_ref) = handle_job(func);
+    unsafe {
+        // (as in `spawn_in`, a pool that is being dropped aborts the
+        // job, so that the handle reports `Outcome::Aborted`)
+        registry.check_poisoned();
+        registry.inject_or_abort(job_ref);
+    }
+    inner
+}
+
+/// Creates a heap job running `func`, along with the state it shares
+/// with the handle. The job must be injected, or it leaks.
+fn handle_job<F, T>(func: F) -> (Arc<HandleInner<T>>, JobRef)
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let inner = Arc::new(HandleInner {
+        outcome: Mutex::new(None),
+        spin_latch: SpinLatch::new(),
+        lock_latch: LockLatch::new(),
+    });
+    let job_inner = inner.clone();
+    let context = context::capture();
+    let job_ref = unsafe {
+        Box::new(HeapJob::new(move |mode| {
+                let outcome = match mode {
+                    JobMode::Execute => {
+                        match unwind::halt_unwinding(|| context.install(func)) {
+                            Ok(value) => Outcome::Ok(value),
+                            Err(err) => Outcome::Panic(err),
+                        }
+                    }
+                    JobMode::Abort => Outcome::Aborted,
+                };
+                job_inner.complete(outcome);
+            }))
+            .as_job_ref()
+    };
+    (inner, job_ref)
+}
+
+/// A handle to a task that was spawned with `spawn_handle()`.
+pub struct ScopeHandle<T> {
+    inner: Arc<HandleInner<T>>,
+}
+
+impl<T> ScopeHandle<T> {
+    /// Waits for the task to complete and returns its result, or
+    /// propagates its panic. When called from a worker thread, the
+    /// worker executes other jobs (possibly including the task itself)
+    /// while it waits, like `join` does while waiting for a stolen
+    /// closure; otherwise, the calling thread blocks.
+    ///
+    /// Panics if the pool was dropped before the task got to run.
+    pub fn join(self) -> T {
+        self.inner.wait();
+        self.inner.take_result()
+    }
+}
+
+/// The result of a task that was submitted with
+/// `ThreadPool::submit()`, which may not be available yet.
+pub struct PendingResult<T> {
+    inner: Arc<HandleInner<T>>,
+}
+
+impl<T> PendingResult<T> {
+    /// Returns the result of the task if it has completed, or
+    /// propagates its panic; returns `None` right away if it is not
+    /// done yet. Once this has returned the result, it has been moved
+    /// out, so calling `try_get()` or `get()` again panics.
+    ///
+    /// Panics if the pool was dropped before the task got to run.
+    pub fn try_get(&mut self) -> Option<T> {
+        if self.inner.spin_latch.probe() {
+            Some(self.inner.take_result())
+        } else {
+            None
+        }
+    }
+
+    /// Waits for the task to complete and returns its result, or
+    /// propagates its panic, just like `ScopeHandle::join()`.
+    pub fn get(self) -> T {
+        self.inner.wait();
+        self.inner.take_result()
+    }
+}
+
+enum Outcome<T> {
+    Ok(T),
+    Panic(Box<Any + Send>),
+    Aborted,
+}
+
+/// The state shared between a `ScopeHandle` (or `PendingResult`) and
+/// its task. It has two latches, so that workers can wait by stealing
+/// (which requires a `SpinLatch`) while other threads can block (on
+/// the `LockLatch`).
+struct HandleInner<T> {
+    /// `None` until the task completes.
+    outcome: Mutex<Option<Outcome<T>>>,
+    spin_latch: SpinLatch,
+    lock_latch: LockLatch,
+}
+
+impl<T> HandleInner<T> {
+    fn complete(&self, outcome: Outcome<T>) {
+        *self.outcome.lock().unwrap() = Some(outcome);
+        self.spin_latch.set();
+        self.lock_latch.set();
+    }
+
+    /// Waits for the task to complete, stealing from a worker thread
+    /// and blocking elsewhere.
+    fn wait(&self) {
+        unsafe {
+            let worker_thread = WorkerThread::current();
+            if worker_thread.is_null() {
+                self.lock_latch.wait();
+            } else {
+                (*worker_thread).steal_until(&self.spin_latch);
+            }
+        }
+    }
+
+    /// Takes the result of the completed task, or propagates its panic.
+    fn take_result(&self) -> T {
+        let outcome = self.outcome.lock().unwrap().take();
+        match outcome {
+            Some(Outcome::Ok(value)) => value,
+            Some(Outcome::Panic(err)) => unwind::resume_unwinding(err),
+            Some(Outcome::Aborted) => {
+                panic!("spawned task was dropped because its thread pool terminated")
+            }
+            None => panic!("the result of the task was already taken"),
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use Configuration;
+use ThreadPool;
+use spawn;
+use push_job;
+use job::{Job, JobMode, JobRef};
+use spawn_handle;
+use unwind;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender};
+
+#[test]
+fn spawn_then_join_in_worker() {
+    let (tx, rx) = channel();
+    ::join(move || spawn(move || tx.send(22).unwrap()), move || ());
+    assert_eq!(22, rx.recv().unwrap());
+}
+
+#[test]
+fn spawn_then_join_outside_worker() {
+    let (tx, rx) = channel();
+    spawn(move || tx.send(22).unwrap());
+    assert_eq!(22, rx.recv().unwrap());
+}
+
+#[test]
+fn spawn_in_pool() {
+    let (tx, rx) = channel();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    pool.spawn(move || tx.send(::current_num_threads()).unwrap());
+    assert_eq!(2, rx.recv().unwrap());
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn panic_fwd() {
+    let (tx, rx) = channel();
+
+    let tx = Mutex::new(tx);
+    let panic_handler = move |err: Box<::std::any::Any + Send>| {
+        let tx = tx.lock().unwrap();
+        if let Some(&msg) = err.downcast_ref::<&str>() {
+            if msg == "Hello, world!" {
+                tx.send(1).unwrap();
+            } else {
+                tx.send(2).unwrap();
+            }
+        } else {
+            tx.send(3).unwrap();
+        }
+    };
+
+    let configuration = Configuration::new().set_panic_handler(panic_handler);
+    let pool = ThreadPool::new(configuration).unwrap();
+
+    pool.spawn(move || panic!("Hello, world!"));
+
+    assert_eq!(1, rx.recv().unwrap());
+}
+
+#[test]
+fn spawn_handle_outside_worker() {
+    let handle = spawn_handle(|| 22);
+    assert_eq!(handle.join(), 22);
+}
+
+#[test]
+fn spawn_handle_in_worker() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let sum = pool.install(|| {
+        let handles: Vec<_> = (0..10).map(|i| spawn_handle(move || i * 2)).collect();
+        handles.into_iter().map(|handle| handle.join()).fold(0, |a, b| a + b)
+    });
+    assert_eq!(sum, 90);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn spawn_handle_propagates_panic() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let handle = pool.spawn_handle(|| -> i32 { panic!("Hello, world!") });
+    let err = unwind::halt_unwinding(|| handle.join()).unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"Hello, world!"));
+}
+
+#[test]
+fn spawn_handle_dropped_still_runs() {
+    let (tx, rx) = channel();
+    drop(spawn_handle(move || tx.send(22).unwrap()));
+    assert_eq!(22, rx.recv().unwrap());
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn spawn_while_pool_is_dropped() {
+    use std::thread;
+    use std::time::Duration;
+
+    // by the time the job spawns, `drop(pool)` has terminated the pool;
+    // the new tasks are dropped without running, rather than bringing
+    // down the worker
+    let (tx, rx) = channel();
+    let (handle_tx, handle_rx) = channel();
+    let (started_tx, started_rx) = channel();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let spawned_tx = tx.clone();
+        spawn(move || spawned_tx.send("spawned").unwrap());
+        handle_tx.send(spawn_handle(|| 22)).unwrap();
+        tx.send("done").unwrap();
+    });
+    started_rx.recv().unwrap();
+    drop(pool);
+
+    assert_eq!(rx.iter().collect::<Vec<_>>(), vec!["done"]);
+    let handle = handle_rx.recv().unwrap();
+    let err = unwind::halt_unwinding(|| handle.join()).unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(),
+               Some(&"spawned task was dropped because its thread pool terminated"));
+}
+
+#[test]
+fn submit_then_poll_from_outside() {
+    use std::thread;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let mut pending = pool.submit(|| (0..1000u32).sum::<u32>()).unwrap();
+    loop {
+        if let Some(result) = pending.try_get() {
+            assert_eq!(result, 499500);
+            break;
+        }
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn submit_try_get_before_done() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let (tx, rx) = channel::<()>();
+    let mut pending = pool.submit(move || rx.recv().unwrap()).unwrap();
+    assert!(pending.try_get().is_none());
+    tx.send(()).unwrap();
+    pending.get();
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn submit_propagates_panic() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let pending = pool.submit(|| -> i32 { panic!("Hello, world!") }).unwrap();
+    let err = unwind::halt_unwinding(|| pending.get()).unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"Hello, world!"));
+}
+
+#[test]
+fn submit_all_runs_each_task_once() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let runs: Arc<Vec<AtomicUsize>> = Arc::new((0..1000).map(|_| AtomicUsize::new(0)).collect());
+    let pending = pool.submit_all((0..1000).map(|i| {
+        let runs = runs.clone();
+        move || {
+            runs[i].fetch_add(1, Ordering::SeqCst);
+            i * 2
+        }
+    }))
+        .unwrap();
+    let results: Vec<usize> = pending.into_iter().map(|p| p.get()).collect();
+    assert_eq!(results, (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+    assert!(runs.iter().all(|r| r.load(Ordering::SeqCst) == 1));
+}
+
+#[test]
+fn submit_all_fair_wake() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(3).set_fair_wake(true))
+        .unwrap();
+    let pending = pool.submit_all((0..100u32).map(|i| move || i + 1)).unwrap();
+    let sum: u32 = pending.into_iter().map(|p| p.get()).sum();
+    assert_eq!(sum, 5050);
+}
+
+#[test]
+fn submit_all_empty() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let pending = pool.submit_all(Vec::<fn() -> ()>::new()).unwrap();
+    assert!(pending.is_empty());
+}
+
+/// Submits a task to `pool` that keeps its (only) worker busy until the
+/// returned sender is used or dropped.
+fn occupy_worker(pool: &ThreadPool) -> Sender<()> {
+    let (started_tx, started_rx) = channel();
+    let (release_tx, release_rx) = channel::<()>();
+    pool.submit(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        })
+        .unwrap();
+    started_rx.recv().unwrap();
+    release_tx
+}
+
+#[test]
+fn submit_rejects_when_queue_full() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use PoolError;
+    use QueuePolicy;
+
+    let config = Configuration::new().set_num_threads(1).set_max_queued(4, QueuePolicy::Reject);
+    let pool = ThreadPool::new(config).unwrap();
+    let release = occupy_worker(&pool);
+
+    let runs = Arc::new(AtomicUsize::new(0));
+    let submit = |i: usize| {
+        let runs = runs.clone();
+        pool.submit(move || {
+            runs.fetch_add(1, Ordering::SeqCst);
+            i
+        })
+    };
+    let accepted: Vec<_> = (0..4).map(|i| submit(i).unwrap()).collect();
+    assert_eq!(submit(4).err(), Some(PoolError::QueueFull));
+    assert_eq!(pool.submit_all((0..2).map(|i| move || i)).err(),
+               Some(PoolError::QueueFull));
+
+    drop(release);
+    let results: Vec<usize> = accepted.into_iter().map(|p| p.get()).collect();
+    assert_eq!(results, vec![0, 1, 2, 3]);
+    assert_eq!(runs.load(Ordering::SeqCst), 4);
+
+    // once the queue has drained, there is room again
+    assert_eq!(submit(5).unwrap().get(), 5);
+}
+
+#[test]
+fn submit_blocks_when_queue_full() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use QueuePolicy;
+
+    let config = Configuration::new().set_num_threads(1).set_max_queued(2, QueuePolicy::Block);
+    let pool = Arc::new(ThreadPool::new(config).unwrap());
+    let release = occupy_worker(&pool);
+
+    // flood the pool from another thread, which gets two tasks in and
+    // then has to wait for the worker
+    let submitted = Arc::new(AtomicUsize::new(0));
+    let flood = {
+        let pool = pool.clone();
+        let submitted = submitted.clone();
+        thread::spawn(move || {
+            (0..20)
+                .map(|i| {
+                    let pending = pool.submit(move || i).unwrap();
+                    submitted.fetch_add(1, Ordering::SeqCst);
+                    pending
+                })
+                .collect::<Vec<_>>()
+        })
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(submitted.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.queued_len(), 2);
+
+    drop(release);
+    let results: Vec<usize> = flood.join().unwrap().into_iter().map(|p| p.get()).collect();
+    assert_eq!(results, (0..20).collect::<Vec<_>>());
+}
+
+#[test]
+fn submit_from_worker_does_not_block() {
+    use PoolError;
+    use QueuePolicy;
+
+    // the only worker is busy running the `install` closure, so the
+    // tasks it submits pile up
+    let config = Configuration::new().set_num_threads(1).set_max_queued(2, QueuePolicy::Block);
+    let pool = ThreadPool::new(config).unwrap();
+    let (pending, err) = pool.install(|| {
+        let pending: Vec<_> = (0..2).map(|i| pool.submit(move || i).unwrap()).collect();
+        (pending, pool.submit(|| 2).err())
+    });
+    assert_eq!(err, Some(PoolError::QueueFull));
+    assert_eq!(pending.into_iter().map(|p| p.get()).sum::<i32>(), 1);
+}
+
+/// A job of our own, which owns its allocation and reports how it was
+/// executed.
+struct SendJob {
+    value: usize,
+    sender: Sender<(usize, bool)>,
+}
+
+impl Job for SendJob {
+    unsafe fn execute(this: *const Self, mode: JobMode) {
+        let this = Box::from_raw(this as *mut Self);
+        let executed = match mode {
+            JobMode::Execute => true,
+            JobMode::Abort => false,
+        };
+        this.sender.send((this.value, executed)).unwrap();
+    }
+}
+
+fn push_send_job(value: usize, sender: &Sender<(usize, bool)>) {
+    let job = Box::new(SendJob {
+        value: value,
+        sender: sender.clone(),
+    });
+    unsafe {
+        push_job(JobRef::new(Box::into_raw(job)));
+    }
+}
+
+#[test]
+fn push_job_custom() {
+    let (tx, rx) = channel();
+    push_send_job(1, &tx);
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    pool.install(|| {
+        for i in 2..10 {
+            push_send_job(i, &tx);
+        }
+    });
+    let mut results: Vec<_> = rx.iter().take(9).collect();
+    results.sort();
+    assert_eq!(results, (1..10).map(|i| (i, true)).collect::<Vec<_>>());
+}
@@ -0,0 +1,114 @@
+//! Recursive splitting with an adaptive sequential cutoff.
+//!
+//! Code that divides its work with `join` usually stops splitting at
+//! some fixed size. But the right cutoff depends on what the rest of
+//! the pool is doing: when other threads are idle, splitting further
+//! gives them something to steal; when they are all busy, every extra
+//! split is pure overhead. The `Splitter` type makes this decision at
+//! runtime, using the `migrated` signal from `join_context`.
+
+use api::{self, FnContext};
+use std::cmp;
+
+#[cfg(test)]
+mod test;
+
+/// Decides whether a task in `split()` should be split any further.
+///
+/// A `Splitter` starts out willing to split roughly `log2(N)` times,
+/// where `N` is the number of threads in the current pool, which is
+/// enough to give every thread a piece of the work. Beyond that, it
+/// only keeps splitting while tasks are being stolen: whenever a task
+/// finds that it was migrated to another thread, which is a sign that
+/// some thread ran out of work, the budget is topped up again. Tasks
+/// that keep running inline on the thread that created them soon stop
+/// splitting and just process their state sequentially.
+#[derive(Copy, Clone, Debug)]
+pub struct Splitter {
+    /// How many more times we are willing to split (halved on each
+    /// split, so this is more of a "width" than a depth).
+    splits: usize,
+
+    /// Whether the task currently holding this splitter was stolen.
+    migrated: bool,
+}
+
+impl Splitter {
+    fn new() -> Splitter {
+        Splitter {
+            splits: api::current_num_threads(),
+            migrated: false,
+        }
+    }
+
+    /// True if the current task was stolen by another thread, rather
+    /// than run inline on the thread that split it off.
+    pub fn migrated(&self) -> bool {
+        self.migrated
+    }
+
+    /// Returns true if the current task should be split in two. Each
+    /// call consumes part of the splitting budget, so it should be
+    /// called once per potential split.
+    pub fn try_split(&mut self) -> bool {
+        if self.migrated {
+            // We were stolen, so some other thread is hungry for work;
+            // reset the budget so that we split enough to feed it.
+            self.migrated = false;
+            self.splits = cmp::max(api::current_num_threads(), self.splits / 2);
+            true
+        } else if self.splits > 0 {
+            self.splits /= 2;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Recursively divides `state` in parallel, using `Splitter` to decide
+/// when to stop.
+///
+/// `splitter` is called with each piece of state and a `Splitter`. If
+/// `Splitter::try_split()` says to split (and the state is big enough
+/// to be split), it should return the two halves, which are then
+/// processed in parallel via `join_context`, each with another call
+/// to `splitter`. Otherwise, it should process the state sequentially
+/// and return `None`.
+///
+/// # Example
+///
+/// ```
+/// let v: Vec<u32> = (0..1000).collect();
+/// rayon::split(&v[..], |slice, splitter| {
+///     if slice.len() > 1 && splitter.try_split() {
+///         Some(slice.split_at(slice.len() / 2))
+///     } else {
+///         for x in slice {
+///             assert!(*x < 1000);
+///         }
+///         None
+///     }
+/// });
+/// ```
+pub fn split<S, F>(state: S, splitter: F)
+    where S: Send,
+          F: Fn(S, &mut Splitter) -> Option<(S, S)> + Sync
+{
+    split_helper(state, Splitter::new(), &splitter);
+}
+
+fn split_helper<S, F>(state: S, mut splitter: Splitter, func: &F)
+    where S: Send,
+          F: Fn(S, &mut Splitter) -> Option<(S, S)> + Sync
+{
+    if let Some((left, right)) = func(state, &mut splitter) {
+        let helper = |state, context: FnContext| {
+            let mut splitter = splitter;
+            splitter.migrated = context.migrated();
+            split_helper(state, splitter, func)
+        };
+        api::join_context(|context| helper(left, context),
+                          |context| helper(right, context));
+    }
+}
@@ -0,0 +1,59 @@
+use Configuration;
+use ThreadPool;
+use split;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Splits a slice with `split()`, returning how many times it was
+/// split and the sum of its elements.
+fn count_splits(v: &[usize]) -> (usize, usize) {
+    let splits = AtomicUsize::new(0);
+    let sum = AtomicUsize::new(0);
+    split(v, |slice, splitter| {
+        if slice.len() > 1 && splitter.try_split() {
+            splits.fetch_add(1, Ordering::SeqCst);
+            Some(slice.split_at(slice.len() / 2))
+        } else {
+            sum.fetch_add(slice.iter().sum(), Ordering::SeqCst);
+            None
+        }
+    });
+    (splits.load(Ordering::SeqCst), sum.load(Ordering::SeqCst))
+}
+
+#[test]
+fn split_visits_everything() {
+    let v: Vec<usize> = (0..10000).collect();
+    let (_, sum) = count_splits(&v);
+    assert_eq!(sum, v.iter().sum());
+}
+
+#[test]
+fn split_single_thread_pool() {
+    // Nothing can ever be stolen, so we only split the initial
+    // budget of one.
+    let v: Vec<usize> = (0..10000).collect();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let (splits, sum) = pool.install(|| count_splits(&v));
+    assert_eq!(splits, 1);
+    assert_eq!(sum, v.iter().sum());
+}
+
+#[test]
+fn split_more_threads_more_splits() {
+    // With four threads, we split at least enough to give each thread
+    // a share, i.e. three levels deep.
+    let v: Vec<usize> = (0..10000).collect();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let (splits, sum) = pool.install(|| count_splits(&v));
+    assert!(splits >= 7, "only split {} times", splits);
+    assert_eq!(sum, v.iter().sum());
+}
+
+#[test]
+fn split_respects_state() {
+    // The closure may decline to split even when the splitter agrees.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let (splits, sum) = pool.install(|| count_splits(&[1, 2]));
+    assert_eq!(splits, 1);
+    assert_eq!(sum, 3);
+}
@@ -0,0 +1,182 @@
+//! Running a closure on a fresh stack segment, for workers whose stack
+//! is running low; see `Configuration::set_growable_stacks`.
+//!
+//! A segment is a block of memory from `mmap`, with an inaccessible
+//! page at its low end, so that overflowing it faults rather than
+//! corrupting whatever lies below. The switch onto the segment and back
+//! goes through `swapcontext`, so this is only available where the C
+//! library provides it, and where we know how to split a pointer into
+//! the `int` arguments that `makecontext` passes on: glibc on x86_64
+//! and aarch64. Elsewhere, `on_new_segment` always runs the closure on
+//! the caller's own stack.
+//!
+//! A panic can not unwind from the segment into the frames on the
+//! original stack, which `swapcontext` left; it is caught on the
+//! segment, and resumed once we are back.
+
+#[cfg(all(target_os = "linux",
+          target_env = "gnu",
+          any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod imp {
+    use libc;
+    use std::cell::RefCell;
+    use std::cmp;
+    use std::mem;
+    use std::ptr;
+    use std::thread;
+    use unwind;
+
+    /// The smallest segment we allocate, whatever was configured.
+    const MIN_SEGMENT_SIZE: usize = 64 * 1024;
+
+    /// How many free segments each thread keeps around for reuse.
+    const MAX_FREE_SEGMENTS: usize = 4;
+
+    /// A stack segment, along with its guard page.
+    struct Segment {
+        base: *mut libc::c_void,
+        len: usize,
+    }
+
+    impl Segment {
+        fn new(len: usize) -> Option<Segment> {
+            let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+            unsafe {
+                let base = libc::mmap(ptr::null_mut(),
+                                      len,
+                                      libc::PROT_READ | libc::PROT_WRITE,
+                                      libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_STACK,
+                                      -1,
+                                      0);
+                if base == libc::MAP_FAILED {
+                    return None;
+                }
+                let segment = Segment { base: base, len: len };
+                if libc::mprotect(base, page, libc::PROT_NONE) != 0 {
+                    return None;
+                }
+                Some(segment)
+            }
+        }
+    }
+
+    impl Drop for Segment {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.base, self.len);
+            }
+        }
+    }
+
+    thread_local! {
+        static FREE_SEGMENTS: RefCell<Vec<Segment>> = RefCell::new(Vec::new())
+    }
+
+    /// Takes a free segment of `len` bytes, or allocates one.
+    fn take_segment(len: usize) -> Option<Segment> {
+        let free = FREE_SEGMENTS.with(|free| {
+            let mut free = free.borrow_mut();
+            match free.iter().position(|segment| segment.len == len) {
+                Some(index) => Some(free.swap_remove(index)),
+                None => None,
+            }
+        });
+        free.or_else(|| Segment::new(len))
+    }
+
+    fn release_segment(segment: Segment) {
+        FREE_SEGMENTS.with(|free| {
+            let mut free = free.borrow_mut();
+            if free.len() < MAX_FREE_SEGMENTS {
+                free.push(segment);
+            }
+        });
+    }
+
+    /// What the segment's first frame needs, and leaves behind.
+    struct Call<F, R> {
+        func: Option<F>,
+        low_end: usize,
+        result: Option<thread::Result<R>>,
+    }
+
+    /// The first frame on a segment: runs the closure of the `Call`
+    /// that `hi` and `lo` point to, and returns, which switches back
+    /// to the context in `uc_link`.
+    extern "C" fn trampoline<F, R>(hi: u32, lo: u32)
+        where F: FnOnce(Option<usize>) -> R
+    {
+        unsafe {
+            let call = &mut *((((hi as usize) << 32) | lo as usize) as *mut Call<F, R>);
+            let func = call.func.take().unwrap();
+            let low_end = call.low_end;
+            call.result = Some(unwind::halt_unwinding(move || func(Some(low_end))));
+        }
+    }
+
+    pub fn on_new_segment<F, R>(size: usize, func: F) -> R
+        where F: FnOnce(Option<usize>) -> R
+    {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+        let usable = (cmp::max(size, MIN_SEGMENT_SIZE) + page - 1) / page * page;
+        let segment = match take_segment(usable + page) {
+            Some(segment) => segment,
+            None => return func(None),
+        };
+        let low_end = segment.base as usize + page;
+        let mut call = Call {
+            func: Some(func),
+            low_end: low_end,
+            result: None,
+        };
+        unsafe {
+            let mut caller: libc::ucontext_t = mem::zeroed();
+            let mut callee: libc::ucontext_t = mem::zeroed();
+            if libc::getcontext(&mut callee) != 0 {
+                release_segment(segment);
+                return (call.func.take().unwrap())(None);
+            }
+            callee.uc_stack.ss_sp = low_end as *mut libc::c_void;
+            callee.uc_stack.ss_size = usable;
+            callee.uc_link = &mut caller;
+            let address = &mut call as *mut Call<F, R> as usize;
+            let entry: extern "C" fn(u32, u32) = trampoline::<F, R>;
+            libc::makecontext(&mut callee,
+                              mem::transmute::<extern "C" fn(u32, u32), extern "C" fn()>(entry),
+                              2,
+                              (address >> 32) as u32,
+                              address as u32);
+            if libc::swapcontext(&mut caller, &callee) != 0 {
+                release_segment(segment);
+                return (call.func.take().unwrap())(None);
+            }
+        }
+        release_segment(segment);
+        match call.result.take().unwrap() {
+            Ok(result) => result,
+            Err(err) => unwind::resume_unwinding(err),
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux",
+              target_env = "gnu",
+              any(target_arch = "x86_64", target_arch = "aarch64"))))]
+mod imp {
+    pub fn on_new_segment<F, R>(_size: usize, func: F) -> R
+        where F: FnOnce(Option<usize>) -> R
+    {
+        func(None)
+    }
+}
+
+/// Runs `func` on a fresh stack segment of (at least) `size` bytes,
+/// passing it the lowest usable address of the segment, and returns its
+/// result; a panic in `func` is propagated. If no segment can be had,
+/// e.g. because the platform is not supported, `func` runs right here,
+/// and gets `None`.
+pub fn on_new_segment<F, R>(size: usize, func: F) -> R
+    where F: FnOnce(Option<usize>) -> R
+{
+    imp::on_new_segment(size, func)
+}
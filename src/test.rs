@@ -3,9 +3,12 @@
 extern crate compiletest_rs as compiletest;
 
 use api::*;
+#[cfg(feature = "log")]
+use schedule::StealMode;
 use rand::{Rng, SeedableRng, XorShiftRng};
 use std::path::PathBuf;
 use std::error::Error;
+use std::time::Duration;
 
 fn quick_sort<T: PartialOrd + Send>(v: &mut [T]) {
     if v.len() <= 1 {
@@ -168,3 +171,2776 @@ fn coerce_to_box_error() {
     // check that coercion succeeds
     let _: Box<Error> = From::from(InitError::NumberOfThreadsZero);
 }
+
+#[test]
+fn join_n_results_in_order() {
+    let mut opers: Vec<_> = (0..8).map(|i| move || i * 10).collect();
+    let results = join_n(&mut opers);
+    assert_eq!(results, vec![0, 10, 20, 30, 40, 50, 60, 70]);
+}
+
+#[test]
+fn join_n_empty_and_single() {
+    let mut none: Vec<fn() -> i32> = vec![];
+    assert_eq!(join_n(&mut none), Vec::<i32>::new());
+
+    let mut one = [|| 22];
+    assert_eq!(join_n(&mut one), vec![22]);
+}
+
+#[test]
+fn join_n_in_pool() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let results = pool.install(|| {
+        let mut opers: Vec<_> = (0..5)
+            .map(|i| {
+                move || {
+                    let (a, b) = join(|| i, || i);
+                    a + b
+                }
+            })
+            .collect();
+        join_n(&mut opers)
+    });
+    assert_eq!(results, vec![0, 2, 4, 6, 8]);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_n_panic_waits_for_all() {
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // (in a pool with a single thread, `join_n` would run the closures
+    // in turn, and stop at the one that panics)
+    let counter = AtomicUsize::new(0);
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut opers: Vec<_> = (0..6)
+            .map(|i| {
+                let counter = &counter;
+                move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    if i == 3 {
+                        panic!("join_n panic");
+                    }
+                }
+            })
+            .collect();
+        pool.install(|| join_n(&mut opers));
+    }));
+    assert!(result.is_err());
+    assert_eq!(counter.load(Ordering::SeqCst), 6);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_n_head_panic_runs_all() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use unwind;
+
+    // nobody steals the other closures, so they are still on the deque
+    // when the first one panics, and must be run before the panic goes
+    // on; a panic of their own is dropped in favor of the first one
+    let counter = AtomicUsize::new(0);
+    with_busy_thief(|| {
+        let err = unwind::halt_unwinding(|| {
+                let mut opers: Vec<_> = (0..6)
+                    .map(|i| {
+                        let counter = &counter;
+                        move || {
+                            if i > 0 {
+                                counter.fetch_add(1, Ordering::SeqCst);
+                            }
+                            if i % 2 == 0 {
+                                panic!("join_n panic {}", i);
+                            }
+                        }
+                    })
+                    .collect();
+                join_n(&mut opers);
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<String>().map(|s| &s[..]), Some("join_n panic 0"));
+    });
+    assert_eq!(counter.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn spin_latch_has_own_cache_line() {
+    use SpinLatch;
+    use latch::CACHE_LINE;
+    use std::mem;
+
+    // the flag is padded on both sides (see `SpinLatch`), rather than
+    // aligned, so that any cache line it falls into is within the latch
+    assert_eq!(mem::size_of::<SpinLatch>(), 2 * CACHE_LINE - 1);
+    assert_eq!(mem::align_of::<SpinLatch>(), 1);
+}
+
+#[test]
+fn join3_distinct_result_types() {
+    use join3;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let (a, b, c) = pool.install(|| {
+        join3(|| 22,
+              || format!("{}-{}", "twenty", "two"),
+              || vec![2u8, 2])
+    });
+    assert_eq!(a, 22);
+    assert_eq!(b, "twenty-two");
+    assert_eq!(c, vec![2u8, 2]);
+
+    // and from outside of any pool
+    assert_eq!(join3(|| 1, || "2".to_string(), || vec![3u8]),
+               (1, "2".to_string(), vec![3u8]));
+}
+
+#[test]
+fn join4_nested() {
+    use join4;
+
+    fn sum(v: &[u64]) -> u64 {
+        if v.len() < 4 {
+            v.iter().sum()
+        } else {
+            let quarter = v.len() / 4;
+            let (a, b, c, d) = join4(|| sum(&v[..quarter]),
+                                     || sum(&v[quarter..2 * quarter]),
+                                     || sum(&v[2 * quarter..3 * quarter]),
+                                     || sum(&v[3 * quarter..]));
+            a + b + c + d
+        }
+    }
+
+    let v: Vec<u64> = (0..1000).collect();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    assert_eq!(pool.install(|| sum(&v)), 499500);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join3_propagates_first_panic_after_all_done() {
+    use join3;
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let counter = AtomicUsize::new(0);
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        pool.install(|| {
+            join3(|| {
+                      counter.fetch_add(1, Ordering::SeqCst);
+                  },
+                  || -> i32 {
+                      counter.fetch_add(1, Ordering::SeqCst);
+                      panic!("b")
+                  },
+                  || -> String {
+                      counter.fetch_add(1, Ordering::SeqCst);
+                      panic!("c")
+                  })
+        })
+    }));
+    assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "b");
+    assert_eq!(counter.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join3_head_panic_runs_tail() {
+    use join3;
+    use join4;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use unwind;
+
+    // nobody steals the tail closures, so they are still on the deque
+    // when `oper_a` panics, and must be run before the panic goes on; a
+    // panic of their own is dropped in favor of that of `oper_a`
+    let tail_ran = AtomicUsize::new(0);
+    with_busy_thief(|| {
+        let err = unwind::halt_unwinding(|| {
+                join3(|| -> i32 { panic!("a") },
+                      || {
+                          tail_ran.fetch_add(1, Ordering::SeqCst);
+                      },
+                      || -> i32 {
+                          tail_ran.fetch_add(1, Ordering::SeqCst);
+                          panic!("c")
+                      })
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"a"));
+    });
+    assert_eq!(tail_ran.load(Ordering::SeqCst), 2);
+
+    // with thieves around, some of the tail closures may be stolen
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    for i in 0..100 {
+        let err = unwind::halt_unwinding(|| {
+                pool.install(|| {
+                    let tail = || {
+                        tail_ran.fetch_add(1, Ordering::SeqCst);
+                    };
+                    join4(|| -> i32 { panic!("a") }, &tail, &tail, &tail)
+                })
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"a"));
+        assert_eq!(tail_ran.load(Ordering::SeqCst), 2 + 3 * (i + 1));
+    }
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join3_join4_join_n_note_no_origin() {
+    use PanicOrigin;
+    use join3;
+    use join4;
+    use unwind;
+
+    // in any of their closures, and whether they run in parallel or in
+    // turn, the panic comes out with no origin, unless a `join` inside
+    // the closure that panicked noted one
+    for &num_threads in &[1, 2] {
+        let pool = ThreadPool::new(Configuration::new().set_num_threads(num_threads)).unwrap();
+        let origin = |op: &(Fn() + Sync)| {
+            let err = unwind::halt_unwinding(|| pool.install(|| op())).unwrap_err();
+            join_panic_origin(&err)
+        };
+        assert_eq!(origin(&|| {
+                       join3(|| panic!("a"), || (), || ());
+                   }),
+                   None);
+        assert_eq!(origin(&|| {
+                       join3(|| (), || (), || panic!("c"));
+                   }),
+                   None);
+        assert_eq!(origin(&|| {
+                       join4(|| (), || panic!("b"), || (), || ());
+                   }),
+                   None);
+        assert_eq!(origin(&|| {
+                       let mut opers: Vec<_> = (0..3)
+                           .map(|i| move || if i == 1 {
+                               panic!("1")
+                           })
+                           .collect();
+                       join_n(&mut opers);
+                   }),
+                   None);
+        assert_eq!(origin(&|| {
+                       join3(|| (), || join(|| (), || panic!("b")), || ());
+                   }),
+                   Some(PanicOrigin::OperB));
+    }
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_catch_both_panic() {
+    let (a, b) = join_catch(|| -> i32 { panic!("left") },
+                            || -> i32 { panic!("right") });
+    assert_eq!(*a.unwrap_err().downcast::<&str>().unwrap(), "left");
+    assert_eq!(*b.unwrap_err().downcast::<&str>().unwrap(), "right");
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_catch_ok() {
+    let (a, b) = join_catch(|| 1, || -> i32 { panic!("right") });
+    assert_eq!(a.unwrap(), 1);
+    assert!(b.is_err());
+}
+
+#[test]
+fn join_try_ok_and_err() {
+    fn parse(s: &str) -> Result<i32, String> {
+        s.parse().map_err(|_| format!("bad: {}", s))
+    }
+
+    fn parse_both(a: &str, b: &str) -> Result<i32, String> {
+        let (a, b) = try!(join_try(|| parse(a), || parse(b)));
+        Ok(a + b)
+    }
+
+    assert_eq!(parse_both("1", "2"), Ok(3));
+    assert_eq!(parse_both("x", "2"), Err("bad: x".to_string()));
+    assert_eq!(parse_both("1", "y"), Err("bad: y".to_string()));
+    assert_eq!(parse_both("x", "y"), Err("bad: x".to_string()));
+}
+
+#[test]
+fn join_try_runs_both() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let b_ran = AtomicBool::new(false);
+    let result: Result<((), ()), &str> = join_try(|| Err("a"), || {
+        b_ran.store(true, Ordering::SeqCst);
+        Ok(())
+    });
+    assert_eq!(result, Err("a"));
+    assert!(b_ran.load(Ordering::SeqCst));
+}
+
+#[test]
+fn join_weighted_runs_heavier_inline() {
+    use std::sync::Barrier;
+
+    // The barrier forces the two closures onto distinct workers; the
+    // lighter one must be the one that moves.
+    let barrier = Barrier::new(2);
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    for &(weight_a, weight_b, heavy_is_a) in &[(1.0, 10.0, false), (10.0, 1.0, true)] {
+        let (caller, (a, b)) = pool.install(|| {
+            let index = || {
+                barrier.wait();
+                current_thread_index().unwrap()
+            };
+            (current_thread_index().unwrap(), join_weighted(weight_a, weight_b, &index, &index))
+        });
+        assert_eq!(a == caller, heavy_is_a);
+        assert_eq!(b == caller, !heavy_is_a);
+    }
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_weighted_panics_like_join() {
+    use PanicOrigin;
+    use std::panic::{self, AssertUnwindSafe};
+
+    // the origin refers to the arguments, whichever way round the
+    // closures ran
+    fn payload(result: ::std::thread::Result<((), ())>) -> (&'static str, Option<PanicOrigin>) {
+        let err = result.unwrap_err();
+        let origin = join_panic_origin(&err);
+        (*err.downcast::<&'static str>().unwrap(), origin)
+    }
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    for &(weight_a, weight_b) in &[(1.0, 10.0), (10.0, 1.0)] {
+        let both = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.install(|| {
+                join_weighted(weight_a, weight_b, || panic!("a"), || panic!("b"))
+            })
+        }));
+        assert_eq!(payload(both), ("a", Some(PanicOrigin::OperA)));
+
+        let only_a = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.install(|| join_weighted(weight_a, weight_b, || panic!("a"), || ()))
+        }));
+        assert_eq!(payload(only_a), ("a", Some(PanicOrigin::OperA)));
+
+        let only_b = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.install(|| join_weighted(weight_a, weight_b, || (), || panic!("b")))
+        }));
+        assert_eq!(payload(only_b), ("b", Some(PanicOrigin::OperB)));
+    }
+}
+
+#[test]
+fn join_local_stays_on_node() {
+    use std::sync::Barrier;
+
+    // The barrier forces `oper_b` to be stolen; with two nodes of two
+    // workers each, only the other worker on the caller's node may
+    // take it.
+    let barrier = Barrier::new(2);
+    let config = Configuration::new().set_num_threads(4).set_numa_node(|index| index % 2);
+    let pool = ThreadPool::new(config).unwrap();
+    for _ in 0..20 {
+        let (caller, (a, b)) = pool.install(|| {
+            let index = || {
+                barrier.wait();
+                current_thread_index().unwrap()
+            };
+            (current_thread_index().unwrap(), join_local(&index, &index))
+        });
+        assert_eq!(a, caller);
+        assert!(b != caller);
+        assert_eq!(b % 2, caller % 2);
+    }
+}
+
+#[test]
+fn join_local_sum() {
+    fn sum(v: &[u64]) -> u64 {
+        if v.len() <= 16 {
+            return v.iter().fold(0, |a, &b| a + b);
+        }
+        let (left, right) = v.split_at(v.len() / 2);
+        let (a, b) = join_local(|| sum(left), || sum(right));
+        a + b
+    }
+
+    let v: Vec<u64> = (0..10000).collect();
+    let config = Configuration::new().set_num_threads(4).set_numa_node(|index| index / 2);
+    let pool = ThreadPool::new(config).unwrap();
+    assert_eq!(pool.install(|| sum(&v)), 10000 * 9999 / 2);
+    assert_eq!(sum(&v), 10000 * 9999 / 2);
+}
+
+#[test]
+fn current_num_threads_in_pool() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(3)).unwrap();
+    let (a, b) = pool.install(|| join(current_num_threads, current_num_threads));
+    assert_eq!((a, b), (3, 3));
+}
+
+#[test]
+fn current_thread_index_in_pool() {
+    use std::sync::{Barrier, Mutex};
+
+    // The barrier forces each of the four jobs onto a distinct worker.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let barrier = Barrier::new(4);
+    let indices = Mutex::new(vec![]);
+    pool.install(|| {
+        ::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|_| {
+                    barrier.wait();
+                    indices.lock().unwrap().push(current_thread_index().unwrap());
+                });
+            }
+        })
+    });
+    let mut indices = indices.into_inner().unwrap();
+    indices.sort();
+    assert_eq!(indices, vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn current_thread_index_outside_pool() {
+    assert_eq!(current_thread_index(), None);
+}
+
+#[test]
+fn is_worker_thread_in_and_out_of_pools() {
+    use std::thread;
+
+    assert!(!is_worker_thread());
+    assert!(!thread::spawn(is_worker_thread).join().unwrap());
+
+    // on whichever worker runs either closure of `join`, and in nested
+    // `install` calls, each of which runs on a worker of its own pool
+    let pool2 = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let pool3 = ThreadPool::new(Configuration::new().set_num_threads(3)).unwrap();
+    pool2.install(|| {
+        assert_eq!(join(is_worker_thread, is_worker_thread), (true, true));
+        assert!(pool3.install(|| {
+            assert_eq!(join(is_worker_thread, is_worker_thread), (true, true));
+            is_worker_thread()
+        }));
+        assert!(is_worker_thread());
+    });
+    assert!(pool3.install(is_worker_thread));
+
+    // the calling thread is none the worse for it
+    assert!(!is_worker_thread());
+}
+
+#[test]
+fn current_num_threads_outside_pool() {
+    use thread_pool;
+    assert_eq!(current_num_threads(), thread_pool::get_registry().num_threads());
+}
+
+#[test]
+fn nested_install_confines_work() {
+    let pool2 = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let pool3 = ThreadPool::new(Configuration::new().set_num_threads(3)).unwrap();
+    let counts = pool2.install(|| {
+        let before = current_num_threads();
+        let inner = pool3.install(|| join(current_num_threads, current_num_threads));
+        let after = join(current_num_threads, current_num_threads);
+        (before, inner, after)
+    });
+    assert_eq!(counts, (2, (3, 3), (2, 2)));
+}
+
+#[test]
+fn nested_install_same_pool_runs_inline() {
+    // With a single worker, re-dispatching the inner `install` would
+    // deadlock, since that worker is the one waiting for it.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let result = pool.install(|| join(|| pool.install(|| join(|| 11, || 22)), || 33));
+    assert_eq!(result, ((11, 22), 33));
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn install_panic_leaves_caller_as_it_was() {
+    use std::panic::{self, AssertUnwindSafe};
+    use with_context;
+    use current_context;
+
+    // `install` runs its closure on a worker of the pool, so a panic in
+    // there must not change which pool (if any) the caller is on, nor
+    // its context
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(3)).unwrap();
+    let before = (current_thread_index(), current_num_threads());
+    let context = with_context(7u32, || {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.install(|| -> () { panic!("Hello, world!") })
+        }));
+        assert!(result.is_err());
+        current_context::<u32>()
+    });
+    assert_eq!(context, Some(7));
+    assert_eq!((current_thread_index(), current_num_threads()), before);
+    assert_eq!(current_context::<u32>(), None);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn nested_install_panic_leaves_outer_pool() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let pool2 = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let pool3 = ThreadPool::new(Configuration::new().set_num_threads(3)).unwrap();
+    let (before, after) = pool2.install(|| {
+        let before = (current_thread_index(), current_num_threads());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool3.install(|| {
+                assert_eq!(current_num_threads(), 3);
+                panic!("Hello, world!")
+            })
+        }));
+        assert!(result.is_err());
+        let after = (current_thread_index(), current_num_threads());
+        (before, after)
+    });
+    assert_eq!(before, after);
+    assert_eq!(after.1, 2);
+    assert_eq!(pool2.install(|| join(current_num_threads, current_num_threads)), (2, 2));
+}
+
+#[test]
+fn install_catch_ok() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let result = pool.install_catch(|| join(|| 1, || 2));
+    assert_eq!(result.ok(), Some((1, 2)));
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn install_catch_returns_panic() {
+    // the panic comes out of a `join` on a worker of the pool, and is
+    // handed back rather than unwinding this thread
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let result = pool.install_catch(|| join(|| 1, || -> i32 { panic!("Hello, world!") }));
+    let payload = result.unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"Hello, world!"));
+
+    // the pool is still usable afterwards
+    assert_eq!(pool.install_catch(|| current_num_threads()).ok(), Some(2));
+}
+
+#[test]
+fn yield_now_outside_pool() {
+    assert!(!yield_now());
+}
+
+#[test]
+fn yield_now_runs_queued_job() {
+    use spawn;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    pool.install(|| {
+        // the only worker is busy with us, so the spawned job can only
+        // ever run if we yield to it
+        let done = Arc::new(AtomicBool::new(false));
+        let job_done = done.clone();
+        spawn(move || job_done.store(true, Ordering::SeqCst));
+        while !done.load(Ordering::SeqCst) {
+            yield_now();
+        }
+        assert!(!yield_now());
+    });
+}
+
+#[test]
+fn scope_in_pool_stays_in_pool() {
+    use scope;
+    use std::sync::Mutex;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(3)).unwrap();
+    let counts = Mutex::new(vec![]);
+    pool.install(|| {
+        scope(|s| {
+            for _ in 0..10 {
+                s.spawn(|_| counts.lock().unwrap().push(current_num_threads()));
+            }
+        });
+    });
+    assert_eq!(counts.into_inner().unwrap(), vec![3; 10]);
+}
+
+#[test]
+fn join_context_both() {
+    // If we're not in a pool, both should be marked stolen as they're injected.
+    let (a_migrated, b_migrated) = join_context(|a| a.migrated(), |b| b.migrated());
+    assert!(a_migrated);
+    assert!(b_migrated);
+}
+
+#[test]
+fn join_context_neither() {
+    // If we're already in a 1-thread pool, neither job should be stolen.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let (a_migrated, b_migrated) =
+        pool.install(|| join_context(|a| a.migrated(), |b| b.migrated()));
+    assert!(!a_migrated);
+    assert!(!b_migrated);
+}
+
+#[test]
+fn deterministic_join_runs_in_order() {
+    use std::sync::Mutex;
+
+    fn visit(log: &Mutex<Vec<(usize, Option<usize>)>>, lo: usize, hi: usize) {
+        if hi - lo == 1 {
+            log.lock().unwrap().push((lo, current_thread_index()));
+        } else {
+            let mid = (lo + hi) / 2;
+            join(|| visit(log, lo, mid), || visit(log, mid, hi));
+        }
+    }
+
+    let config = Configuration::new().set_num_threads(4).set_deterministic(true);
+    let pool = ThreadPool::new(config).unwrap();
+    for _ in 0..10 {
+        let log = Mutex::new(Vec::new());
+        pool.install(|| visit(&log, 0, 1024));
+        let log = log.into_inner().unwrap();
+        let thread = log[0].1;
+        assert!(thread.is_some());
+        assert!(log.iter().all(|&(_, t)| t == thread));
+        assert_eq!(log.iter().map(|&(i, _)| i).collect::<Vec<_>>(),
+                   (0..1024).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn join_depth_limit_deep_recursion() {
+    fn chain(n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            join(|| chain(n - 1), || 1).0 + 1
+        }
+    }
+
+    let config = Configuration::new().set_num_threads(4).set_join_depth_limit(16);
+    let pool = ThreadPool::new(config).unwrap();
+    assert_eq!(pool.install(|| chain(2000)), 2000);
+}
+
+#[test]
+fn stack_guard_deep_recursion() {
+    fn chain(n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            join(|| chain(n - 1), || 1).0 + 1
+        }
+    }
+
+    // In a debug build, a `join` that pushes its second closure takes
+    // more than a kilobyte of stack, so this recursion does not fit
+    // into 2 MiB. Once half of the stack is used up, `join` runs its
+    // closures in turn, which takes about half as much, so it does.
+    let config = Configuration::new()
+        .set_num_threads(2)
+        .set_stack_size(2 << 20)
+        .set_stack_guard(1 << 20);
+    let pool = ThreadPool::new(config).unwrap();
+    assert_eq!(pool.install(|| chain(1800)), 1800);
+}
+
+#[test]
+#[cfg(all(target_os = "linux",
+          target_env = "gnu",
+          any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn growable_stacks_deep_recursion() {
+    fn chain(n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            join(|| chain(n - 1), || (0..10).sum::<usize>()).0 + 1
+        }
+    }
+
+    // In a debug build, this takes some 20 MiB of stack, in segments of
+    // 1 MiB; the workers' own stacks only have 256 KiB.
+    let config = Configuration::new()
+        .set_num_threads(2)
+        .set_stack_size(256 << 10)
+        .set_stack_guard(64 << 10)
+        .set_growable_stacks(1 << 20);
+    let pool = ThreadPool::new(config).unwrap();
+    for _ in 0..3 {
+        assert_eq!(pool.install(|| chain(20000)), 20000);
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "linux",
+          target_env = "gnu",
+          any(target_arch = "x86_64", target_arch = "aarch64"),
+          not(panic = "abort")))]
+fn growable_stacks_propagate_panic() {
+    use std::panic;
+
+    fn chain(n: usize) -> usize {
+        if n == 0 {
+            panic!("bottom");
+        }
+        join(|| chain(n - 1), || 1).0 + 1
+    }
+
+    let config = Configuration::new()
+        .set_num_threads(2)
+        .set_stack_size(256 << 10)
+        .set_stack_guard(64 << 10)
+        .set_growable_stacks(256 << 10);
+    let pool = ThreadPool::new(config).unwrap();
+    let err = panic::catch_unwind(panic::AssertUnwindSafe(|| pool.install(|| chain(5000))))
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"bottom"));
+
+    // the pool (and its workers' stack limits) survived
+    assert_eq!(pool.install(|| join(|| 1, || 2)), (1, 2));
+}
+
+#[test]
+fn spin_iterations_zero_stolen_join_completes() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    // `oper_a` only returns once `oper_b` has started, so `oper_b` must
+    // be stolen by the other worker, which is asleep when it is pushed;
+    // then the caller waits for it, sleeping between polls.
+    let config = Configuration::new().set_num_threads(2).set_spin_iterations(0);
+    let pool = ThreadPool::new(config).unwrap();
+    for _ in 0..10 {
+        thread::sleep(Duration::from_millis(5));
+        let started = AtomicBool::new(false);
+        let (a, b) = pool.install(|| {
+            join(|| {
+                     while !started.load(Ordering::SeqCst) {
+                         thread::yield_now();
+                     }
+                     current_thread_index()
+                 },
+                 || {
+                     started.store(true, Ordering::SeqCst);
+                     thread::sleep(Duration::from_millis(10));
+                     current_thread_index()
+                 })
+        });
+        assert!(a != b);
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn spin_iterations_zero_waits_without_cpu() {
+    use {CountLatch, Latch};
+    use libc;
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    fn thread_cpu_time() -> Duration {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe {
+            assert_eq!(libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts), 0);
+        }
+        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+    }
+
+    // A worker waits for 200ms for a latch that another thread sets.
+    let config = Configuration::new().set_num_threads(1).set_spin_iterations(0);
+    let pool = ThreadPool::new(config).unwrap();
+    let latch = Arc::new(CountLatch::new(1));
+    let (tx, rx) = channel();
+    let setter = {
+        let latch = latch.clone();
+        thread::spawn(move || {
+            rx.recv().unwrap();
+            thread::sleep(Duration::from_millis(200));
+            latch.set();
+        })
+    };
+    let cpu_time = pool.install(|| {
+        let start = thread_cpu_time();
+        tx.send(()).unwrap();
+        wait_until(&*latch);
+        thread_cpu_time() - start
+    });
+    setter.join().unwrap();
+    assert!(cpu_time < Duration::from_millis(50), "waiting took {:?} of CPU", cpu_time);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn affinity_pins_workers() {
+    use CoreId;
+    use libc;
+    use std::mem;
+
+    fn current_core() -> usize {
+        let cpu = unsafe { libc::sched_getcpu() };
+        assert!(cpu >= 0);
+        cpu as usize
+    }
+
+    // pin all workers to the last core that we may run on, three
+    // workers per core if there is only one
+    let core = unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        assert_eq!(libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set), 0);
+        (0..libc::CPU_SETSIZE as usize).filter(|&cpu| libc::CPU_ISSET(cpu, &set)).last().unwrap()
+    };
+    let config = Configuration::new().set_num_threads(3).set_affinity(move |_| Some(CoreId(core)));
+    let pool = ThreadPool::new(config).unwrap();
+    for _ in 0..10 {
+        let (a, b) = pool.install(|| join(current_core, current_core));
+        assert_eq!((a, b), (core, core));
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn affinity_to_missing_core_is_ignored() {
+    use CoreId;
+
+    let config = Configuration::new()
+        .set_num_threads(2)
+        .set_affinity(|index| if index == 0 { Some(CoreId(::std::usize::MAX)) } else { None });
+    let pool = ThreadPool::new(config).unwrap();
+    assert_eq!(pool.install(|| join(|| 1, || 2)), (1, 2));
+}
+
+#[test]
+fn join_depth_limit_zero_never_splits() {
+    use std::sync::Mutex;
+
+    fn visit(log: &Mutex<Vec<usize>>, lo: usize, hi: usize) {
+        if hi - lo == 1 {
+            log.lock().unwrap().push(lo);
+        } else {
+            let mid = (lo + hi) / 2;
+            join(|| visit(log, lo, mid), || visit(log, mid, hi));
+        }
+    }
+
+    let config = Configuration::new().set_num_threads(4).set_join_depth_limit(0);
+    let pool = ThreadPool::new(config).unwrap();
+    let log = Mutex::new(Vec::new());
+    pool.install(|| visit(&log, 0, 256));
+    assert_eq!(log.into_inner().unwrap(), (0..256).collect::<Vec<_>>());
+}
+
+#[test]
+fn join_fn_mut() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let (mut left, mut right) = (vec![], vec![]);
+    let mut counter = 0;
+    {
+        let mut push_left = || left.push(1);
+        let mut bump = || {
+            counter += 1;
+            counter
+        };
+        pool.install(|| join(&mut push_left, &mut bump));
+        pool.install(|| join(&mut push_left, &mut bump));
+        pool.install(|| join(|| right.push(2), bump));
+    }
+    assert_eq!((left, right, counter), (vec![1, 1], vec![2], 3));
+}
+
+#[test]
+fn worker_thread_names() {
+    use std::thread;
+
+    let config = Configuration::new()
+        .set_num_threads(2)
+        .set_thread_name(|index| format!("rayon-worker-{}", index));
+    let pool = ThreadPool::new(config).unwrap();
+    let (a, b) = pool.install(|| {
+        join(|| (current_thread_index(), thread::current().name().map(str::to_owned)),
+             || (current_thread_index(), thread::current().name().map(str::to_owned)))
+    });
+    for &(index, ref name) in &[a, b] {
+        assert_eq!(*name, Some(format!("rayon-worker-{}", index.unwrap())));
+    }
+}
+
+#[test]
+fn worker_stack_size() {
+    fn recurse(n: usize) -> usize {
+        let buf = [n as u8; 1024];
+        if n == 0 {
+            0
+        } else {
+            recurse(n - 1) + buf[n % 1024] as usize % 2
+        }
+    }
+
+    // 8k frames of over 1KB each do not fit on the default stack.
+    let config = Configuration::new().set_num_threads(1).set_stack_size(64 << 20);
+    let pool = ThreadPool::new(config).unwrap();
+    assert_eq!(pool.install(|| recurse(8192)), 4096);
+}
+
+#[test]
+fn donated_threads_run_workers() {
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+    use std::thread;
+
+    fn fib(n: u32) -> u32 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = join(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+
+    let pool = ThreadPool::new(Configuration::new()
+            .set_num_threads(3)
+            .set_use_current_threads(true))
+        .unwrap();
+    let threads: Vec<_> = (0..3)
+        .map(|index| {
+            let worker = pool.take_worker(index).unwrap();
+            assert_eq!(worker.index(), index);
+            thread::Builder::new()
+                .name(format!("host-{}", index))
+                .spawn(move || {
+                    worker.run();
+
+                    // once the pool is gone, this is an ordinary thread again
+                    current_thread_index()
+                })
+                .unwrap()
+        })
+        .collect();
+    assert!(pool.take_worker(0).is_none());
+    assert!(pool.take_worker(3).is_none());
+
+    let names = Mutex::new(HashSet::new());
+    let result = pool.install(|| {
+        fib(10) +
+        (0..3)
+            .map(|_| {
+                let (a, b) = join(|| thread::current().name().map(str::to_owned),
+                                  || thread::current().name().map(str::to_owned));
+                names.lock().unwrap().extend(vec![a, b]);
+                fib(15)
+            })
+            .sum::<u32>()
+    });
+    assert_eq!(result, 55 + 3 * 610);
+    for name in names.into_inner().unwrap() {
+        assert!(name.unwrap().starts_with("host-"));
+    }
+
+    drop(pool);
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), None);
+    }
+
+    // a pool that spawns its own threads has none to hand out
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    assert!(pool.take_worker(0).is_none());
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+#[should_panic(expected = "already a worker thread")]
+fn donated_worker_run_on_worker_panics() {
+    let donor = ThreadPool::new(Configuration::new()
+            .set_num_threads(1)
+            .set_use_current_threads(true))
+        .unwrap();
+    let worker = donor.take_worker(0).unwrap();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    pool.install(move || worker.run());
+}
+
+#[test]
+fn join_tree_with_bulk_steals() {
+    // Thieves take several jobs at once, and may leave some of them on
+    // their own deque when they return to an enclosing `join`, which
+    // must then not mistake them for its own job.
+    fn fib(n: u32) -> u32 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = join(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    for _ in 0..20 {
+        assert_eq!(pool.install(|| fib(22)), 17711);
+    }
+}
+
+#[test]
+fn heartbeat_join_tree() {
+    fn fib(n: u32) -> u32 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = join(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+
+    for &interval in &[1, 7, 100] {
+        let config = Configuration::new().set_num_threads(4).set_heartbeat_interval(interval);
+        let pool = ThreadPool::new(config).unwrap();
+        for _ in 0..5 {
+            assert_eq!(pool.install(|| fib(20)), 6765);
+        }
+    }
+}
+
+#[test]
+fn heartbeat_promotes_oldest_job() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    let config = Configuration::new().set_num_threads(2).set_heartbeat_interval(4);
+    let pool = ThreadPool::new(config).unwrap();
+    let b_started = AtomicBool::new(false);
+    let (thread_a, thread_b) = pool.install(|| {
+        join(|| {
+                 // these joins make the heartbeat fire, which must
+                 // promote the outer `b` rather than one of theirs
+                 for _ in 0..8 {
+                     join(|| (), || ());
+                 }
+                 while !b_started.load(Ordering::SeqCst) {
+                     thread::yield_now();
+                 }
+                 current_thread_index()
+             },
+             || {
+                 b_started.store(true, Ordering::SeqCst);
+                 current_thread_index()
+             })
+    });
+    assert!(thread_a != thread_b);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn heartbeat_join_panic() {
+    use unwind;
+
+    let config = Configuration::new().set_num_threads(2).set_heartbeat_interval(3);
+    let pool = ThreadPool::new(config).unwrap();
+    for _ in 0..10 {
+        let err = unwind::halt_unwinding(|| {
+                pool.install(|| {
+                    join(|| {
+                             join(|| (), || ());
+                             join(|| panic!("a"), || ())
+                         },
+                         || join(|| (), || ()))
+                })
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"a"));
+    }
+    assert_eq!(pool.install(|| join(|| 1, || 2)), (1, 2));
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_in_drop_during_unwinding() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use unwind;
+
+    struct JoinOnDrop<'a>(&'a AtomicUsize);
+
+    impl<'a> Drop for JoinOnDrop<'a> {
+        fn drop(&mut self) {
+            let (a, b) = join(|| 1, || 2);
+            self.0.fetch_add(a + b, Ordering::SeqCst);
+        }
+    }
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let dropped = AtomicUsize::new(0);
+    let err = unwind::halt_unwinding(|| {
+            pool.install(|| {
+                join(|| {
+                         let _guard = JoinOnDrop(&dropped);
+                         panic!("a")
+                     },
+                     || drop(JoinOnDrop(&dropped)))
+            })
+        })
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"a"));
+    assert!(dropped.load(Ordering::SeqCst) >= 3);
+
+    // outside of the pool, too
+    let err = unwind::halt_unwinding(|| {
+            let _guard = JoinOnDrop(&dropped);
+            panic!("b")
+        })
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"b"));
+}
+
+#[test]
+fn fair_wake_rotates_through_sleeping_workers() {
+    use std::thread;
+    use std::time::Duration;
+
+    // Under a load this light, every worker is asleep by the time the
+    // next job comes in; each job should wake the worker that has
+    // slept the longest, so the jobs go round the workers rather than
+    // landing on the same one every time.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4).set_fair_wake(true))
+        .unwrap();
+    let mut ran_on = [0; 4];
+    for _ in 0..12 {
+        thread::sleep(Duration::from_millis(50));
+        let index = pool.spawn_handle(|| current_thread_index().unwrap()).join();
+        ran_on[index] += 1;
+    }
+    assert!(ran_on.iter().all(|&count| count > 0), "jobs ran on {:?}", ran_on);
+}
+
+#[test]
+fn breadth_first_runs_own_jobs_in_order() {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    fn spawn_order(breadth_first: bool) -> Vec<usize> {
+        let config = Configuration::new().set_num_threads(2).set_breadth_first(breadth_first);
+        let pool = ThreadPool::new(config).unwrap();
+        let order = Mutex::new(vec![]);
+        pool.install(|| {
+            // keep the other worker busy, so that nobody steals from us
+            let (tx, rx) = channel::<()>();
+            let blocked = AtomicBool::new(false);
+            ::scope(|s| {
+                let blocked = &blocked;
+                s.spawn(move |_| {
+                    blocked.store(true, Ordering::SeqCst);
+                    rx.recv().unwrap_err();
+                });
+                while !blocked.load(Ordering::SeqCst) {
+                    thread::yield_now();
+                }
+
+                ::scope(|s| {
+                    for i in 0..10 {
+                        let order = &order;
+                        s.spawn(move |_| order.lock().unwrap().push(i));
+                    }
+                });
+                drop(tx);
+            });
+        });
+        order.into_inner().unwrap()
+    }
+
+    assert_eq!(spawn_order(false), (0..10).rev().collect::<Vec<_>>());
+    assert_eq!(spawn_order(true), (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn steal_stats_skewed_join() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let busy = pool.install(|| {
+        // only this worker ever calls `join`
+        for _ in 0..1000 {
+            join(|| (), || ());
+        }
+        current_thread_index().unwrap()
+    });
+
+    let stats = pool.steal_stats();
+    assert_eq!(stats.len(), 2);
+    let (busy, idle) = (stats[busy], stats[1 - busy]);
+    assert_eq!(busy.popped + busy.lost, 1000);
+    assert!(busy.popped > idle.popped);
+    assert_eq!(idle.popped + idle.lost, 0);
+    assert_eq!(idle.stolen, busy.lost);
+}
+
+#[test]
+#[cfg(feature = "bench")]
+fn bench_sum_matches_sequential() {
+    use bench;
+
+    for &len in &[0, 1, 2, 3, 1000, 1 << 12] {
+        let v: Vec<u64> = (0..len as u64).collect();
+        assert_eq!(bench::sum(&v), bench::sum_sequential(&v));
+        assert_eq!(bench::sum(&v), len as u64 * (len as u64).saturating_sub(1) / 2);
+    }
+    assert_eq!(bench::num_joins(0), 0);
+    assert_eq!(bench::num_joins(1000), 999);
+    bench::measure(3, || bench::sum(&[1, 2, 3]));
+    assert!(bench::steal_free_join_nanos(3) > 0);
+}
+
+/// Guards the `join` fast path against regressions; see
+/// `bench::STEAL_FREE_JOIN_BUDGET_NANOS`.
+#[test]
+#[cfg(feature = "timing")]
+fn steal_free_join_within_budget() {
+    use bench;
+
+    assert!(!cfg!(debug_assertions),
+            "the timing test is only meaningful in a release build (use `--release`)");
+    let nanos = bench::steal_free_join_nanos(201);
+    assert!(nanos <= bench::STEAL_FREE_JOIN_BUDGET_NANOS,
+            "a steal-free join took {}ns, over the budget of {}ns",
+            nanos,
+            bench::STEAL_FREE_JOIN_BUDGET_NANOS);
+}
+
+#[test]
+fn try_join_outside_pool() {
+    assert_eq!(try_join(|| 1, || 2), Ok((1, 2)));
+}
+
+#[test]
+fn try_join_in_pool() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    assert_eq!(pool.install(|| try_join(|| 1, || 2)), Ok((1, 2)));
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_single_thread_pool() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use unwind;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    assert_eq!(pool.install(|| join(|| 1, || 2)), (1, 2));
+
+    // If the first closure panics, the second one never runs.
+    let b_ran = AtomicBool::new(false);
+    let result = unwind::halt_unwinding(|| {
+        pool.install(|| {
+            join(|| panic!("a"), || b_ran.store(true, Ordering::SeqCst))
+        })
+    });
+    assert!(result.is_err());
+    assert!(!b_ran.load(Ordering::SeqCst));
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_panic_in_a_runs_b() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use unwind;
+
+    // `oper_a` panics right away, so `oper_b` is usually still on the
+    // deque, and must be run before the panic goes on; `oper_b` is not
+    // allowed to spoil that with a panic of its own.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let b_ran = AtomicUsize::new(0);
+    for i in 0..100 {
+        let err = unwind::halt_unwinding(|| {
+                pool.install(|| {
+                    join(|| panic!("a"), || {
+                        b_ran.fetch_add(1, Ordering::SeqCst);
+                        if i % 2 == 0 {
+                            panic!("b");
+                        }
+                    })
+                })
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"a"));
+        assert_eq!(b_ran.load(Ordering::SeqCst), i + 1);
+    }
+}
+
+#[test]
+fn join_cancellable_b_skips_its_work() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // on a single worker, `b` always starts after `a` is done
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let b_worked = AtomicBool::new(false);
+    let (a, b) = pool.install(|| {
+        join_cancellable(|token| {
+                             token.cancel();
+                             "found"
+                         },
+                         |token| if token.is_cancelled() {
+                             None
+                         } else {
+                             b_worked.store(true, Ordering::SeqCst);
+                             Some(22)
+                         })
+    });
+    assert_eq!((a, b), ("found", None));
+    assert!(!b_worked.load(Ordering::SeqCst));
+}
+
+#[test]
+fn join_cancellable_not_cancelled() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let (a, b) = pool.install(|| {
+        join_cancellable(|token| token.is_cancelled(),
+                         |token| if token.is_cancelled() { 0 } else { 22 })
+    });
+    assert_eq!((a, b), (false, 22));
+}
+
+#[test]
+fn join_result_order_under_contention() {
+    use scope;
+
+    // A million joins, from four tasks at once, so that idle workers
+    // steal some of the second closures and the callers take back the
+    // others (or find them gone and wait for them). Whichever way a
+    // join went, `a`'s result comes first.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    pool.install(|| {
+        scope(|s| for _ in 0..4 {
+            s.spawn(|_| for _ in 0..250_000 {
+                assert_eq!(join(|| 1, || 2), (1, 2));
+            });
+        })
+    });
+}
+
+#[test]
+fn join_result_order_when_stolen() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // `a` only returns once a thief is running `b`, and `b` only once
+    // `a` has returned, so that `join` always finds `b` gone and has to
+    // wait for it.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    for _ in 0..1000 {
+        let b_started = AtomicBool::new(false);
+        let a_done = AtomicBool::new(false);
+        let (a, b) = pool.install(|| {
+            let caller = current_thread_index();
+            join(|| {
+                     while !b_started.load(Ordering::SeqCst) {
+                         yield_now();
+                     }
+                     a_done.store(true, Ordering::SeqCst);
+                     1
+                 },
+                 || {
+                     b_started.store(true, Ordering::SeqCst);
+                     while !a_done.load(Ordering::SeqCst) {
+                         ::std::thread::yield_now();
+                     }
+                     assert!(current_thread_index() != caller, "b was not stolen");
+                     2
+                 })
+        });
+        assert_eq!((a, b), (1, 2));
+    }
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_drops_result_b_once() {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use unwind;
+
+    struct Counted<'a>(&'a AtomicUsize, [u8; 4096]);
+
+    impl<'a> Drop for Counted<'a> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // the result of `oper_b` is dropped exactly once, by the caller
+    // or, if `oper_a` panicked, along with the job: whether `oper_b`
+    // was taken back or stolen
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let drops = AtomicUsize::new(0);
+    pool.install(|| {
+        let (_, b) = join(|| (), || Counted(&drops, [1; 4096]));
+        assert_eq!(b.1[4095], 1);
+    });
+    assert_eq!(drops.load(Ordering::SeqCst), 1);
+
+    for &a_panics in &[false, true] {
+        drops.store(0, Ordering::SeqCst);
+        let b_started = AtomicBool::new(false);
+        let result = unwind::halt_unwinding(|| {
+            pool.install(|| {
+                join(|| {
+                         while !b_started.load(Ordering::SeqCst) {
+                             yield_now();
+                         }
+                         if a_panics {
+                             panic!("a");
+                         }
+                     },
+                     || {
+                         b_started.store(true, Ordering::SeqCst);
+                         Counted(&drops, [2; 4096])
+                     })
+                    .1
+                    .1[0]
+            })
+        });
+        assert_eq!(result.is_err(), a_panics);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[test]
+fn join_reclaims_bulk_stolen_job() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Instant;
+
+    // Keep one worker busy while the other pushes `b1`, `b2` and `b3`
+    // in three nested joins; then let it loose, so that it steals `b1`
+    // and, in the same go, `b2`. It runs `b1`, which waits for `b2`,
+    // so `b2` has to run on the owner, which takes it back from the
+    // thief's deque (as opposed to stealing it like any other job).
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let go = Arc::new(AtomicBool::new(false));
+    let (thief_tx, thief_rx) = channel();
+    {
+        let go = go.clone();
+        pool.spawn(move || {
+            thief_tx.send(current_thread_index()).unwrap();
+            while !go.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+        });
+    }
+    let thief = thief_rx.recv().unwrap();
+
+    let b1_started = AtomicBool::new(false);
+    let b2_done = AtomicBool::new(false);
+    let (owner, b1, b2) = pool.install(|| {
+        let ((((), _), b2), b1) = join(|| {
+            join(|| {
+                     join(|| {
+                              go.store(true, Ordering::SeqCst);
+                              while !b1_started.load(Ordering::SeqCst) {
+                                  thread::yield_now();
+                              }
+                          },
+                          || ())
+                 },
+                 || {
+                     b2_done.store(true, Ordering::SeqCst);
+                     current_thread_index()
+                 })
+        },
+                                       || {
+            b1_started.store(true, Ordering::SeqCst);
+            let start = Instant::now();
+            while !b2_done.load(Ordering::SeqCst) && start.elapsed() < Duration::from_secs(5) {
+                thread::yield_now();
+            }
+            current_thread_index()
+        });
+        (current_thread_index(), b1, b2)
+    });
+    assert_eq!(b1, thief);
+    assert_eq!(b2, owner);
+    let stats = pool.steal_stats();
+    assert_eq!(stats[owner.unwrap()].stolen, 0);
+    assert_eq!(stats[thief.unwrap()].stolen, 2);
+}
+
+/// `depth` nested joins, each with a trivial `oper_b`, around `inner`.
+fn join_chain<F: FnOnce() + Send>(depth: usize, inner: F) {
+    if depth == 0 {
+        inner()
+    } else {
+        join(|| join_chain(depth - 1, inner), || ());
+    }
+}
+
+/// Runs `op` in a pool of two threads, one of which is kept busy, so
+/// that nothing the other one pushes is stolen; returns the stats.
+fn with_busy_thief<F: FnOnce() + Send>(op: F) -> Vec<WorkerStats> {
+    use std::sync::mpsc::channel;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let (started_tx, started_rx) = channel();
+    let (release_tx, release_rx) = channel::<()>();
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+    });
+    started_rx.recv().unwrap();
+    pool.install(op);
+    release_tx.send(()).unwrap();
+    pool.steal_stats()
+}
+
+#[test]
+fn join_stops_pushing_on_crowded_deque() {
+    use thread_pool::CROWDED_JOBS_PER_THREAD;
+
+    // each join leaves its `oper_b` on the deque while the ones it
+    // nests run, until the deque is crowded; deeper joins push nothing
+    let stats = with_busy_thief(|| join_chain(50, || ()));
+    let popped: usize = stats.iter().map(|stats| stats.popped).sum();
+    let lost: usize = stats.iter().map(|stats| stats.lost).sum();
+    assert_eq!((popped, lost), (2 * CROWDED_JOBS_PER_THREAD, 0));
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_on_crowded_deque_panic() {
+    use PanicOrigin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use thread_pool::CROWDED_JOBS_PER_THREAD;
+    use unwind;
+
+    // past a crowded deque, `join` runs its closures in turn, but
+    // `oper_b` still runs if `oper_a` panics, and its own panic is
+    // dropped, just as if it had been pushed
+    let b_ran = AtomicBool::new(false);
+    with_busy_thief(|| {
+        let inner = || {
+            join(|| panic!("a"), || {
+                b_ran.store(true, Ordering::SeqCst);
+                panic!("b")
+            });
+        };
+        let err = unwind::halt_unwinding(|| join_chain(2 * CROWDED_JOBS_PER_THREAD, inner))
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"a"));
+        assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperA));
+    });
+    assert!(b_ran.load(Ordering::SeqCst));
+}
+
+#[test]
+fn join_result_order_without_stealing() {
+    // `b` is taken back and run by the caller: on a single worker, in
+    // heartbeat mode (where `b` is never promoted to the deque), and
+    // past the depth limit (where `join` runs both closures in turn)
+    let configs = vec![Configuration::new().set_num_threads(1),
+                       Configuration::new().set_num_threads(2).set_heartbeat_interval(1 << 30),
+                       Configuration::new().set_num_threads(2).set_join_depth_limit(0)];
+    for config in configs {
+        let pool = ThreadPool::new(config).unwrap();
+        pool.install(|| for _ in 0..100_000 {
+            assert_eq!(join(|| 1, || 2), (1, 2));
+        });
+    }
+}
+
+#[test]
+fn join_context_second() {
+    use std::sync::Barrier;
+
+    // If we're already in a 2-thread pool, the second job should be stolen.
+    let barrier = Barrier::new(2);
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let (a_migrated, b_migrated) = pool.install(|| {
+        join_context(|a| {
+                         barrier.wait();
+                         a.migrated()
+                     },
+                     |b| {
+                         barrier.wait();
+                         b.migrated()
+                     })
+    });
+    assert!(!a_migrated);
+    assert!(b_migrated);
+}
+
+#[test]
+fn join_a_never_leaves_calling_worker() {
+    // Every `oper_a` in a tree of joins must run on the worker that
+    // called its `join`, however much of the tree gets stolen; only
+    // `oper_b` may move. (An `oper_b` that was stolen in bulk can be
+    // stolen back by its own caller, so it may be migrated without
+    // having moved.)
+    fn visit(depth: usize) {
+        if depth == 0 {
+            return;
+        }
+        let caller = current_thread_index();
+        assert!(caller.is_some());
+        join_context(|a| {
+                         assert_eq!(current_thread_index(), caller);
+                         assert!(!a.migrated());
+                         visit(depth - 1)
+                     },
+                     |b| {
+                         if current_thread_index() != caller {
+                             assert!(b.migrated());
+                         }
+                         visit(depth - 1)
+                     });
+    }
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    pool.install(|| visit(12));
+}
+
+#[test]
+fn join_waits_for_slow_stolen_job() {
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    // The barrier forces `oper_b` to be stolen, and waiting for it
+    // takes long enough that the owner backs off all the way to
+    // sleeping; check that it still wakes up.
+    let barrier = Barrier::new(2);
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let (a, b) = pool.install(|| {
+        join(|| {
+                 barrier.wait();
+                 1
+             },
+             || {
+                 barrier.wait();
+                 thread::sleep(Duration::from_millis(100));
+                 2
+             })
+    });
+    assert_eq!((a, b), (1, 2));
+}
+
+#[test]
+fn drop_pool_joins_workers() {
+    use std::cell::Cell;
+    use std::sync::Barrier;
+    use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+    static EXITED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    struct OnExit(Cell<bool>);
+
+    impl Drop for OnExit {
+        fn drop(&mut self) {
+            if self.0.get() {
+                EXITED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    thread_local! {
+        static ON_EXIT: OnExit = OnExit(Cell::new(false))
+    }
+
+    // The barrier ensures that both workers register a destructor,
+    // which runs when the worker thread exits.
+    let barrier = Barrier::new(2);
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    pool.install(|| {
+        join(|| {
+                 ON_EXIT.with(|e| e.0.set(true));
+                 barrier.wait();
+             },
+             || {
+                 ON_EXIT.with(|e| e.0.set(true));
+                 barrier.wait();
+             })
+    });
+    drop(pool);
+    assert_eq!(EXITED.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn drop_pool_with_queued_and_running_work() {
+    use job::{HeapJob, JobMode};
+    use push_job;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+    use unwind;
+
+    // The only worker is busy with a job that has pushed another one
+    // onto its deque, while more tasks wait in the injector queue; the
+    // pool is dropped before the worker gets to any of them.
+    let (tx, rx) = channel();
+    let (started_tx, started_rx) = channel();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let running_tx = tx.clone();
+    pool.spawn(move || {
+        let deque_tx = running_tx.clone();
+        unsafe {
+            push_job(Box::new(HeapJob::new(move |mode| if let JobMode::Execute = mode {
+                    deque_tx.send("deque").unwrap();
+                }))
+                .as_job_ref());
+        }
+        started_tx.send(()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        running_tx.send("running").unwrap();
+    });
+    started_rx.recv().unwrap();
+    let queued_tx = tx.clone();
+    pool.spawn(move || queued_tx.send("queued").unwrap());
+    let handle = pool.spawn_handle(|| 22);
+    drop(tx);
+    drop(pool);
+
+    // the running job and the one on the deque ran; the queued ones
+    // were aborted
+    assert_eq!(rx.iter().collect::<Vec<_>>(), vec!["running", "deque"]);
+    let err = unwind::halt_unwinding(|| handle.join()).unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(),
+               Some(&"spawned task was dropped because its thread pool terminated"));
+}
+
+#[test]
+fn drop_detached_pool_does_not_join() {
+    use std::sync::Mutex;
+    use std::sync::mpsc::channel;
+
+    // The only worker is stuck in a job until after the pool is gone,
+    // so dropping the pool returns only if it does not wait for it.
+    let (exit_tx, exit_rx) = channel();
+    let exit_tx = Mutex::new(exit_tx);
+    let pool = ThreadPool::new(Configuration::new()
+            .set_num_threads(1)
+            .set_detach_threads(true)
+            .set_exit_handler(move |index| exit_tx.lock().unwrap().send(index).unwrap()))
+        .unwrap();
+    let (started_tx, started_rx) = channel();
+    let (release_tx, release_rx) = channel::<()>();
+    let (done_tx, done_rx) = channel();
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+        // the worker keeps the pool's state alive, so this still works
+        done_tx.send(join(|| 1, || 2)).unwrap();
+    });
+    started_rx.recv().unwrap();
+    drop(pool);
+
+    release_tx.send(()).unwrap();
+    assert_eq!(done_rx.recv().unwrap(), (1, 2));
+    assert_eq!(exit_rx.recv().unwrap(), 0);
+}
+
+#[test]
+fn inject_many_jobs_from_outside() {
+    use std::sync::mpsc::channel;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let (tx, rx) = channel();
+    for i in 0..1000 {
+        let tx = tx.clone();
+        pool.spawn(move || tx.send(i).unwrap());
+    }
+    drop(tx);
+
+    let mut results: Vec<usize> = rx.iter().collect();
+    results.sort();
+    assert_eq!(results, (0..1000).collect::<Vec<_>>());
+}
+
+/// A pool of `num_threads` threads, all kept busy until the returned
+/// senders are dropped, so only a helper can run what goes into it.
+fn pool_with_busy_workers(num_threads: usize)
+                          -> (::std::sync::Arc<ThreadPool>, Vec<::std::sync::mpsc::Sender<()>>) {
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+
+    let pool = Arc::new(ThreadPool::new(Configuration::new().set_num_threads(num_threads))
+        .unwrap());
+    let (started_tx, started_rx) = channel();
+    let release = (0..num_threads)
+        .map(|_| {
+            let started_tx = started_tx.clone();
+            let (release_tx, release_rx) = channel::<()>();
+            pool.spawn(move || {
+                started_tx.send(()).unwrap();
+                let _ = release_rx.recv();
+            });
+            release_tx
+        })
+        .collect();
+    for _ in 0..num_threads {
+        started_rx.recv().unwrap();
+    }
+    (pool, release)
+}
+
+#[test]
+fn try_help_drains_backlog() {
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    let (pool, release) = pool_with_busy_workers(1);
+    let (tx, rx) = channel();
+    for i in 0..100 {
+        let tx = tx.clone();
+        pool.spawn(move || tx.send((i, current_thread_index())).unwrap());
+    }
+    drop(tx);
+
+    // another thread helps until there is nothing left, taking one job
+    // per call, on the index after the pool's only worker
+    let helper = {
+        let pool = pool.clone();
+        thread::spawn(move || {
+            let mut helped = 0;
+            while pool.try_help() {
+                helped += 1;
+            }
+            helped
+        })
+    };
+    assert_eq!(helper.join().unwrap(), 100);
+    let mut results: Vec<_> = rx.iter().collect();
+    results.sort();
+    assert_eq!(results, (0..100).map(|i| (i, Some(1))).collect::<Vec<_>>());
+
+    // the helper leaves nothing behind, so it has nothing left to do
+    assert_eq!(pool.queued_len(), 0);
+    assert!(!pool.try_help());
+    drop(release);
+}
+
+#[test]
+fn try_help_runs_join_and_scope() {
+    use scope::scope;
+    use std::sync::Mutex;
+    use std::sync::mpsc::channel;
+
+    // the job that the helper takes uses the helper's own deque, and
+    // runs all of what it pushes there, since nobody else can
+    let (pool, release) = pool_with_busy_workers(2);
+    let (tx, rx) = channel();
+    pool.spawn(move || {
+        let mut v: Vec<u32> = (0..1000).rev().collect();
+        quick_sort(&mut v);
+        let sums = Mutex::new(Vec::new());
+        scope(|s| for half in v.chunks(500) {
+            let sums = &sums;
+            s.spawn(move |_| {
+                sums.lock().unwrap().push(half.iter().sum::<u32>());
+            });
+        });
+        let mut sums = sums.into_inner().unwrap();
+        sums.sort();
+        tx.send((v, sums, join(current_thread_index, current_thread_index))).unwrap();
+    });
+    assert!(pool.try_help());
+    let (v, sums, indices) = rx.recv().unwrap();
+    assert_eq!(v, (0..1000).collect::<Vec<_>>());
+    assert_eq!(sums, vec![124_750, 374_750]);
+    assert_eq!(indices, (Some(2), Some(2)));
+    assert!(!pool.try_help());
+    drop(release);
+}
+
+#[test]
+fn try_help_from_worker() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    pool.spawn(|| ());
+    assert!(!pool.install(|| pool.try_help()));
+}
+
+#[test]
+fn queued_len_counts_submitted_jobs() {
+    use std::sync::mpsc::channel;
+
+    // keep the only worker busy, so that the burst has to wait
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    let (started_tx, started_rx) = channel();
+    let (release_tx, release_rx) = channel::<()>();
+    let blocker = pool.submit(move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+    })
+        .unwrap();
+    started_rx.recv().unwrap();
+    assert_eq!(pool.queued_len(), 0);
+
+    let burst: Vec<_> = (0..100).map(|i| pool.submit(move || i).unwrap()).collect();
+    assert_eq!(pool.queued_len(), 100);
+
+    release_tx.send(()).unwrap();
+    blocker.get();
+    let sum: i32 = burst.into_iter().map(|p| p.get()).sum();
+    assert_eq!(sum, 4950);
+    assert_eq!(pool.queued_len(), 0);
+}
+
+#[test]
+fn install_many_times_from_outside() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let sum: usize = (0..1000).map(|i| pool.install(|| i)).sum();
+    assert_eq!(sum, 999 * 1000 / 2);
+}
+
+#[test]
+fn blocked_workers_run_injected_jobs() {
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    // Both workers end up blocked: one running `b`, which waits for
+    // the spawned job; the other in `steal_until`, waiting for `b`.
+    // Neither returns to the main loop, so the spawned job only runs
+    // if blocked workers also look at the injector queue.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let barrier = Barrier::new(2);
+    pool.install(|| {
+        join(|| {
+                 barrier.wait();
+             },
+             || {
+                 barrier.wait();
+                 let done = Arc::new(AtomicBool::new(false));
+                 let done2 = done.clone();
+                 ::spawn(move || done2.store(true, Ordering::SeqCst));
+                 while !done.load(Ordering::SeqCst) {
+                     thread::yield_now();
+                 }
+             })
+    });
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn poisoned_pool_refuses_work() {
+    use thread_pool::Registry;
+    use unwind;
+
+    let registry = Registry::new(Configuration::new().set_num_threads(2)).unwrap();
+    registry.poison();
+
+    let err = unwind::halt_unwinding(|| registry.in_worker(|| ())).unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"rayon thread pool is poisoned"));
+
+    registry.terminate();
+    registry.join_threads();
+}
+
+#[test]
+fn start_and_exit_handlers() {
+    use std::cell::Cell;
+    use std::sync::{Arc, Mutex};
+
+    thread_local! {
+        static STARTED: Cell<bool> = Cell::new(false)
+    }
+
+    let started = Arc::new(Mutex::new(vec![]));
+    let exited = Arc::new(Mutex::new(vec![]));
+    let config = {
+        let started = started.clone();
+        let exited = exited.clone();
+        Configuration::new()
+            .set_num_threads(3)
+            .set_start_handler(move |index| {
+                STARTED.with(|s| s.set(true));
+                started.lock().unwrap().push(index);
+            })
+            .set_exit_handler(move |index| exited.lock().unwrap().push(index))
+    };
+    let pool = ThreadPool::new(config).unwrap();
+    let (a, b) = pool.install(|| {
+        join(|| STARTED.with(|s| s.get()), || STARTED.with(|s| s.get()))
+    });
+    assert!(a && b);
+    drop(pool);
+
+    for handled in &[started, exited] {
+        let mut handled = handled.lock().unwrap();
+        handled.sort();
+        assert_eq!(*handled, vec![0, 1, 2]);
+    }
+}
+
+/// Runs `op` in a pool of `num_threads` with job handlers, and returns
+/// the calls to them, in order: `(index, None)` when a job starts on
+/// worker `index`, and `(index, Some(elapsed))` when it ends.
+fn job_handler_calls<OP>(num_threads: usize, op: OP) -> Vec<(usize, Option<Duration>)>
+    where OP: FnOnce() + Send
+{
+    use std::sync::{Arc, Mutex};
+
+    let calls = Arc::new(Mutex::new(vec![]));
+    let config = {
+        let started = calls.clone();
+        let ended = calls.clone();
+        Configuration::new()
+            .set_num_threads(num_threads)
+            .set_job_start_handler(move |index| started.lock().unwrap().push((index, None)))
+            .set_job_end_handler(move |index, elapsed| {
+                ended.lock().unwrap().push((index, Some(elapsed)))
+            })
+    };
+    let pool = ThreadPool::new(config).unwrap();
+    pool.install(op);
+    // the `install` job itself may only end once `install` has
+    // returned, so wait for the workers to exit
+    drop(pool);
+    let calls = calls.lock().unwrap().clone();
+    calls
+}
+
+#[test]
+fn job_handlers_fire_once_per_job() {
+    use std::thread;
+
+    let sleep = || thread::sleep(Duration::from_millis(5));
+    let calls = job_handler_calls(2, || {
+        join(|| join(&sleep, &sleep), || join(&sleep, &sleep));
+    });
+
+    // the `install` job, and the second closure of each `join`
+    assert_eq!(calls.iter().filter(|&&(_, elapsed)| elapsed.is_none()).count(), 4);
+    assert_eq!(calls.iter().filter(|&&(_, elapsed)| elapsed.is_some()).count(), 4);
+
+    // on each worker, the jobs nest, and each of them sleeps at least
+    // once
+    for index in 0..2 {
+        let mut depth = 0;
+        for &(_, elapsed) in calls.iter().filter(|&&(i, _)| i == index) {
+            match elapsed {
+                None => depth += 1,
+                Some(elapsed) => {
+                    assert!(depth > 0, "job ended before it started");
+                    depth -= 1;
+                    assert!(elapsed >= Duration::from_millis(5), "{:?}", elapsed);
+                    assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+                }
+            }
+        }
+        assert_eq!(depth, 0);
+    }
+}
+
+#[test]
+fn job_handlers_skip_sequential_joins() {
+    // with a single worker, `join` never pushes a job, so only the
+    // `install` job is reported
+    let calls = job_handler_calls(1, || {
+        join(|| join(|| 1, || 2), || join(|| 3, || 4));
+    });
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0], (0, None));
+    assert_eq!(calls[1].0, 0);
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn start_handler_panic_poisons_pool() {
+    use thread_pool::Registry;
+
+    let config = Configuration::new()
+        .set_num_threads(2)
+        .set_start_handler(|index| if index == 1 {
+            panic!("start handler failed");
+        });
+    let registry = Registry::new(config).unwrap();
+    registry.wait_until_primed();
+    registry.terminate();
+    registry.join_threads();
+    assert!(registry.is_poisoned());
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_in_poisoned_pool_panics() {
+    use thread_pool::Registry;
+    use unwind;
+
+    let registry = Registry::new(Configuration::new().set_num_threads(2)).unwrap();
+    let err = unwind::halt_unwinding(|| {
+            registry.in_worker(|| {
+                Registry::current().poison();
+                join(|| (), || ())
+            })
+        })
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"rayon thread pool is poisoned"));
+
+    registry.terminate();
+    registry.join_threads();
+}
+
+#[test]
+#[cfg(not(feature = "log"))]
+fn log_compiled_out() {
+    #[allow(unused_imports)]
+    use log::Event::*;
+    use std::cell::Cell;
+
+    let evaluated = Cell::new(false);
+    log!({
+        evaluated.set(true);
+        Join { worker: 0 }
+    });
+    assert!(!evaluated.get());
+}
+
+#[test]
+#[cfg(feature = "log")]
+fn steal_schedule_replay() {
+    use std::sync::Mutex;
+    use std::thread;
+
+    // Runs a join tree, and returns the worker that ran each leaf.
+    fn run(pool: &ThreadPool) -> Vec<usize> {
+        fn tree(log: &Mutex<Vec<(usize, usize)>>, id: usize, depth: usize) {
+            if depth == 0 {
+                thread::yield_now(); // give the thieves a chance
+                log.lock().unwrap().push((id, current_thread_index().unwrap()));
+            } else {
+                join(|| tree(log, 2 * id, depth - 1), || tree(log, 2 * id + 1, depth - 1));
+            }
+        }
+        let log = Mutex::new(vec![]);
+        pool.install(|| tree(&log, 1, 8));
+        let mut log = log.into_inner().unwrap();
+        log.sort();
+        log.into_iter().map(|(_, worker)| worker).collect()
+    }
+
+    let config = Configuration::new().set_num_threads(4).set_steal_mode(StealMode::Record);
+    let pool = ThreadPool::new(config).unwrap();
+    let recorded = run(&pool);
+    let schedule = pool.steal_schedule().unwrap();
+
+    // at the very least, some worker took the job that `install` injected
+    assert!(schedule.steals.iter().any(|steals| !steals.is_empty()));
+
+    for _ in 0..3 {
+        let config = Configuration::new()
+            .set_num_threads(4)
+            .set_steal_mode(StealMode::Replay(schedule.clone()));
+        let pool = ThreadPool::new(config).unwrap();
+        assert_eq!(run(&pool), recorded);
+        assert_eq!(pool.steal_schedule(), None);
+    }
+}
+
+// The log is global, so all of the checks that look at it are done
+// in a single test, lest they take each other's events.
+#[test]
+#[cfg(feature = "log")]
+fn log_records_events() {
+    use log::{self, Event};
+    use std::env;
+    use std::fs::File;
+    use std::io::Read;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    pool.install(|| join(|| (), || ()));
+
+    let records = log::drain();
+    assert!(records.iter().flat_map(|r| r).any(|r| match r.event {
+        Event::Join { .. } => true,
+        _ => false,
+    }));
+
+    if cfg!(feature = "profiling") {
+        pool.install(|| join_named("test-span", || (), || ()));
+        let records = log::drain();
+        let spans: Vec<_> = records.iter()
+            .flat_map(|r| r)
+            .filter_map(|r| match r.event {
+                Event::SpanEnter { name: "test-span", oper, .. } => Some(("enter", oper)),
+                Event::SpanExit { name: "test-span", oper, .. } => Some(("exit", oper)),
+                _ => None,
+            })
+            .collect();
+        for &oper in &["a", "b"] {
+            assert_eq!(spans.iter().filter(|s| s.1 == oper).count(), 2);
+        }
+    }
+
+    pool.install(|| join(|| (), || ()));
+    let path = env::temp_dir().join("rayon-log-dump-to-file.txt");
+    ::dump_log_to(&path).unwrap();
+    let mut contents = String::new();
+    File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+
+    let times: Vec<u64> = contents.lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            assert_eq!(fields.len(), 3);
+            fields[0].parse().unwrap()
+        })
+        .collect();
+    assert!(!times.is_empty());
+    assert!(times.windows(2).all(|w| w[0] <= w[1]));
+
+    // Only the kinds of events that pass the filter are recorded.
+    // (Other tests may log concurrently, but their events have to pass
+    // the same filter.)
+    log::set_filter("Join, StoleWork");
+    log::drain();
+    pool.install(|| join(|| (), || ()));
+    let records = log::drain();
+    log::set_filter("");
+    assert!(records.iter().flat_map(|r| r).all(|r| match r.event {
+        Event::Join { .. } | Event::StoleWork { .. } => true,
+        _ => false,
+    }));
+    assert!(records.iter().flat_map(|r| r).any(|r| match r.event {
+        Event::Join { .. } => true,
+        _ => false,
+    }));
+}
+
+#[test]
+fn join_named_is_join() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    assert_eq!(pool.install(|| join_named("span", || 1, || 2)), (1, 2));
+}
+
+#[test]
+fn wide_fan_out_runs_each_job_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Spawning many jobs from one thread gives thieves a big backlog
+    // to steal from, so some of them likely take several jobs at once.
+    let counts: Vec<AtomicUsize> = (0..10000).map(|_| AtomicUsize::new(0)).collect();
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    pool.install(|| {
+        ::scope(|s| {
+            for count in &counts {
+                s.spawn(move |_| {
+                    join(|| count.fetch_add(1, Ordering::SeqCst),
+                         || count.fetch_add(1, Ordering::SeqCst));
+                });
+            }
+        })
+    });
+    assert!(counts.iter().all(|c| c.load(Ordering::SeqCst) == 2));
+}
+
+#[test]
+fn push_wakes_sleeping_worker() {
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    // Let the pool go idle, so that its workers fall asleep; then make
+    // one worker push a job which only the other one can run. If the
+    // push failed to wake the sleeper, this would hang.
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    for _ in 0..20 {
+        thread::sleep(Duration::from_millis(10));
+        let barrier = Barrier::new(2);
+        pool.install(|| join(|| barrier.wait(), || barrier.wait()));
+    }
+}
+
+#[test]
+fn broadcast_runs_once_per_worker() {
+    use std::sync::Mutex;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let broadcast = || {
+        let seen = Mutex::new(Vec::new());
+        pool.broadcast(|index| {
+            assert_eq!(current_thread_index(), Some(index));
+            seen.lock().unwrap().push(index);
+        });
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        assert_eq!(seen, vec![0, 1, 2, 3]);
+    };
+
+    // from outside the pool, from one of its workers, and from a
+    // worker that is busy with a join that the broadcast must not
+    // be stuck behind
+    broadcast();
+    pool.install(&broadcast);
+    pool.install(|| join(&broadcast, &broadcast));
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn broadcast_propagates_panic() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use unwind;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(3)).unwrap();
+    let calls = AtomicUsize::new(0);
+    let result = unwind::halt_unwinding(|| {
+        pool.broadcast(|index| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            if index == 1 {
+                panic!("worker 1");
+            }
+        })
+    });
+    assert!(result.is_err());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn broadcast_from_helper() {
+    use std::sync::Mutex;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    // the helper is not one of the workers, so it must not run `op`
+    // itself: only the two workers do, once they are released
+    let (pool, release) = pool_with_busy_workers(2);
+    let (started_tx, started_rx) = channel();
+    let (tx, rx) = channel();
+    let job_pool = pool.clone();
+    pool.spawn(move || {
+        started_tx.send(current_thread_index()).unwrap();
+        let seen = Mutex::new(Vec::new());
+        job_pool.broadcast(|index| {
+            assert_eq!(current_thread_index(), Some(index));
+            seen.lock().unwrap().push(index);
+        });
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        tx.send(seen).unwrap();
+    });
+    let helper_pool = pool.clone();
+    let helper = thread::spawn(move || helper_pool.try_help());
+    assert_eq!(started_rx.recv().unwrap(), Some(2));
+    drop(release);
+    assert_eq!(rx.recv().unwrap(), vec![0, 1]);
+    assert!(helper.join().unwrap());
+}
+
+#[test]
+fn deadlock_handler_detects_cycle() {
+    use std::mem;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    let (tx, rx) = channel();
+    let tx = Mutex::new(tx);
+    let config = Configuration::new()
+        .set_num_threads(2)
+        .set_deadlock_handler(move || tx.lock().unwrap().send(()).unwrap());
+    let pool = ThreadPool::new(config).unwrap();
+
+    // Two tasks, each of which waits for the other one to finish. Once
+    // both are waiting, neither worker has anything left to do.
+    let handles: Arc<Vec<Mutex<Option<::ScopeHandle<()>>>>> =
+        Arc::new(vec![Mutex::new(None), Mutex::new(None)]);
+    for i in 0..2 {
+        let task_handles = handles.clone();
+        let handle = pool.spawn_handle(move || loop {
+            let other = task_handles[1 - i].lock().unwrap().take();
+            match other {
+                Some(other) => return other.join(),
+                None => thread::yield_now(),
+            }
+        });
+        *handles[i].lock().unwrap() = Some(handle);
+    }
+
+    // The workers stay stuck, so don't wait for them to terminate
+    // (even if the test fails).
+    mem::forget(pool);
+    rx.recv_timeout(Duration::from_secs(10)).unwrap();
+}
+
+#[test]
+fn deadlock_handler_quiet_while_working() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    let fired = Arc::new(AtomicUsize::new(0));
+    let handler_fired = fired.clone();
+    let config = Configuration::new()
+        .set_num_threads(2)
+        .set_deadlock_handler(move || {
+            handler_fired.fetch_add(1, Ordering::SeqCst);
+        });
+    let pool = ThreadPool::new(config).unwrap();
+
+    fn fib(n: u32) -> u32 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = join(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+
+    // One worker waits for a long time while the other one is busy,
+    // and then both of them just have a lot of short jobs to do.
+    pool.install(|| {
+        join(|| (), || thread::sleep(Duration::from_millis(300)));
+        join(|| (), || thread::sleep(Duration::from_millis(300)));
+    });
+    assert_eq!(pool.install(|| fib(20)), 6765);
+    assert_eq!(fired.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn global_pool_is_the_default_pool() {
+    let pool = ThreadPool::global();
+    assert!(pool as *const ThreadPool == ThreadPool::global() as *const ThreadPool);
+    assert_eq!(pool.num_threads(), current_num_threads());
+
+    // Called from a worker of the global pool, `install` on it runs
+    // right away, on the same worker.
+    let ((index, installed_index), ()) =
+        join(|| (current_thread_index(), ThreadPool::global().install(current_thread_index)),
+             || ());
+    assert!(index.is_some());
+    assert_eq!(index, installed_index);
+}
+
+#[test]
+fn join_deep_recursion_grows_deque() {
+    use std::sync::mpsc::channel;
+
+    fn nest(depth: usize) -> usize {
+        if depth == 0 {
+            return 0;
+        }
+        let (a, b) = join(|| nest(depth - 1), || depth);
+        a + b
+    }
+
+    // Keep one of the two workers busy, so that nobody steals from the
+    // other one, and every `join` on the way down leaves its second
+    // closure on the deque: far more than the deque starts out with
+    // room for.
+    let pool = ThreadPool::new(Configuration::new()
+            .set_num_threads(2)
+            .set_stack_size(64 << 20))
+        .unwrap();
+    let (started_tx, started_rx) = channel();
+    let (release_tx, release_rx) = channel::<()>();
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+    });
+    started_rx.recv().unwrap();
+
+    const DEPTH: usize = 10_000;
+    assert_eq!(pool.install(|| nest(DEPTH)), DEPTH * (DEPTH + 1) / 2);
+    release_tx.send(()).unwrap();
+}
+
+#[test]
+fn try_in_worker_after_terminate() {
+    use thread_pool::Registry;
+
+    let registry = Registry::new(Configuration::new().set_num_threads(2)).unwrap();
+    assert_eq!(registry.try_in_worker(|| 22), Ok(22));
+    registry.terminate();
+    assert_eq!(registry.try_in_worker(|| 22), Err(PoolError::PoolShutdown));
+    registry.join_threads();
+}
+
+#[test]
+fn try_in_worker_races_terminate() {
+    use std::thread;
+    use std::time::Duration;
+    use thread_pool::Registry;
+
+    // Each call either runs to completion, or sees the shutdown; none
+    // may hang, which would keep the racers from being joined.
+    for _ in 0..10 {
+        let registry = Registry::new(Configuration::new().set_num_threads(2)).unwrap();
+        let racers: Vec<_> = (0..4)
+            .map(|_| {
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    loop {
+                        match registry.try_in_worker(|| thread::sleep(Duration::from_millis(1))) {
+                            Ok(()) => {}
+                            Err(err) => {
+                                assert_eq!(err, PoolError::PoolShutdown);
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(5));
+        registry.terminate();
+        for racer in racers {
+            racer.join().unwrap();
+        }
+        registry.join_threads();
+    }
+}
+
+#[test]
+fn join_no_steal_pushes_nothing() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let order = AtomicUsize::new(0);
+    let calls = job_handler_calls(4, || {
+        let index = current_thread_index();
+        let run = || (order.fetch_add(1, Ordering::SeqCst), current_thread_index());
+        let (a, b) = join_no_steal(&run, &run);
+        assert_eq!(a, (0, index));
+        assert_eq!(b, (1, index));
+    });
+    // only the `install` job is reported
+    assert_eq!(calls.len(), 2);
+}
+
+#[test]
+fn join_no_steal_records_no_pops() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    pool.install(|| {
+        for i in 0..100 {
+            assert_eq!(join_no_steal(|| i, || i + 1), (i, i + 1));
+        }
+    });
+    for stats in pool.steal_stats() {
+        assert_eq!((stats.popped, stats.lost), (0, 0));
+    }
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_no_steal_panic() {
+    use PanicOrigin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use unwind;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    pool.install(|| {
+        // if `oper_a` panics, `oper_b` never runs
+        let b_ran = AtomicBool::new(false);
+        let err = unwind::halt_unwinding(|| {
+                join_no_steal(|| panic!("a"), || b_ran.store(true, Ordering::SeqCst))
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"a"));
+        assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperA));
+        assert!(!b_ran.load(Ordering::SeqCst));
+
+        // a panic in `oper_b` comes after `oper_a` completed
+        let a_ran = AtomicBool::new(false);
+        let err = unwind::halt_unwinding(|| {
+                join_no_steal(|| a_ran.store(true, Ordering::SeqCst), || panic!("b"))
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"b"));
+        assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperB));
+        assert!(a_ran.load(Ordering::SeqCst));
+    });
+}
+
+#[test]
+#[cfg(not(panic = "abort"))]
+fn join_panic_origin_identifies_closure() {
+    use PanicOrigin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use unwind;
+
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    pool.install(|| {
+        let err = unwind::halt_unwinding(|| join(|| (), || panic!("Hello, world!"))).unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"Hello, world!"));
+        assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperB));
+        assert_eq!(PanicOrigin::OperB.to_string(),
+                   "panic occurred in oper_b of rayon::join");
+
+        let err = unwind::halt_unwinding(|| join(|| panic!("{}", 22), || ())).unwrap_err();
+        assert_eq!(err.downcast_ref::<String>().map(|s| &s[..]), Some("22"));
+        assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperA));
+
+        // the outermost `join` counts
+        let err = unwind::halt_unwinding(|| join(|| (), || join(|| panic!("inner"), || ())))
+            .unwrap_err();
+        assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperB));
+
+        // `oper_b` is stolen, since `oper_a` keeps this thread busy
+        // until it has started
+        let b_started = AtomicBool::new(false);
+        let err = unwind::halt_unwinding(|| {
+                join(|| while !b_started.load(Ordering::SeqCst) {
+                         thread::yield_now();
+                     },
+                     || {
+                         b_started.store(true, Ordering::SeqCst);
+                         panic!("stolen")
+                     })
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"stolen"));
+        assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperB));
+
+        let err = unwind::halt_unwinding(|| panic!("not in a join")).unwrap_err();
+        assert_eq!(join_panic_origin(&err), None);
+    });
+
+    // when called from outside the pool, the panic is propagated from
+    // another thread
+    let err = unwind::halt_unwinding(|| join(|| (), || panic!("outside"))).unwrap_err();
+    assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperB));
+    let err = unwind::halt_unwinding(|| pool.install(|| join(|| panic!("installed"), || ())))
+        .unwrap_err();
+    assert_eq!(join_panic_origin(&err), Some(PanicOrigin::OperA));
+}
+
+#[test]
+fn mark_blocking_lets_spare_worker_run() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // With a single worker, `oper_b` can only run if a spare takes over
+    // while `oper_a` blocks; otherwise `oper_a` gives up after a while.
+    let pool = ThreadPool::new(Configuration::new()
+            .set_num_threads(1)
+            .set_blocking_replacements(1))
+        .unwrap();
+    let b_done = AtomicBool::new(false);
+    let wait_for_b = || {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !b_done.load(Ordering::SeqCst) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        b_done.load(Ordering::SeqCst)
+    };
+    let (a, b) = pool.install(|| {
+        join(|| mark_blocking(&wait_for_b), || {
+            b_done.store(true, Ordering::SeqCst);
+            current_thread_index()
+        })
+    });
+    assert!(a);
+    assert_eq!(b, Some(1));
+    assert_eq!(pool.num_threads(), 1);
+
+    // the spare has gone back to idling, and the pool still works
+    assert_eq!(pool.install(|| join(|| 1, || 2)), (1, 2));
+
+    // outside of a pool, `mark_blocking` just runs its closure
+    assert_eq!(mark_blocking(|| 22), 22);
+}
+
+#[test]
+fn mark_blocking_nested_counts_once() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let started = Arc::new(AtomicUsize::new(0));
+    let config = {
+        let started = started.clone();
+        Configuration::new()
+            .set_num_threads(1)
+            .set_blocking_replacements(2)
+            .set_start_handler(move |_| {
+                started.fetch_add(1, Ordering::SeqCst);
+            })
+    };
+    let pool = ThreadPool::new(config).unwrap();
+    let b_done = AtomicBool::new(false);
+    let wait_for_b = || {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !b_done.load(Ordering::SeqCst) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        b_done.load(Ordering::SeqCst)
+    };
+    let (a, _) = pool.install(|| {
+        join(|| mark_blocking(|| mark_blocking(&wait_for_b)),
+             || b_done.store(true, Ordering::SeqCst))
+    });
+    assert!(a);
+
+    // the worker and one spare, but not the second spare
+    assert_eq!(started.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn count_latch_wait_until_from_workers() {
+    use {scope, CountLatch, Latch};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    assert!(CountLatch::new(0).probe());
+    let latch = CountLatch::new(2);
+    latch.set();
+    assert!(!latch.probe());
+    latch.increment();
+    latch.set();
+    assert!(!latch.probe());
+    latch.set();
+    assert!(latch.probe());
+
+    // The first job only sets the latch once the outside thread has
+    // seen it still unset, so `wait_until` has to wait for all of them.
+    let latch = Arc::new(CountLatch::new(16));
+    let release = Arc::new(AtomicBool::new(false));
+    let done = AtomicUsize::new(0);
+    let releaser = {
+        let (latch, release) = (latch.clone(), release.clone());
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            assert!(!latch.probe());
+            release.store(true, Ordering::SeqCst);
+        })
+    };
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    pool.install(|| {
+        scope(|s| {
+            for i in 0..16 {
+                let (latch, release, done) = (&latch, &release, &done);
+                s.spawn(move |_| {
+                    while i == 0 && !release.load(Ordering::SeqCst) {
+                        thread::yield_now();
+                    }
+                    done.fetch_add(1, Ordering::SeqCst);
+                    latch.set();
+                });
+            }
+            wait_until(&*latch);
+            assert_eq!(done.load(Ordering::SeqCst), 16);
+        })
+    });
+    releaser.join().unwrap();
+
+    // from outside the pool, too
+    let latch = CountLatch::new(1);
+    pool.install(|| latch.set());
+    wait_until(&latch);
+}
@@ -1,97 +1,653 @@
-use Configuration;
+use {Configuration, CoreId, DeadlockHandler, ExitHandler, InitError, JobEndHandler,
+     JobStartHandler, PanicHandler, PoolError, QueuePolicy, StartHandler, WorkerStats};
 use deque;
 use deque::{Worker, Stealer, Stolen};
-use job::{JobRef, JobMode};
-use latch::{Latch, LockLatch, SpinLatch};
+use job::{JobRef, JobMode, StackJob};
+use latch::{self, Backoff, Latch, LockLatch, SpinLatch};
+#[cfg(target_os = "linux")]
+use libc;
 #[allow(unused_imports)]
 use log::Event::*;
-use rand::{self, Rng};
-use std::cell::Cell;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::VecDeque;
 use std::sync::{Arc, Condvar, Mutex, Once, ONCE_INIT};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT};
 use std::thread;
-use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 use std::mem;
+use std::ptr;
+use std::u32;
 use unwind;
 use util::leak;
 use num_cpus;
+use scratch::Arena;
+#[cfg(feature = "log")]
+use schedule::{StealLog, StealSchedule};
 
 /// ////////////////////////////////////////////////////////////////////////
 
 pub struct Registry {
+    /// One entry per worker: first the `num_threads` regular ones,
+    /// then the spares (see `Configuration::set_blocking_replacements`),
+    /// and last the helper (see `Registry::try_help`).
     thread_infos: Vec<ThreadInfo>,
+    num_threads: usize,
     state: Mutex<RegistryState>,
+
+    /// Signalled when there may be new work for sleeping workers; see
+    /// `Registry::sleep`.
     work_available: Condvar,
+
+    /// Number of workers that are asleep, or about to go to sleep.
+    /// Read without the lock by `tickle`, so that pushing a job only
+    /// takes the lock if there is somebody to wake up.
+    sleepers: AtomicUsize,
+
+    /// Stealing end of the global injector queue. Jobs submitted from
+    /// outside of the worker threads are pushed onto the queue in
+    /// `RegistryState`; idle workers pull them off from here, without
+    /// needing to take the lock.
+    injected_jobs: Stealer<JobRef>,
+
+    /// The approximate number of jobs in the injector queue, for
+    /// `queued_len`. Jobs are only pushed (and, on termination, popped)
+    /// with the lock held, so those counters have a single writer at a
+    /// time, like those of a worker's deque.
+    injected_len: DequeLen,
+
+    /// See `Configuration::set_max_queued`. Submitters that wait for
+    /// room in the injector queue wait on `queue_space`, with the lock
+    /// held; `waiting_submitters` counts them, so that workers taking
+    /// jobs off the queue only take the lock if somebody is waiting.
+    max_queued: Option<usize>,
+    queue_policy: QueuePolicy,
+    queue_space: Condvar,
+    waiting_submitters: AtomicUsize,
+
+    panic_handler: Option<Arc<PanicHandler>>,
+    start_handler: Option<Arc<StartHandler>>,
+    exit_handler: Option<Arc<ExitHandler>>,
+
+    /// See `Configuration::set_deadlock_handler`.
+    deadlock_handler: Option<Arc<DeadlockHandler>>,
+
+    /// See `Configuration::set_job_start_handler`.
+    job_start_handler: Option<Arc<JobStartHandler>>,
+    job_end_handler: Option<Arc<JobEndHandler>>,
+
+    /// The number of workers that have nothing to do, how many of
+    /// those are waiting in `steal_until`, and the number of times that
+    /// a worker stopped being blocked; see `Registry::block`. Only
+    /// maintained if there is a deadlock handler.
+    blocked: AtomicUsize,
+    waiting: AtomicUsize,
+    unblocked: AtomicUsize,
+
+    /// See `Configuration::set_deterministic`.
+    deterministic: bool,
+
+    /// See `Configuration::set_join_depth_limit`.
+    join_depth_limit: Option<usize>,
+
+    /// See `Configuration::set_stack_guard`; `stack_size` is only used
+    /// to estimate where a worker's stack ends.
+    stack_guard: usize,
+    stack_size: Option<usize>,
+
+    /// See `Configuration::set_growable_stacks`.
+    growable_stacks: Option<usize>,
+
+    /// See `Configuration::set_spin_iterations`.
+    spin_iterations: Option<usize>,
+
+    /// See `Configuration::set_heartbeat_interval`.
+    heartbeat_interval: Option<usize>,
+
+    /// See `Configuration::set_default_split_threshold`.
+    default_split_threshold: Option<usize>,
+
+    /// See `Configuration::set_breadth_first`.
+    breadth_first: bool,
+
+    /// See `Configuration::set_fair_wake`.
+    fair_wake: bool,
+
+    /// See `Configuration::set_steal_mode`.
+    #[cfg(feature = "log")]
+    steal_log: Option<StealLog>,
+
+    /// Set if a worker thread exits abnormally, i.e., if the rayon
+    /// machinery itself panicked. Once that happens, jobs may be lost,
+    /// so rather than risk deadlocking, we refuse to accept new work.
+    poisoned: AtomicBool,
+
+    /// Handles for the worker threads, so that `join_threads` can
+    /// wait for them to exit after the registry is terminated.
+    thread_handles: Mutex<Vec<thread::JoinHandle<()>>>,
+
+    /// See `Configuration::set_detach_threads`: if set, no handles are
+    /// kept, and `join_threads` has nothing to wait for.
+    detach_threads: bool,
+
+    /// If the workers run on threads that the caller donates (see
+    /// `Configuration::set_use_current_threads`), their deques, until
+    /// `take_worker` hands each of them out.
+    donated_workers: Mutex<Vec<Option<Worker<JobRef>>>>,
+
+    /// The deque of the helper worker, unless a thread is helping with
+    /// it right now; and whether one is, which makes the helper one of
+    /// the active workers. See `Registry::try_help`.
+    helper_worker: Mutex<Option<Worker<JobRef>>>,
+    helping: AtomicBool,
+
+    /// The number of workers that are in `mark_blocking`. The spare
+    /// workers only run while there are more of these than spares
+    /// before them; see `Registry::wait_until_wanted`.
+    blocking: AtomicUsize,
+
+    /// The number of spare workers that are looking for work or running
+    /// jobs, rather than parked; these count towards the workers that
+    /// must all be blocked for a deadlock.
+    active_spares: AtomicUsize,
+
+    /// The deques of the spare workers whose threads have not been
+    /// spawned yet, and how to spawn them.
+    spare_workers: Mutex<Vec<Option<(Worker<JobRef>, thread::Builder)>>>,
+
+    /// Signalled (with the registry lock held) when another spare
+    /// worker is wanted, or when the registry terminates.
+    spares_wanted: Condvar,
 }
 
 struct RegistryState {
     terminate: bool,
-    threads_at_work: usize,
-    injected_jobs: VecDeque<JobRef>,
+
+    /// Bumped whenever sleeping workers should wake up and look for
+    /// work. A worker notes the current value before it checks for
+    /// work one last time and goes to sleep, and only actually sleeps
+    /// if the value hasn't changed since.
+    wake_generation: usize,
+
+    /// Pushing end of the global injector queue. A deque `Worker` may
+    /// only be used by one thread at a time, so it lives under the
+    /// lock; see `Registry::inject`.
+    injected_jobs: Worker<JobRef>,
+
+    /// In fair mode, the workers that are asleep, in the order in which
+    /// they fell asleep; see `Registry::notify_sleepers`.
+    wait_list: VecDeque<usize>,
 }
 
+/// How long all workers must be blocked before we decide that they
+/// are deadlocked; see `Registry::block`. The workers poll for work at
+/// least every millisecond or so (see `Backoff`), so any work that
+/// exists is found well within this time.
+const DEADLOCK_GRACE_MILLIS: u64 = 100;
+
 /// ////////////////////////////////////////////////////////////////////////
 /// Initialization
 
-static mut THE_REGISTRY: Option<&'static Registry> = None;
+static mut THE_REGISTRY: Option<Result<&'static Arc<Registry>, InitError>> = None;
 static THE_REGISTRY_SET: Once = ONCE_INIT;
 
+/// True if `THE_REGISTRY` was set up lazily, with the default
+/// configuration, rather than by `initialize()`. Written along with
+/// `THE_REGISTRY`.
+static mut THE_REGISTRY_IS_DEFAULT: bool = false;
+
+/// Set once `THE_REGISTRY` is; unlike `THE_REGISTRY_SET`, this can be
+/// checked without starting the registry.
+static THE_REGISTRY_STARTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// See `Configuration::disable_implicit_pool`.
+static IMPLICIT_REGISTRY_DISABLED: AtomicBool = ATOMIC_BOOL_INIT;
+
 /// Starts the worker threads (if that has not already happened). If
 /// initialization has not already occurred, use the default
-/// configuration.
-pub fn get_registry() -> &'static Registry {
-    THE_REGISTRY_SET.call_once(|| unsafe { init_registry(Configuration::new()) });
-    unsafe { THE_REGISTRY.unwrap() }
+/// configuration. Panics if the worker threads could not be started;
+/// see `try_get_registry()`.
+pub fn get_registry() -> &'static Arc<Registry> {
+    match try_get_registry() {
+        Ok(registry) => registry,
+        Err(err) => panic!("failed to start the global thread pool: {}", err),
+    }
+}
+
+/// Like `get_registry()`, but returns an error rather than panicking
+/// if the worker threads could not be started. Initialization is only
+/// ever attempted once, so once this fails, it always fails.
+pub fn try_get_registry() -> Result<&'static Arc<Registry>, InitError> {
+    THE_REGISTRY_SET.call_once(|| unsafe { init_registry(Configuration::new(), true) });
+    unsafe { THE_REGISTRY.clone().unwrap() }
 }
 
 /// Starts the worker threads (if that has not already happened) with
 /// the given configuration.
-pub fn get_registry_with_config(config: Configuration) -> &'static Registry {
-    THE_REGISTRY_SET.call_once(|| unsafe { init_registry(config) });
-    unsafe { THE_REGISTRY.unwrap() }
+pub fn get_registry_with_config(config: Configuration)
+                                -> Result<&'static Arc<Registry>, InitError> {
+    THE_REGISTRY_SET.call_once(|| unsafe { init_registry(config, false) });
+    unsafe { THE_REGISTRY.clone().unwrap() }
 }
 
-/// Initializes the global registry with the given configuration.
-/// Meant to be called from within the `THE_REGISTRY_SET` once
-/// function. Declared `unsafe` because it writes to `THE_REGISTRY` in
-/// an unsynchronized fashion.
-unsafe fn init_registry(config: Configuration) {
-    let registry = leak(Registry::new(config.num_threads()));
-    THE_REGISTRY = Some(registry);
+/// From now on, operations that can do without the global registry do
+/// not start it; see `Configuration::disable_implicit_pool`.
+pub fn disable_implicit_registry() {
+    IMPLICIT_REGISTRY_DISABLED.store(true, Ordering::SeqCst);
 }
 
-enum Work {
-    None,
-    Job(JobRef),
-    Terminate,
+/// Like `try_get_registry()`, for an operation called from outside of
+/// any pool that can do without one (e.g., by running sequentially):
+/// returns `None` rather than starting the worker threads if that was
+/// disabled and they are not running yet.
+pub fn try_get_implicit_registry() -> Option<Result<&'static Arc<Registry>, InitError>> {
+    if IMPLICIT_REGISTRY_DISABLED.load(Ordering::SeqCst) &&
+       !THE_REGISTRY_STARTED.load(Ordering::SeqCst) {
+        None
+    } else {
+        Some(try_get_registry())
+    }
+}
+
+/// Like `try_get_implicit_registry()`, but panics like `get_registry()`
+/// if the worker threads could not be started.
+pub fn get_implicit_registry() -> Option<&'static Arc<Registry>> {
+    try_get_implicit_registry().map(|_| get_registry())
+}
+
+/// True if the worker threads were started lazily, with the default
+/// configuration, rather than by `initialize()`. Only meaningful once
+/// they were started.
+pub fn registry_is_default() -> bool {
+    unsafe { THE_REGISTRY_IS_DEFAULT }
+}
+
+/// Initializes the global registry with the given configuration,
+/// which is the default one if `is_default` is true. Meant to be
+/// called from within the `THE_REGISTRY_SET` once function. Declared
+/// `unsafe` because it writes to `THE_REGISTRY` in an unsynchronized
+/// fashion.
+unsafe fn init_registry(config: Configuration, is_default: bool) {
+    THE_REGISTRY = Some(Registry::new(config).map(leak));
+    THE_REGISTRY_IS_DEFAULT = is_default;
+    THE_REGISTRY_STARTED.store(true, Ordering::SeqCst);
 }
 
 impl Registry {
-    pub fn new(num_threads: Option<usize>) -> Arc<Registry> {
-        let limit_value = match num_threads {
+    /// Creates a registry and starts its worker threads. If one of the
+    /// threads cannot be spawned (e.g., because of resource limits),
+    /// the threads started so far are shut down again and an error is
+    /// returned.
+    pub fn new(configuration: Configuration) -> Result<Arc<Registry>, InitError> {
+        let limit_value = match configuration.num_threads() {
             Some(value) => value,
             None => num_cpus::get(),
         };
 
-        let (workers, stealers): (Vec<_>, Vec<_>) = (0..limit_value).map(|_| deque::new()).unzip();
+        let num_workers = limit_value + configuration.blocking_replacements();
+        let (workers, stealers): (Vec<_>, Vec<_>) = (0..num_workers).map(|_| deque::new()).unzip();
+        let (helper_worker, helper_stealer) = deque::new();
+        let (injector, injected_jobs) = deque::new();
 
         let registry = Arc::new(Registry {
             thread_infos: stealers.into_iter()
-                .map(|s| ThreadInfo::new(s))
+                .enumerate()
+                .map(|(index, s)| {
+                    let numa_node = configuration.numa_node(index);
+                    ThreadInfo::new(s, numa_node, configuration.affinity(index))
+                })
+                .chain(Some(ThreadInfo::new(helper_stealer, None, None)))
                 .collect(),
-            state: Mutex::new(RegistryState::new()),
+            num_threads: limit_value,
+            state: Mutex::new(RegistryState::new(injector)),
             work_available: Condvar::new(),
+            sleepers: AtomicUsize::new(0),
+            injected_jobs: injected_jobs,
+            injected_len: DequeLen::new(),
+            max_queued: configuration.max_queued(),
+            queue_policy: configuration.queue_policy(),
+            queue_space: Condvar::new(),
+            waiting_submitters: AtomicUsize::new(0),
+            panic_handler: configuration.panic_handler(),
+            start_handler: configuration.start_handler(),
+            exit_handler: configuration.exit_handler(),
+            deadlock_handler: configuration.deadlock_handler(),
+            job_start_handler: configuration.job_start_handler(),
+            job_end_handler: configuration.job_end_handler(),
+            blocked: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            unblocked: AtomicUsize::new(0),
+            deterministic: configuration.deterministic(),
+            join_depth_limit: configuration.join_depth_limit(),
+            stack_guard: configuration.stack_guard(),
+            stack_size: configuration.stack_size(),
+            growable_stacks: configuration.growable_stacks(),
+            spin_iterations: configuration.spin_iterations(),
+            heartbeat_interval: configuration.heartbeat_interval(),
+            default_split_threshold: configuration.default_split_threshold(),
+            breadth_first: configuration.breadth_first(),
+            fair_wake: configuration.fair_wake(),
+            #[cfg(feature = "log")]
+            steal_log: configuration.steal_mode().map(|mode| StealLog::new(num_workers, mode)),
+            poisoned: AtomicBool::new(false),
+            thread_handles: Mutex::new(Vec::new()),
+            detach_threads: configuration.detach_threads(),
+            donated_workers: Mutex::new(Vec::new()),
+            helper_worker: Mutex::new(Some(helper_worker)),
+            helping: AtomicBool::new(false),
+            blocking: AtomicUsize::new(0),
+            active_spares: AtomicUsize::new(0),
+            spare_workers: Mutex::new(Vec::new()),
+            spares_wanted: Condvar::new(),
         });
 
-        for (index, worker) in workers.into_iter().enumerate() {
-            let registry = registry.clone();
-            thread::spawn(move || unsafe { main_loop(worker, registry, index) });
+        let mut workers = workers.into_iter();
+        let regular_workers: Vec<_> = workers.by_ref().take(limit_value).collect();
+        *registry.spare_workers.lock().unwrap() = workers.enumerate()
+            .map(|(rank, worker)| Some((worker, thread_builder(&configuration, limit_value + rank))))
+            .collect();
+
+        if configuration.use_current_threads() {
+            *registry.donated_workers.lock().unwrap() =
+                regular_workers.into_iter().map(Some).collect();
+            return Ok(registry);
+        }
+
+        for (index, worker) in regular_workers.into_iter().enumerate() {
+            let builder = thread_builder(&configuration, index);
+            if !Registry::spawn_worker(&registry, builder, worker, index) {
+                registry.terminate();
+                registry.join_threads();
+                return Err(InitError::ThreadSpawnFailed);
+            }
+        }
+
+        Ok(registry)
+    }
+
+    /// Spawns a thread to run the given worker; returns false if the
+    /// thread could not be spawned.
+    fn spawn_worker(registry: &Arc<Registry>,
+                    builder: thread::Builder,
+                    worker: Worker<JobRef>,
+                    index: usize)
+                    -> bool {
+        let thread_registry = registry.clone();
+        match builder.spawn(move || unsafe { main_loop(worker, thread_registry, index) }) {
+            Ok(handle) => {
+                // dropping the handle detaches the thread
+                if !registry.detach_threads {
+                    registry.thread_handles.lock().unwrap().push(handle);
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Hands out the worker with the given index, if the registry runs
+    /// on donated threads and nobody has taken that worker yet.
+    pub fn take_worker(registry: &Arc<Registry>, index: usize) -> Option<DonatedWorker> {
+        let worker = registry.donated_workers
+            .lock()
+            .unwrap()
+            .get_mut(index)
+            .and_then(|worker| worker.take());
+        worker.map(|worker| {
+            DonatedWorker {
+                registry: registry.clone(),
+                worker: worker,
+                index: index,
+            }
+        })
+    }
+
+    /// Lets the current thread, which must not be a worker thread, run
+    /// one job of the pool as the helper worker: it becomes a worker
+    /// for the duration, with a deque (and an index) of its own, so the
+    /// job may do whatever a job can do on a worker, such as `join` or
+    /// spawn into a scope. Once the job is done, the helper runs any
+    /// jobs that it left on its deque, so that none are stuck there
+    /// after it leaves. Returns false if there was no job to take, or
+    /// if another thread is helping already. See `ThreadPool::try_help`.
+    pub fn try_help(registry: &Arc<Registry>) -> bool {
+        // (A steal log only knows about the regular workers and spares.)
+        unsafe {
+            if !WorkerThread::current().is_null() || registry.has_steal_log() ||
+               registry.is_poisoned() {
+                return false;
+            }
         }
+        let worker = match registry.helper_worker.lock().unwrap().take() {
+            Some(worker) => worker,
+            None => return false,
+        };
+        let index = registry.helper_index();
+        let mut worker_thread = WorkerThread::new(worker, registry.clone(), index);
+        let helped = unsafe {
+            worker_thread.set_current();
+            let _current_guard = unwind::finally((), |_| WorkerThread::clear_current());
+
+            // as in `main_loop`, a panic here means that the pool is
+            // corrupted; the helper's deque is never given back then
+            let poison_guard = unwind::finally(registry, |registry| registry.poison());
+            registry.helping.store(true, Ordering::SeqCst);
+            let job = worker_thread.steal_remote();
+            if let Some(job) = job {
+                worker_thread.execute_stolen(job);
+                // e.g. jobs pushed through `with_worker`, and not taken back
+                while let Some(job) = worker_thread.pop_local() {
+                    worker_thread.execute_stolen(job);
+                }
+            }
+            registry.helping.store(false, Ordering::SeqCst);
+            mem::forget(poison_guard);
+            job.is_some()
+        };
+        *registry.helper_worker.lock().unwrap() = Some(worker_thread.worker);
+        helped
+    }
 
-        registry
+    /// Returns the registry of the current worker thread, or the
+    /// global registry if this is not a worker thread.
+    pub fn current() -> Arc<Registry> {
+        unsafe {
+            let worker_thread = WorkerThread::current();
+            if worker_thread.is_null() {
+                get_registry().clone()
+            } else {
+                (*worker_thread).registry.clone()
+            }
+        }
     }
 
+    /// The number of regular workers, not counting the spares.
     pub fn num_threads(&self) -> usize {
-        self.thread_infos.len()
+        self.num_threads
+    }
+
+    /// True if there are spare workers, which may stand in for blocked
+    /// ones; see `Configuration::set_blocking_replacements`.
+    #[inline]
+    pub fn has_spares(&self) -> bool {
+        self.helper_index() > self.num_threads
+    }
+
+    /// The index of the helper worker, which comes after the spares.
+    #[inline]
+    fn helper_index(&self) -> usize {
+        self.thread_infos.len() - 1
+    }
+
+    /// True if either of the job handlers is set; see
+    /// `Configuration::set_job_start_handler`.
+    #[inline]
+    pub fn has_job_handlers(&self) -> bool {
+        self.job_start_handler.is_some() || self.job_end_handler.is_some()
+    }
+
+    /// The approximate number of jobs waiting to be picked up: on the
+    /// deques of the workers, in their mailboxes and node-local lists,
+    /// and in the injector queue. This only reads counters (with
+    /// relaxed loads), without taking any locks, so it is cheap, but
+    /// may be slightly off while jobs come and go.
+    pub fn queued_len(&self) -> usize {
+        let queued_on_workers: usize = self.thread_infos
+            .iter()
+            .map(|info| {
+                info.len.get() + info.mailbox_len.load(Ordering::Relaxed) +
+                info.node_jobs_len.load(Ordering::Relaxed)
+            })
+            .sum();
+        queued_on_workers + self.injected_len.get()
+    }
+
+    /// Returns a snapshot of the steal statistics of each worker; see
+    /// `ThreadPool::steal_stats`.
+    pub fn steal_stats(&self) -> Vec<WorkerStats> {
+        self.thread_infos[..self.helper_index()].iter().map(|info| info.stats.snapshot()).collect()
+    }
+
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    pub fn join_depth_limit(&self) -> Option<usize> {
+        self.join_depth_limit
+    }
+
+    /// A `Backoff` for a worker that found nothing to do, starting to
+    /// sleep after as many rounds as configured.
+    #[inline]
+    fn backoff(&self) -> Backoff {
+        match self.spin_iterations {
+            Some(n) => Backoff::with_spin_rounds(cmp::min(n, u32::MAX as usize) as u32),
+            None => Backoff::new(),
+        }
+    }
+
+    pub fn heartbeat_interval(&self) -> Option<usize> {
+        self.heartbeat_interval
+    }
+
+    pub fn default_split_threshold(&self) -> Option<usize> {
+        self.default_split_threshold
+    }
+
+    /// The size of the stack segments that workers move on to once
+    /// their stack runs low, if they may do so. (With no stack guard,
+    /// the stack never runs low.)
+    pub fn growable_stacks(&self) -> Option<usize> {
+        if self.stack_guard == 0 {
+            None
+        } else {
+            self.growable_stacks
+        }
+    }
+
+    /// Handles a panic from a job that has no caller to propagate the
+    /// panic to (e.g., a job submitted via `spawn`). We pass it to the
+    /// configured panic handler, if any; otherwise, or if the handler
+    /// itself panics, we abort.
+    pub fn handle_panic(&self, err: Box<Any + Send>) {
+        match self.panic_handler {
+            Some(ref handler) => {
+                let abort_guard = unwind::AbortIfPanic;
+                handler(err);
+                mem::forget(abort_guard);
+            }
+            None => {
+                // Default behavior is to abort, thanks to the guard
+                // being dropped at the end of this block.
+                let _abort_guard = unwind::AbortIfPanic;
+            }
+        }
+    }
+
+    /// True if we need to know which workers are blocked, i.e., if
+    /// there is a deadlock handler.
+    #[inline]
+    fn detects_deadlocks(&self) -> bool {
+        self.deadlock_handler.is_some()
+    }
+
+    /// Called by a worker that found nothing to do, either while it
+    /// waits for a latch in `steal_until` (`waiting` is true) or while
+    /// it is idle in the main loop. If all workers are now blocked and
+    /// at least one of them is waiting, that may be a deadlock: none of
+    /// them is running a job that could set the latch of a waiting
+    /// worker, or push new work.
+    ///
+    /// But the counts may be out of date: a worker may just have found
+    /// a job, or seen its latch set, without having called `unblock`
+    /// yet. So we give the others a grace period, and only report the
+    /// deadlock if none of them has unblocked since, even though they
+    /// keep looking for work (or are asleep, in which case any new work
+    /// wakes them up).
+    fn block(&self, worker: usize, waiting: bool) {
+        let unblocked = self.unblocked.load(Ordering::SeqCst);
+        if waiting {
+            self.waiting.fetch_add(1, Ordering::SeqCst);
+        }
+        if self.blocked.fetch_add(1, Ordering::SeqCst) + 1 == self.active_workers() {
+            self.check_deadlock(worker, unblocked);
+        }
+    }
+
+    /// Called by a worker that was blocked once it finds a job to run,
+    /// or the latch it was waiting for gets set.
+    fn unblock(&self, waiting: bool) {
+        self.unblocked.fetch_add(1, Ordering::SeqCst);
+        self.blocked.fetch_sub(1, Ordering::SeqCst);
+        if waiting {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// The number of workers that are not parked: the regular ones, the
+    /// active spares, and the helper while a thread helps.
+    #[inline]
+    fn active_workers(&self) -> usize {
+        self.num_threads + self.active_spares.load(Ordering::SeqCst) +
+        self.helping.load(Ordering::SeqCst) as usize
+    }
+
+    #[cold]
+    fn check_deadlock(&self, _worker: usize, unblocked: usize) {
+        thread::sleep(Duration::from_millis(DEADLOCK_GRACE_MILLIS));
+        if self.blocked.load(Ordering::SeqCst) != self.active_workers() ||
+           self.waiting.load(Ordering::SeqCst) == 0 ||
+           self.unblocked.load(Ordering::SeqCst) != unblocked {
+            return;
+        }
+        log!(Deadlock { worker: _worker });
+        if let Some(ref handler) = self.deadlock_handler {
+            let abort_guard = unwind::AbortIfPanic;
+            handler();
+            mem::forget(abort_guard);
+        }
+    }
+
+    /// True if one of our worker threads has exited abnormally.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::SeqCst)
+    }
+
+    /// Panics if the registry is poisoned. Called before handing work
+    /// to the pool, so that callers get an error instead of blocking
+    /// forever on a job that no thread will execute.
+    pub fn check_poisoned(&self) {
+        if self.is_poisoned() {
+            panic!("rayon thread pool is poisoned");
+        }
+    }
+
+    /// Marks the registry as poisoned and wakes up any sleeping
+    /// workers.
+    pub fn poison(&self) {
+        self.poisoned.store(true, Ordering::SeqCst);
+        self.work_available.notify_all();
     }
 
     /// Waits for the worker threads to get up and running.  This is
@@ -99,117 +655,694 @@ impl Registry {
     /// you can get more consistent numbers by having everything
     /// "ready to go".
     pub fn wait_until_primed(&self) {
-        for info in &self.thread_infos {
+        for info in &self.thread_infos[..self.num_threads] {
             info.primed.wait();
         }
     }
 
+    /// Called when one of our workers is about to block in
+    /// `mark_blocking`: lets another spare worker run, if there is one
+    /// left, spawning its thread the first time. If the thread cannot
+    /// be spawned, the worker just blocks without a replacement.
+    pub fn start_blocking(registry: &Arc<Registry>) {
+        let blocking = registry.blocking.fetch_add(1, Ordering::SeqCst) + 1;
+        if blocking > registry.helper_index() - registry.num_threads {
+            return;
+        }
+        let rank = blocking - 1;
+        let spare = registry.spare_workers.lock().unwrap()[rank].take();
+        match spare {
+            Some((worker, builder)) => {
+                Registry::spawn_worker(registry, builder, worker, registry.num_threads + rank);
+            }
+            None => {
+                let _state = registry.state.lock().unwrap();
+                registry.spares_wanted.notify_all();
+            }
+        }
+    }
+
+    /// Called when a worker returns from `mark_blocking`. The spare
+    /// that is no longer wanted notices once it is done with its job.
+    pub fn stop_blocking(&self) {
+        self.blocking.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// True if spare worker `index` is wanted, i.e., if more workers
+    /// are blocking than there are spares before it.
+    #[inline]
+    fn spare_is_wanted(&self, index: usize) -> bool {
+        self.blocking.load(Ordering::SeqCst) > index - self.num_threads
+    }
+
+    /// Parks spare worker `index`, which is no longer wanted (and no
+    /// longer blocked), until it is wanted again. Returns false if the
+    /// registry terminated instead.
+    fn park_spare(&self, index: usize) -> bool {
+        // We may have been woken up for a job that we are not going to
+        // take after all, so pass that on.
+        self.tickle();
+
+        let unblocked = self.unblocked.load(Ordering::SeqCst);
+        self.active_spares.fetch_sub(1, Ordering::SeqCst);
+        if self.detects_deadlocks() && self.blocked.load(Ordering::SeqCst) == self.active_workers() {
+            // we were the last one not blocked
+            self.check_deadlock(index, unblocked);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        while !self.spare_is_wanted(index) {
+            if state.terminate {
+                return false;
+            }
+            state = self.spares_wanted.wait(state).unwrap();
+        }
+        self.active_spares.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
     /// ////////////////////////////////////////////////////////////////////////
-    /// MAIN LOOP
+    /// SLEEPING
     ///
-    /// So long as all of the worker threads are hanging out in their
-    /// top-level loop, there is no work to be done.
+    /// Workers that have not found any work for a while go to sleep
+    /// until somebody pushes or injects a new job. The protocol is:
+    ///
+    /// - The worker announces that it is getting sleepy, which bumps
+    ///   `sleepers` and notes the current `wake_generation`.
+    /// - It then checks for work one last time. If it finds some, it
+    ///   cancels the announcement and goes back to work.
+    /// - Otherwise, it sleeps until `wake_generation` changes.
+    ///
+    /// Whoever pushes a job first issues a fence and then checks
+    /// whether `sleepers` is non-zero; if so, it bumps the generation
+    /// and notifies the condition variable. Since the sleepy worker
+    /// likewise issues a fence between bumping `sleepers` and its last
+    /// check for work, either the pusher sees the sleeper, or the
+    /// sleeper sees the job: a job can't get pushed while everybody
+    /// sleeps through it.
+    ///
+    /// In fair mode, sleeping workers also put themselves on the
+    /// `wait_list`, and keep sleeping until a waker takes them off it,
+    /// so that it is the waker that decides who wakes up. Bumping the
+    /// generation still stops sleepy workers that are not on the list
+    /// yet from going to sleep, and every worker on the list is woken
+    /// up eventually, since a worker is only woken once it has reached
+    /// the front (or by `wake(true)`, which takes everybody off).
 
-    fn start_working(&self, index: usize) {
-        log!(StartWorking { index: index });
-        {
-            let mut state = self.state.lock().unwrap();
-            state.threads_at_work += 1;
+    /// Announces that the current worker is about to go to sleep, and
+    /// returns the generation to pass to `sleep`. The worker must then
+    /// check for work once more, and call `sleep` or `cancel_sleep`.
+    fn get_sleepy(&self) -> usize {
+        let generation = {
+            let state = self.state.lock().unwrap();
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            state.wake_generation
+        };
+        atomic::fence(Ordering::SeqCst);
+        generation
+    }
+
+    /// Called instead of `sleep` if the worker found work after all.
+    fn cancel_sleep(&self) {
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Sleeps until the wake generation moves past `generation`.
+    /// Returns false if the registry was terminated instead.
+    fn sleep(&self, worker: usize, generation: usize) -> bool {
+        log!(Sleep { worker: worker });
+        let mut state = self.state.lock().unwrap();
+        if self.fair_wake {
+            if !state.terminate && state.wake_generation == generation {
+                state.wait_list.push_back(worker);
+                let wake_signal = &self.thread_infos[worker].wake_signal;
+                while !state.terminate && state.wait_list.contains(&worker) {
+                    state = wake_signal.wait(state).unwrap();
+                }
+            }
+        } else {
+            while !state.terminate && state.wake_generation == generation {
+                state = self.work_available.wait(state).unwrap();
+            }
+        }
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        log!(Awake { worker: worker });
+        !state.terminate
+    }
+
+    /// Wakes up a sleeping worker, if there is any, because a new job
+    /// was pushed. This is called on every push, so the common case of
+    /// nobody sleeping must be fast.
+    #[inline]
+    pub fn tickle(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            self.wake(false);
+        }
+    }
+
+    /// Like `tickle`, but wakes up all the sleeping workers, for a job
+    /// that only some of them may take.
+    #[inline]
+    pub fn tickle_all(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            self.wake(true);
+        }
+    }
+
+    #[cold]
+    fn wake(&self, all: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.wake_generation = state.wake_generation.wrapping_add(1);
+        self.notify_sleepers(&mut state, all);
+    }
+
+    /// Wakes up one sleeping worker, or all of them, once the wake
+    /// generation has been bumped. In fair mode, that is the worker at
+    /// the front of the `wait_list`, i.e., the one that has been asleep
+    /// the longest; otherwise, the condition variable picks one.
+    fn notify_sleepers(&self, state: &mut RegistryState, all: bool) {
+        if !self.fair_wake {
+            if all {
+                self.work_available.notify_all();
+            } else {
+                self.work_available.notify_one();
+            }
+            return;
+        }
+        while let Some(worker) = state.wait_list.pop_front() {
+            self.thread_infos[worker].wake_signal.notify_one();
+            if !all {
+                break;
+            }
         }
-        self.work_available.notify_all();
     }
 
     pub unsafe fn inject(&self, injected_jobs: &[JobRef]) {
         log!(InjectJobs { count: injected_jobs.len() });
-        {
+        self.check_poisoned();
+        let mut state = self.state.lock().unwrap();
+        self.inject_locked(&mut state, injected_jobs);
+    }
+
+    /// Like `inject`, but for `ThreadPool::submit`: if the pool has a
+    /// limit on the injector queue (see `Configuration::set_max_queued`)
+    /// and there is no room for the jobs, waits for room, or fails with
+    /// `PoolError::QueueFull`, depending on the policy. The jobs go in
+    /// all together, once they all fit (or into an empty queue, if they
+    /// never could). A worker thread never waits, since the jobs that
+    /// would make room may be waiting for it. If this fails, the jobs
+    /// were not injected, and are still owned by the caller.
+    pub unsafe fn inject_bounded(&self, injected_jobs: &[JobRef]) -> Result<(), PoolError> {
+        let max_queued = match self.max_queued {
+            Some(max_queued) => max_queued,
+            None => {
+                self.inject(injected_jobs);
+                return Ok(());
+            }
+        };
+        log!(InjectJobs { count: injected_jobs.len() });
+        self.check_poisoned();
+        let fits = || {
+            let queued = self.injected_len.get();
+            queued == 0 || queued + injected_jobs.len() <= max_queued
+        };
+        let mut state = self.state.lock().unwrap();
+        if !fits() {
+            if self.queue_policy == QueuePolicy::Reject || !WorkerThread::current().is_null() {
+                return Err(PoolError::QueueFull);
+            }
+            // announce ourselves before we look again, so that a worker
+            // that takes a job after that knows to wake us up (see
+            // `pop_injected_job`)
+            self.waiting_submitters.fetch_add(1, Ordering::SeqCst);
+            atomic::fence(Ordering::SeqCst);
+            while !fits() && !state.terminate {
+                state = self.queue_space.wait(state).unwrap();
+            }
+            self.waiting_submitters.fetch_sub(1, Ordering::SeqCst);
+            if state.terminate {
+                return Err(PoolError::PoolShutdown);
+            }
+        }
+        self.inject_locked(&mut state, injected_jobs);
+        Ok(())
+    }
+
+    /// The body of `inject`, with the lock held.
+    unsafe fn inject_locked(&self, state: &mut RegistryState, injected_jobs: &[JobRef]) {
+        let owner = self.current_index();
+
+        assert!(!state.terminate, "inject() sees state.terminate as true");
+
+        let mut routed = false;
+        for &job_ref in injected_jobs {
+            if self.route(owner, job_ref) {
+                routed = true;
+            } else {
+                state.injected_jobs.push(job_ref);
+                DequeLen::bump(&self.injected_len.pushed);
+            }
+        }
+        state.wake_generation = state.wake_generation.wrapping_add(1);
+
+        // in fair mode, wake one worker per job (but no more workers
+        // than there are), unless some job was handed to a particular
+        // worker (see `WorkerThread::push`)
+        if self.fair_wake && !routed {
+            for _ in 0..cmp::min(injected_jobs.len(), self.thread_infos.len()) {
+                self.notify_sleepers(state, false);
+            }
+        } else {
+            self.notify_sleepers(state, true);
+        }
+    }
+
+    /// Like `inject`, but for jobs that may be submitted after the
+    /// registry has been terminated (e.g., a future being woken up
+    /// after its pool was dropped). In that case, the job is executed
+    /// with `JobMode::Abort` instead.
+    pub unsafe fn inject_or_abort(&self, job_ref: JobRef) {
+        log!(InjectJobs { count: 1 });
+        let owner = self.current_index();
+        let terminated = {
             let mut state = self.state.lock().unwrap();
+            if !state.terminate {
+                let routed = self.route(owner, job_ref);
+                if !routed {
+                    state.injected_jobs.push(job_ref);
+                    DequeLen::bump(&self.injected_len.pushed);
+                    state.wake_generation = state.wake_generation.wrapping_add(1);
+                }
+                self.notify_sleepers(&mut state, !self.fair_wake || routed);
+            }
+            state.terminate
+        };
+        if terminated {
+            job_ref.execute(JobMode::Abort);
+        }
+    }
 
-            // It should not be possible for `state.terminate` to be true
-            // here. It is only set to true when the user creates (and
-            // drops) a `ThreadPool`; and, in that case, they cannot be
-            // calling `inject()` later, since they dropped their
-            // `ThreadPool`.
-            assert!(!state.terminate, "inject() sees state.terminate as true");
+    /// Pops a job from the global injector queue, if any. This may be
+    /// called from any thread, without holding the lock.
+    fn pop_injected_job(&self) -> Option<JobRef> {
+        loop {
+            match self.injected_jobs.steal() {
+                Stolen::Empty => return None,
+                Stolen::Abort => continue,
+                Stolen::Data(job_ref) => {
+                    self.injected_len.stolen.fetch_add(1, Ordering::SeqCst);
+                    if self.waiting_submitters.load(Ordering::SeqCst) > 0 {
+                        let _state = self.state.lock().unwrap();
+                        self.queue_space.notify_all();
+                    }
+                    return Some(job_ref);
+                }
+            }
+        }
+    }
 
-            state.injected_jobs.extend(injected_jobs);
+    /// Executes `op` on one of the worker threads of this registry. If
+    /// the current thread is already one of our workers, `op` simply
+    /// runs inline; otherwise, it is injected into the pool and the
+    /// current thread blocks until it completes. Panics in `op` are
+    /// propagated to the caller.
+    pub fn in_worker<OP, R>(&self, op: OP) -> R
+        where OP: FnOnce() -> R + Send
+    {
+        self.check_poisoned();
+        if self.is_current() {
+            op()
+        } else {
+            self.in_worker_cold(op)
         }
-        self.work_available.notify_all();
     }
 
-    fn wait_for_work(&self, _worker: usize, was_active: bool) -> Work {
-        log!(WaitForWork {
-            worker: _worker,
-            was_active: was_active,
-        });
+    /// Like `in_worker`, but fails instead of blocking forever if the
+    /// registry has been terminated (or is terminated before a worker
+    /// takes the job); see `ThreadPool::try_install`. A worker that is
+    /// still running after termination just runs `op` inline.
+    pub fn try_in_worker<OP, R>(&self, op: OP) -> Result<R, PoolError>
+        where OP: FnOnce() -> R + Send
+    {
+        self.check_poisoned();
+        if self.is_current() {
+            return Ok(op());
+        }
+        unsafe {
+            // `terminate` aborts any job still in the injector queue,
+            // and `inject_or_abort` aborts the job if `terminate` came
+            // first; either way the latch is set, and no result is.
+            let job = StackJob::new(|_| op(), LockLatch::new());
+            self.inject_or_abort(job.as_job_ref());
+            job.latch.wait();
+            job.try_take_result().ok_or(PoolError::PoolShutdown)
+        }
+    }
 
-        let mut state = self.state.lock().unwrap();
+    /// Runs `op(index)` exactly once on each worker thread, where
+    /// `index` is the index of that worker, and returns once all of
+    /// them are done; see `ThreadPool::broadcast`.
+    pub fn broadcast<OP>(&self, op: OP)
+        where OP: Fn(usize) + Sync
+    {
+        self.in_worker(|| unsafe { (*WorkerThread::current()).broadcast(&op) })
+    }
 
-        if was_active {
-            state.threads_at_work -= 1;
+    #[cold]
+    fn in_worker_cold<OP, R>(&self, op: OP) -> R
+        where OP: FnOnce() -> R + Send
+    {
+        unsafe {
+            let job = StackJob::new(|_| op(), LockLatch::new());
+            self.inject(&[job.as_job_ref()]);
+            job.latch.wait();
+            job.take_result()
+        }
+    }
+
+    /// Returns the index of the current thread if it is one of our
+    /// worker threads.
+    fn current_index(&self) -> Option<usize> {
+        if self.is_current() {
+            unsafe { Some((*WorkerThread::current()).index) }
+        } else {
+            None
+        }
+    }
+
+    /// Gives the steal log, if any, a chance to hand `job` straight to
+    /// the worker that is going to take it, in which case this returns
+    /// true and the job must not be pushed anywhere. `owner` is the
+    /// index of the worker pushing the job, if any.
+    #[cfg(feature = "log")]
+    fn route(&self, owner: Option<usize>, job: JobRef) -> bool {
+        self.steal_log.as_ref().map_or(false, |steal_log| steal_log.route(owner, job))
+    }
+
+    #[cfg(not(feature = "log"))]
+    #[inline]
+    fn route(&self, _owner: Option<usize>, _job: JobRef) -> bool {
+        false
+    }
+
+    /// Notes in the steal log, if any, that `thief` took `job` from
+    /// another worker or the injector queue.
+    #[cfg(feature = "log")]
+    fn record_steal(&self, thief: usize, job: &JobRef) {
+        if let Some(ref steal_log) = self.steal_log {
+            steal_log.record_steal(thief, job);
+        }
+    }
+
+    #[cfg(not(feature = "log"))]
+    #[inline]
+    fn record_steal(&self, _thief: usize, _job: &JobRef) {}
+
+    /// True if there is a steal log, in which case workers must steal
+    /// one job at a time.
+    #[cfg(feature = "log")]
+    pub fn has_steal_log(&self) -> bool {
+        self.steal_log.is_some()
+    }
+
+    #[cfg(not(feature = "log"))]
+    #[inline]
+    pub fn has_steal_log(&self) -> bool {
+        false
+    }
+
+    /// In a pool that replays a steal schedule, returns the outcome of
+    /// `StealLog::take`; otherwise, returns `None`.
+    #[cfg(feature = "log")]
+    fn take_replayed(&self, thief: usize) -> Option<Result<Option<JobRef>, ()>> {
+        match self.steal_log {
+            Some(ref steal_log) if steal_log.is_replay() => Some(steal_log.take(thief)),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "log"))]
+    #[inline]
+    fn take_replayed(&self, _thief: usize) -> Option<Result<Option<JobRef>, ()>> {
+        None
+    }
+
+    #[cfg(feature = "log")]
+    pub fn steal_schedule(&self) -> Option<StealSchedule> {
+        self.steal_log.as_ref().and_then(|steal_log| steal_log.schedule())
+    }
+
+    /// True if the current thread is one of our worker threads.
+    pub fn is_current(&self) -> bool {
+        unsafe {
+            let worker_thread = WorkerThread::current();
+            if worker_thread.is_null() {
+                false
+            } else {
+                let registry: &Registry = (*worker_thread).registry();
+                registry as *const Registry == self as *const Registry
+            }
+        }
+    }
+
+    /// Waits for all worker threads to exit. This only makes sense
+    /// after `terminate` has been called, and must not be called from
+    /// one of our own worker threads, or else it would deadlock.
+    pub fn join_threads(&self) {
+        debug_assert!(!self.is_current());
+        let handles = mem::replace(&mut *self.thread_handles.lock().unwrap(), Vec::new());
+        for handle in handles {
+            // A worker that panicked has already poisoned the
+            // registry; there is nothing more to report here.
+            let _ = handle.join();
+        }
+    }
+
+    pub fn terminate(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.terminate = true;
+            while let Some(job) = state.injected_jobs.pop() {
+                DequeLen::bump(&self.injected_len.popped);
+                unsafe {
+                    job.execute(JobMode::Abort);
+                }
+            }
+            self.notify_sleepers(&mut state, true);
+            self.spares_wanted.notify_all();
+            self.queue_space.notify_all();
+        }
+    }
+}
+
+impl RegistryState {
+    pub fn new(injected_jobs: Worker<JobRef>) -> RegistryState {
+        RegistryState {
+            wake_generation: 0,
+            injected_jobs: injected_jobs,
+            wait_list: VecDeque::new(),
+            terminate: false,
+        }
+    }
+}
+
+struct ThreadInfo {
+    // latch is set once thread has started and we are entering into
+    // the main loop
+    primed: LockLatch,
+    stealer: Stealer<JobRef>,
+    len: Arc<DequeLen>,
+    stats: Arc<StealStats>,
+
+    /// Jobs that must be executed by this worker in particular, and
+    /// hence can't go on a deque, where anybody could steal them; see
+    /// `WorkerThread::broadcast`.
+    mailbox: Mutex<VecDeque<JobRef>>,
+
+    /// The number of jobs in `mailbox`, so that the worker can check
+    /// for mail without taking the lock.
+    mailbox_len: AtomicUsize,
+
+    /// Signalled (with the registry lock held) when this worker is
+    /// taken off the `wait_list` in fair mode; see `Registry::sleep`.
+    wake_signal: Condvar,
+
+    /// The NUMA node of this worker (`UNKNOWN_NODE` until it has asked
+    /// the operating system); see `Configuration::set_numa_node`.
+    numa_node: AtomicUsize,
+
+    /// The core that this worker pins itself to when it starts, if
+    /// any; see `Configuration::set_affinity`.
+    core: Option<CoreId>,
+
+    /// Jobs pushed by `join_local`, which only workers on the same NUMA
+    /// node may steal. The owner pushes and pops at the back, thieves
+    /// take the oldest job from the front, as with the deque.
+    node_jobs: Mutex<VecDeque<JobRef>>,
+
+    /// The number of jobs in `node_jobs`, so that thieves can skip an
+    /// empty list without taking the lock.
+    node_jobs_len: AtomicUsize,
+
+    /// The index of the last worker that stole several of our jobs at
+    /// once (see `steal_from`), or `NO_THIEF`; see `reclaim`.
+    bulk_thief: AtomicUsize,
+}
+
+/// The NUMA node of a worker that has not looked up its node yet.
+const UNKNOWN_NODE: usize = ::std::usize::MAX;
+
+/// The `bulk_thief` of a worker that nobody has stolen from in bulk.
+const NO_THIEF: usize = ::std::usize::MAX;
+
+/// How many jobs `reclaim` steals from the bulk thief, at most.
+const RECLAIM_STEALS: usize = 4;
+
+/// How many jobs per worker of the pool make a deque crowded; see
+/// `WorkerThread::deque_is_crowded`.
+pub const CROWDED_JOBS_PER_THREAD: usize = 2;
+
+impl ThreadInfo {
+    fn new(stealer: Stealer<JobRef>,
+           numa_node: Option<usize>,
+           core: Option<CoreId>)
+           -> ThreadInfo {
+        ThreadInfo {
+            primed: LockLatch::new(),
+            stealer: stealer,
+            len: Arc::new(DequeLen::new()),
+            stats: Arc::new(StealStats::new()),
+            mailbox: Mutex::new(VecDeque::new()),
+            mailbox_len: AtomicUsize::new(0),
+            wake_signal: Condvar::new(),
+            numa_node: AtomicUsize::new(numa_node.unwrap_or(UNKNOWN_NODE)),
+            core: core,
+            node_jobs: Mutex::new(VecDeque::new()),
+            node_jobs_len: AtomicUsize::new(0),
+            bulk_thief: AtomicUsize::new(NO_THIEF),
         }
+    }
 
-        loop {
-            // Check if we need to terminate.
-            if state.terminate {
-                return Work::Terminate;
-            }
+    /// Adds `job` to our mailbox. The caller must then wake up the
+    /// sleeping workers, as we may be one of them.
+    fn post(&self, job: JobRef) {
+        let mut mailbox = self.mailbox.lock().unwrap();
+        mailbox.push_back(job);
+        self.mailbox_len.store(mailbox.len(), Ordering::SeqCst);
+    }
 
-            // Otherwise, if anything was injected from outside,
-            // return that.  Note that this gives preference to
-            // injected items over stealing from others, which is a
-            // bit dubious, but then so is the opposite.
-            if let Some(job) = state.injected_jobs.pop_front() {
-                state.threads_at_work += 1;
-                self.work_available.notify_all();
-                return Work::Job(job);
-            }
+    /// Takes the oldest job from our mailbox, if any.
+    fn take_mail(&self) -> Option<JobRef> {
+        if self.mailbox_len.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let mut mailbox = self.mailbox.lock().unwrap();
+        let job = mailbox.pop_front();
+        self.mailbox_len.store(mailbox.len(), Ordering::SeqCst);
+        job
+    }
 
-            // If any of the threads are running a job, we should spin
-            // up, since they may generate subworkitems.
-            if state.threads_at_work > 0 {
-                return Work::None;
-            }
+    fn push_node_job(&self, job: JobRef) {
+        let mut node_jobs = self.node_jobs.lock().unwrap();
+        node_jobs.push_back(job);
+        self.node_jobs_len.store(node_jobs.len(), Ordering::SeqCst);
+    }
 
-            state = self.work_available.wait(state).unwrap();
+    /// Takes `job` back off the end of `node_jobs`, unless it was
+    /// stolen in the meantime. Since thieves take jobs in the order in
+    /// which they were pushed, `job` is either last or gone.
+    fn pop_node_job(&self, job: &JobRef) -> bool {
+        let mut node_jobs = self.node_jobs.lock().unwrap();
+        let found = node_jobs.back().map_or(false, |last| last.same_job(job));
+        if found {
+            node_jobs.pop_back();
+            self.node_jobs_len.store(node_jobs.len(), Ordering::SeqCst);
         }
+        found
     }
 
-    pub fn terminate(&self) {
-        {
-            let mut state = self.state.lock().unwrap();
-            state.terminate = true;
-            for job in state.injected_jobs.drain(..) {
-                unsafe {
-                    job.execute(JobMode::Abort);
-                }
-            }
+    fn steal_node_job(&self) -> Option<JobRef> {
+        if self.node_jobs_len.load(Ordering::SeqCst) == 0 {
+            return None;
         }
-        self.work_available.notify_all();
+        let mut node_jobs = self.node_jobs.lock().unwrap();
+        let job = node_jobs.pop_front();
+        self.node_jobs_len.store(node_jobs.len(), Ordering::SeqCst);
+        job
     }
 }
 
-impl RegistryState {
-    pub fn new() -> RegistryState {
-        RegistryState {
-            threads_at_work: 0,
-            injected_jobs: VecDeque::new(),
-            terminate: false,
+/// Tracks the (approximate) number of jobs in a worker's deque, so that
+/// thieves can tell how much there is to steal. The owning worker only
+/// ever bumps `pushed` and `popped`, so it can do so with plain loads
+/// and stores rather than atomic read-modify-write operations; only
+/// thieves, who may race with each other, need `fetch_add`.
+struct DequeLen {
+    pushed: AtomicUsize,
+    popped: AtomicUsize,
+    stolen: AtomicUsize,
+}
+
+impl DequeLen {
+    fn new() -> DequeLen {
+        DequeLen {
+            pushed: AtomicUsize::new(0),
+            popped: AtomicUsize::new(0),
+            stolen: AtomicUsize::new(0),
         }
     }
+
+    /// Called by the owning worker only.
+    #[inline]
+    fn bump(counter: &AtomicUsize) {
+        counter.store(counter.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> usize {
+        // The counters are read at slightly different times, so the
+        // result may be a bit off (or even "negative", which we treat
+        // as empty); that's fine for a heuristic.
+        let removed = self.popped.load(Ordering::Relaxed)
+            .wrapping_add(self.stolen.load(Ordering::Relaxed));
+        let len = self.pushed.load(Ordering::Relaxed).wrapping_sub(removed);
+        if len > ::std::isize::MAX as usize { 0 } else { len }
+    }
 }
 
-struct ThreadInfo {
-    // latch is set once thread has started and we are entering into
-    // the main loop
-    primed: LockLatch,
-    stealer: Stealer<JobRef>,
+/// Counts what a worker did with its jobs, for `steal_stats`. Like
+/// the counters in `DequeLen`, these are only ever bumped by the
+/// owning worker, so keeping them costs next to nothing.
+struct StealStats {
+    stolen: AtomicUsize,
+    failed_steals: AtomicUsize,
+    popped: AtomicUsize,
+    lost: AtomicUsize,
 }
 
-impl ThreadInfo {
-    fn new(stealer: Stealer<JobRef>) -> ThreadInfo {
-        ThreadInfo {
-            primed: LockLatch::new(),
-            stealer: stealer,
+impl StealStats {
+    fn new() -> StealStats {
+        StealStats {
+            stolen: AtomicUsize::new(0),
+            failed_steals: AtomicUsize::new(0),
+            popped: AtomicUsize::new(0),
+            lost: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called by the owning worker only.
+    #[inline]
+    fn bump(counter: &AtomicUsize) {
+        DequeLen::bump(counter);
+    }
+
+    fn snapshot(&self) -> WorkerStats {
+        WorkerStats {
+            stolen: self.stolen.load(Ordering::Relaxed),
+            failed_steals: self.failed_steals.load(Ordering::Relaxed),
+            popped: self.popped.load(Ordering::Relaxed),
+            lost: self.lost.load(Ordering::Relaxed),
         }
     }
 }
@@ -219,9 +1352,13 @@ impl ThreadInfo {
 
 pub struct WorkerThread {
     worker: Worker<JobRef>,
-    stealers: Vec<Stealer<JobRef>>,
+    len: Arc<DequeLen>,
+    stats: Arc<StealStats>,
     index: usize,
 
+    /// The registry (i.e., thread pool) that this worker belongs to.
+    registry: Arc<Registry>,
+
     /// A counter tracking how many calls to `Scope::spawn` occurred
     /// on the current thread; this is used by the scope code to
     /// ensure that the depth of the local deque is maintained.
@@ -260,8 +1397,45 @@ pub struct WorkerThread {
     /// empty when we go to steal.
     spawn_count: Cell<usize>,
 
+    /// The number of `join` calls on this thread's stack that pushed
+    /// their second closure onto the deque, i.e., that hold a
+    /// `StackJob` live on their frame. Compared against the pool's
+    /// `join_depth_limit`.
+    join_depth: Cell<usize>,
+
+    /// In heartbeat mode (see `Configuration::set_heartbeat_interval`),
+    /// the second closures of the `join` calls on this thread's stack,
+    /// from the outermost to the innermost. These are only pushed onto
+    /// the deque when a heartbeat promotes them, which happens oldest
+    /// first; so the first `promoted` of them have been pushed, and
+    /// the rest have not.
+    pending: RefCell<Vec<JobRef>>,
+    promoted: Cell<usize>,
+
+    /// Number of `push_pending` calls since the last heartbeat.
+    heartbeat: Cell<usize>,
+
+    /// If true, `pop_local` takes jobs from the bottom of our deque
+    /// (the end thieves steal from) rather than the top; see
+    /// `Configuration::set_breadth_first`.
+    breadth_first: bool,
+
+    /// Scratch memory for the jobs executed on this thread; see
+    /// `with_scratch()`.
+    scratch: Arena,
+
     /// A weak random number generator.
-    rng: rand::XorShiftRng,
+    rng: XorShift64Star,
+
+    /// True while this thread is in a `mark_blocking` call that got a
+    /// spare worker to stand in for it.
+    blocking: Cell<bool>,
+
+    /// Once the stack pointer gets below this address, the stack is
+    /// nearly exhausted; see `Configuration::set_stack_guard`. While
+    /// we run on a stack segment (see `with_stack_low_end`), this is
+    /// the limit of the segment.
+    stack_limit: Cell<usize>,
 }
 
 // This is a bit sketchy, but basically: the WorkerThread is
@@ -275,6 +1449,28 @@ thread_local! {
 }
 
 impl WorkerThread {
+    /// The state of worker `index` of `registry`, to run on the current
+    /// thread, with `worker` as its deque.
+    fn new(worker: Worker<JobRef>, registry: Arc<Registry>, index: usize) -> WorkerThread {
+        WorkerThread {
+            worker: worker,
+            len: registry.thread_infos[index].len.clone(),
+            stats: registry.thread_infos[index].stats.clone(),
+            index: index,
+            spawn_count: Cell::new(0),
+            join_depth: Cell::new(0),
+            pending: RefCell::new(Vec::new()),
+            promoted: Cell::new(0),
+            heartbeat: Cell::new(0),
+            breadth_first: registry.breadth_first,
+            scratch: Arena::new(),
+            rng: XorShift64Star::new(index),
+            blocking: Cell::new(false),
+            stack_limit: Cell::new(stack_limit(&registry)),
+            registry: registry,
+        }
+    }
+
     /// Gets the `WorkerThread` index for the current thread; returns
     /// NULL if this is not a worker thread. This pointer is valid
     /// anywhere on the current thread.
@@ -287,16 +1483,86 @@ impl WorkerThread {
     /// This is done during worker thread startup.
     unsafe fn set_current(&mut self) {
         WORKER_THREAD_STATE.with(|t| {
-            assert!(t.get().is_null());
+            assert!(t.get().is_null(), "the current thread is already a worker thread");
             t.set(self);
         });
     }
 
+    /// Undoes `set_current`, once the worker is done. This matters for
+    /// donated threads, which go on to run the caller's code.
+    unsafe fn clear_current() {
+        WORKER_THREAD_STATE.with(|t| t.set(ptr::null_mut()));
+    }
+
     #[inline]
     pub fn index(&self) -> usize {
         self.index
     }
 
+    #[inline]
+    pub fn registry(&self) -> &Arc<Registry> {
+        &self.registry
+    }
+
+    #[inline]
+    pub fn scratch(&self) -> &Arena {
+        &self.scratch
+    }
+
+    /// Called when this thread enters `mark_blocking`: lets a spare
+    /// worker stand in for it. Returns false if an enclosing
+    /// `mark_blocking` call already did, in which case there is
+    /// nothing to undo.
+    pub fn start_blocking(&self) -> bool {
+        if self.blocking.get() {
+            return false;
+        }
+        self.blocking.set(true);
+        Registry::start_blocking(&self.registry);
+        true
+    }
+
+    /// Called when the `mark_blocking` call for which `start_blocking`
+    /// returned true is done.
+    pub fn stop_blocking(&self) {
+        self.blocking.set(false);
+        self.registry.stop_blocking();
+    }
+
+    /// True if less of our stack is left than the pool's stack guard.
+    #[inline]
+    pub fn stack_is_low(&self) -> bool {
+        let here = 0u8;
+        (&here as *const u8 as usize) < self.stack_limit.get()
+    }
+
+    /// Runs `f` with the stack limit moved to the stack guard above
+    /// `low_end`, the low end of the stack segment we are running on
+    /// (see `Configuration::set_growable_stacks`), and then moves it
+    /// back. A segment smaller than twice the guard keeps half of it in
+    /// reserve, so that we get some use out of it before moving on.
+    pub fn with_stack_low_end<F, R>(&self, low_end: usize, f: F) -> R
+        where F: FnOnce() -> R
+    {
+        let here = 0u8;
+        let size = (&here as *const u8 as usize) - low_end;
+        let guard = cmp::min(self.registry.stack_guard, size / 2);
+        let old_limit = self.stack_limit.get();
+        self.stack_limit.set(low_end + guard);
+        let _limit_guard = unwind::finally(&self.stack_limit, |limit| limit.set(old_limit));
+        f()
+    }
+
+    #[inline]
+    pub fn join_depth(&self) -> usize {
+        self.join_depth.get()
+    }
+
+    #[inline]
+    pub fn set_join_depth(&self, depth: usize) {
+        self.join_depth.set(depth);
+    }
+
     /// Read current value of the spawn counter.
     ///
     /// See the `spawn_count` field for an extensive comment on the
@@ -324,9 +1590,9 @@ impl WorkerThread {
     #[inline]
     pub unsafe fn pop_spawned_jobs(&self, start_count: usize) {
         while self.spawn_count.get() != start_count {
-            if let Some(job_ref) = self.pop() {
+            if let Some(job_ref) = self.pop_local() {
                 self.spawn_count.set(self.spawn_count.get() - 1);
-                job_ref.execute(JobMode::Execute);
+                self.execute(job_ref);
             } else {
                 self.spawn_count.set(start_count);
                 break;
@@ -334,115 +1600,708 @@ impl WorkerThread {
         }
     }
 
+    /// Pushes `job` onto the bottom of our deque. This never fails: the
+    /// deque has no fixed capacity, but doubles its buffer whenever it
+    /// fills up, so however deeply `join` calls nest, each one can
+    /// push its second closure. (A deep enough tree of `join` calls
+    /// overflows the stack long before the deque becomes a problem;
+    /// see `Configuration::set_join_depth_limit`.)
     #[inline]
     pub unsafe fn push(&self, job: JobRef) {
+        if self.registry.route(Some(self.index), job) {
+            // only the worker it was handed to can take it, so make
+            // sure that one is awake
+            self.registry.wake(true);
+            return;
+        }
         self.worker.push(job);
+        DequeLen::bump(&self.len.pushed);
+        self.registry.tickle();
+    }
+
+    /// True if our deque holds so many jobs that the other workers have
+    /// plenty to steal, a couple each, even without the next one that
+    /// `join` would push. (Not with a steal log, whose schedule must
+    /// not depend on how far the thieves got.)
+    #[inline]
+    pub fn deque_is_crowded(&self) -> bool {
+        self.len.get() >= CROWDED_JOBS_PER_THREAD * self.registry.num_threads() &&
+        !self.registry.has_steal_log()
+    }
+
+    /// How many jobs other workers have stolen from our deque so far.
+    #[inline]
+    pub fn stolen_count(&self) -> usize {
+        self.len.stolen.load(Ordering::Relaxed)
     }
 
     /// Pop `job` from top of stack, returning `false` if it has been
     /// stolen.
     #[inline]
     pub unsafe fn pop(&self) -> Option<JobRef> {
-        self.worker.pop()
+        let job = self.worker.pop();
+        if job.is_some() {
+            DequeLen::bump(&self.len.popped);
+        }
+        job
+    }
+
+    /// Takes a job from our own deque, to run spawned jobs or when we
+    /// are otherwise idle: the newest one, or in breadth-first mode the
+    /// oldest one, like a thief would. (`join` always pops its own job
+    /// from the top, where it is found right away, no matter the mode.)
+    unsafe fn pop_local(&self) -> Option<JobRef> {
+        if !self.breadth_first {
+            return self.pop();
+        }
+        loop {
+            match self.registry.thread_infos[self.index].stealer.steal() {
+                Stolen::Data(job) => {
+                    DequeLen::bump(&self.len.popped);
+                    return Some(job);
+                }
+                Stolen::Empty => return None,
+                Stolen::Abort => (), // lost a race with a thief; retry
+            }
+        }
+    }
+
+    /// Notes `job` as pending, for use in heartbeat mode; the `join`
+    /// that calls this must later call `pop_pending` with the same
+    /// job. If a heartbeat is due, this also promotes the oldest
+    /// pending job that has not been promoted yet, i.e., pushes it
+    /// onto the deque.
+    pub unsafe fn push_pending(&self, job: JobRef) {
+        let mut pending = self.pending.borrow_mut();
+        pending.push(job);
+
+        let beats = self.heartbeat.get() + 1;
+        if beats < self.registry.heartbeat_interval().unwrap_or(1) {
+            self.heartbeat.set(beats);
+            return;
+        }
+        self.heartbeat.set(0);
+        let promoted = self.promoted.get();
+        if promoted < pending.len() {
+            self.promoted.set(promoted + 1);
+            self.push(pending[promoted]);
+        }
+    }
+
+    /// Removes `job`, which must be the innermost pending job, and
+    /// returns `true` if it was promoted (in which case it must be
+    /// popped from the deque as usual, or may have been stolen).
+    pub fn pop_pending(&self, job: &JobRef) -> bool {
+        let mut pending = self.pending.borrow_mut();
+        let popped = pending.pop().unwrap();
+        debug_assert!(popped.same_job(job));
+        let index = pending.len();
+        if index < self.promoted.get() {
+            self.promoted.set(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Pops jobs from the top of our deque until we find `job`,
+    /// returning `false` if it has been stolen (i.e., the deque ran
+    /// empty first). `job` itself is not executed.
+    ///
+    /// Normally, `job` is right at the top, but there may be jobs
+    /// above it that this thread stole in bulk (see `steal_from`) and
+    /// has not gotten to yet. Those are executed as we go: any thread
+    /// may execute them, so it might as well be us, and now.
+    pub unsafe fn pop_job(&self, job: &JobRef) -> bool {
+        while let Some(popped) = self.pop() {
+            if popped.same_job(job) {
+                return true;
+            }
+            self.execute(popped);
+        }
+        false
+    }
+
+    /// Makes `job` available for stealing by workers on our NUMA node
+    /// only; see `join_local`. The caller must take it back with
+    /// `pop_node_local`, unless it gets stolen.
+    pub unsafe fn push_node_local(&self, job: JobRef) {
+        self.registry.thread_infos[self.index].push_node_job(job);
+        self.registry.tickle_all();
+    }
+
+    /// Takes back a job that was pushed with `push_node_local`. Returns
+    /// false if it was stolen.
+    pub unsafe fn pop_node_local(&self, job: &JobRef) -> bool {
+        self.registry.thread_infos[self.index].pop_node_job(job)
+    }
+
+    /// Notes that `join` found its job still on our deque (`popped`
+    /// is true) or that it had been stolen; see `steal_stats`.
+    #[inline]
+    pub fn record_pop(&self, popped: bool) {
+        if popped {
+            log!(PoppedJob { worker: self.index });
+            StealStats::bump(&self.stats.popped);
+        } else {
+            log!(LostJob { worker: self.index });
+            StealStats::bump(&self.stats.lost);
+        }
+    }
+
+    /// Tries to get `job` back, which was stolen off our deque, before
+    /// the thief starts it; returns true if we got it, and the caller
+    /// must then run it. Called by `join` before it waits for `latch`,
+    /// which is set once `job` has run.
+    ///
+    /// A thief that steals in bulk (see `steal_from`) runs the oldest
+    /// job it took, and puts the others on its own deque, where they
+    /// wait; that is how `job` can be stolen and still not have
+    /// started. The thief left its index with us, so we steal from its
+    /// deque, a few jobs at most: these are the jobs it took from us,
+    /// oldest first, so `job` is likely among them. Any other job we
+    /// get is run right away, as `steal_until` would have. The deque
+    /// hands each job to one thread only, so `job` still runs exactly
+    /// once: by us, if we got it, or else by the thief, or by whoever
+    /// else stole it in the meantime.
+    #[cold]
+    #[inline(never)]
+    pub unsafe fn reclaim<L: Latch + ?Sized>(&self, job: &JobRef, latch: &L) -> bool {
+        let info = &self.registry.thread_infos[self.index];
+        let thief = info.bulk_thief.swap(NO_THIEF, Ordering::Relaxed);
+        if thief == NO_THIEF || self.registry.has_steal_log() {
+            return false;
+        }
+        let thief = &self.registry.thread_infos[thief];
+        let spawn_count = self.spawn_count.get();
+        for _ in 0..RECLAIM_STEALS {
+            if latch.probe() {
+                return false;
+            }
+            let stolen = match thief.stealer.steal() {
+                Stolen::Data(stolen) => stolen,
+                Stolen::Abort => continue,
+                Stolen::Empty => return false,
+            };
+            thief.len.stolen.fetch_add(1, Ordering::Relaxed);
+            if stolen.same_job(job) {
+                return true;
+            }
+            StealStats::bump(&self.stats.stolen);
+            self.execute(stolen);
+            self.pop_spawned_jobs(spawn_count);
+        }
+        false
     }
 
     /// Keep stealing jobs until the latch is set.
     #[cold]
-    pub unsafe fn steal_until(&mut self, latch: &SpinLatch) {
+    pub unsafe fn steal_until<L: Latch + ?Sized>(&mut self, latch: &L) {
         let spawn_count = self.spawn_count.get();
 
         // If another thread stole our job when we panic, we must halt unwinding
         // until that thread is finished using it.
-        let guard = unwind::finally(&latch, |latch| latch.spin());
+        let guard = unwind::finally(latch, |latch| latch::spin_until(*latch));
+        let mut backoff = self.registry.backoff();
+        let mut blocked = Blocked::new(true);
         while !latch.probe() {
             if let Some(job) = self.steal_work() {
+                blocked.set(&self.registry, self.index, false);
                 debug_assert!(self.spawn_count.get() == spawn_count);
-                job.execute(JobMode::Execute);
+                self.execute(job);
                 self.pop_spawned_jobs(spawn_count);
+                backoff.reset();
             } else {
-                thread::yield_now();
+                blocked.set(&self.registry, self.index, true);
+                backoff.snooze();
             }
         }
+        blocked.set(&self.registry, self.index, false);
         mem::forget(guard);
     }
 
-    /// Steal a single job and return it.
+    /// Steals one job from another worker, or failing that from the
+    /// injector queue, and runs it along with anything it spawned.
+    /// Returns false if there was nothing to steal. See `yield_now` in
+    /// the API for why our own deque is left alone.
+    pub unsafe fn yield_now(&mut self) -> bool {
+        let spawn_count = self.spawn_count.get();
+        match self.steal_remote() {
+            Some(job) => {
+                self.execute(job);
+                self.pop_spawned_jobs(spawn_count);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs `op(index)` on each worker of our registry: on ourselves
+    /// directly, and on each of the others by posting a job to its
+    /// mailbox, which it checks before looking for any other work (see
+    /// `steal_work`). We then wait for those jobs as `join` waits for
+    /// a stolen closure. If `op` panics anywhere, the panic is
+    /// propagated once all the jobs are done; our own comes first. A
+    /// spare or the helper is not one of the workers that `op` runs on,
+    /// so from there, all of the jobs are posted.
+    pub unsafe fn broadcast<OP>(&mut self, op: &OP)
+        where OP: Fn(usize) + Sync
+    {
+        let num_threads = self.registry.num_threads();
+        let jobs: Vec<_> = (0..num_threads)
+            .filter(|&index| index != self.index)
+            .map(|index| (index, StackJob::new(move |_| op(index), SpinLatch::new())))
+            .collect();
+        for &(index, ref job) in &jobs {
+            self.registry.thread_infos[index].post(job.as_job_ref());
+        }
+        if !jobs.is_empty() {
+            self.registry.wake(true);
+        }
+
+        let index = self.index;
+        let result = if index < num_threads {
+            unwind::halt_unwinding(|| op(index))
+        } else {
+            Ok(())
+        };
+        for &(_, ref job) in &jobs {
+            self.steal_until(&job.latch);
+        }
+        if let Err(err) = result {
+            unwind::resume_unwinding(err);
+        }
+        for (_, job) in jobs {
+            job.take_result();
+        }
+    }
+
+    /// Executes a job found by `steal_work` from the main loop.
+    unsafe fn execute_stolen(&self, job: JobRef) {
+        debug_assert!(self.spawn_count.get() == 0);
+        self.execute(job);
+        self.pop_spawned_jobs(0);
+    }
+
+    /// Executes `job` on this worker.
+    #[inline]
+    pub unsafe fn execute(&self, job: JobRef) {
+        self.run_job(|| job.execute(JobMode::Execute))
+    }
+
+    /// Runs `func`, which executes a job on this worker, between calls
+    /// to the job handlers of our registry, if there are any (see
+    /// `Configuration::set_job_start_handler`).
+    #[inline]
+    pub fn run_job<F, R>(&self, func: F) -> R
+        where F: FnOnce() -> R
+    {
+        if self.registry.has_job_handlers() {
+            self.run_job_with_handlers(func)
+        } else {
+            func()
+        }
+    }
+
+    /// The cold part of `run_job`, kept out of line so that it takes no
+    /// room in the frames of jobs that run without handlers.
+    #[inline(never)]
+    fn run_job_with_handlers<F, R>(&self, func: F) -> R
+        where F: FnOnce() -> R
+    {
+        let registry = &*self.registry;
+        if let Some(ref job_start_handler) = registry.job_start_handler {
+            job_start_handler(self.index);
+        }
+        let _end_guard = unwind::finally(Instant::now(), |start| {
+            if let Some(ref job_end_handler) = registry.job_end_handler {
+                job_end_handler(self.index, start.elapsed());
+            }
+        });
+        func()
+    }
+
+    /// Find a job to execute when we are otherwise idle and return it.
+    ///
+    /// Jobs posted to our mailbox come first, since no other worker
+    /// can run them. Normally our local deque is empty at this point,
+    /// except if an earlier call stole several jobs at once and left
+    /// the extras there (see `steal_from`), so we check it next. Then
+    /// we try to steal from the other workers. If none of them has
+    /// anything to steal, fall back to the global injector queue, so
+    /// that jobs submitted from outside the pool make progress even
+    /// while all the workers are blocked in `steal_until`.
     unsafe fn steal_work(&mut self) -> Option<JobRef> {
-        // at no point should we try to steal unless our local deque is empty
-        debug_assert!(self.pop().is_none());
+        self.registry.thread_infos[self.index]
+            .take_mail()
+            .or_else(|| self.pop_local())
+            .or_else(|| self.steal_remote())
+    }
+
+    /// Steals from the other workers, or failing that from the
+    /// injector queue. When replaying a steal schedule, takes the next
+    /// job handed to us instead, or once there are no more, only looks
+    /// at the injector queue.
+    unsafe fn steal_remote(&mut self) -> Option<JobRef> {
+        match self.registry.take_replayed(self.index) {
+            None => self.steal_from_others().or_else(|| self.pop_injected_job()),
+            Some(Ok(job)) => job,
+            Some(Err(())) => self.pop_injected_job(),
+        }
+    }
+
+    unsafe fn pop_injected_job(&self) -> Option<JobRef> {
+        let job = self.registry.pop_injected_job();
+        if let Some(ref job) = job {
+            self.registry.record_steal(self.index, job);
+        }
+        job
+    }
 
-        if self.stealers.is_empty() {
+    unsafe fn steal_from_others(&mut self) -> Option<JobRef> {
+        let num_threads = self.registry.thread_infos.len();
+        if num_threads <= 1 {
             return None;
         }
-        let start = self.rng.next_u32() % self.stealers.len() as u32;
-        let (lo, hi) = self.stealers.split_at(start as usize);
-        hi.iter()
-            .chain(lo)
-            .filter_map(|stealer| {
-                match stealer.steal() {
-                    Stolen::Empty => None,
-                    Stolen::Abort => None, // loop?
-                    Stolen::Data(v) => Some(v),
-                }
+        let start = self.rng.next_usize(num_threads);
+        let job = (start..num_threads)
+            .chain(0..start)
+            .filter(|&i| i != self.index)
+            .filter_map(|i| {
+                let victim = &self.registry.thread_infos[i];
+                self.steal_node_job_from(victim).or_else(|| self.steal_from(victim))
             })
-            .next()
+            .next();
+        if job.is_none() {
+            StealStats::bump(&self.stats.failed_steals);
+        }
+        job
+    }
+
+    /// Steals the oldest `join_local` job of `victim`, if it is on the
+    /// same NUMA node as we are.
+    unsafe fn steal_node_job_from(&self, victim: &ThreadInfo) -> Option<JobRef> {
+        let node = self.registry.thread_infos[self.index].numa_node.load(Ordering::Relaxed);
+        if victim.numa_node.load(Ordering::Relaxed) != node {
+            return None;
+        }
+        let job = victim.steal_node_job();
+        if job.is_some() {
+            StealStats::bump(&self.stats.stolen);
+        }
+        job
+    }
+
+    /// Steals a job from `victim`. If the victim has a backlog of jobs,
+    /// we take roughly half of it in one go, executing the first job
+    /// (which we return) and pushing the rest onto our own deque, where
+    /// we will perhaps get to them later. This way, a thief doesn't
+    /// keep returning to the same victim for one job after another.
+    ///
+    /// Note that this means our deque may contain jobs that were not
+    /// pushed by us, and which may still be there when we go back to
+    /// an enclosing `join` (e.g., if the latch we were waiting for in
+    /// `steal_until` got set in the meantime). So `join` must not
+    /// assume that the job at the top of the deque is its own; it uses
+    /// `pop_job` instead. When the victim finds its own job missing,
+    /// it waits for its latch as usual, which is set by whoever runs
+    /// it.
+    unsafe fn steal_from(&self, victim: &ThreadInfo) -> Option<JobRef> {
+        let job = match victim.stealer.steal() {
+            Stolen::Empty => return None,
+            Stolen::Abort => return None, // loop?
+            Stolen::Data(job) => job,
+        };
+        victim.len.stolen.fetch_add(1, Ordering::Relaxed);
+        StealStats::bump(&self.stats.stolen);
+        self.registry.record_steal(self.index, &job);
+
+        // (A steal log keys jobs by who pushed them, so extras would
+        // throw it off; see `Configuration::set_steal_mode`. The helper
+        // takes a single job; see `Registry::try_help`.)
+        let helper = self.index == self.registry.helper_index();
+        let extras = if self.registry.has_steal_log() || helper { 0 } else { victim.len.get() / 2 };
+        for extra in 0..extras {
+            match victim.stealer.steal() {
+                Stolen::Data(extra_job) => {
+                    victim.len.stolen.fetch_add(1, Ordering::Relaxed);
+                    StealStats::bump(&self.stats.stolen);
+                    self.push(extra_job);
+                    if extra == 0 {
+                        victim.bulk_thief.store(self.index, Ordering::Relaxed);
+                    }
+                }
+                Stolen::Empty | Stolen::Abort => break,
+            }
+        }
+
+        Some(job)
+    }
+}
+
+/// Whether a worker currently counts as blocked, for detecting
+/// deadlocks; see `Registry::block`. `waiting` says whether the worker
+/// is waiting for a latch in `steal_until`, or idle in the main loop.
+struct Blocked {
+    blocked: bool,
+    waiting: bool,
+}
+
+impl Blocked {
+    fn new(waiting: bool) -> Blocked {
+        Blocked {
+            blocked: false,
+            waiting: waiting,
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, registry: &Registry, worker: usize, blocked: bool) {
+        if blocked != self.blocked && registry.detects_deadlocks() {
+            if blocked {
+                registry.block(worker, self.waiting);
+            } else {
+                registry.unblock(self.waiting);
+            }
+            self.blocked = blocked;
+        }
+    }
+}
+
+/// [xorshift*] is a fast pseudorandom number generator which will
+/// even tolerate weak seeding, as long as it's not zero. We only use
+/// it to pick victims to steal from, so the quality of the randomness
+/// hardly matters; what matters is that it is cheap, since it runs
+/// every time a worker goes looking for work, and that different
+/// workers produce different sequences.
+///
+/// [xorshift*]: https://en.wikipedia.org/wiki/Xorshift#xorshift*
+struct XorShift64Star {
+    state: u64,
+}
+
+impl XorShift64Star {
+    /// Seeds the generator from the worker index. Multiplying by an
+    /// odd constant spreads out consecutive indices and never yields
+    /// zero (which would make the generator stuck at zero).
+    fn new(index: usize) -> Self {
+        XorShift64Star { state: (index as u64).wrapping_add(1).wrapping_mul(0x9E3779B97F4A7C15) }
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.state;
+        debug_assert!(x != 0);
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Returns a value from `0..n`.
+    fn next_usize(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
     }
 }
 
 /// ////////////////////////////////////////////////////////////////////////
 
-unsafe fn main_loop(worker: Worker<JobRef>, registry: Arc<Registry>, index: usize) {
-    let stealers = registry.thread_infos
-        .iter()
-        .enumerate()
-        .filter(|&(i, _)| i != index)
-        .map(|(_, ti)| ti.stealer.clone())
-        .collect::<Vec<_>>();
-
-    assert!(stealers.len() < ::std::u32::MAX as usize,
-            "We assume this is not going to happen!");
-
-    let mut worker_thread = WorkerThread {
-        worker: worker,
-        stealers: stealers,
-        index: index,
-        spawn_count: Cell::new(0),
-        rng: rand::weak_rng(),
+/// Returns the NUMA node that the current thread is running on.
+#[cfg(target_os = "linux")]
+fn current_numa_node() -> usize {
+    let mut cpu: libc::c_uint = 0;
+    let mut node: libc::c_uint = 0;
+    let result = unsafe {
+        libc::syscall(libc::SYS_getcpu,
+                      &mut cpu as *mut libc::c_uint,
+                      &mut node as *mut libc::c_uint,
+                      ptr::null_mut::<libc::c_void>())
     };
+    if result == 0 { node as usize } else { 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_numa_node() -> usize {
+    0
+}
+
+/// Pins the current thread to `core`, if the operating system lets us;
+/// see `Configuration::set_affinity`.
+#[cfg(target_os = "linux")]
+fn pin_to_core(core: CoreId) {
+    if core.0 >= libc::CPU_SETSIZE as usize {
+        return;
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(core.0, &mut set);
+        // failure leaves the thread unpinned, which is all we promise
+        libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_core: CoreId) {}
+
+/// Returns the address below which the stack of the current thread
+/// (which is about to become a worker of `registry`) is nearly
+/// exhausted, or zero if there is no stack guard.
+fn stack_limit(registry: &Registry) -> usize {
+    if registry.stack_guard == 0 {
+        return 0;
+    }
+    let low_end = stack_low_end().unwrap_or_else(|| {
+        // we are near the top of the stack; stacks grow down
+        let here = 0u8;
+        let stack_size = registry.stack_size.unwrap_or(DEFAULT_STACK_SIZE);
+        (&here as *const u8 as usize).saturating_sub(stack_size)
+    });
+    low_end.saturating_add(registry.stack_guard)
+}
+
+/// The stack size of a thread spawned by `std::thread` without one.
+const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// Returns the lowest address of the current thread's stack.
+#[cfg(target_os = "linux")]
+fn stack_low_end() -> Option<usize> {
+    unsafe {
+        let mut attr: libc::pthread_attr_t = mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let mut stack_addr = ptr::null_mut();
+        let mut stack_size = 0;
+        let result = libc::pthread_attr_getstack(&attr, &mut stack_addr, &mut stack_size);
+        libc::pthread_attr_destroy(&mut attr);
+        if result == 0 { Some(stack_addr as usize) } else { None }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn stack_low_end() -> Option<usize> {
+    None
+}
+
+/// The builder for the thread of worker `index`, with the name and
+/// stack size from the configuration.
+fn thread_builder(configuration: &Configuration, index: usize) -> thread::Builder {
+    let mut builder = thread::Builder::new();
+    if let Some(name) = configuration.thread_name(index) {
+        builder = builder.name(name);
+    }
+    if let Some(stack_size) = configuration.stack_size() {
+        builder = builder.stack_size(stack_size);
+    }
+    builder
+}
+
+unsafe fn main_loop(worker: Worker<JobRef>, registry: Arc<Registry>, index: usize) {
+    let mut worker_thread = WorkerThread::new(worker, registry.clone(), index);
     worker_thread.set_current();
+    let _current_guard = unwind::finally((), |_| WorkerThread::clear_current());
+
+    // Worker threads should not panic. If they do, the internal state
+    // of the threadpool is corrupted, so poison the registry to make
+    // future calls into it fail rather than hang. Note that if **user
+    // code** panics, we should catch that and redirect. (The start and
+    // exit handlers are the exception: a panic there poisons the pool
+    // too, rather than leaving a half-initialized worker running.) We
+    // also set our primed latch, in case we never got to do so.
+    let poison_guard = unwind::finally(&registry, |registry| {
+        registry.poison();
+        registry.thread_infos[index].primed.set();
+    });
+
+    if let Some(core) = registry.thread_infos[index].core {
+        pin_to_core(core);
+    }
+
+    if let Some(ref start_handler) = registry.start_handler {
+        start_handler(index);
+    }
+
+    // look up our NUMA node, unless it was configured (after the start
+    // handler, which may have pinned us)
+    let numa_node = &registry.thread_infos[index].numa_node;
+    if numa_node.load(Ordering::Relaxed) == UNKNOWN_NODE {
+        numa_node.store(current_numa_node(), Ordering::Relaxed);
+    }
 
     // let registry know we are ready to do work
     registry.thread_infos[index].primed.set();
 
-    // Worker threads should not panic. If they do, just abort, as the
-    // internal state of the threadpool is corrupted. Note that if
-    // **user code** panics, we should catch that and redirect.
-    let abort_guard = unwind::AbortIfPanic;
+    // a spare worker only looks for work while it is wanted
+    let spare = index >= registry.num_threads;
+    if spare {
+        registry.active_spares.fetch_add(1, Ordering::SeqCst);
+    }
 
-    let mut was_active = false;
+    let mut backoff = registry.backoff();
+    let mut blocked = Blocked::new(false);
     loop {
-        match registry.wait_for_work(index, was_active) {
-            Work::Job(injected_job) => {
-                injected_job.execute(JobMode::Execute);
-                was_active = true;
-                continue;
+        if spare && !registry.spare_is_wanted(index) {
+            blocked.set(&registry, index, false);
+            if !registry.park_spare(index) {
+                break;
             }
-            Work::Terminate => break,
-            Work::None => {}
+            backoff.reset();
         }
-
-        if let Some(stolen_job) = worker_thread.steal_work() {
+        if let Some(job) = worker_thread.steal_work() {
             log!(StoleWork { worker: index });
-            registry.start_working(index);
-            debug_assert!(worker_thread.spawn_count.get() == 0);
-            stolen_job.execute(JobMode::Execute);
-            worker_thread.pop_spawned_jobs(0);
-            was_active = true;
+            blocked.set(&registry, index, false);
+            worker_thread.execute_stolen(job);
+            backoff.reset();
+        } else if !backoff.is_completed() {
+            blocked.set(&registry, index, true);
+            backoff.snooze();
         } else {
-            was_active = false;
+            // We haven't found anything to do in a while, so go to
+            // sleep (see `Registry::get_sleepy` for the protocol).
+            let generation = registry.get_sleepy();
+            if let Some(job) = worker_thread.steal_work() {
+                registry.cancel_sleep();
+                blocked.set(&registry, index, false);
+                worker_thread.execute_stolen(job);
+            } else if !registry.sleep(index, generation) {
+                break;
+            }
+            backoff.reset();
         }
     }
+    blocked.set(&registry, index, false);
+
+    if let Some(ref exit_handler) = registry.exit_handler {
+        exit_handler(index);
+    }
+
+    // Normal termination, do not poison.
+    mem::forget(poison_guard);
+}
+
+/// A worker of a thread pool that runs on threads donated by the
+/// caller (see `Configuration::set_use_current_threads`), as handed out
+/// by `ThreadPool::take_worker`. It does nothing until some thread
+/// calls `run`.
+pub struct DonatedWorker {
+    registry: Arc<Registry>,
+    worker: Worker<JobRef>,
+    index: usize,
+}
+
+impl DonatedWorker {
+    /// The index of this worker in its pool (see
+    /// `current_thread_index()`).
+    pub fn index(&self) -> usize {
+        self.index
+    }
 
-    // Normal termination, do not abort.
-    mem::forget(abort_guard);
+    /// Makes the current thread this worker of the pool: it runs the
+    /// start handler, executes and steals jobs until the pool is
+    /// dropped, runs the exit handler and returns. Panics if the
+    /// current thread is already a worker thread, of this pool or of
+    /// any other.
+    pub fn run(self) {
+        unsafe { main_loop(self.worker, self.registry, self.index) }
+    }
 }
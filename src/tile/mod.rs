@@ -0,0 +1,65 @@
+//! Parallel iteration over the tiles of a 2D index space.
+//!
+//! Matrix and image kernels tend to work best on rectangular blocks,
+//! which keep both their rows and their columns in cache, rather than
+//! on whole rows. `for_each_tile()` cuts a `(rows, cols)` index space
+//! into such blocks with `join`, always halving the longer side, so
+//! tiles stay close to square however lopsided the space is.
+
+use api;
+use std::cmp;
+use std::ops::Range;
+
+#[cfg(test)]
+mod test;
+
+/// Splits the index space `rows` × `cols` into rectangular tiles, and
+/// calls `f(row_range, col_range)` for each of them, in parallel.
+///
+/// A tile is split in two, along its longer side, for as long as its
+/// area is larger than `tile_area`, so every tile has an area of at most
+/// `tile_area` (where a `tile_area` of 0 is treated as 1). A space that
+/// is no larger than that is passed to `f` as a single tile. The tiles cover the whole space, and do not overlap,
+/// so each index pair is passed to `f` exactly once. An empty space has
+/// no tiles, and `f` is never called.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// let cells = AtomicUsize::new(0);
+/// rayon::for_each_tile(0..100, 0..30, 64, |rows, cols| {
+///     assert!(rows.len() * cols.len() <= 64);
+///     cells.fetch_add(rows.len() * cols.len(), Ordering::SeqCst);
+/// });
+/// assert_eq!(cells.load(Ordering::SeqCst), 100 * 30);
+/// ```
+pub fn for_each_tile<F>(rows: Range<usize>, cols: Range<usize>, tile_area: usize, f: F)
+    where F: Fn(Range<usize>, Range<usize>) + Sync
+{
+    if rows.start < rows.end && cols.start < cols.end {
+        tile_helper(rows, cols, cmp::max(tile_area, 1), &f);
+    }
+}
+
+fn tile_helper<F>(rows: Range<usize>, cols: Range<usize>, tile_area: usize, f: &F)
+    where F: Fn(Range<usize>, Range<usize>) + Sync
+{
+    let (num_rows, num_cols) = (rows.end - rows.start, cols.end - cols.start);
+    if num_rows.saturating_mul(num_cols) <= tile_area {
+        f(rows, cols);
+    } else if num_rows >= num_cols {
+        let mid = rows.start + num_rows / 2;
+        let (top, bottom) = (rows.start..mid, mid..rows.end);
+        let (left_cols, right_cols) = (cols.clone(), cols);
+        api::join(|| tile_helper(top, left_cols, tile_area, f),
+                  || tile_helper(bottom, right_cols, tile_area, f));
+    } else {
+        let mid = cols.start + num_cols / 2;
+        let (left, right) = (cols.start..mid, mid..cols.end);
+        let (top_rows, bottom_rows) = (rows.clone(), rows);
+        api::join(|| tile_helper(top_rows, left, tile_area, f),
+                  || tile_helper(bottom_rows, right, tile_area, f));
+    }
+}
@@ -0,0 +1,95 @@
+use Configuration;
+use ThreadPool;
+use for_each_tile;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tiles `rows` × `cols`, counting how often each cell was covered,
+/// and returns the counts along with the list of tiles.
+fn cover(rows: Range<usize>,
+         cols: Range<usize>,
+         tile_area: usize)
+         -> (Vec<Vec<usize>>, Vec<(Range<usize>, Range<usize>)>) {
+    let grid: Vec<Vec<AtomicUsize>> = (0..rows.end)
+        .map(|_| (0..cols.end).map(|_| AtomicUsize::new(0)).collect())
+        .collect();
+    let tiles = Mutex::new(Vec::new());
+    for_each_tile(rows, cols, tile_area, |rows, cols| {
+        for row in rows.clone() {
+            for col in cols.clone() {
+                grid[row][col].fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        tiles.lock().unwrap().push((rows, cols));
+    });
+    let grid = grid.iter()
+        .map(|row| row.iter().map(|cell| cell.load(Ordering::SeqCst)).collect())
+        .collect();
+    (grid, tiles.into_inner().unwrap())
+}
+
+fn assert_covered_once(grid: &[Vec<usize>], rows: Range<usize>, cols: Range<usize>) {
+    for (row, cells) in grid.iter().enumerate() {
+        for (col, &count) in cells.iter().enumerate() {
+            let inside = row >= rows.start && col >= cols.start;
+            assert_eq!(count, inside as usize, "cell ({}, {})", row, col);
+        }
+    }
+}
+
+#[test]
+fn tiles_cover_square_space() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let (grid, tiles) = pool.install(|| cover(0..64, 0..64, 16));
+    assert_covered_once(&grid, 0..64, 0..64);
+    assert_eq!(tiles.len(), 64 * 64 / 16);
+    for (rows, cols) in tiles {
+        assert_eq!((rows.len(), cols.len()), (4, 4));
+    }
+}
+
+#[test]
+fn tiles_cover_lopsided_space() {
+    // splitting along the longer side keeps the tiles close to square
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    let (grid, tiles) = pool.install(|| cover(3..10, 5..1005, 50));
+    assert_covered_once(&grid, 3..10, 5..1005);
+    for (rows, cols) in tiles {
+        assert!(rows.len() * cols.len() <= 50);
+        assert!(cols.len() <= 2 * rows.len() + 1, "tile {:?} x {:?}", rows, cols);
+    }
+}
+
+#[test]
+fn tiles_cover_odd_sizes() {
+    for &(num_rows, num_cols, tile_area) in &[(1, 1, 1), (13, 7, 5), (1, 100, 3), (99, 1, 10)] {
+        let (grid, tiles) = cover(0..num_rows, 0..num_cols, tile_area);
+        assert_covered_once(&grid, 0..num_rows, 0..num_cols);
+        for (rows, cols) in tiles {
+            assert!(rows.len() * cols.len() <= tile_area);
+        }
+    }
+}
+
+#[test]
+fn space_smaller_than_tile() {
+    let (grid, tiles) = cover(2..5, 1..4, 1000);
+    assert_covered_once(&grid, 2..5, 1..4);
+    assert_eq!(tiles, vec![(2..5, 1..4)]);
+}
+
+#[test]
+fn zero_tile_area_is_one() {
+    let (grid, tiles) = cover(0..4, 0..3, 0);
+    assert_covered_once(&grid, 0..4, 0..3);
+    assert_eq!(tiles.len(), 12);
+}
+
+#[test]
+fn empty_space_has_no_tiles() {
+    let (_, tiles) = cover(0..0, 0..10, 4);
+    assert!(tiles.is_empty());
+    let (_, tiles) = cover(0..10, 5..5, 4);
+    assert!(tiles.is_empty());
+}
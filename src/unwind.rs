@@ -1,9 +1,22 @@
 //! Package up unwind recovery. Note that if you are in some sensitive
 //! place, you can use the `AbortIfPanic` helper to protect against
 //! accidental panics in the rayon code itself.
+//!
+//! When built with `-C panic=abort`, there is no unwinding to recover
+//! from: any panic aborts the process right where it happens. So
+//! `halt_unwinding` just runs the closure, and `resume_unwinding` can
+//! never be reached, since there is never a payload to resume with.
+//!
+//! Payloads are propagated as they are, but `join` notes which of its
+//! closures a panic came from as it unwinds (see `NoteOriginIfPanic`),
+//! and a job that takes a payload to another thread takes that note
+//! along.
 
 use libc;
 use std::any::Any;
+use std::cell::Cell;
+use std::fmt;
+#[cfg(not(panic = "abort"))]
 use std::panic::{self, AssertUnwindSafe};
 use std::io::stderr;
 use std::io::prelude::*;
@@ -13,16 +26,122 @@ use std::thread;
 /// `Err` result. The assumption is that any panic will be propagated
 /// later with `resume_unwinding`, and hence `f` can be treated as
 /// exception safe.
+#[cfg(not(panic = "abort"))]
 pub fn halt_unwinding<F, R>(func: F) -> thread::Result<R>
     where F: FnOnce() -> R
 {
-    panic::catch_unwind(AssertUnwindSafe(func))
+    panic::catch_unwind(AssertUnwindSafe(func)).map_err(|err| {
+        claim_panic_origin(&err);
+        err
+    })
 }
 
+#[cfg(panic = "abort")]
+#[inline]
+pub fn halt_unwinding<F, R>(func: F) -> thread::Result<R>
+    where F: FnOnce() -> R
+{
+    Ok(func())
+}
+
+#[cfg(not(panic = "abort"))]
 pub fn resume_unwinding(payload: Box<Any + Send>) -> ! {
     panic::resume_unwind(payload)
 }
 
+#[cfg(panic = "abort")]
+pub fn resume_unwinding(_payload: Box<Any + Send>) -> ! {
+    unreachable!("no panic can be caught when panics abort")
+}
+
+/// Which of the two closures of a `join` a panic came from; see
+/// `join_panic_origin()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicOrigin {
+    /// The first closure, `oper_a`.
+    OperA,
+
+    /// The second closure, `oper_b`.
+    OperB,
+}
+
+impl fmt::Display for PanicOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PanicOrigin::OperA => write!(f, "panic occurred in oper_a of rayon::join"),
+            PanicOrigin::OperB => write!(f, "panic occurred in oper_b of rayon::join"),
+        }
+    }
+}
+
+thread_local! {
+    /// The origin noted by the last `join` closure that a panic unwound
+    /// out of on this thread, until whatever catches the panic claims
+    /// it (see `claim_panic_origin`).
+    static PENDING_ORIGIN: Cell<Option<PanicOrigin>> = Cell::new(None)
+}
+
+thread_local! {
+    /// The address of the payload of the last panic caught on this
+    /// thread that had an origin, and that origin.
+    static CLAIMED_ORIGIN: Cell<Option<(usize, PanicOrigin)>> = Cell::new(None)
+}
+
+fn payload_address(payload: &Box<Any + Send>) -> usize {
+    &**payload as *const (Any + Send) as *const () as usize
+}
+
+/// Notes `origin` for the panic that is unwinding on this thread,
+/// replacing whatever an inner `join` noted for it.
+pub fn note_unwinding_origin(origin: PanicOrigin) {
+    PENDING_ORIGIN.with(|pending| pending.set(Some(origin)));
+}
+
+/// Ties the origin noted for the panic that unwound on this thread (if
+/// any) to its payload, now that it has been caught. Every panic that
+/// Rayon catches goes through here, so that the note cannot linger and
+/// be taken for the origin of a later panic.
+#[cold]
+fn claim_panic_origin(payload: &Box<Any + Send>) {
+    if let Some(origin) = PENDING_ORIGIN.with(|pending| pending.get()) {
+        PENDING_ORIGIN.with(|pending| pending.set(None));
+        let address = payload_address(payload);
+        CLAIMED_ORIGIN.with(|claimed| claimed.set(Some((address, origin))));
+    }
+}
+
+/// Returns the origin noted for `payload`, which must have been caught
+/// on this thread, if any.
+pub fn panic_origin(payload: &Box<Any + Send>) -> Option<PanicOrigin> {
+    claim_panic_origin(payload);
+    let address = payload_address(payload);
+    CLAIMED_ORIGIN.with(|claimed| match claimed.get() {
+        Some((claimed_address, origin)) if claimed_address == address => Some(origin),
+        _ => None,
+    })
+}
+
+/// Notes its origin for the panic that is unwinding, if it is dropped
+/// (like `AbortIfPanic`, it is meant to be forgotten once the code it
+/// guards has returned). Nothing is caught, so this costs nothing
+/// unless there is a panic.
+pub struct NoteOriginIfPanic(pub PanicOrigin);
+
+impl Drop for NoteOriginIfPanic {
+    fn drop(&mut self) {
+        note_unwinding_origin(self.0);
+    }
+}
+
+/// Returns true if the current thread is unwinding because of a
+/// panic, e.g., if we are being called from a destructor. Scheduling
+/// code that runs in that state should keep things simple, since a
+/// second panic aborts the process.
+#[inline]
+pub fn is_unwinding() -> bool {
+    thread::panicking()
+}
+
 pub struct AbortIfPanic;
 
 impl Drop for AbortIfPanic {
@@ -1,10 +1,6 @@
-use std::mem;
-use std::sync::Arc;
-
-pub fn leak<T>(b: Arc<T>) -> &'static T {
+pub fn leak<T>(v: T) -> &'static T {
     unsafe {
-        let p: *const T = &*b;
-        mem::forget(b); // leak our reference, so that `b` is never freed
-        &*p
+        // leak the box, so that `v` is never freed
+        &*Box::into_raw(Box::new(v))
     }
 }
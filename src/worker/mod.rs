@@ -0,0 +1,149 @@
+//! Direct access to the worker thread, for building new primitives.
+//!
+//! `join` and `scope` are built out of a handful of operations on the
+//! worker thread they run on: push a job onto the worker's deque, take
+//! it back if nobody stole it in the meantime, and otherwise steal
+//! other work until the job is done. `with_worker()` hands these
+//! operations out through a `CurrentWorker`, so that other primitives
+//! can be built the same way, with jobs of their own (see `Job`).
+//!
+//! The operations are only as safe as the jobs they are used with. In
+//! particular:
+//!
+//! - A job must stay valid until it has been executed, and must be
+//!   executed exactly once (see `JobRef`). A job that lives on the
+//!   stack must therefore be taken back or waited for, even if the
+//!   frame that owns it panics.
+//! - A job that is pushed may be executed by any worker of the pool,
+//!   at any time until somebody takes it back.
+//! - Jobs that are pushed but never taken back stay on the deque, where
+//!   the worker (or an enclosing `join` taking back its own job) runs
+//!   them eventually; so a job may well run later than expected, but
+//!   it can not get lost.
+
+use job::JobRef;
+use latch::Latch;
+use thread_pool::{Registry, WorkerThread};
+
+#[cfg(test)]
+mod test;
+
+/// Runs `f` on a worker thread, with access to that worker. If called
+/// from a worker thread, `f` runs right away, on that worker; otherwise,
+/// it runs on a worker of the global pool, and the current thread
+/// blocks until it is done. Panics in `f` are propagated to the caller.
+///
+/// # Example
+///
+/// A primitive that runs a batch of boxed tasks in parallel and waits
+/// for all of them, built from a job kind of its own:
+///
+/// ```rust
+/// use rayon::{CountLatch, Job, JobMode, JobRef, Latch};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// struct TaskJob<'a> {
+///     task: Box<Fn() + Send + Sync + 'a>,
+///     latch: &'a CountLatch,
+/// }
+///
+/// impl<'a> Job for TaskJob<'a> {
+///     unsafe fn execute(this: *const Self, mode: JobMode) {
+///         let this = Box::from_raw(this as *mut Self);
+///         if let JobMode::Execute = mode {
+///             (this.task)();
+///         }
+///         this.latch.set();
+///     }
+/// }
+///
+/// fn run_all<'a>(tasks: Vec<Box<Fn() + Send + Sync + 'a>>) {
+///     let latch = CountLatch::new(tasks.len());
+///     rayon::with_worker(|worker| {
+///         for task in tasks {
+///             let job = Box::new(TaskJob { task: task, latch: &latch });
+///             unsafe {
+///                 worker.push(JobRef::new(Box::into_raw(job)));
+///             }
+///         }
+///         // the jobs borrow `latch`, so wait for all of them
+///         worker.wait_until(&latch);
+///     });
+/// }
+///
+/// let counter = AtomicUsize::new(0);
+/// let tasks = (0..8)
+///     .map(|i| {
+///         let counter = &counter;
+///         Box::new(move || { counter.fetch_add(i, Ordering::SeqCst); })
+///             as Box<Fn() + Send + Sync>
+///     })
+///     .collect();
+/// run_all(tasks);
+/// assert_eq!(counter.load(Ordering::SeqCst), 28);
+/// ```
+pub fn with_worker<F, R>(f: F) -> R
+    where F: FnOnce(&CurrentWorker) -> R + Send,
+          R: Send
+{
+    Registry::current().in_worker(|| {
+        let worker = CurrentWorker { thread: unsafe { WorkerThread::current() } };
+        f(&worker)
+    })
+}
+
+/// The worker thread that a `with_worker()` closure runs on.
+///
+/// A `CurrentWorker` can not be sent to (or shared with) another thread,
+/// since its operations are only meaningful on the worker itself.
+pub struct CurrentWorker {
+    thread: *mut WorkerThread,
+}
+
+impl CurrentWorker {
+    /// The index of this worker in its pool, between 0 and
+    /// `num_threads() - 1`.
+    pub fn index(&self) -> usize {
+        unsafe { (*self.thread).index() }
+    }
+
+    /// The number of worker threads in the pool of this worker.
+    pub fn num_threads(&self) -> usize {
+        unsafe { (*self.thread).registry().num_threads() }
+    }
+
+    /// Pushes `job` onto the deque of this worker, where other workers
+    /// can steal it. Unsafe because the job must stay valid until it
+    /// has been executed; see the module docs.
+    pub unsafe fn push(&self, job: JobRef) {
+        (*self.thread).push(job)
+    }
+
+    /// Pops the newest job off the deque of this worker, if any. The
+    /// caller then owns the job, and must execute it. Unsafe because
+    /// the job may belong to an enclosing `join` or to another
+    /// primitive, which expects it to be executed exactly once.
+    /// (In a debug build, a `join` that finds its second closure popped
+    /// this way, and not run, reports it; see the `join` docs.)
+    pub unsafe fn pop(&self) -> Option<JobRef> {
+        (*self.thread).pop()
+    }
+
+    /// Takes `job`, which must have been pushed by this worker, back
+    /// off the deque, returning `false` if it was stolen. If some jobs
+    /// were pushed after `job` and are still around, they are executed
+    /// along the way; `job` itself is not. Unsafe because this returns
+    /// `false` for a job that was never pushed, too, and because the
+    /// caller must execute `job` if this returns `true`.
+    pub unsafe fn take_back(&self, job: &JobRef) -> bool {
+        let popped = (*self.thread).pop_job(job);
+        (*self.thread).record_pop(popped);
+        popped
+    }
+
+    /// Runs jobs of other workers until `latch` is set, as `join` does
+    /// while it waits for its stolen closure; see `wait_until()`.
+    pub fn wait_until<L: Latch + ?Sized>(&self, latch: &L) {
+        unsafe { (*self.thread).steal_until(latch) }
+    }
+}
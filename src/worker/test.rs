@@ -0,0 +1,129 @@
+use Configuration;
+use ThreadPool;
+use job::{Job, JobMode, JobRef};
+use latch::{Latch, SpinLatch};
+use std::cell::UnsafeCell;
+use super::with_worker;
+
+/// The second closure of `fork`, waiting on the stack of the frame
+/// that pushed it.
+struct ForkJob<F, R> {
+    func: UnsafeCell<Option<F>>,
+    result: UnsafeCell<Option<R>>,
+    latch: SpinLatch,
+}
+
+impl<F, R> Job for ForkJob<F, R>
+    where F: FnOnce() -> R
+{
+    unsafe fn execute(this: *const Self, mode: JobMode) {
+        let this = &*this;
+        if let JobMode::Execute = mode {
+            let func = (*this.func.get()).take().unwrap();
+            *this.result.get() = Some(func());
+        }
+        this.latch.set();
+    }
+}
+
+/// A bare-bones `join`, built only from `with_worker()`: push `b`, run
+/// `a`, then take `b` back and run it, or wait for whoever stole it.
+/// (Unlike `join`, this does not guard against panics.)
+fn fork<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+    where A: FnOnce() -> RA + Send,
+          B: FnOnce() -> RB + Send,
+          RA: Send,
+          RB: Send
+{
+    with_worker(|worker| unsafe {
+        let job = ForkJob {
+            func: UnsafeCell::new(Some(b)),
+            result: UnsafeCell::new(None),
+            latch: SpinLatch::new(),
+        };
+        let job_ref = JobRef::new(&job);
+        worker.push(job_ref);
+        let result_a = a();
+        if worker.take_back(&job_ref) {
+            job_ref.execute(JobMode::Execute);
+        } else {
+            worker.wait_until(&job.latch);
+        }
+        (result_a, (*job.result.get()).take().unwrap())
+    })
+}
+
+fn fib(n: u64) -> u64 {
+    if n < 2 {
+        n
+    } else {
+        let (a, b) = fork(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+}
+
+#[test]
+fn with_worker_outside_pool() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(3)).unwrap();
+    let (index, num_threads) = pool.install(|| with_worker(|w| (w.index(), w.num_threads())));
+    assert!(index < 3);
+    assert_eq!(num_threads, 3);
+
+    // from outside of any pool, `f` runs on a worker of the global one
+    let num_threads = with_worker(|w| w.num_threads());
+    assert_eq!(num_threads, ::current_num_threads());
+}
+
+#[test]
+fn with_worker_fork() {
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(4)).unwrap();
+    assert_eq!(pool.install(|| fib(20)), 6765);
+}
+
+#[test]
+fn with_worker_fork_single_thread() {
+    // nobody can steal, so every job is taken back
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(1)).unwrap();
+    assert_eq!(pool.install(|| fib(15)), 610);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn join_reports_popped_oper_b() {
+    use api::abandoned_jobs;
+    use join;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    // keep the other worker busy, so that nobody steals `oper_b`
+    let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+    let (started_tx, started_rx) = channel();
+    let (release_tx, release_rx) = channel::<()>();
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+    });
+    started_rx.recv().unwrap();
+
+    // `oper_a` takes `oper_b` off the deque and hands it to another
+    // thread, which only runs it once `join` has had time to notice
+    let before = abandoned_jobs();
+    let helper = pool.install(|| {
+        let mut helper = None;
+        let result = join(|| {
+                              let job = with_worker(|worker| unsafe { worker.pop() }).unwrap();
+                              helper = Some(thread::spawn(move || {
+                                  thread::sleep(Duration::from_millis(200));
+                                  unsafe { job.execute(JobMode::Execute) }
+                              }));
+                              1
+                          },
+                          || 2);
+        assert_eq!(result, (1, 2));
+        helper
+    });
+    helper.unwrap().join().unwrap();
+    assert_eq!(abandoned_jobs(), before + 1);
+    release_tx.send(()).unwrap();
+}
@@ -0,0 +1,11 @@
+extern crate rayon;
+
+use std::cell::Cell;
+
+// Sharing a `Cell` between both closures of a `join` would be a data
+// race, since they may run on different threads.
+fn main() {
+    let cell = Cell::new(0);
+    rayon::join(|| cell.set(1), || cell.set(2));
+    //~^ ERROR E0277
+}
@@ -0,0 +1,11 @@
+extern crate rayon;
+
+use std::cell::Cell;
+
+// Returning a borrow of `Cell` data would let both threads get at it,
+// since `&Cell` is not `Send`.
+
+fn main() {
+    let c = Cell::new(22);
+    rayon::join(|| &c, || ()); //~ ERROR E0277
+}
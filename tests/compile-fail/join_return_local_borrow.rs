@@ -0,0 +1,12 @@
+extern crate rayon;
+
+// The closures run before `join` returns, but a borrow of a value that
+// is local to a closure still cannot escape it.
+
+fn main() {
+    let (a, b) = rayon::join(|| {
+        let v = vec![1, 2, 3];
+        &v[0] //~ ERROR E0597
+    }, || 22);
+    println!("{} {}", a, b);
+}
@@ -0,0 +1,13 @@
+extern crate rayon;
+
+// Check that `Scope::spawn` rejects closures that are not `Send`,
+// since spawned tasks may execute on any worker thread.
+
+use std::rc::Rc;
+
+fn main() {
+    let r = Rc::new(22);
+    rayon::scope(|s| {
+        s.spawn(move |_| println!("{}", r)); //~ ERROR E0277
+    });
+}
@@ -0,0 +1,49 @@
+extern crate rayon;
+
+use rayon::*;
+use rayon::prelude::*;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+fn main() {
+    Configuration::disable_implicit_pool();
+
+    // everything runs sequentially on this thread
+    assert_eq!(current_num_threads(), 1);
+    assert_eq!(join(current_thread_index, current_thread_index), (None, None));
+    assert_eq!(join_n(&mut [current_thread_index, current_thread_index]), vec![None, None]);
+    let mut v = vec![0; 10];
+    scope(|s| {
+        for (i, x) in v.iter_mut().enumerate() {
+            s.spawn(move |s| {
+                *x = i;
+                s.spawn(|_| assert_eq!(current_thread_index(), None));
+            });
+        }
+    });
+    assert_eq!(v, (0..10).collect::<Vec<_>>());
+    assert_eq!((0..1000).into_par_iter().sum(), 499500);
+
+    // `oper_b` does not run if `oper_a` panics
+    let b_ran = AtomicBool::new(false);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        join(|| panic!("oper_a"), || b_ran.store(true, Ordering::SeqCst))
+    }));
+    assert_eq!(result.unwrap_err().downcast_ref::<&str>(), Some(&"oper_a"));
+    assert!(!b_ran.load(Ordering::SeqCst));
+
+    // a scope still runs every job and propagates the first panic
+    let result = panic::catch_unwind(|| {
+        scope(|s| {
+            s.spawn(|_| panic!("first"));
+            s.spawn(|_| panic!("second"));
+        })
+    });
+    assert_eq!(result.unwrap_err().downcast_ref::<&str>(), Some(&"first"));
+
+    // no worker threads were started, or this would fail (see
+    // `init_after_default_init.rs`)
+    assert_eq!(initialize(Configuration::new().set_num_threads(2)), Ok(()));
+    assert_eq!(current_num_threads(), 2);
+    assert_eq!(join(|| current_thread_index().is_some(), || true), (true, true));
+}
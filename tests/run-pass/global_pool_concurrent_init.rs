@@ -0,0 +1,41 @@
+extern crate rayon;
+
+use rayon::ThreadPool;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+
+fn fib(n: u32) -> u32 {
+    if n < 2 {
+        return n;
+    }
+    let (a, b) = rayon::join(|| fib(n - 1), || fib(n - 2));
+    a + b
+}
+
+/// Test that threads racing to use the global pool before it exists
+/// all end up using the same one, with one set of worker threads.
+fn main() {
+    const RACERS: usize = 16;
+    let barrier = Arc::new(Barrier::new(RACERS));
+    let racers: Vec<_> = (0..RACERS)
+        .map(|_| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                assert_eq!(fib(15), 610);
+                ThreadPool::global() as *const ThreadPool as usize
+            })
+        })
+        .collect();
+    let pools: Vec<usize> = racers.into_iter().map(|t| t.join().unwrap()).collect();
+    assert!(pools.iter().all(|&pool| pool == pools[0]));
+
+    // `broadcast` runs once on every worker thread there is.
+    let pool = ThreadPool::global();
+    let indices = Mutex::new(Vec::new());
+    pool.broadcast(|index| indices.lock().unwrap().push(index));
+    let mut indices = indices.into_inner().unwrap();
+    indices.sort();
+    assert_eq!(indices, (0..pool.num_threads()).collect::<Vec<_>>());
+    assert_eq!(pool.num_threads(), rayon::current_num_threads());
+}
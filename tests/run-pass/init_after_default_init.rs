@@ -0,0 +1,21 @@
+extern crate rayon;
+
+use rayon::*;
+
+fn main() {
+    // this starts the global pool, with the default configuration
+    assert_eq!(join(|| 1, || 2), (1, 2));
+
+    // so any configuration given afterwards is ignored
+    let result = initialize(Configuration::new().set_num_threads(1));
+    assert_eq!(result, Err(InitError::GlobalPoolAlreadyInitialized));
+    let result = initialize(Configuration::new().set_breadth_first(true));
+    assert_eq!(result, Err(InitError::GlobalPoolAlreadyInitialized));
+
+    // unless it is the default one, too
+    let result = initialize(Configuration::new());
+    assert_eq!(result, Ok(()));
+
+    // and the pool keeps working
+    assert_eq!(join(|| 3, || 4), (3, 4));
+}
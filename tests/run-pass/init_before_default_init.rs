@@ -0,0 +1,14 @@
+extern crate rayon;
+
+use rayon::*;
+
+fn main() {
+    let result = initialize(Configuration::new().set_num_threads(2));
+    assert_eq!(result, Ok(()));
+
+    // using the pool afterwards takes the configuration given above
+    assert_eq!(join(current_num_threads, current_num_threads), (2, 2));
+
+    let result = initialize(Configuration::new().set_num_threads(2));
+    assert_eq!(result, Ok(()));
+}
@@ -0,0 +1,15 @@
+extern crate rayon;
+
+use rayon::*;
+
+fn main() {
+    let result = initialize(Configuration::new().set_num_threads(3));
+    assert_eq!(result, Ok(()));
+
+    assert_eq!(current_num_threads(), 3);
+    assert_eq!(join(current_num_threads, current_num_threads), (3, 3));
+
+    let result = initialize(Configuration::new().set_num_threads(4));
+    assert_eq!(result, Err(InitError::GlobalPoolAlreadyInitialized));
+    assert_eq!(current_num_threads(), 3);
+}
@@ -0,0 +1,12 @@
+extern crate rayon;
+
+use rayon::*;
+
+fn main() {
+    let result = initialize(Configuration::new().set_use_current_threads(true));
+    assert_eq!(result, Err(InitError::GlobalPoolOnCurrentThreads));
+
+    // the global pool was not started, so it can still be initialized
+    assert_eq!(initialize(Configuration::new().set_num_threads(2)), Ok(()));
+    assert_eq!(join(|| current_thread_index().is_some(), || true), (true, true));
+}
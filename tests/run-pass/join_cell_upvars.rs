@@ -0,0 +1,26 @@
+extern crate rayon;
+
+use std::cell::{Cell, RefCell};
+
+/// Test that the closures given to `join` only need to be `Send`, not
+/// `Sync`: each of them runs exactly once, on one thread, so either
+/// one may own (or mutably borrow) a `Cell` or `RefCell`.
+fn use_refcell(rc: &mut RefCell<Vec<i32>>) -> usize {
+    rc.borrow_mut().push(4);
+    rc.borrow().len()
+}
+
+fn main() {
+    let mut rc = RefCell::new(vec![1, 2, 3]);
+    let (len, sum) = rayon::join(|| use_refcell(&mut rc), || (0..10).sum::<i32>());
+    assert_eq!((len, sum), (4, 45));
+    assert_eq!(*rc.borrow(), [1, 2, 3, 4]);
+
+    let cell = Cell::new(1);
+    let (a, b) = rayon::join(move || {
+                                 cell.set(cell.get() + 1);
+                                 cell.get()
+                             },
+                             || Cell::new(22).get());
+    assert_eq!((a, b), (2, 22));
+}
@@ -0,0 +1,28 @@
+extern crate rayon;
+
+/// Test that the closures given to `join` can return borrows of data
+/// that outlives the `join` call, both shared and mutable ones.
+fn max_and_rest<'a>(data: &'a [i32]) -> (&'a i32, &'a [i32]) {
+    rayon::join(|| data.iter().max().unwrap(), || &data[1..])
+}
+
+fn first_of_halves<'a>(data: &'a mut [i32]) -> (&'a mut i32, &'a mut i32) {
+    let mid = data.len() / 2;
+    let (lo, hi) = data.split_at_mut(mid);
+    rayon::join(move || &mut lo[0], move || &mut hi[0])
+}
+
+fn main() {
+    let mut v = vec![1, 5, 3, 4];
+    {
+        let (max, rest) = max_and_rest(&v);
+        assert_eq!(*max, 5);
+        assert_eq!(rest, &[5, 3, 4]);
+    }
+    {
+        let (a, b) = first_of_halves(&mut v);
+        *a += 10;
+        *b += 20;
+    }
+    assert_eq!(v, [11, 5, 23, 4]);
+}
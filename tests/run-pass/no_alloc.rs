@@ -0,0 +1,128 @@
+extern crate rayon;
+
+use rayon::{Configuration, ThreadPool};
+use rayon::prelude::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts every allocation (and reallocation) made by any thread of the
+/// process.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// The number of allocations that `op` makes.
+fn allocations<OP: FnOnce()>(op: OP) -> usize {
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    op();
+    ALLOCATIONS.load(Ordering::SeqCst) - before
+}
+
+fn fib(n: u32) -> u32 {
+    if n < 2 {
+        return n;
+    }
+    let (a, b) = rayon::join(|| fib(n - 1), || fib(n - 2));
+    a + b
+}
+
+/// `join` does not touch the heap: the job for the second closure and
+/// its latch live in the caller's stack frame. The first round lets the
+/// deques grow to their final size (and each worker set up whatever it
+/// sets up lazily); after that, no allocation may happen at all,
+/// whether or not jobs get stolen.
+fn join_no_alloc() {
+    assert_eq!(fib(20), 6765);
+    let allocations = allocations(|| for _ in 0..10 {
+        assert_eq!(fib(20), 6765);
+    });
+    assert_eq!(allocations, 0);
+}
+
+const LEN: u32 = 1 << 20;
+
+/// Extending a vector from an exact parallel iterator writes each item
+/// straight into its slot: once the vector has been sized, there are no
+/// per-task vectors to allocate and merge, and the vector itself is
+/// never reallocated.
+fn par_extend_no_alloc() {
+    // let the deques grow to their final size first
+    let mut v: Vec<u32> = Vec::new();
+    v.par_extend((0..LEN).into_par_iter().map(|i| i / 2));
+
+    v.truncate(0);
+    let allocations = allocations(|| v.par_extend((0..LEN).into_par_iter().map(|i| i + 1)));
+    assert_eq!(allocations, 0);
+
+    assert_eq!(v.len() as u32, LEN);
+    assert!(v.iter().enumerate().all(|(i, &x)| x == i as u32 + 1));
+}
+
+/// Sums `data` in chunks, with each chunk first copied into a
+/// temporary buffer, as a kernel that needs to rearrange its input
+/// might do.
+fn sum(data: &[u32], with_scratch: bool) -> u32 {
+    if data.len() <= 64 {
+        return if with_scratch {
+            rayon::with_scratch(|scratch| {
+                let buf = scratch.alloc_slice(data.len(), 0);
+                buf.copy_from_slice(data);
+                buf.iter().sum()
+            })
+        } else {
+            let buf = data.to_vec();
+            buf.iter().sum()
+        };
+    }
+    let (lo, hi) = data.split_at(data.len() / 2);
+    let (a, b) = rayon::join(|| sum(lo, with_scratch), || sum(hi, with_scratch));
+    a + b
+}
+
+/// Temporaries allocated through `with_scratch` don't hit the global
+/// allocator, once every worker's arena has grown to size, while the
+/// same temporaries on the heap take one allocation each.
+fn scratch_no_alloc() {
+    let data: Vec<u32> = (0..1 << 12).collect();
+    let expected = data.iter().sum::<u32>();
+    for &with_scratch in &[false, true] {
+        assert_eq!(sum(&data, with_scratch), expected);
+
+        let allocations = allocations(|| for _ in 0..10 {
+            assert_eq!(sum(&data, with_scratch), expected);
+        });
+        if with_scratch {
+            assert_eq!(allocations, 0);
+        } else {
+            assert!(allocations >= 10 * 64);
+        }
+    }
+}
+
+/// Test that the hot paths of `join`, `par_extend` and `with_scratch`
+/// do not allocate, each in a pool of its own.
+fn main() {
+    for check in &[join_no_alloc as fn(), par_extend_no_alloc, scratch_no_alloc] {
+        let pool = ThreadPool::new(Configuration::new().set_num_threads(2)).unwrap();
+        pool.install(check);
+    }
+}